@@ -1,6 +1,11 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::env;
+use std::fs;
+use std::net::IpAddr;
 
+use ipnet::IpNet;
+
+use serde::Deserialize;
 use url::Url;
 
 #[derive(Clone, Debug)]
@@ -8,16 +13,21 @@ pub struct RelayConfig {
     pub host: String,
     pub port: u16,
     pub public_base_url: String,
+    pub pairing_join_base_url: String,
     pub max_json_bytes: usize,
     pub max_pair_requests_per_minute: usize,
     pub max_devices_per_session: usize,
+    pub device_connection_history_limit: usize,
     pub session_retention_ms: u64,
     pub pair_approval_timeout_ms: u64,
+    pub session_transfer_token_ttl_ms: u64,
     pub ws_auth_timeout_ms: u64,
     pub ws_heartbeat_interval_ms: u64,
     pub ws_heartbeat_timeout_ms: u64,
     pub token_rotation_grace_ms: u64,
     pub max_pending_join_waiters: usize,
+    pub max_pending_pair_requests_per_session: usize,
+    pub max_pending_pair_requests_per_ip: usize,
     pub max_ws_message_bytes: usize,
     pub max_socket_outbound_queue: usize,
     pub max_active_websocket_connections: usize,
@@ -30,9 +40,58 @@ pub struct RelayConfig {
     pub redis_key_prefix: String,
     pub nats_url: Option<String>,
     pub nats_subject_prefix: String,
+    /// Stable identity of this relay instance on the cross-instance bus. Durable consumer names
+    /// are derived from it, so a restarted pod resumes its JetStream consumers.
+    pub instance_id: Option<String>,
     pub nats_hmac_secret: Option<String>,
+    pub nats_hmac_keys: Vec<(String, String)>,
+    pub nats_hmac_key_id: Option<String>,
+    pub nats_hmac_keys_file: Option<String>,
+    pub nats_hmac_keys_reload_interval_ms: u64,
     pub nats_replay_window_ms: u64,
     pub nats_max_clock_skew_ms: u64,
+    pub nats_jetstream_enabled: bool,
+    pub nats_jetstream_stream: String,
+    pub nats_jetstream_max_age_ms: u64,
+    pub nats_jetstream_max_messages_per_subject: u64,
+    pub nats_jetstream_ack_wait_ms: u64,
+    pub nats_jetstream_max_deliver: u64,
+    pub presence_registry_enabled: bool,
+    pub presence_ttl_ms: u64,
+    pub presence_route_cache_ttl_ms: u64,
+    /// Byte quotas are enforced by each instance against the traffic it handles itself. They
+    /// are not aggregated across instances, so a session split over N instances can move up to
+    /// N times the quota.
+    pub session_byte_quota_per_instance: u64,
+    pub device_byte_quota_per_instance: u64,
+    pub byte_quota_window_ms: u64,
+    pub snapshot_cache_max_bytes: usize,
+    pub snapshot_cache_persist: bool,
+    pub approval_webhook_url: Option<String>,
+    pub approval_webhook_secret: Option<String>,
+    pub approval_webhook_deep_link_base_url: Option<String>,
+    pub lifecycle_webhook_urls: Vec<String>,
+    pub lifecycle_webhook_secret: Option<String>,
+    pub webhook_max_attempts: u64,
+    pub webhook_initial_backoff_ms: u64,
+    pub webhook_timeout_ms: u64,
+    pub penalty_box_failure_threshold: u64,
+    pub penalty_box_subnet_failure_threshold: u64,
+    pub penalty_box_window_ms: u64,
+    pub penalty_box_base_block_ms: u64,
+    pub penalty_box_max_block_ms: u64,
+    pub admin_api_token: Option<String>,
+    pub pairing_allow_cidrs: Vec<String>,
+    pub pairing_deny_cidrs: Vec<String>,
+    pub ws_allow_cidrs: Vec<String>,
+    pub ws_deny_cidrs: Vec<String>,
+    pub admin_allow_cidrs: Vec<String>,
+    pub admin_deny_cidrs: Vec<String>,
+    pub ip_access_rules_file: Option<String>,
+    pub ip_access_rules_reload_interval_ms: u64,
+    pub device_token_signing_keys: Vec<(String, String)>,
+    pub device_token_signing_key_id: Option<String>,
+    pub signed_device_token_ttl_ms: u64,
     pub trust_proxy: bool,
     pub allow_legacy_query_token_auth: bool,
     pub allowed_origins: HashSet<String>,
@@ -44,16 +103,26 @@ impl RelayConfig {
         let port = parse_u16("PORT", 8787);
         let public_base_url =
             env::var("PUBLIC_BASE_URL").unwrap_or_else(|_| format!("http://localhost:{port}"));
+        let pairing_join_base_url = env::var("PAIRING_JOIN_BASE_URL")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| public_base_url.clone());
         let max_json_bytes = parse_usize("MAX_JSON_BYTES", 65_536);
         let max_pair_requests_per_minute = parse_usize("MAX_PAIR_REQUESTS_PER_MINUTE", 60);
         let max_devices_per_session = parse_usize("MAX_DEVICES_PER_SESSION", 2);
+        let device_connection_history_limit = parse_usize("DEVICE_CONNECTION_HISTORY_LIMIT", 20);
         let session_retention_ms = parse_u64("SESSION_RETENTION_MS", 600_000);
         let pair_approval_timeout_ms = parse_u64("PAIR_APPROVAL_TIMEOUT_MS", 45_000);
+        let session_transfer_token_ttl_ms = parse_u64("SESSION_TRANSFER_TOKEN_TTL_MS", 600_000);
         let ws_auth_timeout_ms = parse_u64("WS_AUTH_TIMEOUT_MS", 10_000);
         let ws_heartbeat_interval_ms = parse_u64("WS_HEARTBEAT_INTERVAL_MS", 20_000);
         let ws_heartbeat_timeout_ms = parse_u64("WS_HEARTBEAT_TIMEOUT_MS", 60_000);
         let token_rotation_grace_ms = parse_u64("TOKEN_ROTATION_GRACE_MS", 30_000);
         let max_pending_join_waiters = parse_usize("MAX_PENDING_JOIN_WAITERS", 64);
+        let max_pending_pair_requests_per_session =
+            parse_usize("MAX_PENDING_PAIR_REQUESTS_PER_SESSION", 4);
+        let max_pending_pair_requests_per_ip = parse_usize("MAX_PENDING_PAIR_REQUESTS_PER_IP", 1);
         let max_ws_message_bytes = parse_usize("MAX_WS_MESSAGE_BYTES", 65_536);
         let max_socket_outbound_queue = parse_usize("MAX_SOCKET_OUTBOUND_QUEUE", 256);
         let max_active_websocket_connections =
@@ -82,12 +151,111 @@ impl RelayConfig {
             .map(|value| value.trim().to_string())
             .filter(|value| !value.is_empty())
             .unwrap_or_else(|| "codexchat.remote.relay".to_string());
+        let instance_id = ["RELAY_INSTANCE_ID", "POD_NAME"]
+            .into_iter()
+            .find_map(|name| {
+                env::var(name)
+                    .ok()
+                    .map(|value| value.trim().to_string())
+                    .filter(|value| !value.is_empty())
+            });
         let nats_hmac_secret = env::var("NATS_HMAC_SECRET")
             .ok()
             .map(|value| value.trim().to_string())
             .filter(|value| !value.is_empty());
+        let nats_hmac_keys = parse_keyring(&env::var("NATS_HMAC_KEYS").unwrap_or_default());
+        let nats_hmac_key_id = env::var("NATS_HMAC_KEY_ID")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+        let nats_hmac_keys_file = env::var("NATS_HMAC_KEYS_FILE")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+        let nats_hmac_keys_reload_interval_ms =
+            parse_u64("NATS_HMAC_KEYS_RELOAD_INTERVAL_MS", 30_000);
         let nats_replay_window_ms = parse_u64("NATS_REPLAY_WINDOW_MS", 120_000);
         let nats_max_clock_skew_ms = parse_u64("NATS_MAX_CLOCK_SKEW_MS", 30_000);
+        let nats_jetstream_enabled = parse_bool_env("NATS_JETSTREAM_ENABLED");
+        let nats_jetstream_stream = env::var("NATS_JETSTREAM_STREAM")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| "REMOTE_CONTROL_RELAY".to_string());
+        let nats_jetstream_max_age_ms = parse_u64("NATS_JETSTREAM_MAX_AGE_MS", 120_000);
+        let nats_jetstream_max_messages_per_subject =
+            parse_u64("NATS_JETSTREAM_MAX_MESSAGES_PER_SUBJECT", 1_000);
+        let nats_jetstream_ack_wait_ms = parse_u64("NATS_JETSTREAM_ACK_WAIT_MS", 5_000);
+        let nats_jetstream_max_deliver = parse_u64("NATS_JETSTREAM_MAX_DELIVER", 5);
+        let presence_registry_enabled = parse_bool_env("PRESENCE_REGISTRY_ENABLED");
+        let presence_ttl_ms = parse_u64("PRESENCE_TTL_MS", 30_000);
+        let presence_route_cache_ttl_ms = parse_u64("PRESENCE_ROUTE_CACHE_TTL_MS", 1_000);
+        let session_byte_quota_per_instance = parse_u64("SESSION_BYTE_QUOTA_PER_INSTANCE", 0);
+        let device_byte_quota_per_instance = parse_u64("DEVICE_BYTE_QUOTA_PER_INSTANCE", 0);
+        let byte_quota_window_ms = parse_u64("BYTE_QUOTA_WINDOW_MS", 60_000);
+        let snapshot_cache_max_bytes = parse_usize("SNAPSHOT_CACHE_MAX_BYTES", 65_536);
+        let snapshot_cache_persist = parse_bool_env("SNAPSHOT_CACHE_PERSIST");
+        let approval_webhook_url = env::var("APPROVAL_WEBHOOK_URL")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+        let approval_webhook_secret = env::var("APPROVAL_WEBHOOK_SECRET")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+        let approval_webhook_deep_link_base_url = env::var("APPROVAL_WEBHOOK_DEEP_LINK_BASE_URL")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+        let lifecycle_webhook_urls = env::var("LIFECYCLE_WEBHOOK_URLS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>();
+        let lifecycle_webhook_secret = env::var("LIFECYCLE_WEBHOOK_SECRET")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+        let webhook_max_attempts = parse_u64("WEBHOOK_MAX_ATTEMPTS", 5);
+        let webhook_initial_backoff_ms = parse_u64("WEBHOOK_INITIAL_BACKOFF_MS", 500);
+        let webhook_timeout_ms = parse_u64("WEBHOOK_TIMEOUT_MS", 5_000);
+        let penalty_box_failure_threshold = parse_u64("PENALTY_BOX_FAILURE_THRESHOLD", 20);
+        let penalty_box_subnet_failure_threshold =
+            parse_u64("PENALTY_BOX_SUBNET_FAILURE_THRESHOLD", 100);
+        let penalty_box_window_ms = parse_u64("PENALTY_BOX_WINDOW_MS", 600_000);
+        let penalty_box_base_block_ms = parse_u64("PENALTY_BOX_BASE_BLOCK_MS", 60_000);
+        let penalty_box_max_block_ms = parse_u64("PENALTY_BOX_MAX_BLOCK_MS", 3_600_000);
+        let admin_api_token = env::var("ADMIN_API_TOKEN")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+        let pairing_allow_cidrs = parse_list_env("PAIRING_ALLOW_CIDRS");
+        let pairing_deny_cidrs = parse_list_env("PAIRING_DENY_CIDRS");
+        let ws_allow_cidrs = parse_list_env("WS_ALLOW_CIDRS");
+        let ws_deny_cidrs = parse_list_env("WS_DENY_CIDRS");
+        let admin_allow_cidrs = parse_list_env("ADMIN_ALLOW_CIDRS");
+        let admin_deny_cidrs = parse_list_env("ADMIN_DENY_CIDRS");
+        let ip_access_rules_file = env::var("IP_ACCESS_RULES_FILE")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+        let ip_access_rules_reload_interval_ms =
+            parse_u64("IP_ACCESS_RULES_RELOAD_INTERVAL_MS", 30_000);
+        let device_token_signing_keys =
+            parse_keyring(&env::var("DEVICE_TOKEN_SIGNING_KEYS").unwrap_or_default());
+        let device_token_signing_key_id = env::var("DEVICE_TOKEN_SIGNING_KEY_ID")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .or_else(|| {
+                device_token_signing_keys
+                    .first()
+                    .map(|(key_id, _)| key_id.clone())
+            });
+        let signed_device_token_ttl_ms =
+            parse_u64("SIGNED_DEVICE_TOKEN_TTL_MS", 30 * 24 * 60 * 60 * 1_000);
         let trust_proxy = parse_bool_env("TRUST_PROXY");
         let allow_legacy_query_token_auth = parse_bool_env("ALLOW_LEGACY_QUERY_TOKEN_AUTH");
 
@@ -108,16 +276,21 @@ impl RelayConfig {
             host,
             port,
             public_base_url,
+            pairing_join_base_url,
             max_json_bytes,
             max_pair_requests_per_minute,
             max_devices_per_session,
+            device_connection_history_limit,
             session_retention_ms,
             pair_approval_timeout_ms,
+            session_transfer_token_ttl_ms,
             ws_auth_timeout_ms,
             ws_heartbeat_interval_ms,
             ws_heartbeat_timeout_ms,
             token_rotation_grace_ms,
             max_pending_join_waiters,
+            max_pending_pair_requests_per_session,
+            max_pending_pair_requests_per_ip,
             max_ws_message_bytes,
             max_socket_outbound_queue,
             max_active_websocket_connections,
@@ -130,9 +303,53 @@ impl RelayConfig {
             redis_key_prefix,
             nats_url,
             nats_subject_prefix,
+            instance_id,
             nats_hmac_secret,
+            nats_hmac_keys,
+            nats_hmac_key_id,
+            nats_hmac_keys_file,
+            nats_hmac_keys_reload_interval_ms,
             nats_replay_window_ms,
             nats_max_clock_skew_ms,
+            nats_jetstream_enabled,
+            nats_jetstream_stream,
+            nats_jetstream_max_age_ms,
+            nats_jetstream_max_messages_per_subject,
+            nats_jetstream_ack_wait_ms,
+            nats_jetstream_max_deliver,
+            presence_registry_enabled,
+            presence_ttl_ms,
+            presence_route_cache_ttl_ms,
+            session_byte_quota_per_instance,
+            device_byte_quota_per_instance,
+            byte_quota_window_ms,
+            snapshot_cache_max_bytes,
+            snapshot_cache_persist,
+            approval_webhook_url,
+            approval_webhook_secret,
+            approval_webhook_deep_link_base_url,
+            lifecycle_webhook_urls,
+            lifecycle_webhook_secret,
+            webhook_max_attempts,
+            webhook_initial_backoff_ms,
+            webhook_timeout_ms,
+            penalty_box_failure_threshold,
+            penalty_box_subnet_failure_threshold,
+            penalty_box_window_ms,
+            penalty_box_base_block_ms,
+            penalty_box_max_block_ms,
+            admin_api_token,
+            pairing_allow_cidrs,
+            pairing_deny_cidrs,
+            ws_allow_cidrs,
+            ws_deny_cidrs,
+            admin_allow_cidrs,
+            admin_deny_cidrs,
+            ip_access_rules_file,
+            ip_access_rules_reload_interval_ms,
+            device_token_signing_keys,
+            device_token_signing_key_id,
+            signed_device_token_ttl_ms,
             trust_proxy,
             allow_legacy_query_token_auth,
             allowed_origins,
@@ -153,6 +370,97 @@ impl RelayConfig {
         format!("ws://localhost:{}/ws", self.port)
     }
 
+    pub fn device_token_signing_key(&self, key_id: &str) -> Option<&str> {
+        self.device_token_signing_keys
+            .iter()
+            .find(|(candidate, _)| candidate == key_id)
+            .map(|(_, secret)| secret.as_str())
+    }
+
+    pub fn active_device_token_signing_key(&self) -> Option<(&str, &str)> {
+        let key_id = self.device_token_signing_key_id.as_deref()?;
+        self.device_token_signing_key(key_id)
+            .map(|secret| (key_id, secret))
+    }
+
+    /// Merges `NATS_HMAC_KEYS` with the keys in `NATS_HMAC_KEYS_FILE`. The file is re-read on
+    /// every call so the relay can pick up rotated keys without a restart.
+    pub fn load_nats_hmac_keyring(&self) -> Result<NatsHmacKeyring, String> {
+        let mut keys = self.nats_hmac_keys.clone();
+        let mut active_key_id = self.nats_hmac_key_id.clone();
+        if let Some(path) = &self.nats_hmac_keys_file {
+            let raw = fs::read_to_string(path)
+                .map_err(|error| format!("NATS_HMAC_KEYS_FILE could not be read: {error}"))?;
+            let file = serde_json::from_str::<NatsHmacKeysFile>(&raw)
+                .map_err(|error| format!("NATS_HMAC_KEYS_FILE is invalid: {error}"))?;
+            keys.extend(file.keys);
+            if file.active_key_id.is_some() {
+                active_key_id = file.active_key_id;
+            }
+        }
+        if keys.is_empty() {
+            return Ok(NatsHmacKeyring::default());
+        }
+
+        validate_keyring("NATS_HMAC_KEYS", &keys)?;
+        let keyring = NatsHmacKeyring {
+            active_key_id: active_key_id.or_else(|| keys.first().map(|(key_id, _)| key_id.clone())),
+            keys,
+        };
+        if keyring.active_key().is_none() {
+            return Err(
+                "NATS_HMAC_KEY_ID must name a key in NATS_HMAC_KEYS or NATS_HMAC_KEYS_FILE."
+                    .to_string(),
+            );
+        }
+        Ok(keyring)
+    }
+
+    /// Builds the per-route-group CIDR lists from the `*_ALLOW_CIDRS`/`*_DENY_CIDRS` variables.
+    /// Lists present in `IP_ACCESS_RULES_FILE` replace the matching variable, and the file is
+    /// re-read on every call so the lists can change without a restart.
+    pub fn load_ip_access_rules(&self) -> Result<IpAccessRules, String> {
+        let mut rules = IpAccessRules {
+            pairing: IpAccessList {
+                allow: parse_cidrs("PAIRING_ALLOW_CIDRS", &self.pairing_allow_cidrs)?,
+                deny: parse_cidrs("PAIRING_DENY_CIDRS", &self.pairing_deny_cidrs)?,
+            },
+            websocket: IpAccessList {
+                allow: parse_cidrs("WS_ALLOW_CIDRS", &self.ws_allow_cidrs)?,
+                deny: parse_cidrs("WS_DENY_CIDRS", &self.ws_deny_cidrs)?,
+            },
+            admin: IpAccessList {
+                allow: parse_cidrs("ADMIN_ALLOW_CIDRS", &self.admin_allow_cidrs)?,
+                deny: parse_cidrs("ADMIN_DENY_CIDRS", &self.admin_deny_cidrs)?,
+            },
+        };
+        let Some(path) = &self.ip_access_rules_file else {
+            return Ok(rules);
+        };
+
+        let raw = fs::read_to_string(path)
+            .map_err(|error| format!("IP_ACCESS_RULES_FILE could not be read: {error}"))?;
+        let file = serde_json::from_str::<IpAccessRulesFile>(&raw)
+            .map_err(|error| format!("IP_ACCESS_RULES_FILE is invalid: {error}"))?;
+        for (group, list, target) in [
+            ("pairing", file.pairing, &mut rules.pairing),
+            ("ws", file.ws, &mut rules.websocket),
+            ("admin", file.admin, &mut rules.admin),
+        ] {
+            let Some(list) = list else {
+                continue;
+            };
+            let name = format!("IP_ACCESS_RULES_FILE {group}");
+            if let Some(allow) = list.allow {
+                target.allow = parse_cidrs(&format!("{name}.allow"), &allow)?;
+            }
+            if let Some(deny) = list.deny {
+                target.deny = parse_cidrs(&format!("{name}.deny"), &deny)?;
+            }
+        }
+        Ok(rules)
+    }
+
     pub fn validate(&self) -> Result<(), String> {
         if self.host.trim().is_empty() {
             return Err("HOST must not be empty.".to_string());
@@ -165,6 +473,7 @@ impl RelayConfig {
         if !matches!(public_base_url.scheme(), "http" | "https") {
             return Err("PUBLIC_BASE_URL must use http or https.".to_string());
         }
+        validate_http_url("PAIRING_JOIN_BASE_URL", &self.pairing_join_base_url)?;
 
         let zero_invalidations = [
             ("MAX_JSON_BYTES", self.max_json_bytes == 0),
@@ -178,6 +487,10 @@ impl RelayConfig {
                 "PAIR_APPROVAL_TIMEOUT_MS",
                 self.pair_approval_timeout_ms == 0,
             ),
+            (
+                "SESSION_TRANSFER_TOKEN_TTL_MS",
+                self.session_transfer_token_ttl_ms == 0,
+            ),
             ("WS_AUTH_TIMEOUT_MS", self.ws_auth_timeout_ms == 0),
             (
                 "WS_HEARTBEAT_INTERVAL_MS",
@@ -188,6 +501,14 @@ impl RelayConfig {
                 "MAX_PENDING_JOIN_WAITERS",
                 self.max_pending_join_waiters == 0,
             ),
+            (
+                "MAX_PENDING_PAIR_REQUESTS_PER_SESSION",
+                self.max_pending_pair_requests_per_session == 0,
+            ),
+            (
+                "MAX_PENDING_PAIR_REQUESTS_PER_IP",
+                self.max_pending_pair_requests_per_ip == 0,
+            ),
             ("MAX_WS_MESSAGE_BYTES", self.max_ws_message_bytes == 0),
             (
                 "MAX_SOCKET_OUTBOUND_QUEUE",
@@ -227,15 +548,31 @@ impl RelayConfig {
                     .to_string(),
             );
         }
+        if self.max_pending_pair_requests_per_ip > self.max_pending_pair_requests_per_session {
+            return Err(
+                "MAX_PENDING_PAIR_REQUESTS_PER_IP must be less than or equal to MAX_PENDING_PAIR_REQUESTS_PER_SESSION."
+                    .to_string(),
+            );
+        }
         if self.allowed_origins.is_empty() {
             return Err("ALLOWED_ORIGINS must contain at least one origin.".to_string());
         }
         if self.nats_url.is_some() {
-            let Some(secret) = self.nats_hmac_secret.as_ref() else {
-                return Err("NATS_HMAC_SECRET must be set when NATS_URL is configured.".to_string());
-            };
-            if secret.len() < 32 {
-                return Err("NATS_HMAC_SECRET must be at least 32 characters.".to_string());
+            let keyring = self.load_nats_hmac_keyring()?;
+            match self.nats_hmac_secret.as_ref() {
+                Some(secret) if secret.len() < 32 => {
+                    return Err("NATS_HMAC_SECRET must be at least 32 characters.".to_string());
+                }
+                None if keyring.keys.is_empty() => {
+                    return Err(
+                        "NATS_HMAC_SECRET or NATS_HMAC_KEYS must be set when NATS_URL is configured."
+                            .to_string(),
+                    );
+                }
+                _ => {}
+            }
+            if self.nats_hmac_keys_file.is_some() && self.nats_hmac_keys_reload_interval_ms == 0 {
+                return Err("NATS_HMAC_KEYS_RELOAD_INTERVAL_MS must be greater than 0.".to_string());
             }
             if self.nats_replay_window_ms == 0 {
                 return Err("NATS_REPLAY_WINDOW_MS must be greater than 0.".to_string());
@@ -245,10 +582,329 @@ impl RelayConfig {
             }
         }
 
+        if let Some(instance_id) = &self.instance_id {
+            let is_valid_instance_id = instance_id.len() <= 64
+                && instance_id
+                    .chars()
+                    .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_');
+            if !is_valid_instance_id {
+                return Err(
+                    "RELAY_INSTANCE_ID may only contain letters, digits, '-' and '_' (at most 64)."
+                        .to_string(),
+                );
+            }
+        }
+
+        if self.nats_jetstream_enabled {
+            if self.nats_url.is_none() {
+                return Err("NATS_JETSTREAM_ENABLED requires NATS_URL.".to_string());
+            }
+            let is_valid_stream_name = self
+                .nats_jetstream_stream
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_');
+            if !is_valid_stream_name {
+                return Err(
+                    "NATS_JETSTREAM_STREAM may only contain letters, digits, '-' and '_'."
+                        .to_string(),
+                );
+            }
+            let zero_invalidations = [
+                (
+                    "NATS_JETSTREAM_MAX_AGE_MS",
+                    self.nats_jetstream_max_age_ms == 0,
+                ),
+                (
+                    "NATS_JETSTREAM_MAX_MESSAGES_PER_SUBJECT",
+                    self.nats_jetstream_max_messages_per_subject == 0,
+                ),
+                (
+                    "NATS_JETSTREAM_ACK_WAIT_MS",
+                    self.nats_jetstream_ack_wait_ms == 0,
+                ),
+                (
+                    "NATS_JETSTREAM_MAX_DELIVER",
+                    self.nats_jetstream_max_deliver == 0,
+                ),
+            ];
+            if let Some((name, _)) = zero_invalidations.into_iter().find(|(_, invalid)| *invalid) {
+                return Err(format!("{name} must be greater than 0."));
+            }
+            // Redeliveries older than the replay window are rejected anyway, so retaining them
+            // longer only wastes stream storage.
+            if self.nats_jetstream_max_age_ms > self.nats_replay_window_ms {
+                return Err(
+                    "NATS_JETSTREAM_MAX_AGE_MS must not exceed NATS_REPLAY_WINDOW_MS.".to_string(),
+                );
+            }
+            // A random identity would create fresh durable consumers on every restart and leave
+            // the previous ones behind.
+            if self.instance_id.is_none() {
+                return Err(
+                    "NATS_JETSTREAM_ENABLED requires RELAY_INSTANCE_ID or POD_NAME.".to_string(),
+                );
+            }
+        }
+
+        if self.presence_registry_enabled {
+            if self.redis_url.is_none() || self.nats_url.is_none() {
+                return Err(
+                    "PRESENCE_REGISTRY_ENABLED requires REDIS_URL and NATS_URL.".to_string()
+                );
+            }
+            if self.presence_ttl_ms < 3_000 {
+                return Err("PRESENCE_TTL_MS must be at least 3000.".to_string());
+            }
+            if self.presence_route_cache_ttl_ms >= self.presence_ttl_ms {
+                return Err(
+                    "PRESENCE_ROUTE_CACHE_TTL_MS must be less than PRESENCE_TTL_MS.".to_string(),
+                );
+            }
+        }
+
+        if let Some(webhook_url) = self.approval_webhook_url.as_deref() {
+            validate_http_url("APPROVAL_WEBHOOK_URL", webhook_url)?;
+            match self.approval_webhook_secret.as_ref() {
+                Some(secret) if secret.len() >= 32 => {}
+                Some(_) => {
+                    return Err(
+                        "APPROVAL_WEBHOOK_SECRET must be at least 32 characters.".to_string()
+                    );
+                }
+                None => {
+                    return Err(
+                        "APPROVAL_WEBHOOK_SECRET must be set when APPROVAL_WEBHOOK_URL is configured."
+                            .to_string(),
+                    );
+                }
+            }
+            let Some(deep_link_base_url) = self.approval_webhook_deep_link_base_url.as_deref()
+            else {
+                return Err(
+                    "APPROVAL_WEBHOOK_DEEP_LINK_BASE_URL must be set when APPROVAL_WEBHOOK_URL is configured."
+                        .to_string(),
+                );
+            };
+            validate_http_url("APPROVAL_WEBHOOK_DEEP_LINK_BASE_URL", deep_link_base_url)?;
+        }
+
+        if !self.lifecycle_webhook_urls.is_empty() {
+            for webhook_url in &self.lifecycle_webhook_urls {
+                validate_http_url("LIFECYCLE_WEBHOOK_URLS", webhook_url)?;
+            }
+            match self.lifecycle_webhook_secret.as_ref() {
+                Some(secret) if secret.len() >= 32 => {}
+                Some(_) => {
+                    return Err(
+                        "LIFECYCLE_WEBHOOK_SECRET must be at least 32 characters.".to_string()
+                    );
+                }
+                None => {
+                    return Err(
+                        "LIFECYCLE_WEBHOOK_SECRET must be set when LIFECYCLE_WEBHOOK_URLS is configured."
+                            .to_string(),
+                    );
+                }
+            }
+        }
+
+        if self.approval_webhook_url.is_some() || !self.lifecycle_webhook_urls.is_empty() {
+            let zero_invalidations = [
+                ("WEBHOOK_MAX_ATTEMPTS", self.webhook_max_attempts == 0),
+                ("WEBHOOK_TIMEOUT_MS", self.webhook_timeout_ms == 0),
+            ];
+            if let Some((name, _)) = zero_invalidations.into_iter().find(|(_, invalid)| *invalid) {
+                return Err(format!("{name} must be greater than 0."));
+            }
+        }
+
+        if self.penalty_box_failure_threshold > 0 {
+            let zero_invalidations = [
+                ("PENALTY_BOX_WINDOW_MS", self.penalty_box_window_ms == 0),
+                (
+                    "PENALTY_BOX_BASE_BLOCK_MS",
+                    self.penalty_box_base_block_ms == 0,
+                ),
+            ];
+            if let Some((name, _)) = zero_invalidations.into_iter().find(|(_, invalid)| *invalid) {
+                return Err(format!("{name} must be greater than 0."));
+            }
+            if self.penalty_box_max_block_ms < self.penalty_box_base_block_ms {
+                return Err(
+                    "PENALTY_BOX_MAX_BLOCK_MS must be at least PENALTY_BOX_BASE_BLOCK_MS."
+                        .to_string(),
+                );
+            }
+        }
+
+        if self
+            .admin_api_token
+            .as_ref()
+            .is_some_and(|token| token.len() < 32)
+        {
+            return Err("ADMIN_API_TOKEN must be at least 32 characters.".to_string());
+        }
+
+        self.load_ip_access_rules()?;
+        if self.ip_access_rules_file.is_some() && self.ip_access_rules_reload_interval_ms == 0 {
+            return Err("IP_ACCESS_RULES_RELOAD_INTERVAL_MS must be greater than 0.".to_string());
+        }
+
+        if (self.session_byte_quota_per_instance > 0 || self.device_byte_quota_per_instance > 0)
+            && self.byte_quota_window_ms == 0
+        {
+            return Err("BYTE_QUOTA_WINDOW_MS must be greater than 0.".to_string());
+        }
+
+        if self.snapshot_cache_persist {
+            if self.redis_url.is_none() {
+                return Err("SNAPSHOT_CACHE_PERSIST requires REDIS_URL.".to_string());
+            }
+            if self.snapshot_cache_max_bytes == 0 {
+                return Err(
+                    "SNAPSHOT_CACHE_PERSIST requires SNAPSHOT_CACHE_MAX_BYTES greater than 0."
+                        .to_string(),
+                );
+            }
+        }
+
+        if !self.device_token_signing_keys.is_empty() {
+            validate_keyring("DEVICE_TOKEN_SIGNING_KEYS", &self.device_token_signing_keys)?;
+            if self.active_device_token_signing_key().is_none() {
+                return Err(
+                    "DEVICE_TOKEN_SIGNING_KEY_ID must name a key in DEVICE_TOKEN_SIGNING_KEYS."
+                        .to_string(),
+                );
+            }
+            if self.signed_device_token_ttl_ms == 0 {
+                return Err("SIGNED_DEVICE_TOKEN_TTL_MS must be greater than 0.".to_string());
+            }
+        }
+
         Ok(())
     }
 }
 
+/// Cross-instance envelope keys: envelopes are signed with the active key and accepted when
+/// signed with any key in the ring.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NatsHmacKeyring {
+    pub active_key_id: Option<String>,
+    pub keys: Vec<(String, String)>,
+}
+
+impl NatsHmacKeyring {
+    pub fn key(&self, key_id: &str) -> Option<&str> {
+        self.keys
+            .iter()
+            .find(|(candidate, _)| candidate == key_id)
+            .map(|(_, secret)| secret.as_str())
+    }
+
+    pub fn active_key(&self) -> Option<(&str, &str)> {
+        let key_id = self.active_key_id.as_deref()?;
+        self.key(key_id).map(|secret| (key_id, secret))
+    }
+}
+
+#[derive(Deserialize)]
+struct NatsHmacKeysFile {
+    #[serde(rename = "activeKeyID")]
+    active_key_id: Option<String>,
+    keys: BTreeMap<String, String>,
+}
+
+/// Client address allow/deny lists for one route group. A deny match always rejects; a non-empty
+/// allow list rejects every address it does not contain.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IpAccessList {
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+}
+
+impl IpAccessList {
+    pub fn permits(&self, ip: Option<IpAddr>) -> bool {
+        let Some(ip) = ip else {
+            return self.allow.is_empty() && self.deny.is_empty();
+        };
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IpAccessRules {
+    pub pairing: IpAccessList,
+    pub websocket: IpAccessList,
+    pub admin: IpAccessList,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IpAccessRulesFile {
+    pairing: Option<IpAccessListFile>,
+    ws: Option<IpAccessListFile>,
+    admin: Option<IpAccessListFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IpAccessListFile {
+    allow: Option<Vec<String>>,
+    deny: Option<Vec<String>>,
+}
+
+/// Accepts CIDR ranges and bare addresses, which match only themselves.
+fn parse_cidrs(name: &str, entries: &[String]) -> Result<Vec<IpNet>, String> {
+    entries
+        .iter()
+        .map(|entry| {
+            let entry = entry.trim();
+            entry
+                .parse::<IpNet>()
+                .map(|net| net.trunc())
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| format!("{name} contains invalid CIDR '{entry}'."))
+        })
+        .collect()
+}
+
+fn validate_keyring(name: &str, keys: &[(String, String)]) -> Result<(), String> {
+    let mut seen = HashSet::new();
+    for (key_id, secret) in keys {
+        if key_id.is_empty()
+            || key_id.len() > 32
+            || !key_id
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
+        {
+            return Err(format!(
+                "{name} key IDs must be 1-32 characters of [A-Za-z0-9_-]."
+            ));
+        }
+        if !seen.insert(key_id.as_str()) {
+            return Err(format!("{name} contains duplicate key ID '{key_id}'."));
+        }
+        if secret.len() < 32 {
+            return Err(format!(
+                "{name} secret for key ID '{key_id}' must be at least 32 characters."
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_http_url(name: &str, raw: &str) -> Result<(), String> {
+    let parsed = Url::parse(raw).map_err(|error| format!("{name} is invalid: {error}"))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(format!("{name} must use http or https."));
+    }
+    Ok(())
+}
+
 fn parse_u16(name: &str, default: u16) -> u16 {
     env::var(name)
         .ok()
@@ -281,6 +937,27 @@ fn parse_bool_env(name: &str) -> bool {
     )
 }
 
+fn parse_list_env(name: &str) -> Vec<String> {
+    env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn parse_keyring(raw: &str) -> Vec<(String, String)> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let (key_id, secret) = entry.split_once(':')?;
+            Some((key_id.trim().to_string(), secret.trim().to_string()))
+        })
+        .collect()
+}
+
 fn normalized_origin(raw: &str) -> Option<String> {
     let Ok(url) = Url::parse(raw) else {
         return None;
@@ -349,6 +1026,56 @@ mod tests {
         assert!(error.contains("NATS_HMAC_SECRET"));
     }
 
+    #[test]
+    fn validate_rejects_per_ip_pending_pair_limit_above_session_limit() {
+        let mut config = RelayConfig::from_env();
+        config.max_pending_pair_requests_per_session = 2;
+        config.max_pending_pair_requests_per_ip = 3;
+        let error = config
+            .validate()
+            .expect_err("per-IP pending pair limit above the session limit should fail");
+        assert!(error.contains("MAX_PENDING_PAIR_REQUESTS_PER_IP"));
+    }
+
+    #[test]
+    fn nats_hmac_keyring_merges_env_and_file_keys() {
+        let path = env::temp_dir().join(format!("relay-nats-keys-{}.json", std::process::id()));
+        fs::write(
+            &path,
+            r#"{"activeKeyID":"k2","keys":{"k2":"bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb"}}"#,
+        )
+        .expect("write keys file");
+
+        let mut config = RelayConfig::from_env();
+        config.nats_url = Some("nats://localhost:4222".to_string());
+        config.nats_hmac_secret = None;
+        config.nats_hmac_keys = parse_keyring("k1:aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+        config.nats_hmac_key_id = Some("k1".to_string());
+        config.nats_hmac_keys_file = Some(path.to_string_lossy().into_owned());
+        config
+            .validate()
+            .expect("keyring without legacy secret should validate");
+
+        let keyring = config.load_nats_hmac_keyring().expect("keyring");
+        assert_eq!(
+            keyring.active_key(),
+            Some(("k2", "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb"))
+        );
+        assert_eq!(keyring.key("k1"), Some("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"));
+
+        fs::write(
+            &path,
+            r#"{"keys":{"k1":"cccccccccccccccccccccccccccccccc"}}"#,
+        )
+        .expect("rewrite keys file");
+        let error = config
+            .load_nats_hmac_keyring()
+            .expect_err("duplicate key ID across env and file should fail");
+        assert!(error.contains("duplicate key ID"));
+
+        let _ = fs::remove_file(&path);
+    }
+
     #[test]
     fn validate_rejects_short_nats_hmac_secret() {
         let mut config = RelayConfig::from_env();
@@ -383,4 +1110,239 @@ mod tests {
             .expect_err("zero NATS_MAX_CLOCK_SKEW_MS should fail");
         assert!(error.contains("NATS_MAX_CLOCK_SKEW_MS"));
     }
+
+    #[test]
+    fn validate_requires_redis_and_nats_for_presence_registry() {
+        let mut config = RelayConfig::from_env();
+        config.redis_url = None;
+        config.presence_registry_enabled = true;
+        let error = config
+            .validate()
+            .expect_err("presence registry without redis should fail");
+        assert!(error.contains("PRESENCE_REGISTRY_ENABLED"));
+    }
+
+    #[test]
+    fn validate_requires_presence_route_cache_shorter_than_presence_ttl() {
+        let mut config = RelayConfig::from_env();
+        config.redis_url = Some("redis://localhost:6379".to_string());
+        config.nats_url = Some("nats://localhost:4222".to_string());
+        config.nats_hmac_secret = Some("01234567890123456789012345678901".to_string());
+        config.presence_registry_enabled = true;
+        config.presence_route_cache_ttl_ms = config.presence_ttl_ms;
+        let error = config
+            .validate()
+            .expect_err("route cache as long as the presence TTL should fail");
+        assert!(error.contains("PRESENCE_ROUTE_CACHE_TTL_MS"));
+    }
+
+    #[test]
+    fn validate_requires_secret_and_deep_link_for_approval_webhook() {
+        let mut config = RelayConfig::from_env();
+        config.approval_webhook_url = Some("https://hooks.example.com/relay".to_string());
+        config.approval_webhook_secret = None;
+        config.approval_webhook_deep_link_base_url =
+            Some("https://remote.example.com/".to_string());
+        let error = config
+            .validate()
+            .expect_err("approval webhook without secret should fail");
+        assert!(error.contains("APPROVAL_WEBHOOK_SECRET"));
+
+        config.approval_webhook_secret = Some("01234567890123456789012345678901".to_string());
+        config.approval_webhook_deep_link_base_url = Some("remote.example.com".to_string());
+        let error = config
+            .validate()
+            .expect_err("relative deep link base should fail");
+        assert!(error.contains("APPROVAL_WEBHOOK_DEEP_LINK_BASE_URL"));
+
+        config.approval_webhook_deep_link_base_url =
+            Some("https://remote.example.com/".to_string());
+        config.approval_webhook_url = Some("ftp://hooks.example.com/relay".to_string());
+        let error = config
+            .validate()
+            .expect_err("non-http webhook url should fail");
+        assert!(error.contains("APPROVAL_WEBHOOK_URL"));
+    }
+
+    #[test]
+    fn validate_requires_http_urls_and_secret_for_lifecycle_webhooks() {
+        let mut config = RelayConfig::from_env();
+        config.lifecycle_webhook_urls = vec!["https://hooks.example.com/lifecycle".to_string()];
+        config.lifecycle_webhook_secret = Some("too-short".to_string());
+        let error = config
+            .validate()
+            .expect_err("short lifecycle webhook secret should fail");
+        assert!(error.contains("LIFECYCLE_WEBHOOK_SECRET"));
+
+        config.lifecycle_webhook_secret = Some("01234567890123456789012345678901".to_string());
+        config
+            .lifecycle_webhook_urls
+            .push("hooks.example.com/second".to_string());
+        let error = config
+            .validate()
+            .expect_err("relative lifecycle webhook url should fail");
+        assert!(error.contains("LIFECYCLE_WEBHOOK_URLS"));
+
+        config.lifecycle_webhook_urls.pop();
+        config.webhook_max_attempts = 0;
+        let error = config
+            .validate()
+            .expect_err("zero webhook attempts should fail");
+        assert!(error.contains("WEBHOOK_MAX_ATTEMPTS"));
+    }
+
+    #[test]
+    fn validate_rejects_inverted_penalty_box_limits_and_short_admin_token() {
+        let mut config = RelayConfig::from_env();
+        config.penalty_box_failure_threshold = 5;
+        config.penalty_box_base_block_ms = 60_000;
+        config.penalty_box_max_block_ms = 1_000;
+        let error = config
+            .validate()
+            .expect_err("max block below base block should fail");
+        assert!(error.contains("PENALTY_BOX_MAX_BLOCK_MS"));
+
+        config.penalty_box_max_block_ms = 120_000;
+        config.admin_api_token = Some("short".to_string());
+        let error = config
+            .validate()
+            .expect_err("short admin token should fail");
+        assert!(error.contains("ADMIN_API_TOKEN"));
+
+        config.admin_api_token = None;
+        config.penalty_box_failure_threshold = 0;
+        config.penalty_box_max_block_ms = 1_000;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn ip_access_rules_parse_cidrs_and_file_overrides_route_group_lists() {
+        let path = env::temp_dir().join(format!("relay-ip-access-{}.json", std::process::id()));
+        fs::write(
+            &path,
+            r#"{"ws":{"allow":["2001:db8::/32"]},"admin":{"deny":["192.0.2.7"]}}"#,
+        )
+        .expect("write ip access rules file");
+
+        let mut config = RelayConfig::from_env();
+        config.pairing_allow_cidrs = vec!["10.8.0.0/16".to_string()];
+        config.pairing_deny_cidrs = vec!["10.8.4.9".to_string()];
+        config.ws_allow_cidrs = vec!["10.0.0.0/8".to_string()];
+        config.ip_access_rules_file = Some(path.to_string_lossy().to_string());
+        let rules = config.load_ip_access_rules().expect("ip access rules");
+        let _ = fs::remove_file(&path);
+
+        let ip = |raw: &str| Some(raw.parse::<IpAddr>().expect("ip"));
+        assert!(rules.pairing.permits(ip("10.8.3.1")));
+        assert!(!rules.pairing.permits(ip("10.8.4.9")));
+        assert!(!rules.pairing.permits(ip("10.9.0.1")));
+        assert!(!rules.pairing.permits(None));
+        assert!(rules.websocket.permits(ip("2001:db8::1")));
+        assert!(!rules.websocket.permits(ip("10.1.1.1")));
+        assert!(!rules.admin.permits(ip("192.0.2.7")));
+        assert!(rules.admin.permits(ip("192.0.2.8")));
+
+        config.ip_access_rules_file = None;
+        config.ws_deny_cidrs = vec!["10.0.0.0/33".to_string()];
+        let error = config.validate().expect_err("invalid CIDR should fail");
+        assert!(error.contains("WS_DENY_CIDRS"));
+    }
+
+    #[test]
+    fn validate_requires_redis_for_snapshot_cache_persistence() {
+        let mut config = RelayConfig::from_env();
+        config.redis_url = None;
+        config.snapshot_cache_persist = true;
+        let error = config
+            .validate()
+            .expect_err("snapshot persistence without redis should fail");
+        assert!(error.contains("SNAPSHOT_CACHE_PERSIST"));
+
+        config.redis_url = Some("redis://localhost:6379".to_string());
+        config.snapshot_cache_max_bytes = 0;
+        let error = config
+            .validate()
+            .expect_err("snapshot persistence with caching disabled should fail");
+        assert!(error.contains("SNAPSHOT_CACHE_MAX_BYTES"));
+    }
+
+    #[test]
+    fn validate_rejects_jetstream_retention_beyond_replay_window() {
+        let mut config = RelayConfig::from_env();
+        config.nats_url = Some("nats://localhost:4222".to_string());
+        config.nats_hmac_secret = Some("01234567890123456789012345678901".to_string());
+        config.nats_jetstream_enabled = true;
+        config.nats_replay_window_ms = 60_000;
+        config.nats_jetstream_max_age_ms = 120_000;
+        let error = config
+            .validate()
+            .expect_err("JetStream retention beyond replay window should fail");
+        assert!(error.contains("NATS_JETSTREAM_MAX_AGE_MS"));
+
+        config.nats_jetstream_max_age_ms = 60_000;
+        config.nats_jetstream_stream = "relay.stream".to_string();
+        let error = config
+            .validate()
+            .expect_err("dotted JetStream stream name should fail");
+        assert!(error.contains("NATS_JETSTREAM_STREAM"));
+    }
+
+    #[test]
+    fn validate_requires_stable_instance_id_for_jetstream() {
+        let mut config = RelayConfig::from_env();
+        config.nats_url = Some("nats://localhost:4222".to_string());
+        config.nats_hmac_secret = Some("01234567890123456789012345678901".to_string());
+        config.nats_jetstream_enabled = true;
+        config.instance_id = None;
+        let error = config
+            .validate()
+            .expect_err("JetStream without a stable instance id should fail");
+        assert!(error.contains("RELAY_INSTANCE_ID"));
+
+        config.instance_id = Some("relay.0".to_string());
+        let error = config
+            .validate()
+            .expect_err("dotted instance id should fail");
+        assert!(error.contains("RELAY_INSTANCE_ID"));
+
+        config.instance_id = Some("relay-0".to_string());
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn parse_keyring_preserves_order_and_skips_malformed_entries() {
+        let keys = parse_keyring(" k2:second-secret , broken, k1:first:secret ");
+        assert_eq!(
+            keys,
+            vec![
+                ("k2".to_string(), "second-secret".to_string()),
+                ("k1".to_string(), "first:secret".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_rejects_unknown_device_token_signing_key_id() {
+        let mut config = RelayConfig::from_env();
+        config.device_token_signing_keys = vec![(
+            "k1".to_string(),
+            "01234567890123456789012345678901".to_string(),
+        )];
+        config.device_token_signing_key_id = Some("k2".to_string());
+        let error = config
+            .validate()
+            .expect_err("unknown DEVICE_TOKEN_SIGNING_KEY_ID should fail");
+        assert!(error.contains("DEVICE_TOKEN_SIGNING_KEY_ID"));
+    }
+
+    #[test]
+    fn validate_rejects_short_device_token_signing_secret() {
+        let mut config = RelayConfig::from_env();
+        config.device_token_signing_keys = vec![("k1".to_string(), "short".to_string())];
+        config.device_token_signing_key_id = Some("k1".to_string());
+        let error = config
+            .validate()
+            .expect_err("short device token signing secret should fail");
+        assert!(error.contains("at least 32"));
+    }
 }
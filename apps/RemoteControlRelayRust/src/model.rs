@@ -1,6 +1,7 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct PairStartRequest {
     #[serde(rename = "schemaVersion", default)]
@@ -17,18 +18,32 @@ pub struct PairStartRequest {
     pub relay_web_socket_url: Option<String>,
     #[serde(rename = "idleTimeoutSeconds")]
     pub idle_timeout_seconds: Option<u64>,
+    #[serde(rename = "maxDevices", default)]
+    pub max_devices: Option<usize>,
+    #[serde(rename = "deviceEvictionPolicy", default)]
+    pub device_eviction_policy: Option<String>,
+    #[serde(rename = "supportedSchemaVersions", default)]
+    pub supported_schema_versions: Option<Vec<u32>>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct PairStartResponse {
     pub accepted: bool,
     #[serde(rename = "sessionID")]
     pub session_id: String,
     #[serde(rename = "wsURL")]
     pub ws_url: String,
+    #[serde(rename = "pairingURI")]
+    pub pairing_uri: String,
+    #[serde(rename = "maxDevices")]
+    pub max_devices: usize,
+    #[serde(rename = "deviceEvictionPolicy")]
+    pub device_eviction_policy: String,
+    #[serde(rename = "schemaVersion")]
+    pub schema_version: u32,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct PairRefreshRequest {
     #[serde(rename = "schemaVersion", default)]
@@ -43,16 +58,30 @@ pub struct PairRefreshRequest {
     pub join_token_expires_at: String,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct PairRefreshResponse {
     pub accepted: bool,
     #[serde(rename = "sessionID")]
     pub session_id: String,
     #[serde(rename = "wsURL")]
     pub ws_url: String,
+    #[serde(rename = "pairingURI")]
+    pub pairing_uri: String,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PairQrRequest {
+    #[serde(rename = "schemaVersion", default)]
+    pub schema_version: Option<u32>,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    #[serde(rename = "desktopSessionToken")]
+    pub desktop_session_token: String,
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct PairJoinRequest {
     #[serde(rename = "sessionID")]
@@ -61,9 +90,69 @@ pub struct PairJoinRequest {
     pub join_token: String,
     #[serde(rename = "deviceName")]
     pub device_name: Option<String>,
+    #[serde(default)]
+    pub platform: Option<String>,
+    #[serde(rename = "appVersion", default)]
+    pub app_version: Option<String>,
+    #[serde(rename = "async", default)]
+    pub async_join: bool,
+    #[serde(rename = "supportedSchemaVersions", default)]
+    pub supported_schema_versions: Option<Vec<u32>>,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PairJoinPendingResponse {
+    pub accepted: bool,
+    pub status: String,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    #[serde(rename = "requestID")]
+    pub request_id: String,
+    #[serde(rename = "statusToken")]
+    pub status_token: String,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: String,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PairStatusRequest {
+    #[serde(rename = "schemaVersion", default)]
+    pub schema_version: Option<u32>,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    #[serde(rename = "requestID")]
+    pub request_id: String,
+    #[serde(rename = "statusToken")]
+    pub status_token: String,
+    #[serde(rename = "waitMs", default)]
+    pub wait_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PairStatusResponse {
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    #[serde(rename = "requestID")]
+    pub request_id: String,
+    pub status: String,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: String,
+    #[serde(rename = "deviceID", skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    #[serde(rename = "deviceSessionToken", skip_serializing_if = "Option::is_none")]
+    pub device_session_token: Option<String>,
+    #[serde(rename = "wsURL", skip_serializing_if = "Option::is_none")]
+    pub ws_url: Option<String>,
+    #[serde(rename = "schemaVersion", skip_serializing_if = "Option::is_none")]
+    pub schema_version: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct PairJoinResponse {
     pub accepted: bool,
     #[serde(rename = "sessionID")]
@@ -74,9 +163,11 @@ pub struct PairJoinResponse {
     pub device_session_token: String,
     #[serde(rename = "wsURL")]
     pub ws_url: String,
+    #[serde(rename = "schemaVersion")]
+    pub schema_version: u32,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct PairStopRequest {
     #[serde(rename = "schemaVersion", default)]
@@ -87,14 +178,14 @@ pub struct PairStopRequest {
     pub desktop_session_token: String,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct PairStopResponse {
     pub accepted: bool,
     #[serde(rename = "sessionID")]
     pub session_id: String,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct DevicesListRequest {
     #[serde(rename = "schemaVersion", default)]
@@ -105,28 +196,47 @@ pub struct DevicesListRequest {
     pub desktop_session_token: String,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct DeviceSummary {
     #[serde(rename = "deviceID")]
     pub device_id: String,
     #[serde(rename = "deviceName")]
     pub device_name: String,
+    pub nickname: Option<String>,
+    pub platform: Option<String>,
+    #[serde(rename = "appVersion")]
+    pub app_version: Option<String>,
+    #[serde(rename = "lastIP")]
+    pub last_ip: Option<String>,
+    pub scopes: Vec<String>,
     pub connected: bool,
     #[serde(rename = "joinedAt")]
     pub joined_at: String,
     #[serde(rename = "lastSeenAt")]
     pub last_seen_at: String,
+    /// Bytes received from this device by the instance answering the request.
+    #[serde(rename = "bytesIn")]
+    pub bytes_in: u64,
+    /// Bytes delivered to this device by the instance answering the request.
+    #[serde(rename = "bytesOut")]
+    pub bytes_out: u64,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct DevicesListResponse {
     pub accepted: bool,
     #[serde(rename = "sessionID")]
     pub session_id: String,
     pub devices: Vec<DeviceSummary>,
+    /// Session traffic counted by the instance answering the request; other instances keep
+    /// their own counters.
+    #[serde(rename = "sessionBytesIn")]
+    pub session_bytes_in: u64,
+    #[serde(rename = "sessionBytesOut")]
+    pub session_bytes_out: u64,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct DeviceRevokeRequest {
     #[serde(rename = "schemaVersion", default)]
@@ -139,7 +249,200 @@ pub struct DeviceRevokeRequest {
     pub device_id: String,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceHistoryRequest {
+    #[serde(rename = "schemaVersion", default)]
+    pub schema_version: Option<u32>,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    #[serde(rename = "desktopSessionToken")]
+    pub desktop_session_token: String,
+    #[serde(rename = "deviceID")]
+    pub device_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct DeviceConnectionEventSummary {
+    pub event: String,
+    pub at: String,
+    #[serde(rename = "remoteIP")]
+    pub remote_ip: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(rename = "durationMs", skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct DeviceHistoryResponse {
+    pub accepted: bool,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    #[serde(rename = "deviceID")]
+    pub device_id: String,
+    pub events: Vec<DeviceConnectionEventSummary>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceRenameRequest {
+    #[serde(rename = "schemaVersion", default)]
+    pub schema_version: Option<u32>,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    #[serde(rename = "desktopSessionToken")]
+    pub desktop_session_token: String,
+    #[serde(rename = "deviceID")]
+    pub device_id: String,
+    pub nickname: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct DeviceRenameResponse {
+    pub accepted: bool,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    pub device: DeviceSummary,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct InviteCreateRequest {
+    #[serde(rename = "schemaVersion", default)]
+    pub schema_version: Option<u32>,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    #[serde(rename = "desktopSessionToken")]
+    pub desktop_session_token: String,
+    #[serde(rename = "maxRedemptions", default)]
+    pub max_redemptions: Option<u32>,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(rename = "autoApprove", default)]
+    pub auto_approve: bool,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct InviteSummary {
+    #[serde(rename = "inviteID")]
+    pub invite_id: String,
+    #[serde(rename = "maxRedemptions")]
+    pub max_redemptions: u32,
+    pub redemptions: u32,
+    #[serde(rename = "createdAt")]
+    pub created_at: String,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: String,
+    pub scopes: Vec<String>,
+    #[serde(rename = "autoApprove")]
+    pub auto_approve: bool,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct InviteCreateResponse {
+    pub accepted: bool,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    #[serde(rename = "inviteToken")]
+    pub invite_token: String,
+    #[serde(rename = "pairingURI")]
+    pub pairing_uri: String,
+    pub invite: InviteSummary,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct InvitesListRequest {
+    #[serde(rename = "schemaVersion", default)]
+    pub schema_version: Option<u32>,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    #[serde(rename = "desktopSessionToken")]
+    pub desktop_session_token: String,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct InvitesListResponse {
+    pub accepted: bool,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    pub invites: Vec<InviteSummary>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct InviteRevokeRequest {
+    #[serde(rename = "schemaVersion", default)]
+    pub schema_version: Option<u32>,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    #[serde(rename = "desktopSessionToken")]
+    pub desktop_session_token: String,
+    #[serde(rename = "inviteID")]
+    pub invite_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct InviteRevokeResponse {
+    pub accepted: bool,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    #[serde(rename = "inviteID")]
+    pub invite_id: String,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SessionTransferIssueRequest {
+    #[serde(rename = "schemaVersion", default)]
+    pub schema_version: Option<u32>,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    #[serde(rename = "desktopSessionToken", default)]
+    pub desktop_session_token: Option<String>,
+    #[serde(rename = "deviceSessionToken", default)]
+    pub device_session_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct SessionTransferIssueResponse {
+    pub accepted: bool,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    #[serde(rename = "transferToken")]
+    pub transfer_token: String,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: String,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SessionTransferRedeemRequest {
+    #[serde(rename = "schemaVersion", default)]
+    pub schema_version: Option<u32>,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    #[serde(rename = "transferToken")]
+    pub transfer_token: String,
+    #[serde(rename = "desktopSessionToken")]
+    pub desktop_session_token: String,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct SessionTransferRedeemResponse {
+    pub accepted: bool,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    #[serde(rename = "wsURL")]
+    pub ws_url: String,
+    #[serde(rename = "deviceCount")]
+    pub device_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct DeviceRevokeResponse {
     pub accepted: bool,
     #[serde(rename = "sessionID")]
@@ -148,14 +451,14 @@ pub struct DeviceRevokeResponse {
     pub device_id: String,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ErrorResponse {
     pub error: String,
     pub message: String,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct HealthResponse {
     pub ok: bool,
@@ -165,11 +468,14 @@ pub struct HealthResponse {
     pub device_tokens: usize,
     pub bus_subscriptions: usize,
     pub cross_instance_bus_enabled: bool,
+    /// Set when the cross-instance bus could not provide the delivery it was configured for,
+    /// such as a JetStream stream or durable consumer that failed to set up.
+    pub cross_instance_bus_degraded: bool,
     pub redis_persistence_enabled: bool,
     pub now: String,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct RelayMetricsResponse {
     pub ok: bool,
@@ -198,32 +504,81 @@ pub struct RelayMetricsResponse {
     pub ws_auth_successes: u64,
     pub ws_auth_failures: u64,
     pub ws_auth_failure_reasons: std::collections::HashMap<String, u64>,
+    #[serde(rename = "envelopeSignatureFailuresByKeyID")]
+    pub envelope_signature_failures_by_key_id: std::collections::HashMap<String, u64>,
+    pub webhook_delivery_attempts: u64,
+    pub webhook_deliveries_succeeded: u64,
+    pub webhook_deliveries_dead_lettered: u64,
+    pub webhook_dead_letters_by_event: std::collections::HashMap<String, u64>,
+    pub penalty_box_blocks_issued: u64,
+    pub penalty_box_rejections: u64,
+    pub ip_access_rejections_by_route_group: std::collections::HashMap<String, u64>,
+    pub session_bytes_in: u64,
+    pub session_bytes_out: u64,
+    pub byte_quota_drops: u64,
+    /// Cross-instance publishes dropped because their publish queue was full.
+    pub cross_instance_publish_drops: u64,
     pub cross_instance_bus_enabled: bool,
     pub redis_persistence_enabled: bool,
     pub now: String,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PenaltyBlockSummary {
+    pub key: String,
+    pub blocked_until: String,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PenaltyBoxListResponse {
+    pub blocks: Vec<PenaltyBlockSummary>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PenaltyBoxClearRequest {
+    pub key: String,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct PenaltyBoxClearResponse {
+    pub cleared: bool,
+    pub key: String,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct RelayAuthMessage {
     #[serde(rename = "type")]
+    #[schemars(extend("const" = "relay.auth"))]
     pub message_type: String,
     pub token: String,
+    #[serde(default)]
+    pub platform: Option<String>,
+    #[serde(rename = "appVersion", default)]
+    pub app_version: Option<String>,
+    #[serde(rename = "supportedSchemaVersions", default)]
+    pub supported_schema_versions: Option<Vec<u32>>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct RelayPairDecision {
     #[serde(rename = "type")]
+    #[schemars(extend("const" = "relay.pair_decision"))]
     pub message_type: String,
     #[serde(rename = "sessionID")]
     pub session_id: Option<String>,
     #[serde(rename = "requestID")]
     pub request_id: Option<String>,
     pub approved: Option<bool>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct RelayAuthOk {
     #[serde(rename = "type")]
+    #[schemars(extend("const" = "auth_ok"))]
     pub message_type: String,
     pub role: String,
     #[serde(rename = "sessionID")]
@@ -238,11 +593,14 @@ pub struct RelayAuthOk {
     pub connected_device_count: usize,
     #[serde(rename = "desktopConnected")]
     pub desktop_connected: bool,
+    #[serde(rename = "schemaVersion")]
+    pub schema_version: u32,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct RelayPairRequest {
     #[serde(rename = "type")]
+    #[schemars(extend("const" = "relay.pair_request"))]
     pub message_type: String,
     #[serde(rename = "sessionID")]
     pub session_id: String,
@@ -257,11 +615,30 @@ pub struct RelayPairRequest {
     pub requested_at: String,
     #[serde(rename = "expiresAt")]
     pub expires_at: String,
+    #[serde(rename = "evictsDeviceID", skip_serializing_if = "Option::is_none")]
+    pub evicts_device_id: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct RelayDeviceEvicted {
+    #[serde(rename = "type")]
+    #[schemars(extend("const" = "relay.device_evicted"))]
+    pub message_type: String,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    #[serde(rename = "deviceID")]
+    pub device_id: String,
+    #[serde(rename = "deviceName")]
+    pub device_name: String,
+    #[serde(rename = "replacedByDeviceID")]
+    pub replaced_by_device_id: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct RelayPairResult {
     #[serde(rename = "type")]
+    #[schemars(extend("const" = "relay.pair_result"))]
     pub message_type: String,
     #[serde(rename = "sessionID")]
     pub session_id: String,
@@ -270,9 +647,10 @@ pub struct RelayPairResult {
     pub approved: bool,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct RelayDeviceCount {
     #[serde(rename = "type")]
+    #[schemars(extend("const" = "relay.device_count"))]
     pub message_type: String,
     #[serde(rename = "sessionID")]
     pub session_id: String,
@@ -280,12 +658,188 @@ pub struct RelayDeviceCount {
     pub connected_device_count: usize,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct RelayDesktopStatus {
     #[serde(rename = "type")]
+    #[schemars(extend("const" = "relay.desktop_status"))]
     pub message_type: String,
     #[serde(rename = "sessionID")]
     pub session_id: String,
     #[serde(rename = "desktopConnected")]
     pub desktop_connected: bool,
 }
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct RelayError {
+    #[serde(rename = "type")]
+    #[schemars(extend("const" = "relay.error"))]
+    pub message_type: String,
+    pub error: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct RelayDisconnect {
+    #[serde(rename = "type")]
+    #[schemars(extend("const" = "disconnect"))]
+    pub message_type: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RelaySnapshotRequest {
+    #[serde(rename = "type")]
+    #[schemars(extend("const" = "relay.snapshot_request"))]
+    pub message_type: String,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    #[serde(default)]
+    #[schemars(length(max = 128))]
+    pub reason: Option<String>,
+    #[serde(rename = "lastSeq", default)]
+    pub last_seq: Option<SnapshotSequence>,
+}
+
+/// `lastSeq` is accepted as a number or as a string of up to 20 digits.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum SnapshotSequence {
+    Number(u64),
+    Digits(#[schemars(regex(pattern = r"^[0-9]{1,20}$"))] String),
+}
+
+/// Command envelope a mobile client sends in schema version 2.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CommandEnvelope {
+    #[serde(rename = "schemaVersion")]
+    #[schemars(extend("const" = 2))]
+    pub schema_version: u32,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    pub seq: u64,
+    #[serde(default)]
+    pub timestamp: Option<String>,
+    pub payload: CommandWrapper,
+    /// Overwritten by the relay before the envelope is forwarded to the desktop.
+    #[serde(rename = "relayConnectionID", default)]
+    pub relay_connection_id: Option<String>,
+    /// Overwritten by the relay before the envelope is forwarded to the desktop.
+    #[serde(rename = "relayDeviceID", default)]
+    pub relay_device_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CommandWrapper {
+    #[serde(rename = "type")]
+    #[schemars(extend("const" = "command"))]
+    pub payload_type: String,
+    pub payload: RemoteCommand,
+}
+
+/// Command envelope a mobile client sends in schema version 3, with the `payload` wrapper
+/// flattened into `type` and `body`.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CommandEnvelopeV3 {
+    #[serde(rename = "schemaVersion")]
+    #[schemars(extend("const" = 3))]
+    pub schema_version: u32,
+    #[serde(rename = "sessionID")]
+    pub session_id: String,
+    pub seq: u64,
+    #[serde(default)]
+    pub timestamp: Option<String>,
+    #[serde(rename = "type")]
+    #[schemars(extend("const" = "command"))]
+    pub payload_type: String,
+    pub body: RemoteCommand,
+    /// Overwritten by the relay before the envelope is forwarded to the desktop.
+    #[serde(rename = "relayConnectionID", default)]
+    pub relay_connection_id: Option<String>,
+    /// Overwritten by the relay before the envelope is forwarded to the desktop.
+    #[serde(rename = "relayDeviceID", default)]
+    pub relay_device_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteCommand {
+    pub name: RemoteCommandName,
+    #[serde(rename = "commandID")]
+    #[schemars(regex(pattern = r"^[A-Za-z0-9_:-]{1,128}$"))]
+    pub command_id: String,
+    /// Required by `thread.send_message` and `thread.select`.
+    #[serde(rename = "threadID", default)]
+    #[schemars(regex(pattern = r"^[A-Za-z0-9_:-]{1,128}$"))]
+    pub thread_id: Option<String>,
+    /// Required by `project.select`.
+    #[serde(rename = "projectID", default)]
+    #[schemars(regex(pattern = r"^[A-Za-z0-9_:-]{1,128}$"))]
+    pub project_id: Option<String>,
+    /// Required by `thread.send_message`.
+    #[serde(default)]
+    pub text: Option<String>,
+    /// Required by `runtime_request.respond`.
+    #[serde(rename = "runtimeRequestID", default)]
+    #[schemars(regex(pattern = r"^[0-9]{0,32}$"))]
+    pub runtime_request_id: Option<String>,
+    #[serde(rename = "runtimeRequestKind", default)]
+    pub runtime_request_kind: Option<RuntimeRequestKind>,
+    /// Required by `runtime_request.respond`.
+    #[serde(rename = "runtimeRequestResponse", default)]
+    pub runtime_request_response: Option<RuntimeRequestResponse>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+pub enum RemoteCommandName {
+    #[serde(rename = "thread.send_message")]
+    ThreadSendMessage,
+    #[serde(rename = "thread.select")]
+    ThreadSelect,
+    #[serde(rename = "project.select")]
+    ProjectSelect,
+    #[serde(rename = "runtime_request.respond")]
+    RuntimeRequestRespond,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum RuntimeRequestKind {
+    Approval,
+    PermissionsApproval,
+    UserInput,
+    McpElicitation,
+    DynamicToolCall,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[schemars(extend("minProperties" = 1))]
+pub struct RuntimeRequestResponse {
+    #[serde(default)]
+    pub decision: Option<RuntimeRequestDecision>,
+    #[serde(default)]
+    pub permissions: Option<Vec<String>>,
+    #[serde(default)]
+    #[schemars(length(min = 1, max = 128))]
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(rename = "optionID", default)]
+    #[schemars(regex(pattern = r"^[A-Za-z0-9_:-]{1,128}$"))]
+    pub option_id: Option<String>,
+    #[serde(default)]
+    pub approved: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum RuntimeRequestDecision {
+    Accept,
+    AcceptForSession,
+    Decline,
+    Cancel,
+}
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 use std::sync::Arc;
@@ -17,42 +18,87 @@ use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use tokio::sync::mpsc::error::TrySendError;
-use tokio::sync::{mpsc, oneshot, watch, Mutex};
+use tokio::sync::{mpsc, oneshot, watch, Mutex, Notify};
 use tokio::time::{interval, sleep, timeout, MissedTickBehavior};
 use tower_http::cors::{AllowOrigin, Any, CorsLayer};
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 use url::Url;
 
-use crate::config::{is_allowed_origin, RelayConfig};
+use crate::config::{is_allowed_origin, IpAccessRules, NatsHmacKeyring, RelayConfig};
 use crate::model::{
-    DeviceRevokeRequest, DeviceRevokeResponse, DeviceSummary, DevicesListRequest,
-    DevicesListResponse, ErrorResponse, HealthResponse, PairJoinRequest, PairJoinResponse,
-    PairRefreshRequest, PairRefreshResponse, PairStartRequest, PairStartResponse, PairStopRequest,
-    PairStopResponse, RelayAuthMessage, RelayAuthOk, RelayDesktopStatus, RelayDeviceCount,
-    RelayMetricsResponse, RelayPairDecision, RelayPairRequest, RelayPairResult,
+    CommandEnvelope, CommandEnvelopeV3, DeviceConnectionEventSummary, DeviceHistoryRequest,
+    DeviceHistoryResponse, DeviceRenameRequest, DeviceRenameResponse, DeviceRevokeRequest,
+    DeviceRevokeResponse, DeviceSummary, DevicesListRequest, DevicesListResponse, ErrorResponse,
+    HealthResponse, InviteCreateRequest, InviteCreateResponse, InviteRevokeRequest,
+    InviteRevokeResponse, InviteSummary, InvitesListRequest, InvitesListResponse,
+    PairJoinPendingResponse, PairJoinRequest, PairJoinResponse, PairQrRequest, PairRefreshRequest,
+    PairRefreshResponse, PairStartRequest, PairStartResponse, PairStatusRequest,
+    PairStatusResponse, PairStopRequest, PairStopResponse, PenaltyBlockSummary,
+    PenaltyBoxClearRequest, PenaltyBoxClearResponse, PenaltyBoxListResponse, RelayAuthMessage,
+    RelayAuthOk, RelayDesktopStatus, RelayDeviceCount, RelayDeviceEvicted, RelayDisconnect,
+    RelayError, RelayMetricsResponse, RelayPairDecision, RelayPairRequest, RelayPairResult,
+    RelaySnapshotRequest, RemoteCommandName, RuntimeRequestResponse, SessionTransferIssueRequest,
+    SessionTransferIssueResponse, SessionTransferRedeemRequest, SessionTransferRedeemResponse,
+    SnapshotSequence,
 };
 
+const SOCKET_CONTROL_QUEUE_CAPACITY: usize = 64;
+
+mod async_join;
 mod auth;
+mod bus;
+mod devices;
+mod inbound;
+mod invites;
+mod ip_access;
 mod metrics;
+mod openapi;
+mod pairing;
+mod penalty;
+mod presence;
 mod protocol;
+mod schema;
 mod session;
+mod signed_token;
+mod snapshot;
 mod state;
+mod traffic;
+mod transfer;
 mod transport;
+mod webhooks;
 
+use self::async_join::*;
 use self::auth::*;
+use self::bus::*;
+use self::devices::*;
+use self::inbound::*;
+use self::invites::*;
+use self::ip_access::*;
 use self::metrics::*;
+use self::openapi::*;
+use self::pairing::*;
+use self::penalty::*;
+use self::presence::*;
 use self::protocol::*;
+use self::schema::*;
 use self::session::*;
+use self::signed_token::*;
+use self::snapshot::*;
 use self::state::*;
+use self::traffic::*;
+use self::transfer::*;
+use self::webhooks::*;
 
+pub use self::bus::{CrossInstanceBus, CrossInstanceBusMessage, InProcessCrossInstanceBus};
+pub use self::openapi::{message_schemas, openapi_document};
 pub use self::session::drain_sessions_for_shutdown;
-pub use self::state::new_state;
+pub use self::state::{new_state, new_state_with_cross_instance_bus};
 pub use self::transport::build_router;
 
 fn apply_pair_decision(
     session: &mut SessionRecord,
     decision: &RelayPairDecision,
-    desktop_tx: Option<&mpsc::Sender<Message>>,
+    desktop_tx: Option<&SocketSender>,
 ) -> bool {
     let Some(request_id) = decision.request_id.as_deref() else {
         return false;
@@ -60,23 +106,29 @@ fn apply_pair_decision(
 
     let approved = decision.approved.unwrap_or(false);
 
-    let matches_request = session
-        .pending_join_request
-        .as_ref()
-        .map(|pending| safe_token_equals(&pending.request_id, request_id))
-        .unwrap_or(false);
-
-    if matches_request {
-        if let Some(pending) = session.pending_join_request.take() {
-            let _ = pending.decision_tx.send(JoinDecision {
-                approved,
-                reason: if approved {
-                    "approved".to_string()
-                } else {
-                    "denied".to_string()
-                },
-            });
-        }
+    let matched_request_id = session
+        .pending_join_requests
+        .keys()
+        .find(|pending_request_id| safe_token_equals(pending_request_id, request_id))
+        .cloned();
+    let matches_request = matched_request_id.is_some();
+
+    if let Some(pending) =
+        matched_request_id.and_then(|request_id| session.pending_join_requests.remove(&request_id))
+    {
+        let _ = pending.decision_tx.send(JoinDecision {
+            approved,
+            reason: if approved {
+                "approved".to_string()
+            } else {
+                "denied".to_string()
+            },
+            scopes: if approved {
+                sanitize_device_scopes(&decision.scopes)
+            } else {
+                Vec::new()
+            },
+        });
     }
 
     if let Some(desktop_tx) = desktop_tx {
@@ -114,27 +166,32 @@ fn inject_mobile_metadata(raw: &str, connection_id: &str, device_id: &str) -> St
     serde_json::to_string(&value).unwrap_or_else(|_| raw.to_string())
 }
 
-fn send_relay_error(tx: &mpsc::Sender<Message>, code: &str, message: &str) {
-    let payload = json!({
-        "type": "relay.error",
-        "error": code,
-        "message": message,
-    });
-    let _ = try_send_payload(tx, payload.to_string());
+fn send_relay_error(tx: &SocketSender, code: &str, message: &str) {
+    let payload = RelayError {
+        message_type: "relay.error".to_string(),
+        error: code.to_string(),
+        message: message.to_string(),
+    };
+    let _ = try_send_payload(
+        tx,
+        serde_json::to_string(&payload).unwrap_or_else(|_| "{}".to_string()),
+    );
+}
+
+fn disconnect_payload(reason: &str) -> String {
+    serde_json::to_string(&RelayDisconnect {
+        message_type: "disconnect".to_string(),
+        reason: reason.to_string(),
+    })
+    .unwrap_or_else(|_| "{}".to_string())
 }
 
 fn request_socket_disconnect(handle: &SocketHandle, reason: &str) {
     if reason == "slow_consumer" {
         warn!("[relay-rs] slow_consumer_disconnect");
     }
-    let _ = try_send_payload(
-        &handle.tx,
-        json!({
-            "type": "disconnect",
-            "reason": reason
-        })
-        .to_string(),
-    );
+    let _ = try_send_payload(&handle.tx, disconnect_payload(reason));
+    let _ = handle.tx.disconnect_reason.set(reason.to_string());
     let _ = handle.shutdown.send(true);
 }
 
@@ -145,19 +202,83 @@ fn record_ws_auth_failure_reason(relay: &mut RelayState, reason: &str) {
         .or_insert(0) += 1;
 }
 
-fn try_send_payload(tx: &mpsc::Sender<Message>, payload: String) -> bool {
+/// Per-socket outbound queues. Relay control frames (`relay.*`, `auth_ok`, `disconnect`) and
+/// pings go on a small control lane that the writer always drains first; forwarded
+/// desktop/mobile traffic goes on the bulk lane, which is the only one whose overflow marks the
+/// socket as a slow consumer.
+#[derive(Clone)]
+struct SocketSender {
+    control: mpsc::Sender<Message>,
+    bulk: mpsc::Sender<Message>,
+    /// First reason passed to `request_socket_disconnect`, read back when the socket loop exits.
+    disconnect_reason: Arc<std::sync::OnceLock<String>>,
+}
+
+struct SocketReceiver {
+    control: mpsc::Receiver<Message>,
+    bulk: mpsc::Receiver<Message>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutboundLane {
+    Control,
+    Bulk,
+}
+
+fn socket_channel(bulk_capacity: usize) -> (SocketSender, SocketReceiver) {
+    let (control_tx, control_rx) = mpsc::channel::<Message>(SOCKET_CONTROL_QUEUE_CAPACITY);
+    let (bulk_tx, bulk_rx) = mpsc::channel::<Message>(bulk_capacity);
+    (
+        SocketSender {
+            control: control_tx,
+            bulk: bulk_tx,
+            disconnect_reason: Arc::new(std::sync::OnceLock::new()),
+        },
+        SocketReceiver {
+            control: control_rx,
+            bulk: bulk_rx,
+        },
+    )
+}
+
+impl SocketReceiver {
+    /// Next frame to write, preferring the control lane. Returns `None` once both lanes are
+    /// closed and drained.
+    async fn recv(&mut self) -> Option<Message> {
+        tokio::select! {
+            biased;
+            Some(payload) = self.control.recv() => Some(payload),
+            Some(payload) = self.bulk.recv() => Some(payload),
+            else => None,
+        }
+    }
+}
+
+fn try_send_payload(tx: &SocketSender, payload: String) -> bool {
     try_send_message(tx, Message::Text(payload.into()))
 }
 
-fn try_send_message(tx: &mpsc::Sender<Message>, payload: Message) -> bool {
-    match tx.try_send(payload) {
+fn try_send_bulk_payload(tx: &SocketSender, payload: String) -> bool {
+    try_send_on_lane(tx, OutboundLane::Bulk, Message::Text(payload.into()))
+}
+
+fn try_send_message(tx: &SocketSender, payload: Message) -> bool {
+    try_send_on_lane(tx, OutboundLane::Control, payload)
+}
+
+fn try_send_on_lane(tx: &SocketSender, lane: OutboundLane, payload: Message) -> bool {
+    let (queue, lane_label) = match lane {
+        OutboundLane::Control => (&tx.control, "control"),
+        OutboundLane::Bulk => (&tx.bulk, "bulk"),
+    };
+    match queue.try_send(payload) {
         Ok(()) => true,
         Err(TrySendError::Full(_)) => {
-            warn!("[relay-rs] outbound_send_failure reason=queue_full");
+            warn!("[relay-rs] outbound_send_failure reason=queue_full lane={lane_label}");
             false
         }
         Err(TrySendError::Closed(_)) => {
-            warn!("[relay-rs] outbound_send_failure reason=queue_closed");
+            warn!("[relay-rs] outbound_send_failure reason=queue_closed lane={lane_label}");
             false
         }
     }
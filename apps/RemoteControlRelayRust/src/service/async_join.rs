@@ -0,0 +1,235 @@
+use super::*;
+
+/// How long a finished async pair request stays readable through `/pair/status`.
+pub(super) const ASYNC_PAIR_RESULT_RETENTION_MS: i64 = 120_000;
+/// Upper bound for a `/pair/status` long-poll.
+pub(super) const MAX_PAIR_STATUS_WAIT_MS: u64 = 25_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum AsyncPairRequestState {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+/// Outcome of a `pair/join` sent with `"async": true`. It is persisted with the session, so any
+/// instance can answer `/pair/status` once the instance waiting on the desktop records it.
+#[derive(Clone, Serialize, Deserialize)]
+pub(super) struct AsyncPairRequest {
+    pub(super) status_token: String,
+    pub(super) state: AsyncPairRequestState,
+    pub(super) requested_at_ms: i64,
+    pub(super) expires_at_ms: i64,
+    #[serde(default)]
+    pub(super) completed_at_ms: Option<i64>,
+    #[serde(default)]
+    pub(super) device_id: Option<String>,
+    #[serde(default)]
+    pub(super) device_session_token: Option<String>,
+    #[serde(default)]
+    pub(super) error: Option<String>,
+    #[serde(default)]
+    pub(super) message: Option<String>,
+}
+
+pub(super) enum AsyncPairOutcome {
+    Approved {
+        device_id: String,
+        device_session_token: String,
+    },
+    Rejected {
+        code: String,
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum PendingJoinSlot {
+    Available,
+    RequesterBusy {
+        request_id: String,
+        expires_at_ms: i64,
+    },
+    SessionFull(usize),
+}
+
+/// Checks whether another approval request may wait on the desktop. Each session holds a bounded
+/// set of pending requests, and a single IP may only hold a few of them so one requester cannot
+/// crowd out the rest. Requests past their expiry no longer hold a slot.
+pub(super) fn pending_join_slot(
+    config: &RelayConfig,
+    session: &SessionRecord,
+    requester_ip: &str,
+    now: i64,
+) -> PendingJoinSlot {
+    let live = session
+        .pending_join_requests
+        .values()
+        .filter(|pending| now < pending.expires_at_ms);
+    let mut live_count = 0_usize;
+    let mut requester_oldest: Option<&PendingJoinRequest> = None;
+    let mut requester_count = 0_usize;
+    for pending in live {
+        live_count += 1;
+        if pending.requester_ip == requester_ip {
+            requester_count += 1;
+            if requester_oldest
+                .is_none_or(|oldest| pending.requested_at_ms < oldest.requested_at_ms)
+            {
+                requester_oldest = Some(pending);
+            }
+        }
+    }
+
+    if let Some(oldest) = requester_oldest {
+        if requester_count >= config.max_pending_pair_requests_per_ip {
+            return PendingJoinSlot::RequesterBusy {
+                request_id: oldest.request_id.clone(),
+                expires_at_ms: oldest.expires_at_ms,
+            };
+        }
+    }
+    if live_count >= config.max_pending_pair_requests_per_session {
+        return PendingJoinSlot::SessionFull(config.max_pending_pair_requests_per_session);
+    }
+    PendingJoinSlot::Available
+}
+
+/// Drops finished requests past their retention, and pending ones whose waiting instance went
+/// away before recording an outcome.
+pub(super) fn prune_async_pair_requests(session: &mut SessionRecord, now: i64) {
+    session.async_pair_requests.retain(|_, request| {
+        let finished_at_ms = request.completed_at_ms.unwrap_or(request.expires_at_ms);
+        now < finished_at_ms.saturating_add(ASYNC_PAIR_RESULT_RETENTION_MS)
+    });
+}
+
+pub(super) async fn record_async_pair_outcome(
+    state: &SharedRelayState,
+    session_id: &str,
+    request_id: &str,
+    outcome: AsyncPairOutcome,
+) {
+    {
+        let mut relay = state.inner.lock().await;
+        let Some(request) = relay
+            .sessions
+            .get_mut(session_id)
+            .and_then(|session| session.async_pair_requests.get_mut(request_id))
+        else {
+            return;
+        };
+        request.completed_at_ms = Some(now_ms());
+        match outcome {
+            AsyncPairOutcome::Approved {
+                device_id,
+                device_session_token,
+            } => {
+                request.state = AsyncPairRequestState::Approved;
+                request.device_id = Some(device_id);
+                request.device_session_token = Some(device_session_token);
+            }
+            AsyncPairOutcome::Rejected { code, message } => {
+                request.state = AsyncPairRequestState::Rejected;
+                request.error = Some(code);
+                request.message = Some(message);
+            }
+        }
+    }
+    state.async_pair_updates.notify_waiters();
+    persist_session_if_needed(state, session_id).await;
+    publish_cross_instance_control_session_refresh(state, session_id);
+}
+
+pub(super) enum PairStatusLookup {
+    Found(Box<PairStatusResponse>),
+    NotFound,
+    InvalidToken,
+}
+
+pub(super) fn lookup_pair_status(
+    session: &SessionRecord,
+    request: &PairStatusRequest,
+    now: i64,
+) -> PairStatusLookup {
+    let Some(record) = session.async_pair_requests.get(&request.request_id) else {
+        return PairStatusLookup::NotFound;
+    };
+    if !safe_token_equals(&record.status_token, &request.status_token) {
+        return PairStatusLookup::InvalidToken;
+    }
+
+    let status = match record.state {
+        AsyncPairRequestState::Pending if now >= record.expires_at_ms => "expired",
+        AsyncPairRequestState::Pending => "pending",
+        AsyncPairRequestState::Approved => "approved",
+        AsyncPairRequestState::Rejected => "rejected",
+    };
+    let approved = record.state == AsyncPairRequestState::Approved;
+    PairStatusLookup::Found(Box::new(PairStatusResponse {
+        session_id: session.session_id.clone(),
+        request_id: request.request_id.clone(),
+        status: status.to_string(),
+        expires_at: iso_from_millis(record.expires_at_ms),
+        device_id: record.device_id.clone(),
+        device_session_token: record.device_session_token.clone(),
+        ws_url: approved.then(|| session.relay_web_socket_url.clone()),
+        schema_version: record
+            .device_id
+            .as_deref()
+            .filter(|_| approved)
+            .map(|device_id| device_schema_version(&session.devices, Some(device_id))),
+        error: record.error.clone(),
+        message: record.message.clone(),
+    }))
+}
+
+/// Answers `/pair/status`, holding the request open for up to `waitMs` while the decision is
+/// still pending. The session is loaded from persistence once; after that the wait is woken by
+/// `async_pair_updates`, which fires when this instance records an outcome or merges a newer
+/// write from another instance.
+pub(super) async fn wait_for_pair_status(
+    state: &SharedRelayState,
+    request: &PairStatusRequest,
+) -> Option<PairStatusLookup> {
+    let wait_ms = request.wait_ms.unwrap_or(0).min(MAX_PAIR_STATUS_WAIT_MS);
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(wait_ms);
+    refresh_session_for_request(state, &request.session_id).await;
+    loop {
+        let updated = state.async_pair_updates.notified();
+        tokio::pin!(updated);
+        updated.as_mut().enable();
+
+        let (lookup, expires_at_ms) = {
+            let mut relay = state.inner.lock().await;
+            let session = relay.sessions.get_mut(&request.session_id)?;
+            let now = now_ms();
+            prune_async_pair_requests(session, now);
+            let expires_at_ms = session
+                .async_pair_requests
+                .get(&request.request_id)
+                .map(|record| record.expires_at_ms);
+            (lookup_pair_status(session, request, now), expires_at_ms)
+        };
+        let still_pending = matches!(
+            &lookup,
+            PairStatusLookup::Found(response) if response.status == "pending"
+        );
+        if !still_pending || tokio::time::Instant::now() >= deadline {
+            return Some(lookup);
+        }
+
+        // A pending request turns into `expired` without any write, so wake up for that too.
+        let wake_at = expires_at_ms
+            .map(|expires_at_ms| {
+                let until_expiry = expires_at_ms.saturating_sub(now_ms()).max(0) as u64;
+                deadline.min(tokio::time::Instant::now() + Duration::from_millis(until_expiry))
+            })
+            .unwrap_or(deadline);
+        tokio::select! {
+            _ = &mut updated => {}
+            _ = tokio::time::sleep_until(wake_at) => {}
+        }
+    }
+}
@@ -62,6 +62,12 @@ fn rollback_mobile_auth_registration(
         session.last_activity_at_ms = now;
         if let Some(device) = session.devices.get_mut(device_id) {
             device.current_session_token = old_token.to_string();
+            device.token_generation = device.token_generation.saturating_sub(1);
+            if device.connection_history.back().is_some_and(|event| {
+                event.kind == DeviceConnectionEventKind::Connected && event.at_ms == now
+            }) {
+                device.connection_history.pop_back();
+            }
             device
                 .retired_session_tokens
                 .retain(|token| token.token != old_token);
@@ -70,14 +76,52 @@ fn rollback_mobile_auth_registration(
     }
 
     relay.device_token_index.remove(next_token);
-    relay.device_token_index.insert(
-        old_token.to_string(),
-        DeviceTokenContext {
-            session_id: session_id.to_string(),
-            device_id: device_id.to_string(),
-            expires_at_ms: None,
-        },
+    if !is_signed_device_token(old_token) {
+        relay.device_token_index.insert(
+            old_token.to_string(),
+            DeviceTokenContext {
+                session_id: session_id.to_string(),
+                device_id: device_id.to_string(),
+                expires_at_ms: None,
+            },
+        );
+    }
+}
+
+/// Signed device tokens carry their own session and device claims, so an unknown token never
+/// triggers a full persistence reload: at most the one session named in the claims is reloaded.
+async fn resolve_signed_auth_context(
+    state: &SharedRelayState,
+    token: &str,
+    remote_ip: &str,
+    user_agent: Option<&str>,
+) -> Result<AuthContext, SocketAuthFailure> {
+    let resolution = {
+        let relay = state.inner.lock().await;
+        resolve_signed_device_token(&relay, &state.config, token, now_ms())
+    };
+    let resolution = match resolution {
+        SignedDeviceTokenResolution::SessionStale { session_id } => {
+            refresh_session_from_persistence(state, &session_id).await;
+            let relay = state.inner.lock().await;
+            resolve_signed_device_token(&relay, &state.config, token, now_ms())
+        }
+        resolution => resolution,
+    };
+
+    let reason = match resolution {
+        SignedDeviceTokenResolution::Resolved(auth_context) => return Ok(auth_context),
+        SignedDeviceTokenResolution::SessionStale { .. } => "token_not_found",
+        SignedDeviceTokenResolution::Rejected(reason) => reason,
+    };
+    let mut relay = state.inner.lock().await;
+    record_ws_auth_failure_reason(&mut relay, reason);
+    warn!(
+        "[relay-rs] ws_auth_failure reason={reason} remote_ip={} user_agent={}",
+        remote_ip,
+        user_agent.unwrap_or("-")
     );
+    Err(SocketAuthFailure::SessionExpired)
 }
 
 async fn resolve_cross_instance_desktop_presence(
@@ -91,6 +135,23 @@ async fn resolve_cross_instance_desktop_presence(
     if state.cross_instance_bus.is_none() {
         return false;
     }
+    if let Some(presence) = &state.presence {
+        let desktop_connected = match presence.owners(session_id, "desktop").await {
+            Ok(owners) => !owners.is_empty(),
+            Err(error) => {
+                warn!(
+                    "[relay-rs] failed to look up desktop presence session={}: {error}",
+                    session_log_id(session_id)
+                );
+                false
+            }
+        };
+        let mut relay = state.inner.lock().await;
+        if let Some(session) = relay.sessions.get_mut(session_id) {
+            session.desktop_connected = desktop_connected;
+        }
+        return desktop_connected;
+    }
 
     {
         let mut relay = state.inner.lock().await;
@@ -123,14 +184,21 @@ async fn resolve_cross_instance_desktop_presence(
 
 pub(super) async fn authenticate_socket(
     state: &SharedRelayState,
-    token: &str,
+    auth_message: &RelayAuthMessage,
     origin: Option<&str>,
     remote_ip: &str,
     user_agent: Option<&str>,
-    tx: &mpsc::Sender<Message>,
+    tx: &SocketSender,
     shutdown_tx: &watch::Sender<bool>,
 ) -> Result<AuthenticatedSocket, SocketAuthFailure> {
-    let auth_context = {
+    let token = auth_message.token.as_str();
+    let declared_schema_version = auth_message
+        .supported_schema_versions
+        .as_deref()
+        .and_then(negotiate_schema_version);
+    let auth_context = if is_signed_device_token(token) {
+        Some(resolve_signed_auth_context(state, token, remote_ip, user_agent).await?)
+    } else {
         let relay = state.inner.lock().await;
         resolve_auth_context(&relay, token)
     };
@@ -138,7 +206,7 @@ pub(super) async fn authenticate_socket(
     let auth_context = if let Some(auth_context) = auth_context {
         auth_context
     } else {
-        refresh_sessions_from_persistence(state, true).await;
+        refresh_session_for_token(state, token).await;
         let mut relay = state.inner.lock().await;
         let refreshed = resolve_auth_context(&relay, token);
         if refreshed.is_none() {
@@ -181,10 +249,7 @@ pub(super) async fn authenticate_socket(
             remote_ip,
             user_agent.unwrap_or("-")
         );
-        if !try_send_payload(
-            tx,
-            json!({ "type": "disconnect", "reason": "relay_over_capacity" }).to_string(),
-        ) {
+        if !try_send_payload(tx, disconnect_payload("relay_over_capacity")) {
             relay.outbound_send_failures = relay.outbound_send_failures.saturating_add(1);
         }
         return Err(SocketAuthFailure::Rejected);
@@ -192,12 +257,7 @@ pub(super) async fn authenticate_socket(
 
     match auth_context {
         AuthContext::Desktop { session_id } => {
-            let (
-                auth_payload,
-                desktop_status_event,
-                desktop_status_send_failures,
-                desktop_status_slow_consumer_disconnects,
-            ) = {
+            let (auth_payload, desktop_status_event, desktop_status_send_failures) = {
                 let Some(session) = relay.sessions.get_mut(&session_id) else {
                     record_ws_auth_failure_reason(&mut relay, "desktop_session_missing");
                     warn!(
@@ -219,6 +279,9 @@ pub(super) async fn authenticate_socket(
                     device_id: None,
                 });
                 session.desktop_connected = true;
+                if let Some(schema_version) = declared_schema_version {
+                    session.desktop_schema_version = schema_version;
+                }
 
                 let payload = RelayAuthOk {
                     message_type: "auth_ok".to_string(),
@@ -228,15 +291,10 @@ pub(super) async fn authenticate_socket(
                     next_device_session_token: None,
                     connected_device_count: session.mobile_sockets.len(),
                     desktop_connected: desktop_connected(session),
+                    schema_version: session.desktop_schema_version,
                 };
-                let (desktop_status_event, send_failures, slow_consumer_disconnects) =
-                    send_desktop_status(session);
-                (
-                    payload,
-                    desktop_status_event,
-                    send_failures,
-                    slow_consumer_disconnects,
-                )
+                let (desktop_status_event, send_failures) = send_desktop_status(session);
+                (payload, desktop_status_event, send_failures)
             };
             if !try_send_payload(
                 tx,
@@ -249,9 +307,6 @@ pub(super) async fn authenticate_socket(
             relay.outbound_send_failures = relay
                 .outbound_send_failures
                 .saturating_add(desktop_status_send_failures);
-            relay.slow_consumer_disconnects = relay
-                .slow_consumer_disconnects
-                .saturating_add(desktop_status_slow_consumer_disconnects);
             relay.ws_auth_successes = relay.ws_auth_successes.saturating_add(1);
 
             info!(
@@ -289,9 +344,15 @@ pub(super) async fn authenticate_socket(
 
             let connection_id = random_token(10);
             let now = now_ms();
-            let next_token = random_token(32);
 
-            let (old_token, connected_device_count, device_count_event, local_desktop) = {
+            let (
+                old_token,
+                next_token,
+                connected_device_count,
+                device_count_event,
+                local_desktop,
+                schema_version,
+            ) = {
                 let Some(session) = relay.sessions.get_mut(&session_id) else {
                     record_ws_auth_failure_reason(&mut relay, "mobile_session_missing");
                     warn!(
@@ -335,6 +396,14 @@ pub(super) async fn authenticate_socket(
                     return Err(SocketAuthFailure::SessionExpired);
                 };
                 let old_token = device.current_session_token.clone();
+                device.token_generation = device.token_generation.saturating_add(1);
+                let next_token = issue_device_session_token(
+                    &state.config,
+                    &session_id,
+                    &device_id,
+                    device.token_generation,
+                    now,
+                );
                 device.current_session_token = next_token.clone();
                 device
                     .retired_session_tokens
@@ -346,6 +415,27 @@ pub(super) async fn authenticate_socket(
                     });
                 }
                 device.last_seen_at_ms = now;
+                if let Some(schema_version) = declared_schema_version {
+                    device.schema_version = schema_version;
+                }
+                let schema_version = device.schema_version;
+                DeviceClientInfo::from_request(
+                    auth_message.platform.as_deref(),
+                    auth_message.app_version.as_deref(),
+                    user_agent,
+                )
+                .apply(device, remote_ip);
+                record_device_connection_event(
+                    device,
+                    &state.config,
+                    DeviceConnectionEvent {
+                        kind: DeviceConnectionEventKind::Connected,
+                        at_ms: now,
+                        remote_ip: remote_ip.to_string(),
+                        reason: None,
+                        duration_ms: None,
+                    },
+                );
 
                 session.mobile_sockets.insert(
                     connection_id.clone(),
@@ -359,13 +449,15 @@ pub(super) async fn authenticate_socket(
                 let connected_device_count = session.mobile_sockets.len();
                 (
                     old_token,
+                    next_token,
                     connected_device_count,
                     device_count_payload(session),
                     session.desktop_socket.clone(),
+                    schema_version,
                 )
             };
 
-            if state.config.token_rotation_grace_ms == 0 {
+            if state.config.token_rotation_grace_ms == 0 || is_signed_device_token(&old_token) {
                 relay.device_token_index.remove(&old_token);
             } else {
                 relay.device_token_index.insert(
@@ -378,14 +470,16 @@ pub(super) async fn authenticate_socket(
                 );
             }
 
-            relay.device_token_index.insert(
-                next_token.clone(),
-                DeviceTokenContext {
-                    session_id: session_id.clone(),
-                    device_id: device_id.clone(),
-                    expires_at_ms: None,
-                },
-            );
+            if !is_signed_device_token(&next_token) {
+                relay.device_token_index.insert(
+                    next_token.clone(),
+                    DeviceTokenContext {
+                        session_id: session_id.clone(),
+                        device_id: device_id.clone(),
+                        expires_at_ms: None,
+                    },
+                );
+            }
 
             drop(relay);
             if let Err(error) = persist_session_if_needed_checked(state, &session_id).await {
@@ -424,6 +518,7 @@ pub(super) async fn authenticate_socket(
                 next_device_session_token: Some(next_token.clone()),
                 connected_device_count,
                 desktop_connected,
+                schema_version,
             };
             if !try_send_payload(
                 tx,
@@ -445,12 +540,9 @@ pub(super) async fn authenticate_socket(
                 return Err(SocketAuthFailure::Rejected);
             }
             let mut outbound_send_failures = 0_u64;
-            let mut slow_consumer_disconnects = 0_u64;
             if let Some(desktop) = &local_desktop {
                 if !try_send_payload(&desktop.tx, device_count_event.clone()) {
                     outbound_send_failures = outbound_send_failures.saturating_add(1);
-                    slow_consumer_disconnects = slow_consumer_disconnects.saturating_add(1);
-                    request_socket_disconnect(desktop, "slow_consumer");
                 }
             }
 
@@ -458,9 +550,6 @@ pub(super) async fn authenticate_socket(
             relay.outbound_send_failures = relay
                 .outbound_send_failures
                 .saturating_add(outbound_send_failures);
-            relay.slow_consumer_disconnects = relay
-                .slow_consumer_disconnects
-                .saturating_add(slow_consumer_disconnects);
             relay.ws_auth_successes = relay.ws_auth_successes.saturating_add(1);
 
             info!(
@@ -470,6 +559,9 @@ pub(super) async fn authenticate_socket(
             );
             drop(relay);
             publish_cross_instance_session(state, &session_id, "desktop", None, device_count_event);
+            if !desktop_connected {
+                serve_cached_desktop_snapshot(state, &session_id, tx, schema_version).await;
+            }
 
             Ok(AuthenticatedSocket {
                 session_id,
@@ -509,33 +601,39 @@ pub(super) fn resolve_auth_context(relay: &RelayState, token: &str) -> Option<Au
     })
 }
 
-pub(super) async fn disconnect_socket(state: &SharedRelayState, auth: &AuthenticatedSocket) {
+pub(super) async fn disconnect_socket(
+    state: &SharedRelayState,
+    auth: &AuthenticatedSocket,
+    remote_ip: &str,
+    connected_at_ms: i64,
+    reason: &str,
+) {
     let mut relay = state.inner.lock().await;
     let Some(session) = relay.sessions.get_mut(auth.session_id()) else {
         return;
     };
 
-    session.last_activity_at_ms = now_ms();
+    let now = now_ms();
+    session.last_activity_at_ms = now;
+    let mut record_history = false;
     let mut device_count_event: Option<String> = None;
     let mut desktop_status_event: Option<String> = None;
     let mut outbound_send_failures = 0_u64;
-    let mut slow_consumer_disconnects = 0_u64;
 
     match &auth.auth {
         SocketAuth::Desktop => {
             session.desktop_socket = None;
             session.desktop_connected = false;
-            if let Some(pending) = session.pending_join_request.take() {
+            for (_, pending) in session.pending_join_requests.drain() {
                 let _ = pending.decision_tx.send(JoinDecision {
                     approved: false,
                     reason: "desktop_disconnected".to_string(),
+                    scopes: Vec::new(),
                 });
             }
-            let (event, send_failures, consumer_disconnects) = send_desktop_status(session);
+            let (event, send_failures) = send_desktop_status(session);
             desktop_status_event = Some(event);
             outbound_send_failures = outbound_send_failures.saturating_add(send_failures);
-            slow_consumer_disconnects =
-                slow_consumer_disconnects.saturating_add(consumer_disconnects);
             info!(
                 "[relay-rs] desktop_disconnected session={}",
                 session_log_id(auth.session_id())
@@ -543,8 +641,22 @@ pub(super) async fn disconnect_socket(state: &SharedRelayState, auth: &Authentic
         }
         SocketAuth::Mobile {
             connection_id,
-            device_id: _,
+            device_id,
         } => {
+            if let Some(device) = session.devices.get_mut(device_id) {
+                record_device_connection_event(
+                    device,
+                    &state.config,
+                    DeviceConnectionEvent {
+                        kind: DeviceConnectionEventKind::Disconnected,
+                        at_ms: now,
+                        remote_ip: remote_ip.to_string(),
+                        reason: Some(reason.to_string()),
+                        duration_ms: Some(now.saturating_sub(connected_at_ms)),
+                    },
+                );
+                record_history = state.config.device_connection_history_limit > 0;
+            }
             session.mobile_sockets.remove(connection_id);
             session
                 .command_sequence_by_connection_id
@@ -552,7 +664,7 @@ pub(super) async fn disconnect_socket(state: &SharedRelayState, auth: &Authentic
             send_device_count(session);
             device_count_event = Some(device_count_payload(session));
             info!(
-                "[relay-rs] mobile_disconnected session={} devices={}",
+                "[relay-rs] mobile_disconnected session={} devices={} reason={reason}",
                 session_log_id(auth.session_id()),
                 session.mobile_sockets.len()
             );
@@ -561,9 +673,6 @@ pub(super) async fn disconnect_socket(state: &SharedRelayState, auth: &Authentic
     relay.outbound_send_failures = relay
         .outbound_send_failures
         .saturating_add(outbound_send_failures);
-    relay.slow_consumer_disconnects = relay
-        .slow_consumer_disconnects
-        .saturating_add(slow_consumer_disconnects);
 
     drop(relay);
 
@@ -573,6 +682,8 @@ pub(super) async fn disconnect_socket(state: &SharedRelayState, auth: &Authentic
     if let Some(event) = desktop_status_event {
         publish_cross_instance_session(state, auth.session_id(), "mobile", None, event);
         persist_session_if_needed(state, auth.session_id()).await;
+    } else if record_history {
+        persist_session_if_needed(state, auth.session_id()).await;
     }
     sync_session_bus_subscription(state, auth.session_id()).await;
 }
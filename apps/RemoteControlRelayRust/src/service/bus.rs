@@ -0,0 +1,371 @@
+use super::*;
+use futures_util::future::BoxFuture;
+use futures_util::stream::BoxStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::broadcast;
+
+const JETSTREAM_PUBLISH_ATTEMPTS: u64 = 3;
+const JETSTREAM_PUBLISH_RETRY_DELAY_MS: u64 = 100;
+const IN_PROCESS_BUS_CAPACITY: usize = 4_096;
+
+/// Transport that carries cross-instance envelopes between relay instances. Subjects are plain
+/// strings (`<prefix>.session.<id>`, `<prefix>.instance.<id>`, `<prefix>.control`); routing,
+/// signing and presence lookups stay in the relay so a transport only moves bytes.
+pub trait CrossInstanceBus: Send + Sync {
+    fn publish(&self, subject: String, payload: Vec<u8>) -> BoxFuture<'_, Result<(), String>>;
+
+    /// Delivers every message published to exactly `subject` from now on. Durable transports
+    /// use `consumer_name` to resume a subscription after a reconnect.
+    fn subscribe(
+        &self,
+        subject: String,
+        consumer_name: String,
+    ) -> BoxFuture<'_, Result<BoxStream<'static, CrossInstanceBusMessage>, String>>;
+
+    /// Called when the relay stops routing a subject it subscribed to under `consumer_name`.
+    fn release_subscription(&self, consumer_name: String) {
+        let _ = consumer_name;
+    }
+
+    /// True once the transport has fallen short of the delivery guarantees it was configured
+    /// for. Reported as `crossInstanceBusDegraded` in `/healthz`.
+    fn is_degraded(&self) -> bool {
+        false
+    }
+}
+
+pub struct CrossInstanceBusMessage {
+    delivery: CrossInstanceDelivery,
+}
+
+enum CrossInstanceDelivery {
+    Bytes(Arc<[u8]>),
+    Core(async_nats::Message),
+    JetStream(Box<async_nats::jetstream::Message>),
+}
+
+impl CrossInstanceBusMessage {
+    pub fn new(payload: impl Into<Arc<[u8]>>) -> Self {
+        Self {
+            delivery: CrossInstanceDelivery::Bytes(payload.into()),
+        }
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        match &self.delivery {
+            CrossInstanceDelivery::Bytes(payload) => payload,
+            CrossInstanceDelivery::Core(message) => &message.payload,
+            CrossInstanceDelivery::JetStream(message) => &message.payload,
+        }
+    }
+
+    /// Acknowledges a JetStream delivery once it has been handled (or deliberately discarded).
+    /// Other deliveries need no acknowledgement.
+    pub async fn ack(&self) {
+        if let CrossInstanceDelivery::JetStream(message) = &self.delivery {
+            if let Err(error) = message.ack().await {
+                warn!("[relay-rs] failed to ack cross-instance delivery: {error}");
+            }
+        }
+    }
+}
+
+/// Bus shared by relay instances running in one process. Publishes fan out to every subscriber
+/// of the subject and are dropped when nobody is subscribed, like core NATS.
+#[derive(Clone)]
+pub struct InProcessCrossInstanceBus {
+    sender: broadcast::Sender<(String, Arc<[u8]>)>,
+}
+
+impl InProcessCrossInstanceBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(IN_PROCESS_BUS_CAPACITY);
+        Self { sender }
+    }
+}
+
+impl Default for InProcessCrossInstanceBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CrossInstanceBus for InProcessCrossInstanceBus {
+    fn publish(&self, subject: String, payload: Vec<u8>) -> BoxFuture<'_, Result<(), String>> {
+        let _ = self.sender.send((subject, payload.into()));
+        Box::pin(async { Ok(()) })
+    }
+
+    fn subscribe(
+        &self,
+        subject: String,
+        _consumer_name: String,
+    ) -> BoxFuture<'_, Result<BoxStream<'static, CrossInstanceBusMessage>, String>> {
+        let receiver = self.sender.subscribe();
+        Box::pin(async move {
+            Ok(
+                futures_util::stream::unfold(
+                    (receiver, subject),
+                    |(mut receiver, subject)| async move {
+                        loop {
+                            match receiver.recv().await {
+                                Ok((published_subject, payload)) if published_subject == subject => {
+                                    let message = CrossInstanceBusMessage::new(payload);
+                                    return Some((message, (receiver, subject)));
+                                }
+                                Ok(_) => {}
+                                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                                    warn!(
+                                        "[relay-rs] in-process bus subscriber lagged subject={subject} skipped={skipped}"
+                                    );
+                                }
+                                Err(broadcast::error::RecvError::Closed) => return None,
+                            }
+                        }
+                    },
+                )
+                .boxed(),
+            )
+        })
+    }
+}
+
+pub(super) struct NatsCrossInstanceBus {
+    client: async_nats::Client,
+    jetstream: Option<RelayJetStream>,
+    degraded: Arc<AtomicBool>,
+}
+
+#[derive(Clone)]
+struct RelayJetStream {
+    context: async_nats::jetstream::Context,
+    stream_name: String,
+    ack_wait: Duration,
+    max_deliver: i64,
+    inactive_threshold: Duration,
+}
+
+pub(super) async fn connect_nats_cross_instance_bus(
+    config: &RelayConfig,
+) -> Option<Arc<dyn CrossInstanceBus>> {
+    let nats_url = config.nats_url.as_ref()?;
+    let client = match async_nats::connect(nats_url).await {
+        Ok(client) => client,
+        Err(error) => {
+            warn!("[relay-rs] failed to connect to NATS; cross-instance bus disabled: {error}");
+            return None;
+        }
+    };
+
+    let redacted_nats_url = redact_url_for_logs(nats_url);
+    info!(
+        "[relay-rs] connected to NATS for cross-instance routing: url={} subject_prefix={}",
+        redacted_nats_url, config.nats_subject_prefix
+    );
+
+    let jetstream = if config.nats_jetstream_enabled {
+        build_jetstream(&client, config).await
+    } else {
+        None
+    };
+    let degraded = config.nats_jetstream_enabled && jetstream.is_none();
+    Some(Arc::new(NatsCrossInstanceBus {
+        client,
+        jetstream,
+        degraded: Arc::new(AtomicBool::new(degraded)),
+    }))
+}
+
+/// Durable mode captures the session and control subjects in one stream bounded by age and by
+/// messages per subject. When the stream cannot be set up the relay still routes over core NATS,
+/// but reports the bus as degraded.
+async fn build_jetstream(
+    client: &async_nats::Client,
+    config: &RelayConfig,
+) -> Option<RelayJetStream> {
+    let context = async_nats::jetstream::new(client.clone());
+    let max_age = Duration::from_millis(config.nats_jetstream_max_age_ms);
+    let stream_config = async_nats::jetstream::stream::Config {
+        name: config.nats_jetstream_stream.clone(),
+        subjects: vec![
+            format!("{}.session.*", config.nats_subject_prefix),
+            format!("{}.instance.*", config.nats_subject_prefix),
+            format!("{}.control", config.nats_subject_prefix),
+        ],
+        max_age,
+        max_messages_per_subject: i64::try_from(config.nats_jetstream_max_messages_per_subject)
+            .unwrap_or(i64::MAX),
+        storage: async_nats::jetstream::stream::StorageType::File,
+        ..Default::default()
+    };
+    if let Err(error) = context.get_or_create_stream(stream_config).await {
+        error!(
+            "[relay-rs] jetstream_stream_setup_failed stream={} fallback=core_nats error={error}",
+            config.nats_jetstream_stream
+        );
+        return None;
+    }
+
+    info!(
+        "[relay-rs] JetStream durable delivery enabled: stream={} max_age_ms={} max_messages_per_subject={}",
+        config.nats_jetstream_stream,
+        config.nats_jetstream_max_age_ms,
+        config.nats_jetstream_max_messages_per_subject
+    );
+    Some(RelayJetStream {
+        context,
+        stream_name: config.nats_jetstream_stream.clone(),
+        ack_wait: Duration::from_millis(config.nats_jetstream_ack_wait_ms),
+        max_deliver: i64::try_from(config.nats_jetstream_max_deliver).unwrap_or(i64::MAX),
+        inactive_threshold: max_age,
+    })
+}
+
+/// Waits for the stream ack, retrying with a short delay. The delay only holds up the publish
+/// lane the subject is queued on; see `RelayCrossInstanceBus::enqueue_publish`.
+async fn publish_jetstream_with_retry(
+    jetstream: &RelayJetStream,
+    subject: &str,
+    payload: &[u8],
+) -> Result<(), String> {
+    let mut attempt = 1;
+    loop {
+        let result = match jetstream
+            .context
+            .publish(subject.to_string(), payload.to_vec().into())
+            .await
+        {
+            Ok(ack) => ack.await.map(|_| ()).map_err(|error| error.to_string()),
+            Err(error) => Err(error.to_string()),
+        };
+        match result {
+            Ok(()) => return Ok(()),
+            Err(error) if attempt < JETSTREAM_PUBLISH_ATTEMPTS => {
+                warn!(
+                    "[relay-rs] JetStream publish attempt {attempt} failed subject={subject}: {error}"
+                );
+                sleep(Duration::from_millis(
+                    JETSTREAM_PUBLISH_RETRY_DELAY_MS * attempt,
+                ))
+                .await;
+                attempt += 1;
+            }
+            Err(error) => return Err(format!("JetStream publish failed: {error}")),
+        }
+    }
+}
+
+impl CrossInstanceBus for NatsCrossInstanceBus {
+    fn publish(&self, subject: String, payload: Vec<u8>) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            if let Some(jetstream) = &self.jetstream {
+                return publish_jetstream_with_retry(jetstream, &subject, &payload).await;
+            }
+            self.client
+                .publish(subject, payload.into())
+                .await
+                .map_err(|error| error.to_string())
+        })
+    }
+
+    /// In JetStream mode this binds a durable pull consumer named `consumer_name`, so
+    /// deliveries missed during a reconnect are redelivered until acked.
+    fn subscribe(
+        &self,
+        subject: String,
+        consumer_name: String,
+    ) -> BoxFuture<'_, Result<BoxStream<'static, CrossInstanceBusMessage>, String>> {
+        Box::pin(async move {
+            let Some(jetstream) = &self.jetstream else {
+                let subscription = self
+                    .client
+                    .subscribe(subject)
+                    .await
+                    .map_err(|error| error.to_string())?;
+                return Ok(subscription
+                    .map(|message| CrossInstanceBusMessage {
+                        delivery: CrossInstanceDelivery::Core(message),
+                    })
+                    .boxed());
+            };
+
+            let messages = match bind_jetstream_consumer(jetstream, subject, &consumer_name).await {
+                Ok(messages) => messages,
+                Err(error) => {
+                    error!(
+                        "[relay-rs] jetstream_consumer_setup_failed consumer={consumer_name} error={error}"
+                    );
+                    self.degraded.store(true, Ordering::Relaxed);
+                    return Err(error);
+                }
+            };
+            Ok(messages
+                .filter_map(|message| async move {
+                    match message {
+                        Ok(message) => Some(CrossInstanceBusMessage {
+                            delivery: CrossInstanceDelivery::JetStream(Box::new(message)),
+                        }),
+                        Err(error) => {
+                            warn!("[relay-rs] JetStream delivery error: {error}");
+                            None
+                        }
+                    }
+                })
+                .boxed())
+        })
+    }
+
+    /// Deletes the durable consumer of a subject this instance no longer routes, instead of
+    /// leaving it to expire after the inactivity threshold.
+    fn release_subscription(&self, consumer_name: String) {
+        let Some(jetstream) = self.jetstream.clone() else {
+            return;
+        };
+        tokio::spawn(async move {
+            let result = match jetstream.context.get_stream(&jetstream.stream_name).await {
+                Ok(stream) => stream
+                    .delete_consumer(&consumer_name)
+                    .await
+                    .map(|_| ())
+                    .map_err(|error| error.to_string()),
+                Err(error) => Err(error.to_string()),
+            };
+            if let Err(error) = result {
+                warn!("[relay-rs] failed to delete JetStream consumer {consumer_name}: {error}");
+            }
+        });
+    }
+
+    fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+}
+
+async fn bind_jetstream_consumer(
+    jetstream: &RelayJetStream,
+    subject: String,
+    consumer_name: &str,
+) -> Result<async_nats::jetstream::consumer::pull::Stream, String> {
+    let stream = jetstream
+        .context
+        .get_stream(&jetstream.stream_name)
+        .await
+        .map_err(|error| error.to_string())?;
+    let consumer = stream
+        .get_or_create_consumer(
+            consumer_name,
+            async_nats::jetstream::consumer::pull::Config {
+                durable_name: Some(consumer_name.to_string()),
+                filter_subject: subject,
+                deliver_policy: async_nats::jetstream::consumer::DeliverPolicy::New,
+                ack_policy: async_nats::jetstream::consumer::AckPolicy::Explicit,
+                ack_wait: jetstream.ack_wait,
+                max_deliver: jetstream.max_deliver,
+                inactive_threshold: jetstream.inactive_threshold,
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(|error| error.to_string())?;
+    consumer.messages().await.map_err(|error| error.to_string())
+}
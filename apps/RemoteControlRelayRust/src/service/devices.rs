@@ -0,0 +1,218 @@
+use super::*;
+
+const DEVICE_METADATA_MAX_CHARS: usize = 32;
+
+/// Client details a mobile reports in its `relay.auth` payload or `pair/join` body, with the
+/// `User-Agent` header as a fallback for the platform.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) struct DeviceClientInfo {
+    pub(super) platform: Option<String>,
+    pub(super) app_version: Option<String>,
+}
+
+impl DeviceClientInfo {
+    pub(super) fn from_request(
+        platform: Option<&str>,
+        app_version: Option<&str>,
+        user_agent: Option<&str>,
+    ) -> Self {
+        Self {
+            platform: sanitize_device_metadata(platform)
+                .or_else(|| user_agent.and_then(platform_from_user_agent)),
+            app_version: sanitize_device_metadata(app_version)
+                .or_else(|| user_agent.and_then(app_version_from_user_agent)),
+        }
+    }
+
+    /// Updates a device record, keeping previously reported values for fields this client
+    /// left out.
+    pub(super) fn apply(self, device: &mut DeviceRecord, client_ip: &str) {
+        if self.platform.is_some() {
+            device.platform = self.platform;
+        }
+        if self.app_version.is_some() {
+            device.app_version = self.app_version;
+        }
+        device.last_ip = Some(client_ip.to_string());
+    }
+}
+
+fn sanitize_device_metadata(raw: Option<&str>) -> Option<String> {
+    raw.map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            value
+                .chars()
+                .filter(|ch| !ch.is_control())
+                .take(DEVICE_METADATA_MAX_CHARS)
+                .collect::<String>()
+        })
+}
+
+/// Order matters: iPad and iPhone user agents also mention "Mac OS X", and Android ones mention
+/// "Linux".
+fn platform_from_user_agent(user_agent: &str) -> Option<String> {
+    [
+        ("iPhone", "ios"),
+        ("iPad", "ipados"),
+        ("Android", "android"),
+        ("Mac OS X", "macos"),
+        ("Windows", "windows"),
+        ("Linux", "linux"),
+    ]
+    .into_iter()
+    .find(|(needle, _)| user_agent.contains(needle))
+    .map(|(_, platform)| platform.to_string())
+}
+
+/// Native clients identify themselves as `CodexChat/<version>` in their user agent.
+fn app_version_from_user_agent(user_agent: &str) -> Option<String> {
+    user_agent
+        .split_whitespace()
+        .find_map(|product| product.strip_prefix("CodexChat/"))
+        .and_then(|version| sanitize_device_metadata(Some(version)))
+}
+
+pub(super) fn sanitize_device_nickname(raw: Option<&str>) -> Option<String> {
+    raw.map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.chars().take(64).collect::<String>())
+}
+
+pub(super) fn device_summary(
+    session: &SessionRecord,
+    device_id: &str,
+    record: &DeviceRecord,
+) -> DeviceSummary {
+    let traffic = session.traffic.device(device_id);
+    DeviceSummary {
+        device_id: device_id.to_string(),
+        device_name: record.name.clone(),
+        nickname: record.nickname.clone(),
+        platform: record.platform.clone(),
+        app_version: record.app_version.clone(),
+        last_ip: record.last_ip.clone(),
+        scopes: record.scopes.clone(),
+        connected: session
+            .mobile_sockets
+            .values()
+            .any(|socket| socket.device_id.as_deref() == Some(device_id)),
+        joined_at: iso_from_millis(record.joined_at_ms),
+        last_seen_at: iso_from_millis(record.last_seen_at_ms),
+        bytes_in: traffic.bytes_in,
+        bytes_out: traffic.bytes_out,
+    }
+}
+
+/// Appends to a device's connection history, dropping the oldest entries beyond
+/// `DEVICE_CONNECTION_HISTORY_LIMIT`.
+pub(super) fn record_device_connection_event(
+    device: &mut DeviceRecord,
+    config: &RelayConfig,
+    event: DeviceConnectionEvent,
+) {
+    if config.device_connection_history_limit == 0 {
+        return;
+    }
+    device.connection_history.push_back(event);
+    while device.connection_history.len() > config.device_connection_history_limit {
+        device.connection_history.pop_front();
+    }
+}
+
+pub(super) fn device_connection_event_summary(
+    event: &DeviceConnectionEvent,
+) -> DeviceConnectionEventSummary {
+    DeviceConnectionEventSummary {
+        event: match event.kind {
+            DeviceConnectionEventKind::Connected => "connected",
+            DeviceConnectionEventKind::Disconnected => "disconnected",
+        }
+        .to_string(),
+        at: iso_from_millis(event.at_ms),
+        remote_ip: event.remote_ip.clone(),
+        reason: event.reason.clone(),
+        duration_ms: event.duration_ms,
+    }
+}
+
+/// What `pair/join` does once a session holds as many devices as its cap allows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum DeviceEvictionPolicy {
+    #[default]
+    Reject,
+    LeastRecentlySeen,
+}
+
+impl DeviceEvictionPolicy {
+    pub(super) fn parse(raw: Option<&str>) -> Option<Self> {
+        match raw.map(str::trim).unwrap_or("reject") {
+            "reject" => Some(Self::Reject),
+            "least_recently_seen" => Some(Self::LeastRecentlySeen),
+            _ => None,
+        }
+    }
+
+    pub(super) fn as_str(self) -> &'static str {
+        match self {
+            Self::Reject => "reject",
+            Self::LeastRecentlySeen => "least_recently_seen",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum DeviceCapDecision {
+    Room,
+    Evict(String),
+    Full(usize),
+}
+
+/// The cap requested at `pair/start`, never above `MAX_DEVICES_PER_SESSION`.
+pub(super) fn session_device_cap(config: &RelayConfig, session: &SessionRecord) -> usize {
+    session
+        .max_devices
+        .map_or(config.max_devices_per_session, |cap| {
+            cap.min(config.max_devices_per_session)
+        })
+}
+
+pub(super) fn device_cap_decision(
+    config: &RelayConfig,
+    session: &SessionRecord,
+) -> DeviceCapDecision {
+    let cap = session_device_cap(config, session);
+    if session.devices.len() < cap {
+        return DeviceCapDecision::Room;
+    }
+    match session.device_eviction_policy {
+        DeviceEvictionPolicy::Reject => DeviceCapDecision::Full(cap),
+        DeviceEvictionPolicy::LeastRecentlySeen => session
+            .devices
+            .iter()
+            .min_by(|(left_id, left), (right_id, right)| {
+                left.last_seen_at_ms
+                    .cmp(&right.last_seen_at_ms)
+                    .then_with(|| left_id.cmp(right_id))
+            })
+            .map_or(DeviceCapDecision::Full(cap), |(device_id, _)| {
+                DeviceCapDecision::Evict(device_id.clone())
+            }),
+    }
+}
+
+/// Drops a device and its per-device state from the session and disconnects its local socket.
+/// Callers still clear its tokens from the index and notify other instances.
+pub(super) fn remove_session_device(
+    session: &mut SessionRecord,
+    device_id: &str,
+    reason: &str,
+) -> Option<DeviceRecord> {
+    let removed = session.devices.remove(device_id)?;
+    session.command_rate_buckets.remove(device_id);
+    session.snapshot_request_rate_buckets.remove(device_id);
+    session.traffic.remove_device(device_id);
+    close_existing_mobile_socket_for_device(session, device_id, reason);
+    Some(removed)
+}
@@ -0,0 +1,202 @@
+use super::*;
+
+/// Fields every inbound frame is routed on. Serde skips everything else without allocating, so a
+/// desktop snapshot is scanned once and then forwarded exactly as it was received.
+#[derive(Deserialize)]
+struct FrameHead<'a> {
+    #[serde(rename = "type", borrow, default)]
+    message_type: Option<Cow<'a, str>>,
+    #[serde(rename = "schemaVersion", default)]
+    schema_version: Option<u64>,
+    #[serde(rename = "sessionID", borrow, default)]
+    session_id: Option<Cow<'a, str>>,
+    #[serde(borrow, default)]
+    payload: Option<PayloadHead<'a>>,
+}
+
+#[derive(Deserialize)]
+struct PayloadHead<'a> {
+    #[serde(rename = "type", borrow, default)]
+    payload_type: Option<Cow<'a, str>>,
+}
+
+/// A text frame received on an authenticated socket, or the `relay.auth` frame that opens one.
+pub(super) enum InboundMessage {
+    Auth(RelayAuthMessage),
+    PairDecision(RelayPairDecision),
+    SnapshotRequest(RelaySnapshotRequest),
+    Command(Box<CommandEnvelope>),
+    /// Anything else: desktop events and snapshots that are forwarded to mobiles as received.
+    Passthrough {
+        payload_type: Option<String>,
+    },
+    /// A frame that claims to be one of the typed messages above but does not decode as one.
+    Invalid(RelayValidationError),
+}
+
+impl InboundMessage {
+    /// `payload.type` of a forwarded desktop envelope, such as `snapshot` or `event`.
+    pub(super) fn passthrough_payload_type(&self) -> Option<&str> {
+        match self {
+            Self::Passthrough { payload_type } => payload_type.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// Inbound frame in canonical form, ready to route.
+pub(super) struct InboundFrame<'a> {
+    /// Frame text in the canonical envelope version. Borrowed unless a v3 envelope was rewritten.
+    pub(super) raw: Cow<'a, str>,
+    /// Top-level `sessionID`, when the frame names one.
+    pub(super) session_id: Option<String>,
+    pub(super) message: InboundMessage,
+}
+
+fn invalid(code: &'static str, message: impl Into<String>) -> InboundMessage {
+    InboundMessage::Invalid(RelayValidationError {
+        code,
+        message: message.into(),
+    })
+}
+
+fn decode_typed<T: serde::de::DeserializeOwned>(
+    raw: &str,
+    code: &'static str,
+    wrap: impl FnOnce(T) -> InboundMessage,
+) -> InboundMessage {
+    match serde_json::from_str::<T>(raw) {
+        Ok(message) => wrap(message),
+        Err(error) => invalid(code, format!("{error}.")),
+    }
+}
+
+fn decode_frame(
+    raw: &str,
+    head: Result<FrameHead<'_>, serde_json::Error>,
+) -> (Option<String>, InboundMessage) {
+    let head = match head {
+        Ok(head) => head,
+        Err(error) if error.is_data() => {
+            // The routing fields did not have the expected shapes, but the frame is still JSON.
+            // Read `sessionID` from it anyway so the cross-session check applies to it as well.
+            let session_id = serde_json::from_str::<Value>(raw).ok().and_then(|value| {
+                value
+                    .get("sessionID")
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+            });
+            return (
+                session_id,
+                invalid(
+                    "invalid_payload",
+                    format!("Payload must be a JSON object: {error}."),
+                ),
+            );
+        }
+        Err(_) => {
+            return (
+                None,
+                invalid("invalid_payload", "Payload must be valid JSON."),
+            )
+        }
+    };
+    let session_id = head.session_id.map(Cow::into_owned);
+    let message = match head.message_type.as_deref() {
+        Some("relay.auth") => decode_typed(raw, "invalid_auth_payload", InboundMessage::Auth),
+        Some("relay.pair_decision") => {
+            decode_typed(raw, "invalid_pair_decision", InboundMessage::PairDecision)
+        }
+        Some("relay.snapshot_request") => decode_typed(
+            raw,
+            "invalid_snapshot_request",
+            InboundMessage::SnapshotRequest,
+        ),
+        _ => {
+            let payload_type = head
+                .payload
+                .and_then(|payload| payload.payload_type)
+                .map(Cow::into_owned);
+            if payload_type.as_deref() == Some("command") {
+                decode_typed(raw, "invalid_command", |envelope| {
+                    InboundMessage::Command(Box::new(envelope))
+                })
+            } else {
+                InboundMessage::Passthrough { payload_type }
+            }
+        }
+    };
+    (session_id, message)
+}
+
+/// Decodes a text frame into an [`InboundMessage`]. Routing fields are read in one borrowed pass;
+/// only relay control messages and commands, which are small, are then decoded into their typed
+/// bodies. v3 envelopes are rewritten into the canonical v2 shape before they are decoded.
+pub(super) fn decode_inbound_frame(raw: &str) -> InboundFrame<'_> {
+    let head = serde_json::from_str::<FrameHead>(raw);
+    let is_v3_envelope = head
+        .as_ref()
+        .is_ok_and(|head| head.schema_version == Some(3));
+    if is_v3_envelope {
+        let canonical = serde_json::from_str::<Value>(raw)
+            .ok()
+            .as_ref()
+            .and_then(canonicalize_envelope);
+        if let Some(canonical) = canonical {
+            let canonical = canonical.to_string();
+            let (session_id, message) = decode_frame(&canonical, serde_json::from_str(&canonical));
+            return InboundFrame {
+                raw: Cow::Owned(canonical),
+                session_id,
+                message,
+            };
+        }
+    }
+    let (session_id, message) = decode_frame(raw, head);
+    InboundFrame {
+        raw: Cow::Borrowed(raw),
+        session_id,
+        message,
+    }
+}
+
+/// Adds the relay's connection and device ids to a validated mobile message before it is
+/// forwarded to the desktop. Messages that do not carry either field get them appended to the
+/// received text; a command that tried to set them is rewritten so the relay's values win. The
+/// decision comes from the decoded `envelope`, so `\u`-escaped keys are caught too. Appending is
+/// also skipped for frames that spell either key out or contain escapes, since a `null` value
+/// decodes as absent and appending would leave the client's key first in the forwarded text.
+pub(super) fn forwarded_command_payload(
+    raw: &str,
+    envelope: Option<&CommandEnvelope>,
+    connection_id: &str,
+    device_id: &str,
+) -> String {
+    let sets_metadata = envelope.is_some_and(|envelope| {
+        envelope.relay_connection_id.is_some() || envelope.relay_device_id.is_some()
+    }) || raw.contains("\"relayConnectionID\"")
+        || raw.contains("\"relayDeviceID\"")
+        || raw.contains("\\u");
+    if !sets_metadata {
+        if let Some(forwarded) = append_mobile_metadata(raw, connection_id, device_id) {
+            return forwarded;
+        }
+    }
+    inject_mobile_metadata(raw, connection_id, device_id)
+}
+
+/// Splices the metadata in front of the closing brace of a JSON object that does not already
+/// carry it.
+fn append_mobile_metadata(raw: &str, connection_id: &str, device_id: &str) -> Option<String> {
+    let body = raw.trim_end().strip_suffix('}')?;
+    let separator = if body.trim_end().ends_with('{') {
+        ""
+    } else {
+        ","
+    };
+    let connection_id = serde_json::to_string(connection_id).ok()?;
+    let device_id = serde_json::to_string(device_id).ok()?;
+    Some(format!(
+        "{body}{separator}\"relayConnectionID\":{connection_id},\"relayDeviceID\":{device_id}}}"
+    ))
+}
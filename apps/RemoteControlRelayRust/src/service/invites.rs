@@ -0,0 +1,143 @@
+use super::*;
+
+pub(super) const MAX_INVITES_PER_SESSION: usize = 16;
+pub(super) const MAX_INVITE_REDEMPTIONS: u32 = 100;
+pub(super) const MAX_INVITE_TTL_MS: i64 = 7 * 24 * 60 * 60 * 1_000;
+
+/// Multi-use join credential minted by the desktop through `/invites/create`. Redeemed at
+/// `pair/join` in place of the session join token.
+#[derive(Clone, Serialize, Deserialize)]
+pub(super) struct SessionInvite {
+    pub(super) token: String,
+    pub(super) max_redemptions: u32,
+    pub(super) redemptions: u32,
+    pub(super) created_at_ms: i64,
+    pub(super) expires_at_ms: i64,
+    #[serde(default)]
+    pub(super) scopes: Vec<String>,
+    #[serde(default)]
+    pub(super) auto_approve: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum JoinCredential {
+    JoinToken,
+    Invite {
+        invite_id: String,
+        auto_approve: bool,
+    },
+}
+
+impl JoinCredential {
+    pub(super) fn auto_approve(&self) -> bool {
+        matches!(
+            self,
+            Self::Invite {
+                auto_approve: true,
+                ..
+            }
+        )
+    }
+
+    /// When the credential stops being redeemable. An approval has to land before this, since
+    /// the credential is resolved again once the desktop decides.
+    pub(super) fn expires_at_ms(&self, session: &SessionRecord) -> Option<i64> {
+        match self {
+            Self::JoinToken => Some(session.join_token_expires_at_ms),
+            Self::Invite { invite_id, .. } => session
+                .invites
+                .get(invite_id)
+                .map(|invite| invite.expires_at_ms),
+        }
+    }
+}
+
+/// Matches the token presented at `pair/join` against the session join token and its invites.
+pub(super) fn resolve_join_credential(
+    session: &SessionRecord,
+    token: &str,
+    now: i64,
+) -> Result<JoinCredential, (StatusCode, &'static str, &'static str)> {
+    if safe_token_equals(&session.join_token, token) {
+        if now >= session.join_token_expires_at_ms {
+            return Err((
+                StatusCode::GONE,
+                "join_token_expired",
+                "Join token has expired.",
+            ));
+        }
+        if session.join_token_used_at_ms.is_some() {
+            return Err((
+                StatusCode::CONFLICT,
+                "join_token_already_used",
+                "Join token has already been redeemed. Start a new session from desktop.",
+            ));
+        }
+        return Ok(JoinCredential::JoinToken);
+    }
+
+    let Some((invite_id, invite)) = session
+        .invites
+        .iter()
+        .find(|(_, invite)| safe_token_equals(&invite.token, token))
+    else {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "invalid_join_token",
+            "Join token is invalid.",
+        ));
+    };
+    if now >= invite.expires_at_ms {
+        return Err((StatusCode::GONE, "invite_expired", "Invite has expired."));
+    }
+    if invite.redemptions >= invite.max_redemptions {
+        return Err((
+            StatusCode::CONFLICT,
+            "invite_exhausted",
+            "Invite has no redemptions left.",
+        ));
+    }
+    Ok(JoinCredential::Invite {
+        invite_id: invite_id.clone(),
+        auto_approve: invite.auto_approve,
+    })
+}
+
+/// Marks a credential as used and returns the scopes it pre-assigns to the new device.
+pub(super) fn redeem_join_credential(
+    session: &mut SessionRecord,
+    credential: &JoinCredential,
+    now: i64,
+) -> Vec<String> {
+    match credential {
+        JoinCredential::JoinToken => {
+            session.join_token_used_at_ms = Some(now);
+            Vec::new()
+        }
+        JoinCredential::Invite { invite_id, .. } => {
+            let Some(invite) = session.invites.get_mut(invite_id) else {
+                return Vec::new();
+            };
+            invite.redemptions = invite.redemptions.saturating_add(1);
+            invite.scopes.clone()
+        }
+    }
+}
+
+pub(super) fn prune_expired_invites(session: &mut SessionRecord, now: i64) {
+    session
+        .invites
+        .retain(|_, invite| now < invite.expires_at_ms);
+}
+
+pub(super) fn invite_summary(invite_id: &str, invite: &SessionInvite) -> InviteSummary {
+    InviteSummary {
+        invite_id: invite_id.to_string(),
+        max_redemptions: invite.max_redemptions,
+        redemptions: invite.redemptions,
+        created_at: iso_from_millis(invite.created_at_ms),
+        expires_at: iso_from_millis(invite.expires_at_ms),
+        scopes: invite.scopes.clone(),
+        auto_approve: invite.auto_approve,
+    }
+}
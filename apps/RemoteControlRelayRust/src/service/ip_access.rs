@@ -0,0 +1,105 @@
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum RouteGroup {
+    Pairing,
+    WebSocket,
+    Admin,
+}
+
+impl RouteGroup {
+    pub(super) fn as_str(self) -> &'static str {
+        match self {
+            Self::Pairing => "pairing",
+            Self::WebSocket => "ws",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+pub(super) fn ip_access_permits(
+    state: &SharedRelayState,
+    group: RouteGroup,
+    client_ip: &str,
+) -> bool {
+    let rules = state
+        .ip_access_rules
+        .read()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let list = match group {
+        RouteGroup::Pairing => &rules.pairing,
+        RouteGroup::WebSocket => &rules.websocket,
+        RouteGroup::Admin => &rules.admin,
+    };
+    list.permits(client_ip.parse::<std::net::IpAddr>().ok())
+}
+
+/// Route-group middleware that rejects client addresses outside the configured CIDR lists
+/// before the handler runs.
+pub(super) async fn enforce_ip_access(
+    State((state, group)): State<(SharedRelayState, RouteGroup)>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let client_ip = client_ip(&state.config, request.headers(), addr);
+    if ip_access_permits(&state, group, &client_ip) {
+        return next.run(request).await;
+    }
+
+    {
+        let mut relay = state.inner.lock().await;
+        *relay
+            .ip_access_rejections
+            .entry(group.as_str().to_string())
+            .or_insert(0) += 1;
+    }
+    warn!(
+        "[relay-rs] ip_access_rejected group={} remote_ip={client_ip}",
+        group.as_str()
+    );
+    error_response(
+        StatusCode::FORBIDDEN,
+        "ip_not_allowed",
+        "Client address is not allowed to use this endpoint.",
+    )
+}
+
+/// Re-reads `IP_ACCESS_RULES_FILE` periodically. A file that fails to load keeps the previous
+/// rules in place.
+pub(super) fn start_ip_access_rules_reloader(state: SharedRelayState) {
+    if state.config.ip_access_rules_file.is_none() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let reload_interval =
+            Duration::from_millis(state.config.ip_access_rules_reload_interval_ms);
+        loop {
+            sleep(reload_interval).await;
+            let rules = match state.config.load_ip_access_rules() {
+                Ok(rules) => rules,
+                Err(error) => {
+                    warn!("[relay-rs] failed to reload IP access rules: {error}");
+                    continue;
+                }
+            };
+            let mut current = state
+                .ip_access_rules
+                .write()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            if *current != rules {
+                info!(
+                    "[relay-rs] reloaded IP access rules pairing={}/{} ws={}/{} admin={}/{}",
+                    rules.pairing.allow.len(),
+                    rules.pairing.deny.len(),
+                    rules.websocket.allow.len(),
+                    rules.websocket.deny.len(),
+                    rules.admin.allow.len(),
+                    rules.admin.deny.len()
+                );
+                *current = rules;
+            }
+        }
+    });
+}
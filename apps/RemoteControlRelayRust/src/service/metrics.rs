@@ -12,6 +12,10 @@ pub(super) async fn healthz(State(state): State<SharedRelayState>) -> impl IntoR
         device_tokens: stats.device_tokens,
         bus_subscriptions: stats.bus_subscriptions,
         cross_instance_bus_enabled: state.cross_instance_bus.is_some(),
+        cross_instance_bus_degraded: state
+            .cross_instance_bus
+            .as_ref()
+            .is_some_and(|bus| bus.transport.is_degraded()),
         redis_persistence_enabled: state.persistence.is_some(),
         now: Utc::now().to_rfc3339(),
     };
@@ -49,6 +53,20 @@ pub(super) async fn metricsz(State(state): State<SharedRelayState>) -> impl Into
         ws_auth_successes: stats.ws_auth_successes,
         ws_auth_failures: stats.ws_auth_failures,
         ws_auth_failure_reasons: stats.ws_auth_failure_reasons.clone(),
+        envelope_signature_failures_by_key_id: stats.envelope_signature_failures.clone(),
+        webhook_delivery_attempts: relay.webhook_stats.attempts,
+        webhook_deliveries_succeeded: relay.webhook_stats.delivered,
+        webhook_deliveries_dead_lettered: relay.webhook_stats.dead_lettered,
+        webhook_dead_letters_by_event: relay.webhook_stats.dead_letters_by_event.clone(),
+        penalty_box_blocks_issued: relay.penalty_blocks_issued,
+        penalty_box_rejections: relay.penalty_box_rejections,
+        ip_access_rejections_by_route_group: relay.ip_access_rejections.clone(),
+        session_bytes_in: stats.session_bytes_in,
+        session_bytes_out: stats.session_bytes_out,
+        byte_quota_drops: relay.byte_quota_drops,
+        cross_instance_publish_drops: state.cross_instance_bus.as_ref().map_or(0, |bus| {
+            bus.publish_drops.load(std::sync::atomic::Ordering::Relaxed)
+        }),
         cross_instance_bus_enabled: state.cross_instance_bus.is_some(),
         redis_persistence_enabled: state.persistence.is_some(),
         now: Utc::now().to_rfc3339(),
@@ -81,6 +99,9 @@ pub(super) struct RelayRuntimeStats {
     pub(super) ws_auth_successes: u64,
     pub(super) ws_auth_failures: u64,
     pub(super) ws_auth_failure_reasons: HashMap<String, u64>,
+    pub(super) envelope_signature_failures: HashMap<String, u64>,
+    pub(super) session_bytes_in: u64,
+    pub(super) session_bytes_out: u64,
 }
 
 pub(super) fn relay_runtime_stats(relay: &RelayState) -> RelayRuntimeStats {
@@ -121,6 +142,16 @@ pub(super) fn relay_runtime_stats(relay: &RelayState) -> RelayRuntimeStats {
     let ws_auth_failures = relay
         .ws_auth_attempts
         .saturating_sub(relay.ws_auth_successes);
+    let (session_bytes_in, session_bytes_out) =
+        relay
+            .sessions
+            .values()
+            .fold((0_u64, 0_u64), |(bytes_in, bytes_out), session| {
+                (
+                    bytes_in.saturating_add(session.traffic.totals.bytes_in),
+                    bytes_out.saturating_add(session.traffic.totals.bytes_out),
+                )
+            });
     RelayRuntimeStats {
         sessions_with_desktop,
         sessions_with_mobile,
@@ -146,5 +177,8 @@ pub(super) fn relay_runtime_stats(relay: &RelayState) -> RelayRuntimeStats {
         ws_auth_successes: relay.ws_auth_successes,
         ws_auth_failures,
         ws_auth_failure_reasons: relay.ws_auth_failure_reasons.clone(),
+        envelope_signature_failures: relay.envelope_signature_failures.clone(),
+        session_bytes_in,
+        session_bytes_out,
     }
 }
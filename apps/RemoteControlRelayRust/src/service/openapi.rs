@@ -0,0 +1,280 @@
+use super::*;
+
+use schemars::generate::{SchemaGenerator, SchemaSettings};
+use schemars::{JsonSchema, Schema};
+
+const OPENAPI_VERSION: &str = "3.1.0";
+const ADMIN_SECURITY_SCHEME: &str = "adminToken";
+
+fn schema_generator(definitions_path: &'static str) -> SchemaGenerator {
+    SchemaSettings::draft2020_12()
+        .with(|settings| settings.definitions_path = definitions_path.into())
+        .into_generator()
+}
+
+fn json_content(schema: Schema) -> Value {
+    json!({ "application/json": { "schema": schema } })
+}
+
+fn error_responses(generator: &mut SchemaGenerator) -> Value {
+    json!({
+        "description": "Request rejected. `error` carries a stable machine-readable code.",
+        "content": json_content(generator.subschema_for::<ErrorResponse>()),
+    })
+}
+
+fn request_body<Request: JsonSchema>(generator: &mut SchemaGenerator) -> Value {
+    json!({
+        "required": true,
+        "content": json_content(generator.subschema_for::<Request>()),
+    })
+}
+
+fn post_operation<Request: JsonSchema, Response: JsonSchema>(
+    generator: &mut SchemaGenerator,
+    operation_id: &str,
+    summary: &str,
+) -> Value {
+    json!({
+        "operationId": operation_id,
+        "summary": summary,
+        "requestBody": request_body::<Request>(generator),
+        "responses": {
+            "200": {
+                "description": "OK",
+                "content": json_content(generator.subschema_for::<Response>()),
+            },
+            "default": error_responses(generator),
+        },
+    })
+}
+
+fn get_operation<Response: JsonSchema>(
+    generator: &mut SchemaGenerator,
+    operation_id: &str,
+    summary: &str,
+) -> Value {
+    json!({
+        "operationId": operation_id,
+        "summary": summary,
+        "responses": {
+            "200": {
+                "description": "OK",
+                "content": json_content(generator.subschema_for::<Response>()),
+            },
+            "default": error_responses(generator),
+        },
+    })
+}
+
+/// OpenAPI 3.1 description of the HTTP routes, with component schemas derived from the request
+/// and response types in `model`. Served at `/openapi.json`.
+pub fn openapi_document() -> Value {
+    let mut generator = schema_generator("/components/schemas");
+
+    let mut pair_join = post_operation::<PairJoinRequest, PairJoinResponse>(
+        &mut generator,
+        "pairJoin",
+        "Redeem a join token or invite and wait for the desktop to approve the device.",
+    );
+    pair_join["responses"]["202"] = json!({
+        "description": "Async join accepted. Poll `/pair/status` with the returned statusToken.",
+        "content": json_content(generator.subschema_for::<PairJoinPendingResponse>()),
+    });
+
+    let pair_qr = json!({
+        "operationId": "pairQr",
+        "summary": "Render the pairing URI as an SVG or PNG QR code.",
+        "requestBody": request_body::<PairQrRequest>(&mut generator),
+        "responses": {
+            "200": {
+                "description": "QR code image.",
+                "content": {
+                    "image/svg+xml": { "schema": { "type": "string" } },
+                    "image/png": { "schema": { "type": "string", "contentMediaType": "image/png" } },
+                },
+            },
+            "default": error_responses(&mut generator),
+        },
+    });
+
+    let mut penalty_box_list = get_operation::<PenaltyBoxListResponse>(
+        &mut generator,
+        "penaltyBoxList",
+        "List active penalty box blocks.",
+    );
+    penalty_box_list["security"] = json!([{ ADMIN_SECURITY_SCHEME: [] }]);
+    let mut penalty_box_clear = post_operation::<PenaltyBoxClearRequest, PenaltyBoxClearResponse>(
+        &mut generator,
+        "penaltyBoxClear",
+        "Lift a penalty box block.",
+    );
+    penalty_box_clear["security"] = json!([{ ADMIN_SECURITY_SCHEME: [] }]);
+
+    let paths = json!({
+        "/pair/start": { "post": post_operation::<PairStartRequest, PairStartResponse>(
+            &mut generator,
+            "pairStart",
+            "Register a desktop pairing session.",
+        ) },
+        "/pair/join": { "post": pair_join },
+        "/pair/status": { "post": post_operation::<PairStatusRequest, PairStatusResponse>(
+            &mut generator,
+            "pairStatus",
+            "Read or long-poll the outcome of an async pair join.",
+        ) },
+        "/pair/refresh": { "post": post_operation::<PairRefreshRequest, PairRefreshResponse>(
+            &mut generator,
+            "pairRefresh",
+            "Rotate the session join token.",
+        ) },
+        "/pair/qr": { "post": pair_qr },
+        "/pair/stop": { "post": post_operation::<PairStopRequest, PairStopResponse>(
+            &mut generator,
+            "pairStop",
+            "Close a session and disconnect its sockets.",
+        ) },
+        "/devices/list": { "post": post_operation::<DevicesListRequest, DevicesListResponse>(
+            &mut generator,
+            "devicesList",
+            "List the devices paired with a session.",
+        ) },
+        "/devices/history": { "post": post_operation::<DeviceHistoryRequest, DeviceHistoryResponse>(
+            &mut generator,
+            "deviceHistory",
+            "Read the recent connection history of a device.",
+        ) },
+        "/devices/rename": { "post": post_operation::<DeviceRenameRequest, DeviceRenameResponse>(
+            &mut generator,
+            "deviceRename",
+            "Rename a paired device.",
+        ) },
+        "/devices/revoke": { "post": post_operation::<DeviceRevokeRequest, DeviceRevokeResponse>(
+            &mut generator,
+            "deviceRevoke",
+            "Revoke a paired device and disconnect it.",
+        ) },
+        "/invites/create": { "post": post_operation::<InviteCreateRequest, InviteCreateResponse>(
+            &mut generator,
+            "inviteCreate",
+            "Mint a multi-use session invite.",
+        ) },
+        "/invites/list": { "post": post_operation::<InvitesListRequest, InvitesListResponse>(
+            &mut generator,
+            "invitesList",
+            "List the live invites of a session.",
+        ) },
+        "/invites/revoke": { "post": post_operation::<InviteRevokeRequest, InviteRevokeResponse>(
+            &mut generator,
+            "inviteRevoke",
+            "Revoke a session invite.",
+        ) },
+        "/session/transfer/issue": { "post": post_operation::<
+            SessionTransferIssueRequest,
+            SessionTransferIssueResponse,
+        >(
+            &mut generator,
+            "sessionTransferIssue",
+            "Issue a one-time token that moves the desktop role to another host.",
+        ) },
+        "/session/transfer/redeem": { "post": post_operation::<
+            SessionTransferRedeemRequest,
+            SessionTransferRedeemResponse,
+        >(
+            &mut generator,
+            "sessionTransferRedeem",
+            "Redeem a session transfer token for a new desktop session token.",
+        ) },
+        "/admin/penalty-box": { "get": penalty_box_list },
+        "/admin/penalty-box/clear": { "post": penalty_box_clear },
+        "/healthz": { "get": get_operation::<HealthResponse>(
+            &mut generator,
+            "healthz",
+            "Liveness and coarse relay counters.",
+        ) },
+        "/metricsz": { "get": get_operation::<RelayMetricsResponse>(
+            &mut generator,
+            "metricsz",
+            "Relay counters for scraping.",
+        ) },
+        "/ws": { "get": {
+            "operationId": "webSocket",
+            "summary": "Open the relay WebSocket. Message schemas are published at `/schemas`.",
+            "parameters": [{
+                "name": "token",
+                "in": "query",
+                "required": false,
+                "description": "Session token. May instead be sent in the first `relay.auth` frame.",
+                "schema": { "type": "string" },
+            }],
+            "responses": {
+                "101": { "description": "Switching protocols." },
+                "default": error_responses(&mut generator),
+            },
+        } },
+        "/openapi.json": { "get": {
+            "operationId": "openapi",
+            "summary": "This document.",
+            "responses": { "200": { "description": "OpenAPI document." } },
+        } },
+        "/schemas": { "get": {
+            "operationId": "messageSchemas",
+            "summary": "JSON Schemas for every WebSocket message type.",
+            "responses": { "200": { "description": "Message schemas keyed by direction and type." } },
+        } },
+    });
+
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {
+            "title": "Remote Control Relay",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": generator.take_definitions(true),
+            "securitySchemes": {
+                ADMIN_SECURITY_SCHEME: { "type": "http", "scheme": "bearer" },
+            },
+        },
+    })
+}
+
+fn message_schema<T: JsonSchema>() -> Value {
+    schema_generator("/$defs")
+        .into_root_schema_for::<T>()
+        .to_value()
+}
+
+/// Standalone JSON Schemas for the WebSocket frames, split by who sends them. Desktop envelopes
+/// are forwarded to mobiles unchanged and are not described here. Served at `/schemas`.
+pub fn message_schemas() -> Value {
+    json!({
+        "schemaVersions": SUPPORTED_SCHEMA_VERSIONS,
+        "inbound": {
+            "relay.auth": message_schema::<RelayAuthMessage>(),
+            "relay.pair_decision": message_schema::<RelayPairDecision>(),
+            "relay.snapshot_request": message_schema::<RelaySnapshotRequest>(),
+            "command.v2": message_schema::<CommandEnvelope>(),
+            "command.v3": message_schema::<CommandEnvelopeV3>(),
+        },
+        "outbound": {
+            "auth_ok": message_schema::<RelayAuthOk>(),
+            "relay.pair_request": message_schema::<RelayPairRequest>(),
+            "relay.pair_result": message_schema::<RelayPairResult>(),
+            "relay.device_evicted": message_schema::<RelayDeviceEvicted>(),
+            "relay.device_count": message_schema::<RelayDeviceCount>(),
+            "relay.desktop_status": message_schema::<RelayDesktopStatus>(),
+            "relay.error": message_schema::<RelayError>(),
+            "disconnect": message_schema::<RelayDisconnect>(),
+        },
+    })
+}
+
+pub(super) async fn openapi_json() -> impl IntoResponse {
+    Json(openapi_document())
+}
+
+pub(super) async fn schemas_json() -> impl IntoResponse {
+    Json(message_schemas())
+}
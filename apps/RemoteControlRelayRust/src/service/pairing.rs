@@ -0,0 +1,114 @@
+use super::*;
+
+const PAIRING_QR_MODULE_PIXELS: usize = 8;
+const PAIRING_QR_QUIET_ZONE_MODULES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum PairingQrFormat {
+    Svg,
+    Png,
+}
+
+impl PairingQrFormat {
+    pub(super) fn parse(raw: Option<&str>) -> Option<Self> {
+        match raw.map(str::trim).unwrap_or("svg") {
+            "svg" => Some(Self::Svg),
+            "png" => Some(Self::Png),
+            _ => None,
+        }
+    }
+
+    pub(super) fn content_type(self) -> &'static str {
+        match self {
+            Self::Svg => "image/svg+xml",
+            Self::Png => "image/png",
+        }
+    }
+}
+
+/// HTTP(S) origin of the relay a session's websocket URL points at, which is what clients pass
+/// back to `/pair/join`.
+fn relay_base_url(relay_web_socket_url: &str) -> Option<String> {
+    let mut url = Url::parse(relay_web_socket_url).ok()?;
+    let scheme = match url.scheme() {
+        "wss" | "https" => "https",
+        "ws" | "http" => "http",
+        _ => return None,
+    };
+    url.set_scheme(scheme).ok()?;
+    url.set_path("");
+    url.set_query(None);
+    url.set_fragment(None);
+    Some(url.as_str().trim_end_matches('/').to_string())
+}
+
+/// Canonical pairing link: `<PAIRING_JOIN_BASE_URL>#sid=<id>&jt=<token>&relay=<origin>`. The join
+/// token lives in the fragment so browsers never send it to the page's server.
+pub(super) fn pairing_uri(
+    config: &RelayConfig,
+    session_id: &str,
+    join_token: &str,
+    relay_web_socket_url: &str,
+) -> String {
+    let mut fragment = url::form_urlencoded::Serializer::new(String::new());
+    fragment.append_pair("sid", session_id);
+    fragment.append_pair("jt", join_token);
+    if let Some(relay) = relay_base_url(relay_web_socket_url) {
+        fragment.append_pair("relay", &relay);
+    }
+    let fragment = fragment.finish();
+
+    match Url::parse(&config.pairing_join_base_url) {
+        Ok(mut url) => {
+            url.set_query(None);
+            url.set_fragment(Some(&fragment));
+            url.to_string()
+        }
+        Err(_) => format!("{}#{fragment}", config.pairing_join_base_url),
+    }
+}
+
+pub(super) fn render_pairing_qr(uri: &str, format: PairingQrFormat) -> Result<Vec<u8>, String> {
+    let code = qrcode::QrCode::new(uri.as_bytes()).map_err(|error| error.to_string())?;
+    match format {
+        PairingQrFormat::Svg => Ok(code
+            .render::<qrcode::render::svg::Color>()
+            .min_dimensions(256, 256)
+            .build()
+            .into_bytes()),
+        PairingQrFormat::Png => render_pairing_qr_png(&code),
+    }
+}
+
+fn render_pairing_qr_png(code: &qrcode::QrCode) -> Result<Vec<u8>, String> {
+    let modules = code.width();
+    let colors = code.to_colors();
+    let side_modules = modules + PAIRING_QR_QUIET_ZONE_MODULES * 2;
+    let side = side_modules * PAIRING_QR_MODULE_PIXELS;
+
+    let mut pixels = vec![0xff_u8; side * side];
+    for (index, color) in colors.iter().enumerate() {
+        if *color != qrcode::Color::Dark {
+            continue;
+        }
+        let module_x = index % modules + PAIRING_QR_QUIET_ZONE_MODULES;
+        let module_y = index / modules + PAIRING_QR_QUIET_ZONE_MODULES;
+        for row in 0..PAIRING_QR_MODULE_PIXELS {
+            let start = (module_y * PAIRING_QR_MODULE_PIXELS + row) * side
+                + module_x * PAIRING_QR_MODULE_PIXELS;
+            pixels[start..start + PAIRING_QR_MODULE_PIXELS].fill(0);
+        }
+    }
+
+    let side = u32::try_from(side).map_err(|error| error.to_string())?;
+    let mut encoded = Vec::new();
+    let mut encoder = png::Encoder::new(&mut encoded, side, side);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|error| error.to_string())?;
+    writer
+        .write_image_data(&pixels)
+        .map_err(|error| error.to_string())?;
+    writer.finish().map_err(|error| error.to_string())?;
+    Ok(encoded)
+}
@@ -0,0 +1,416 @@
+use super::*;
+use std::net::IpAddr;
+
+pub(super) const PENALTY_STRIKE_TTL_MS: i64 = 86_400_000;
+
+/// Shared penalty box in Redis. Failure counters, strike counts and blocks live under
+/// `<prefix>:penalty:v1` so every instance enforces the same blocks.
+#[derive(Clone)]
+pub(super) struct RelayPenaltyStore {
+    pub(super) redis_client: redis::Client,
+    pub(super) key_prefix: String,
+}
+
+/// Per-key state for the in-process penalty box used without Redis.
+#[derive(Clone, Copy, Default)]
+pub(super) struct PenaltyRecord {
+    pub(super) failures: u64,
+    pub(super) window_started_at_ms: i64,
+    pub(super) strikes: u32,
+    pub(super) blocked_until_ms: i64,
+    pub(super) last_failure_at_ms: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct PenaltyBlock {
+    pub(super) key: String,
+    pub(super) blocked_until_ms: i64,
+}
+
+pub(super) fn build_penalty_store(config: &RelayConfig) -> Option<RelayPenaltyStore> {
+    if config.penalty_box_failure_threshold == 0 {
+        return None;
+    }
+    let redis_url = config.redis_url.as_ref()?;
+    let redis_client = match redis::Client::open(redis_url.as_str()) {
+        Ok(client) => client,
+        Err(error) => {
+            warn!("[relay-rs] invalid REDIS_URL; penalty box is local to this instance: {error}");
+            return None;
+        }
+    };
+
+    Some(RelayPenaltyStore {
+        redis_client,
+        key_prefix: format!("{}:penalty:v1", config.redis_key_prefix),
+    })
+}
+
+/// IPv4 addresses are grouped by /24 and IPv6 addresses by /64, the smallest ranges a single
+/// client can usually rotate through.
+fn subnet_key(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            format!("subnet:{a}.{b}.{c}.0/24")
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            let prefix = std::net::Ipv6Addr::new(
+                segments[0],
+                segments[1],
+                segments[2],
+                segments[3],
+                0,
+                0,
+                0,
+                0,
+            );
+            format!("subnet:{prefix}/64")
+        }
+    }
+}
+
+/// Penalty keys for a client address with the failure threshold that applies to each.
+pub(super) fn penalty_keys(config: &RelayConfig, client_ip: &str) -> Vec<(String, u64)> {
+    let mut keys = vec![(
+        format!("ip:{client_ip}"),
+        config.penalty_box_failure_threshold,
+    )];
+    if config.penalty_box_subnet_failure_threshold > 0 {
+        if let Ok(ip) = client_ip.parse::<IpAddr>() {
+            keys.push((subnet_key(ip), config.penalty_box_subnet_failure_threshold));
+        }
+    }
+    keys
+}
+
+/// Block length doubles with every strike, from `PENALTY_BOX_BASE_BLOCK_MS` up to
+/// `PENALTY_BOX_MAX_BLOCK_MS`.
+pub(super) fn penalty_block_duration_ms(config: &RelayConfig, strikes: u32) -> i64 {
+    let exponent = strikes.saturating_sub(1).min(32);
+    let duration = config
+        .penalty_box_base_block_ms
+        .saturating_mul(1_u64 << exponent)
+        .min(config.penalty_box_max_block_ms);
+    i64::try_from(duration).unwrap_or(i64::MAX)
+}
+
+/// Counts one failure against a key in the local penalty box. Returns the block expiry when
+/// this failure crosses the threshold.
+pub(super) fn record_local_penalty_failure(
+    relay: &mut RelayState,
+    config: &RelayConfig,
+    key: &str,
+    threshold: u64,
+    now: i64,
+) -> Option<(i64, u32)> {
+    let window_ms = i64::try_from(config.penalty_box_window_ms).unwrap_or(i64::MAX);
+    let record = relay.penalty_records.entry(key.to_string()).or_default();
+    if now - record.last_failure_at_ms >= PENALTY_STRIKE_TTL_MS {
+        record.strikes = 0;
+    }
+    if now - record.window_started_at_ms >= window_ms {
+        record.failures = 0;
+        record.window_started_at_ms = now;
+    }
+    record.failures += 1;
+    record.last_failure_at_ms = now;
+    if record.failures < threshold {
+        return None;
+    }
+
+    record.failures = 0;
+    record.strikes = record.strikes.saturating_add(1);
+    record.blocked_until_ms = now.saturating_add(penalty_block_duration_ms(config, record.strikes));
+    Some((record.blocked_until_ms, record.strikes))
+}
+
+pub(super) fn prune_penalty_records(relay: &mut RelayState, config: &RelayConfig, now: i64) {
+    let window_ms = i64::try_from(config.penalty_box_window_ms).unwrap_or(i64::MAX);
+    relay.penalty_records.retain(|_, record| {
+        record.blocked_until_ms > now
+            || now - record.last_failure_at_ms < window_ms.max(PENALTY_STRIKE_TTL_MS)
+    });
+}
+
+impl RelayPenaltyStore {
+    fn failures_key(&self, key: &str) -> String {
+        format!("{}:failures:{key}", self.key_prefix)
+    }
+
+    fn strikes_key(&self, key: &str) -> String {
+        format!("{}:strikes:{key}", self.key_prefix)
+    }
+
+    fn block_key(&self, key: &str) -> String {
+        format!("{}:block:{key}", self.key_prefix)
+    }
+
+    fn blocks_index_key(&self) -> String {
+        format!("{}:blocks", self.key_prefix)
+    }
+
+    async fn connection(&self) -> Result<redis::aio::MultiplexedConnection, String> {
+        self.redis_client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|error| format!("redis connection failed: {error}"))
+    }
+
+    /// Counts a failure and, once the threshold is reached inside the window, blocks the key for
+    /// a duration that doubles with each strike. Returns the block expiry and strike count.
+    async fn record_failure(
+        &self,
+        config: &RelayConfig,
+        key: &str,
+        threshold: u64,
+        now: i64,
+    ) -> Result<Option<(i64, u32)>, String> {
+        let mut connection = self.connection().await?;
+        let script = redis::Script::new(
+            r#"
+            local failures = redis.call("INCR", KEYS[1])
+            if failures == 1 then
+                redis.call("PEXPIRE", KEYS[1], ARGV[2])
+            end
+            if failures < tonumber(ARGV[1]) then
+                return {0, 0}
+            end
+            redis.call("DEL", KEYS[1])
+            local strikes = redis.call("INCR", KEYS[2])
+            redis.call("PEXPIRE", KEYS[2], ARGV[6])
+            local duration = tonumber(ARGV[3]) * math.pow(2, math.min(strikes - 1, 32))
+            duration = math.floor(math.min(duration, tonumber(ARGV[4])))
+            local blocked_until = tonumber(ARGV[5]) + duration
+            redis.call("SET", KEYS[3], string.format("%d", blocked_until), "PX", duration)
+            redis.call("ZADD", KEYS[4], blocked_until, ARGV[7])
+            return {blocked_until, strikes}
+            "#,
+        );
+        let (blocked_until_ms, strikes): (i64, u32) = script
+            .key(self.failures_key(key))
+            .key(self.strikes_key(key))
+            .key(self.block_key(key))
+            .key(self.blocks_index_key())
+            .arg(threshold)
+            .arg(config.penalty_box_window_ms.max(1))
+            .arg(config.penalty_box_base_block_ms.max(1))
+            .arg(config.penalty_box_max_block_ms.max(1))
+            .arg(now)
+            .arg(PENALTY_STRIKE_TTL_MS)
+            .arg(key)
+            .invoke_async(&mut connection)
+            .await
+            .map_err(|error| format!("redis penalty script failed: {error}"))?;
+        Ok((blocked_until_ms > 0).then_some((blocked_until_ms, strikes)))
+    }
+
+    async fn blocked_until(&self, keys: &[String]) -> Result<Option<i64>, String> {
+        let mut connection = self.connection().await?;
+        let block_keys = keys
+            .iter()
+            .map(|key| self.block_key(key))
+            .collect::<Vec<_>>();
+        let values: Vec<Option<i64>> = redis::cmd("MGET")
+            .arg(block_keys)
+            .query_async(&mut connection)
+            .await
+            .map_err(|error| format!("redis get penalty blocks failed: {error}"))?;
+        Ok(values.into_iter().flatten().max())
+    }
+
+    async fn list_blocks(&self, now: i64) -> Result<Vec<PenaltyBlock>, String> {
+        let mut connection = self.connection().await?;
+        let index_key = self.blocks_index_key();
+        let (_, entries): ((), Vec<(String, f64)>) = redis::pipe()
+            .atomic()
+            .cmd("ZREMRANGEBYSCORE")
+            .arg(&index_key)
+            .arg("-inf")
+            .arg(now)
+            .ignore()
+            .cmd("ZRANGEBYSCORE")
+            .arg(&index_key)
+            .arg(format!("({now}"))
+            .arg("+inf")
+            .arg("WITHSCORES")
+            .query_async(&mut connection)
+            .await
+            .map_err(|error| format!("redis list penalty blocks failed: {error}"))?;
+        Ok(entries
+            .into_iter()
+            .map(|(key, blocked_until_ms)| PenaltyBlock {
+                key,
+                blocked_until_ms: blocked_until_ms as i64,
+            })
+            .collect())
+    }
+
+    async fn clear(&self, key: &str) -> Result<bool, String> {
+        let mut connection = self.connection().await?;
+        let (removed,): (u64,) = redis::pipe()
+            .atomic()
+            .del(self.block_key(key))
+            .del(self.strikes_key(key))
+            .ignore()
+            .del(self.failures_key(key))
+            .ignore()
+            .zrem(self.blocks_index_key(), key)
+            .ignore()
+            .query_async(&mut connection)
+            .await
+            .map_err(|error| format!("redis clear penalty block failed: {error}"))?;
+        Ok(removed > 0)
+    }
+}
+
+/// Returns when the client's IP or subnet stops being blocked, or `None` when it is not
+/// blocked. Redis errors fail open so an outage cannot lock everyone out.
+pub(super) async fn penalty_blocked_until(
+    state: &SharedRelayState,
+    client_ip: &str,
+) -> Option<i64> {
+    if state.config.penalty_box_failure_threshold == 0 {
+        return None;
+    }
+    let now = now_ms();
+    let keys = penalty_keys(&state.config, client_ip)
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect::<Vec<_>>();
+
+    if let Some(store) = &state.penalty_store {
+        return match store.blocked_until(&keys).await {
+            Ok(blocked_until_ms) => blocked_until_ms.filter(|until| *until > now),
+            Err(error) => {
+                warn!("[relay-rs] penalty box lookup failed remote_ip={client_ip}: {error}");
+                None
+            }
+        };
+    }
+
+    let relay = state.inner.lock().await;
+    keys.iter()
+        .filter_map(|key| relay.penalty_records.get(key))
+        .map(|record| record.blocked_until_ms)
+        .filter(|until| *until > now)
+        .max()
+}
+
+/// Counts an authentication or pairing failure against the client's IP and subnet.
+pub(super) async fn record_penalty_failure(
+    state: &SharedRelayState,
+    client_ip: &str,
+    source: &str,
+) {
+    if state.config.penalty_box_failure_threshold == 0 {
+        return;
+    }
+    let now = now_ms();
+    for (key, threshold) in penalty_keys(&state.config, client_ip) {
+        let blocked = match &state.penalty_store {
+            Some(store) => match store
+                .record_failure(&state.config, &key, threshold, now)
+                .await
+            {
+                Ok(blocked) => blocked,
+                Err(error) => {
+                    warn!("[relay-rs] penalty box update failed key={key}: {error}");
+                    None
+                }
+            },
+            None => {
+                let mut relay = state.inner.lock().await;
+                record_local_penalty_failure(&mut relay, &state.config, &key, threshold, now)
+            }
+        };
+
+        if let Some((blocked_until_ms, strikes)) = blocked {
+            {
+                let mut relay = state.inner.lock().await;
+                relay.penalty_blocks_issued = relay.penalty_blocks_issued.saturating_add(1);
+            }
+            warn!(
+                "[relay-rs] penalty_box_block key={key} source={source} strikes={strikes} blocked_for_ms={}",
+                blocked_until_ms - now
+            );
+        }
+    }
+}
+
+/// Fire-and-forget variant for handlers that detect the failure while holding the relay lock.
+pub(super) fn spawn_penalty_failure(
+    state: &SharedRelayState,
+    client_ip: &str,
+    source: &'static str,
+) {
+    let state = state.clone();
+    let client_ip = client_ip.to_string();
+    tokio::spawn(async move {
+        record_penalty_failure(&state, &client_ip, source).await;
+    });
+}
+
+/// Rejects a blocked client with `429` and a `Retry-After` matching the remaining block.
+pub(super) async fn penalty_box_rejection(
+    state: &SharedRelayState,
+    client_ip: &str,
+) -> Option<axum::response::Response> {
+    let blocked_until_ms = penalty_blocked_until(state, client_ip).await?;
+    {
+        let mut relay = state.inner.lock().await;
+        relay.penalty_box_rejections = relay.penalty_box_rejections.saturating_add(1);
+    }
+    let retry_after_seconds = ((blocked_until_ms - now_ms()).max(0) + 999) / 1_000;
+    let mut response = error_response(
+        StatusCode::TOO_MANY_REQUESTS,
+        "ip_blocked",
+        "Too many failed attempts from this network. Try again later.",
+    );
+    if let Ok(value) = HeaderValue::from_str(&retry_after_seconds.max(1).to_string()) {
+        response.headers_mut().insert(header::RETRY_AFTER, value);
+    }
+    Some(response)
+}
+
+pub(super) async fn list_penalty_blocks(
+    state: &SharedRelayState,
+) -> Result<Vec<PenaltyBlock>, String> {
+    let now = now_ms();
+    let mut blocks = match &state.penalty_store {
+        Some(store) => store.list_blocks(now).await?,
+        None => {
+            let relay = state.inner.lock().await;
+            relay
+                .penalty_records
+                .iter()
+                .filter(|(_, record)| record.blocked_until_ms > now)
+                .map(|(key, record)| PenaltyBlock {
+                    key: key.clone(),
+                    blocked_until_ms: record.blocked_until_ms,
+                })
+                .collect()
+        }
+    };
+    blocks.sort_by(|left, right| left.key.cmp(&right.key));
+    Ok(blocks)
+}
+
+/// Lifts a block and forgets its strikes and pending failures.
+pub(super) async fn clear_penalty_block(
+    state: &SharedRelayState,
+    key: &str,
+) -> Result<bool, String> {
+    let removed_locally = {
+        let mut relay = state.inner.lock().await;
+        relay
+            .penalty_records
+            .remove(key)
+            .is_some_and(|record| record.blocked_until_ms > now_ms())
+    };
+    match &state.penalty_store {
+        Some(store) => store.clear(key).await,
+        None => Ok(removed_locally),
+    }
+}
@@ -0,0 +1,496 @@
+use super::*;
+
+/// Records which instance holds the desktop socket and which instances hold mobile sockets for
+/// each session, so cross-instance publishes go straight to the owners. Entries expire unless
+/// refreshed by the owning instance's heartbeat.
+#[derive(Clone)]
+pub(super) struct RelayPresenceRegistry {
+    pub(super) redis_client: redis::Client,
+    /// One multiplexed connection shared by every lookup, reopened after a Redis error.
+    pub(super) connection: Arc<Mutex<Option<redis::aio::MultiplexedConnection>>>,
+    pub(super) desktop_key_prefix: String,
+    pub(super) mobile_key_prefix: String,
+    pub(super) ttl_ms: u64,
+    /// Owner lists resolved for cross-instance publishes, keyed by session and target.
+    pub(super) route_cache: Arc<Mutex<HashMap<(String, String), CachedPresenceOwners>>>,
+    pub(super) route_cache_ttl_ms: u64,
+    /// Pub/sub channel carrying `<target>:<sessionID>` whenever an instance gains or gives up a
+    /// claim, so every instance drops its cached owners for that session and target.
+    pub(super) changes_channel: String,
+}
+
+#[derive(Clone)]
+pub(super) struct CachedPresenceOwners {
+    pub(super) owners: Vec<String>,
+    pub(super) expires_at_ms: i64,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct PresenceClaim {
+    pub(super) desktop: bool,
+    pub(super) mobile: bool,
+}
+
+#[derive(Clone)]
+pub(super) struct PresenceRoute {
+    pub(super) session_id: String,
+    pub(super) target: String,
+}
+
+impl PresenceClaim {
+    fn for_session(session: Option<&SessionRecord>) -> Self {
+        session
+            .map(|session| Self {
+                desktop: session.desktop_socket.is_some(),
+                mobile: !session.mobile_sockets.is_empty(),
+            })
+            .unwrap_or_default()
+    }
+
+    fn is_empty(self) -> bool {
+        !self.desktop && !self.mobile
+    }
+}
+
+impl RelayPresenceRegistry {
+    fn desktop_key(&self, session_id: &str) -> String {
+        format!("{}:{session_id}", self.desktop_key_prefix)
+    }
+
+    fn mobile_key(&self, session_id: &str) -> String {
+        format!("{}:{session_id}", self.mobile_key_prefix)
+    }
+
+    async fn connection(&self) -> Result<redis::aio::MultiplexedConnection, String> {
+        let mut shared = self.connection.lock().await;
+        if let Some(connection) = shared.as_ref() {
+            return Ok(connection.clone());
+        }
+        let connection = self
+            .redis_client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|error| format!("redis connection failed: {error}"))?;
+        *shared = Some(connection.clone());
+        Ok(connection)
+    }
+
+    /// Drops the shared connection after a failed command so the next call reconnects.
+    async fn discard_connection<T>(&self, result: Result<T, String>) -> Result<T, String> {
+        if result.is_err() {
+            self.connection.lock().await.take();
+        }
+        result
+    }
+
+    /// Desktop ownership is a plain key with a TTL; the latest instance to claim it wins.
+    /// Mobile presence is a sorted set of instance IDs scored by their expiry time.
+    async fn claim(
+        &self,
+        instance_id: &str,
+        claims: &[(String, PresenceClaim)],
+    ) -> Result<(), String> {
+        if claims.is_empty() {
+            return Ok(());
+        }
+        let mut connection = self.connection().await?;
+        let expires_at_ms = now_ms().saturating_add(i64::try_from(self.ttl_ms).unwrap_or(i64::MAX));
+        let mut pipe = redis::pipe();
+        for (session_id, claim) in claims {
+            if claim.desktop {
+                pipe.cmd("SET")
+                    .arg(self.desktop_key(session_id))
+                    .arg(instance_id)
+                    .arg("PX")
+                    .arg(self.ttl_ms)
+                    .ignore();
+            }
+            if claim.mobile {
+                let mobile_key = self.mobile_key(session_id);
+                pipe.zadd(&mobile_key, instance_id, expires_at_ms)
+                    .ignore()
+                    .zrembyscore(&mobile_key, "-inf", now_ms())
+                    .ignore()
+                    .pexpire(&mobile_key, i64::try_from(self.ttl_ms).unwrap_or(i64::MAX))
+                    .ignore();
+            }
+        }
+        let result = pipe
+            .query_async::<()>(&mut connection)
+            .await
+            .map_err(|error| format!("redis presence claim failed: {error}"));
+        self.discard_connection(result).await
+    }
+
+    async fn release(
+        &self,
+        instance_id: &str,
+        session_id: &str,
+        released: PresenceClaim,
+    ) -> Result<(), String> {
+        let mut connection = self.connection().await?;
+        let script = redis::Script::new(
+            r#"
+            if ARGV[2] == "1" and redis.call("GET", KEYS[1]) == ARGV[1] then
+                redis.call("DEL", KEYS[1])
+            end
+            if ARGV[3] == "1" then
+                redis.call("ZREM", KEYS[2], ARGV[1])
+            end
+            return 1
+            "#,
+        );
+        let result = script
+            .key(self.desktop_key(session_id))
+            .key(self.mobile_key(session_id))
+            .arg(instance_id)
+            .arg(if released.desktop { "1" } else { "0" })
+            .arg(if released.mobile { "1" } else { "0" })
+            .invoke_async::<i32>(&mut connection)
+            .await
+            .map(|_| ())
+            .map_err(|error| format!("redis presence release failed: {error}"));
+        self.discard_connection(result).await
+    }
+
+    /// Instances currently holding sockets for `target` ("desktop" or "mobile") in a session.
+    pub(super) async fn owners(
+        &self,
+        session_id: &str,
+        target: &str,
+    ) -> Result<Vec<String>, String> {
+        let mut connection = self.connection().await?;
+        let result = match target {
+            "desktop" => connection
+                .get::<_, Option<String>>(self.desktop_key(session_id))
+                .await
+                .map(|owner| owner.into_iter().collect())
+                .map_err(|error| format!("redis presence lookup failed: {error}")),
+            "mobile" => connection
+                .zrangebyscore::<_, _, _, Vec<String>>(
+                    self.mobile_key(session_id),
+                    now_ms(),
+                    "+inf",
+                )
+                .await
+                .map_err(|error| format!("redis presence lookup failed: {error}")),
+            _ => Ok(Vec::new()),
+        };
+        self.discard_connection(result).await
+    }
+
+    async fn publish_changes(
+        &self,
+        session_id: &str,
+        changed: PresenceClaim,
+    ) -> Result<(), String> {
+        if changed.is_empty() {
+            return Ok(());
+        }
+        let mut connection = self.connection().await?;
+        let mut pipe = redis::pipe();
+        for (target, is_changed) in [("desktop", changed.desktop), ("mobile", changed.mobile)] {
+            if is_changed {
+                pipe.publish(&self.changes_channel, format!("{target}:{session_id}"))
+                    .ignore();
+            }
+        }
+        let result = pipe
+            .query_async::<()>(&mut connection)
+            .await
+            .map_err(|error| format!("redis presence change publish failed: {error}"));
+        self.discard_connection(result).await
+    }
+
+    /// Drops the cached owners named by a `<target>:<sessionID>` change notification.
+    pub(super) async fn apply_change(&self, payload: &str) {
+        let Some((target, session_id)) = payload.split_once(':') else {
+            return;
+        };
+        self.route_cache
+            .lock()
+            .await
+            .remove(&(session_id.to_string(), target.to_string()));
+    }
+
+    /// Owner lookup for the cross-instance publish path. Non-empty results are reused until a
+    /// change notification for the session and target arrives, or for at most
+    /// `PRESENCE_ROUTE_CACHE_TTL_MS` in case that notification is lost. Empty results are not
+    /// cached, so a newly connected socket is routed to immediately.
+    pub(super) async fn route_owners(
+        &self,
+        session_id: &str,
+        target: &str,
+    ) -> Result<Vec<String>, String> {
+        if self.route_cache_ttl_ms == 0 {
+            return self.owners(session_id, target).await;
+        }
+        let key = (session_id.to_string(), target.to_string());
+        let now = now_ms();
+        {
+            let mut cache = self.route_cache.lock().await;
+            match cache.get(&key) {
+                Some(cached) if cached.expires_at_ms > now => return Ok(cached.owners.clone()),
+                Some(_) => {
+                    cache.remove(&key);
+                }
+                None => {}
+            }
+        }
+
+        let owners = self.owners(session_id, target).await?;
+        if !owners.is_empty() {
+            let mut cache = self.route_cache.lock().await;
+            cache.retain(|_, cached| cached.expires_at_ms > now);
+            cache.insert(
+                key,
+                CachedPresenceOwners {
+                    owners: owners.clone(),
+                    expires_at_ms: now
+                        .saturating_add(i64::try_from(self.route_cache_ttl_ms).unwrap_or(i64::MAX)),
+                },
+            );
+        }
+        Ok(owners)
+    }
+}
+
+pub(super) fn build_presence_registry(config: &RelayConfig) -> Option<RelayPresenceRegistry> {
+    if !config.presence_registry_enabled {
+        return None;
+    }
+    let redis_url = config.redis_url.as_ref()?;
+    let redis_client = match redis::Client::open(redis_url.as_str()) {
+        Ok(client) => client,
+        Err(error) => {
+            warn!("[relay-rs] invalid REDIS_URL; presence registry disabled: {error}");
+            return None;
+        }
+    };
+
+    Some(RelayPresenceRegistry {
+        redis_client,
+        connection: Arc::new(Mutex::new(None)),
+        desktop_key_prefix: format!("{}:presence:desktop:v1", config.redis_key_prefix),
+        mobile_key_prefix: format!("{}:presence:mobile:v1", config.redis_key_prefix),
+        ttl_ms: config.presence_ttl_ms,
+        route_cache: Arc::new(Mutex::new(HashMap::new())),
+        route_cache_ttl_ms: config.presence_route_cache_ttl_ms,
+        changes_channel: format!("{}:presence:changes:v1", config.redis_key_prefix),
+    })
+}
+
+/// Brings this instance's registry entries for a session in line with its local sockets.
+pub(super) async fn sync_session_presence(
+    state: &SharedRelayState,
+    presence: &RelayPresenceRegistry,
+    instance_id: &str,
+    session_id: &str,
+) {
+    let (desired, previous) = {
+        let mut relay = state.inner.lock().await;
+        let desired = PresenceClaim::for_session(relay.sessions.get(session_id));
+        let previous = if desired.is_empty() {
+            relay.presence_claims.remove(session_id)
+        } else {
+            relay
+                .presence_claims
+                .insert(session_id.to_string(), desired)
+        }
+        .unwrap_or_default();
+        (desired, previous)
+    };
+
+    if !desired.is_empty() {
+        if let Err(error) = presence
+            .claim(instance_id, &[(session_id.to_string(), desired)])
+            .await
+        {
+            warn!(
+                "[relay-rs] failed to claim presence session={}: {error}",
+                session_log_id(session_id)
+            );
+        }
+    }
+
+    let released = PresenceClaim {
+        desktop: previous.desktop && !desired.desktop,
+        mobile: previous.mobile && !desired.mobile,
+    };
+    if !released.is_empty() {
+        if let Err(error) = presence.release(instance_id, session_id, released).await {
+            warn!(
+                "[relay-rs] failed to release presence session={}: {error}",
+                session_log_id(session_id)
+            );
+        }
+    }
+
+    let changed = PresenceClaim {
+        desktop: previous.desktop != desired.desktop,
+        mobile: previous.mobile != desired.mobile,
+    };
+    if let Err(error) = presence.publish_changes(session_id, changed).await {
+        warn!(
+            "[relay-rs] failed to publish presence change session={}: {error}",
+            session_log_id(session_id)
+        );
+    }
+}
+
+/// Refreshes every claim held by this instance at a third of the TTL, dropping claims for
+/// sessions whose sockets have gone away without an explicit sync.
+pub(super) fn start_presence_heartbeat(state: SharedRelayState) {
+    let (Some(presence), Some(bus)) = (state.presence.clone(), state.cross_instance_bus.clone())
+    else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let heartbeat_interval = Duration::from_millis((presence.ttl_ms / 3).max(1));
+        loop {
+            sleep(heartbeat_interval).await;
+            let (claims, released) = {
+                let mut relay = state.inner.lock().await;
+                let mut claims = Vec::new();
+                let mut released = Vec::new();
+                let session_ids = relay.presence_claims.keys().cloned().collect::<Vec<_>>();
+                for session_id in session_ids {
+                    let desired = PresenceClaim::for_session(relay.sessions.get(&session_id));
+                    let previous = relay
+                        .presence_claims
+                        .get(&session_id)
+                        .copied()
+                        .unwrap_or_default();
+                    if desired.is_empty() {
+                        relay.presence_claims.remove(&session_id);
+                    } else {
+                        relay.presence_claims.insert(session_id.clone(), desired);
+                        claims.push((session_id.clone(), desired));
+                    }
+                    let dropped = PresenceClaim {
+                        desktop: previous.desktop && !desired.desktop,
+                        mobile: previous.mobile && !desired.mobile,
+                    };
+                    if !dropped.is_empty() {
+                        released.push((session_id, dropped));
+                    }
+                }
+                (claims, released)
+            };
+
+            if let Err(error) = presence.claim(&bus.instance_id, &claims).await {
+                warn!("[relay-rs] presence heartbeat failed: {error}");
+            }
+            for (session_id, dropped) in released {
+                if let Err(error) = presence
+                    .release(&bus.instance_id, &session_id, dropped)
+                    .await
+                {
+                    warn!(
+                        "[relay-rs] failed to release presence session={}: {error}",
+                        session_log_id(&session_id)
+                    );
+                }
+                if let Err(error) = presence.publish_changes(&session_id, dropped).await {
+                    warn!(
+                        "[relay-rs] failed to publish presence change session={}: {error}",
+                        session_log_id(&session_id)
+                    );
+                }
+            }
+        }
+    });
+}
+
+/// Listens for claim changes from every instance and drops the matching cached owners. Changes
+/// published while the subscription was down are lost, so the whole cache is cleared after each
+/// (re)subscribe.
+pub(super) fn start_presence_change_subscription(state: SharedRelayState) {
+    let Some(presence) = state.presence.clone() else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let channel = presence.changes_channel.clone();
+        loop {
+            let mut pubsub = match presence.redis_client.get_async_pubsub().await {
+                Ok(pubsub) => pubsub,
+                Err(error) => {
+                    warn!("[relay-rs] failed to connect redis pubsub for {channel}: {error}");
+                    sleep(BUS_SUBSCRIBE_RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            if let Err(error) = pubsub.subscribe(&channel).await {
+                warn!("[relay-rs] failed to subscribe redis channel {channel}: {error}");
+                sleep(BUS_SUBSCRIBE_RETRY_DELAY).await;
+                continue;
+            }
+            presence.route_cache.lock().await.clear();
+
+            let mut messages = pubsub.into_on_message();
+            while let Some(message) = messages.next().await {
+                let Ok(payload) = message.get_payload::<String>() else {
+                    continue;
+                };
+                presence.apply_change(&payload).await;
+            }
+
+            warn!("[relay-rs] presence change subscription ended; retrying");
+            sleep(BUS_SUBSCRIBE_RETRY_DELAY).await;
+        }
+    });
+}
+
+/// With the registry enabled each instance receives session traffic on a single subject of its
+/// own instead of subscribing per session.
+pub(super) fn start_instance_subscription(state: SharedRelayState) {
+    let (Some(_), Some(bus)) = (state.presence.clone(), state.cross_instance_bus.clone()) else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let subject = nats_instance_subject(&bus.subject_prefix, &bus.instance_id);
+        let consumer_name = format!("{}-instance", bus.instance_id);
+        loop {
+            let mut subscription =
+                match subscribe_cross_instance_subject(&bus, &subject, &consumer_name).await {
+                    Ok(subscription) => subscription,
+                    Err(error) => {
+                        warn!("[relay-rs] failed to subscribe instance subject {subject}: {error}");
+                        sleep(BUS_SUBSCRIBE_RETRY_DELAY).await;
+                        continue;
+                    }
+                };
+
+            while let Some(message) = subscription.next().await {
+                handle_session_envelope(&state, &bus.instance_id, message.payload()).await;
+                message.ack().await;
+            }
+
+            warn!("[relay-rs] instance subscription ended; retrying");
+            sleep(BUS_SUBSCRIBE_RETRY_DELAY).await;
+        }
+    });
+}
+
+pub(super) fn nats_instance_subject(subject_prefix: &str, instance_id: &str) -> String {
+    format!("{subject_prefix}.instance.{instance_id}")
+}
+
+/// Resolves the instance subjects a session publish should go to, leaving out this instance.
+pub(super) async fn presence_route_subjects(
+    presence: &RelayPresenceRegistry,
+    subject_prefix: &str,
+    local_instance_id: &str,
+    route: &PresenceRoute,
+) -> Result<Vec<String>, String> {
+    Ok(presence
+        .route_owners(&route.session_id, &route.target)
+        .await?
+        .into_iter()
+        .filter(|owner| owner != local_instance_id)
+        .map(|owner| nats_instance_subject(subject_prefix, &owner))
+        .collect())
+}
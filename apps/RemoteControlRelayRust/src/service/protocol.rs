@@ -1,224 +1,136 @@
 use super::*;
 
+#[derive(Debug, Clone)]
 pub(super) struct RelayValidationError {
     pub(super) code: &'static str,
     pub(super) message: String,
 }
 
+fn invalid_command(message: impl Into<String>) -> RelayValidationError {
+    RelayValidationError {
+        code: "invalid_command",
+        message: message.into(),
+    }
+}
+
+fn invalid_snapshot_request(message: impl Into<String>) -> RelayValidationError {
+    RelayValidationError {
+        code: "invalid_snapshot_request",
+        message: message.into(),
+    }
+}
+
+/// Checks a frame from a mobile socket. Mobiles may only send snapshot requests and commands;
+/// field names and types were already enforced when the frame was decoded.
 pub(super) fn validate_mobile_payload(
     session: &mut SessionRecord,
-    parsed: Option<&Value>,
+    message: &InboundMessage,
     expected_session_id: &str,
     connection_id: &str,
     device_id: &str,
     config: &RelayConfig,
 ) -> Result<(), RelayValidationError> {
-    let Some(parsed) = parsed else {
-        return Err(RelayValidationError {
+    match message {
+        InboundMessage::SnapshotRequest(request) => {
+            validate_snapshot_request(session, request, expected_session_id, device_id, config)
+        }
+        InboundMessage::Command(envelope) => validate_command(
+            session,
+            envelope,
+            expected_session_id,
+            connection_id,
+            device_id,
+            config,
+        ),
+        InboundMessage::Invalid(error) => Err(error.clone()),
+        InboundMessage::Auth(_) => Err(RelayValidationError {
             code: "invalid_payload",
-            message: "Payload must be valid JSON.".to_string(),
-        });
-    };
-    let Some(parsed_object) = parsed.as_object() else {
+            message: "Socket is already authenticated.".to_string(),
+        }),
+        InboundMessage::PairDecision(_) | InboundMessage::Passthrough { .. } => Err(
+            invalid_command("Only command payloads are accepted from mobile clients."),
+        ),
+    }
+}
+
+fn validate_snapshot_request(
+    session: &mut SessionRecord,
+    request: &RelaySnapshotRequest,
+    expected_session_id: &str,
+    device_id: &str,
+    config: &RelayConfig,
+) -> Result<(), RelayValidationError> {
+    if request.session_id != expected_session_id {
         return Err(RelayValidationError {
-            code: "invalid_payload",
-            message: "Payload must be a JSON object.".to_string(),
+            code: "invalid_session",
+            message: "Snapshot request sessionID does not match authenticated session.".to_string(),
         });
-    };
-
-    if let Some(message_type) = parsed.get("type").and_then(Value::as_str) {
-        if message_type == "relay.snapshot_request" {
-            ensure_only_allowed_fields(
-                parsed_object,
-                &["type", "sessionID", "reason", "lastSeq"],
-                "invalid_snapshot_request",
-                "snapshot request",
-            )?;
-
-            let snapshot_session_id =
-                parsed
-                    .get("sessionID")
-                    .and_then(Value::as_str)
-                    .ok_or_else(|| RelayValidationError {
-                        code: "invalid_snapshot_request",
-                        message: "Snapshot request requires sessionID.".to_string(),
-                    })?;
-            if snapshot_session_id != expected_session_id {
-                return Err(RelayValidationError {
-                    code: "invalid_session",
-                    message: "Snapshot request sessionID does not match authenticated session."
-                        .to_string(),
-                });
-            }
-
-            if let Some(reason_value) = parsed.get("reason") {
-                let reason = reason_value.as_str().ok_or_else(|| RelayValidationError {
-                    code: "invalid_snapshot_request",
-                    message: "Snapshot reason must be a string.".to_string(),
-                })?;
-                if reason.len() > 128 {
-                    return Err(RelayValidationError {
-                        code: "invalid_snapshot_request",
-                        message: "Snapshot reason is too long.".to_string(),
-                    });
-                }
-            }
-
-            if let Some(last_seq) = parsed.get("lastSeq") {
-                let valid_string_last_seq = last_seq.as_str().is_some_and(|value| {
-                    !value.is_empty()
-                        && value.len() <= 20
-                        && value.bytes().all(|byte| byte.is_ascii_digit())
-                });
-                if !(last_seq.is_u64()
-                    || last_seq.as_i64().is_some_and(|value| value >= 0)
-                    || valid_string_last_seq)
-                {
-                    return Err(RelayValidationError {
-                        code: "invalid_snapshot_request",
-                        message: "lastSeq must be numeric when provided.".to_string(),
-                    });
-                }
-            }
+    }
 
-            if !consume_snapshot_request_budget(
-                session,
-                device_id,
-                config.max_snapshot_requests_per_minute,
-            ) {
-                return Err(RelayValidationError {
-                    code: "snapshot_rate_limited",
-                    message: "Too many snapshot requests from this device. Retry shortly."
-                        .to_string(),
-                });
-            }
+    if request
+        .reason
+        .as_ref()
+        .is_some_and(|reason| reason.len() > 128)
+    {
+        return Err(invalid_snapshot_request("Snapshot reason is too long."));
+    }
 
-            return Ok(());
+    if let Some(SnapshotSequence::Digits(last_seq)) = &request.last_seq {
+        if last_seq.is_empty()
+            || last_seq.len() > 20
+            || !last_seq.bytes().all(|byte| byte.is_ascii_digit())
+        {
+            return Err(invalid_snapshot_request(
+                "lastSeq must be numeric when provided.",
+            ));
         }
     }
 
-    ensure_only_allowed_fields(
-        parsed_object,
-        &[
-            "schemaVersion",
-            "sessionID",
-            "seq",
-            "timestamp",
-            "payload",
-            "relayConnectionID",
-            "relayDeviceID",
-        ],
-        "invalid_command",
-        "command envelope",
-    )?;
-
-    let envelope_session_id = parsed
-        .get("sessionID")
-        .and_then(Value::as_str)
-        .ok_or_else(|| RelayValidationError {
-            code: "invalid_command",
-            message: "Command envelope requires sessionID.".to_string(),
-        })?;
-    if envelope_session_id != expected_session_id {
+    if !consume_snapshot_request_budget(session, device_id, config.max_snapshot_requests_per_minute)
+    {
+        return Err(RelayValidationError {
+            code: "snapshot_rate_limited",
+            message: "Too many snapshot requests from this device. Retry shortly.".to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+fn validate_command(
+    session: &mut SessionRecord,
+    envelope: &CommandEnvelope,
+    expected_session_id: &str,
+    connection_id: &str,
+    device_id: &str,
+    config: &RelayConfig,
+) -> Result<(), RelayValidationError> {
+    if envelope.session_id != expected_session_id {
         return Err(RelayValidationError {
             code: "invalid_session",
             message: "Command envelope sessionID does not match authenticated session.".to_string(),
         });
     }
 
-    let schema_version = parsed
-        .get("schemaVersion")
-        .and_then(Value::as_i64)
-        .ok_or_else(|| RelayValidationError {
-            code: "invalid_command",
-            message: "schemaVersion is required for command envelopes.".to_string(),
-        })?;
-    if schema_version != 2 {
+    if envelope.schema_version != CANONICAL_SCHEMA_VERSION {
         return Err(RelayValidationError {
             code: "unsupported_schema",
-            message: "Only schemaVersion 2 is supported.".to_string(),
+            message: unsupported_schema_version_message(),
         });
     }
 
-    let payload_type = parsed
-        .pointer("/payload/type")
-        .and_then(Value::as_str)
-        .ok_or_else(|| RelayValidationError {
-            code: "invalid_command",
-            message: "Command envelope payload.type is required.".to_string(),
-        })?;
-    if payload_type != "command" {
-        return Err(RelayValidationError {
-            code: "invalid_command",
-            message: "Only command payloads are accepted from mobile clients.".to_string(),
-        });
+    if envelope.payload.payload_type != "command" {
+        return Err(invalid_command(
+            "Only command payloads are accepted from mobile clients.",
+        ));
     }
-    let payload_wrapper = parsed
-        .get("payload")
-        .and_then(Value::as_object)
-        .ok_or_else(|| RelayValidationError {
-            code: "invalid_command",
-            message: "Command envelope payload object is required.".to_string(),
-        })?;
-    ensure_only_allowed_fields(
-        payload_wrapper,
-        &["type", "payload"],
-        "invalid_command",
-        "command wrapper",
-    )?;
-
-    let command_payload = parsed
-        .pointer("/payload/payload")
-        .and_then(Value::as_object)
-        .ok_or_else(|| RelayValidationError {
-            code: "invalid_command",
-            message: "Command payload object is required.".to_string(),
-        })?;
-    ensure_only_allowed_fields(
-        command_payload,
-        &[
-            "name",
-            "commandID",
-            "threadID",
-            "projectID",
-            "text",
-            "runtimeRequestID",
-            "runtimeRequestKind",
-            "runtimeRequestResponse",
-        ],
-        "invalid_command",
-        "command payload",
-    )?;
-    let command_name = command_payload
-        .get("name")
-        .and_then(Value::as_str)
-        .ok_or_else(|| RelayValidationError {
-            code: "invalid_command",
-            message: "Command name is required.".to_string(),
-        })?;
-    let command_id = command_payload
-        .get("commandID")
-        .and_then(Value::as_str)
-        .ok_or_else(|| RelayValidationError {
-            code: "invalid_command",
-            message: "commandID is required.".to_string(),
-        })?;
-    if !is_small_identifier(command_id) {
-        return Err(RelayValidationError {
-            code: "invalid_command",
-            message: "commandID must be a compact identifier.".to_string(),
-        });
+
+    let command = &envelope.payload.payload;
+    if !is_small_identifier(&command.command_id) {
+        return Err(invalid_command("commandID must be a compact identifier."));
     }
-    let command_seq =
-        parsed
-            .get("seq")
-            .and_then(Value::as_u64)
-            .ok_or_else(|| RelayValidationError {
-                code: "invalid_command",
-                message: "Command envelopes must include numeric seq.".to_string(),
-            })?;
 
-    if !consume_connection_command_sequence(session, connection_id, command_seq) {
+    if !consume_connection_command_sequence(session, connection_id, envelope.seq) {
         return Err(RelayValidationError {
             code: "replayed_command",
             message: "Command sequence was replayed or out of order.".to_string(),
@@ -240,118 +152,62 @@ pub(super) fn validate_mobile_payload(
         });
     }
 
-    match command_name {
-        "thread.send_message" => {
-            let thread_id = command_payload
-                .get("threadID")
-                .and_then(Value::as_str)
-                .ok_or_else(|| RelayValidationError {
-                    code: "invalid_command",
-                    message: "thread.send_message requires threadID.".to_string(),
-                })?;
+    match command.name {
+        RemoteCommandName::ThreadSendMessage => {
+            let thread_id = command
+                .thread_id
+                .as_deref()
+                .ok_or_else(|| invalid_command("thread.send_message requires threadID."))?;
             if !is_small_identifier(thread_id) {
-                return Err(RelayValidationError {
-                    code: "invalid_command",
-                    message: "threadID must be a compact identifier.".to_string(),
-                });
+                return Err(invalid_command("threadID must be a compact identifier."));
             }
 
-            let text = command_payload
-                .get("text")
-                .and_then(Value::as_str)
-                .ok_or_else(|| RelayValidationError {
-                    code: "invalid_command",
-                    message: "thread.send_message requires text.".to_string(),
-                })?;
+            let text = command
+                .text
+                .as_deref()
+                .ok_or_else(|| invalid_command("thread.send_message requires text."))?;
             if text.trim().is_empty() {
-                return Err(RelayValidationError {
-                    code: "invalid_command",
-                    message: "Message text cannot be empty.".to_string(),
-                });
+                return Err(invalid_command("Message text cannot be empty."));
             }
             if text.len() > config.max_remote_command_text_bytes {
-                return Err(RelayValidationError {
-                    code: "invalid_command",
-                    message: format!(
-                        "Message text exceeds {} bytes.",
-                        config.max_remote_command_text_bytes
-                    ),
-                });
+                return Err(invalid_command(format!(
+                    "Message text exceeds {} bytes.",
+                    config.max_remote_command_text_bytes
+                )));
             }
         }
-        "thread.select" => {
-            let thread_id = command_payload
-                .get("threadID")
-                .and_then(Value::as_str)
-                .ok_or_else(|| RelayValidationError {
-                    code: "invalid_command",
-                    message: "thread.select requires threadID.".to_string(),
-                })?;
+        RemoteCommandName::ThreadSelect => {
+            let thread_id = command
+                .thread_id
+                .as_deref()
+                .ok_or_else(|| invalid_command("thread.select requires threadID."))?;
             if !is_small_identifier(thread_id) {
-                return Err(RelayValidationError {
-                    code: "invalid_command",
-                    message: "threadID must be a compact identifier.".to_string(),
-                });
+                return Err(invalid_command("threadID must be a compact identifier."));
             }
         }
-        "project.select" => {
-            let project_id = command_payload
-                .get("projectID")
-                .and_then(Value::as_str)
-                .ok_or_else(|| RelayValidationError {
-                    code: "invalid_command",
-                    message: "project.select requires projectID.".to_string(),
-                })?;
+        RemoteCommandName::ProjectSelect => {
+            let project_id = command
+                .project_id
+                .as_deref()
+                .ok_or_else(|| invalid_command("project.select requires projectID."))?;
             if !is_small_identifier(project_id) {
-                return Err(RelayValidationError {
-                    code: "invalid_command",
-                    message: "projectID must be a compact identifier.".to_string(),
-                });
+                return Err(invalid_command("projectID must be a compact identifier."));
             }
         }
-        "runtime_request.respond" => {
-            let runtime_request_id = command_payload
-                .get("runtimeRequestID")
-                .and_then(Value::as_str)
-                .ok_or_else(|| RelayValidationError {
-                    code: "invalid_command",
-                    message: "runtime_request.respond requires runtimeRequestID.".to_string(),
-                })?;
+        RemoteCommandName::RuntimeRequestRespond => {
+            let runtime_request_id = command.runtime_request_id.as_deref().ok_or_else(|| {
+                invalid_command("runtime_request.respond requires runtimeRequestID.")
+            })?;
             if !runtime_request_id.bytes().all(|byte| byte.is_ascii_digit())
                 || runtime_request_id.len() > 32
             {
-                return Err(RelayValidationError {
-                    code: "invalid_command",
-                    message: "runtimeRequestID must be numeric.".to_string(),
-                });
+                return Err(invalid_command("runtimeRequestID must be numeric."));
             }
 
-            if let Some(kind) = command_payload
-                .get("runtimeRequestKind")
-                .and_then(Value::as_str)
-            {
-                if !matches!(
-                    kind,
-                    "approval"
-                        | "permissionsApproval"
-                        | "userInput"
-                        | "mcpElicitation"
-                        | "dynamicToolCall"
-                ) {
-                    return Err(RelayValidationError {
-                        code: "invalid_command",
-                        message: "runtimeRequestKind is not recognized.".to_string(),
-                    });
-                }
-            }
-
-            validate_runtime_request_response(command_payload, config)?;
-        }
-        _ => {
-            return Err(RelayValidationError {
-                code: "invalid_command",
-                message: "Command name is not allowed.".to_string(),
-            });
+            let runtime_response = command.runtime_request_response.as_ref().ok_or_else(|| {
+                invalid_command("runtime_request.respond requires runtimeRequestResponse.")
+            })?;
+            validate_runtime_request_response(runtime_response, config)?;
         }
     }
 
@@ -359,177 +215,57 @@ pub(super) fn validate_mobile_payload(
 }
 
 fn validate_runtime_request_response(
-    command_payload: &serde_json::Map<String, Value>,
+    response: &RuntimeRequestResponse,
     config: &RelayConfig,
 ) -> Result<(), RelayValidationError> {
-    let runtime_response = command_payload.get("runtimeRequestResponse");
-
-    if runtime_response.is_none() {
-        return Err(RelayValidationError {
-            code: "invalid_command",
-            message: "runtime_request.respond requires runtimeRequestResponse.".to_string(),
-        });
-    }
-
-    let Some(runtime_response) = runtime_response else {
-        return Ok(());
-    };
-    let Some(runtime_response_object) = runtime_response.as_object() else {
-        return Err(RelayValidationError {
-            code: "invalid_command",
-            message: "runtimeRequestResponse must be an object.".to_string(),
-        });
-    };
-    ensure_only_allowed_fields(
-        runtime_response_object,
-        &[
-            "decision",
-            "permissions",
-            "scope",
-            "text",
-            "optionID",
-            "approved",
-        ],
-        "invalid_command",
-        "runtimeRequestResponse",
-    )?;
-
-    let has_known_field = runtime_response_object.contains_key("decision")
-        || runtime_response_object.contains_key("permissions")
-        || runtime_response_object.contains_key("scope")
-        || runtime_response_object.contains_key("text")
-        || runtime_response_object.contains_key("optionID")
-        || runtime_response_object.contains_key("approved");
+    let has_known_field = response.decision.is_some()
+        || response.permissions.is_some()
+        || response.scope.is_some()
+        || response.text.is_some()
+        || response.option_id.is_some()
+        || response.approved.is_some();
     if !has_known_field {
-        return Err(RelayValidationError {
-            code: "invalid_command",
-            message: "runtimeRequestResponse must include at least one response field.".to_string(),
-        });
+        return Err(invalid_command(
+            "runtimeRequestResponse must include at least one response field.",
+        ));
     }
 
-    if let Some(decision) = runtime_response_object
-        .get("decision")
-        .and_then(Value::as_str)
-    {
-        validate_runtime_request_decision(decision)?;
-    }
-
-    if let Some(permissions) = runtime_response_object.get("permissions") {
-        let Some(entries) = permissions.as_array() else {
-            return Err(RelayValidationError {
-                code: "invalid_command",
-                message: "runtimeRequestResponse.permissions must be an array.".to_string(),
-            });
-        };
-
-        for entry in entries {
-            let Some(permission) = entry.as_str() else {
-                return Err(RelayValidationError {
-                    code: "invalid_command",
-                    message: "runtimeRequestResponse.permissions must contain strings.".to_string(),
-                });
-            };
-            if permission.trim().is_empty() || permission.len() > 256 {
-                return Err(RelayValidationError {
-                    code: "invalid_command",
-                    message: "runtimeRequestResponse.permissions contains an invalid value."
-                        .to_string(),
-                });
-            }
+    if let Some(permissions) = &response.permissions {
+        if permissions
+            .iter()
+            .any(|permission| permission.trim().is_empty() || permission.len() > 256)
+        {
+            return Err(invalid_command(
+                "runtimeRequestResponse.permissions contains an invalid value.",
+            ));
         }
     }
 
-    if let Some(scope) = runtime_response_object.get("scope") {
-        let Some(scope_value) = scope.as_str() else {
-            return Err(RelayValidationError {
-                code: "invalid_command",
-                message: "runtimeRequestResponse.scope must be a string.".to_string(),
-            });
-        };
-        if scope_value.trim().is_empty() || scope_value.len() > 128 {
-            return Err(RelayValidationError {
-                code: "invalid_command",
-                message: "runtimeRequestResponse.scope is not valid.".to_string(),
-            });
+    if let Some(scope) = &response.scope {
+        if scope.trim().is_empty() || scope.len() > 128 {
+            return Err(invalid_command(
+                "runtimeRequestResponse.scope is not valid.",
+            ));
         }
     }
 
-    if let Some(text) = runtime_response_object.get("text") {
-        let Some(text_value) = text.as_str() else {
-            return Err(RelayValidationError {
-                code: "invalid_command",
-                message: "runtimeRequestResponse.text must be a string.".to_string(),
-            });
-        };
-        if text_value.len() > config.max_remote_command_text_bytes {
-            return Err(RelayValidationError {
-                code: "invalid_command",
-                message: format!(
-                    "runtimeRequestResponse.text exceeds {} bytes.",
-                    config.max_remote_command_text_bytes
-                ),
-            });
+    if let Some(text) = &response.text {
+        if text.len() > config.max_remote_command_text_bytes {
+            return Err(invalid_command(format!(
+                "runtimeRequestResponse.text exceeds {} bytes.",
+                config.max_remote_command_text_bytes
+            )));
         }
     }
 
-    if let Some(option_id) = runtime_response_object.get("optionID") {
-        let Some(option_id_value) = option_id.as_str() else {
-            return Err(RelayValidationError {
-                code: "invalid_command",
-                message: "runtimeRequestResponse.optionID must be a string.".to_string(),
-            });
-        };
-        if !is_small_identifier(option_id_value) {
-            return Err(RelayValidationError {
-                code: "invalid_command",
-                message: "runtimeRequestResponse.optionID must be a compact identifier."
-                    .to_string(),
-            });
+    if let Some(option_id) = &response.option_id {
+        if !is_small_identifier(option_id) {
+            return Err(invalid_command(
+                "runtimeRequestResponse.optionID must be a compact identifier.",
+            ));
         }
     }
 
-    if let Some(approved) = runtime_response_object.get("approved") {
-        if !approved.is_boolean() {
-            return Err(RelayValidationError {
-                code: "invalid_command",
-                message: "runtimeRequestResponse.approved must be a boolean.".to_string(),
-            });
-        }
-    }
-
-    Ok(())
-}
-
-fn validate_runtime_request_decision(decision: &str) -> Result<(), RelayValidationError> {
-    if matches!(
-        decision,
-        "accept" | "acceptForSession" | "decline" | "cancel"
-    ) {
-        return Ok(());
-    }
-
-    Err(RelayValidationError {
-        code: "invalid_command",
-        message: "runtimeRequestResponse.decision is not recognized.".to_string(),
-    })
-}
-
-fn ensure_only_allowed_fields(
-    object: &serde_json::Map<String, Value>,
-    allowed_fields: &[&str],
-    error_code: &'static str,
-    context: &str,
-) -> Result<(), RelayValidationError> {
-    if let Some(unexpected_key) = object
-        .keys()
-        .find(|key| !allowed_fields.contains(&key.as_str()))
-    {
-        return Err(RelayValidationError {
-            code: error_code,
-            message: format!("Unexpected field '{}' in {}.", unexpected_key, context),
-        });
-    }
-
     Ok(())
 }
 
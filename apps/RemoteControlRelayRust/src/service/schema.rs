@@ -0,0 +1,158 @@
+use super::*;
+
+/// Envelope schema the relay validates, caches and publishes across instances. Clients that do
+/// not declare `supportedSchemaVersions` are assumed to speak it.
+pub(super) const CANONICAL_SCHEMA_VERSION: u32 = 2;
+/// Envelope schema versions this relay can translate between, lowest first.
+pub(super) const SUPPORTED_SCHEMA_VERSIONS: &[u32] = &[2, 3];
+
+pub(super) fn canonical_schema_version() -> u32 {
+    CANONICAL_SCHEMA_VERSION
+}
+
+pub(super) fn is_supported_schema_version(version: u32) -> bool {
+    SUPPORTED_SCHEMA_VERSIONS.contains(&version)
+}
+
+/// Picks the highest envelope version both the client and the relay support.
+pub(super) fn negotiate_schema_version(declared: &[u32]) -> Option<u32> {
+    SUPPORTED_SCHEMA_VERSIONS
+        .iter()
+        .rev()
+        .copied()
+        .find(|version| declared.contains(version))
+}
+
+/// Negotiated version for an optional declaration. Clients that declare nothing get the canonical
+/// version.
+pub(super) fn resolve_schema_version(declared: Option<&[u32]>) -> Option<u32> {
+    match declared {
+        Some(declared) => negotiate_schema_version(declared),
+        None => Some(CANONICAL_SCHEMA_VERSION),
+    }
+}
+
+pub(super) fn unsupported_schema_version_message() -> String {
+    let supported = SUPPORTED_SCHEMA_VERSIONS
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("Supported schemaVersions are {supported}.")
+}
+
+/// Version a mobile socket receives envelopes in, from its device record.
+pub(super) fn device_schema_version(
+    devices: &HashMap<String, DeviceRecord>,
+    device_id: Option<&str>,
+) -> u32 {
+    device_id
+        .and_then(|device_id| devices.get(device_id))
+        .map(|device| device.schema_version)
+        .unwrap_or(CANONICAL_SCHEMA_VERSION)
+}
+
+fn envelope_schema_version(map: &serde_json::Map<String, Value>) -> Option<u64> {
+    map.get("schemaVersion").and_then(Value::as_u64)
+}
+
+/// v3 flattens the v2 `payload` wrapper: `payload.type` becomes the top-level `type` and
+/// `payload.payload` becomes `body`. Any other wrapper fields travel in `meta`.
+fn v2_envelope_to_v3(mut map: serde_json::Map<String, Value>) -> serde_json::Map<String, Value> {
+    let Some(Value::Object(mut wrapper)) = map.remove("payload") else {
+        return map;
+    };
+    map.insert("schemaVersion".to_string(), Value::from(3));
+    if let Some(payload_type) = wrapper.remove("type") {
+        map.insert("type".to_string(), payload_type);
+    }
+    if let Some(body) = wrapper.remove("payload") {
+        map.insert("body".to_string(), body);
+    }
+    if !wrapper.is_empty() {
+        map.insert("meta".to_string(), Value::Object(wrapper));
+    }
+    map
+}
+
+fn v3_envelope_to_v2(mut map: serde_json::Map<String, Value>) -> serde_json::Map<String, Value> {
+    let mut wrapper = match map.remove("meta") {
+        Some(Value::Object(meta)) => meta,
+        _ => serde_json::Map::new(),
+    };
+    if let Some(payload_type) = map.remove("type") {
+        wrapper.insert("type".to_string(), payload_type);
+    }
+    if let Some(body) = map.remove("body") {
+        wrapper.insert("payload".to_string(), body);
+    }
+    map.insert(
+        "schemaVersion".to_string(),
+        Value::from(CANONICAL_SCHEMA_VERSION),
+    );
+    map.insert("payload".to_string(), Value::Object(wrapper));
+    map
+}
+
+/// Rewrites an inbound v3 envelope into the canonical v2 shape. Returns `None` for anything that
+/// is already canonical or is not a versioned envelope, such as `relay.*` control messages.
+pub(super) fn canonicalize_envelope(parsed: &Value) -> Option<Value> {
+    let map = parsed.as_object()?;
+    if envelope_schema_version(map) != Some(3) {
+        return None;
+    }
+    Some(Value::Object(v3_envelope_to_v2(map.clone())))
+}
+
+/// Translates a canonical envelope for a recipient speaking `version`. Payloads the adapter does
+/// not recognize are passed through unchanged.
+pub(super) fn adapt_envelope(canonical: &str, version: u32) -> Cow<'_, str> {
+    if version == CANONICAL_SCHEMA_VERSION {
+        return Cow::Borrowed(canonical);
+    }
+    let Ok(Value::Object(map)) = serde_json::from_str::<Value>(canonical) else {
+        return Cow::Borrowed(canonical);
+    };
+    let is_canonical_envelope = envelope_schema_version(&map)
+        == Some(u64::from(CANONICAL_SCHEMA_VERSION))
+        && map.get("payload").is_some_and(Value::is_object);
+    if version != 3 || !is_canonical_envelope {
+        return Cow::Borrowed(canonical);
+    }
+    Cow::Owned(Value::Object(v2_envelope_to_v3(map)).to_string())
+}
+
+/// Fans one canonical envelope out to recipients on mixed versions, translating it at most once
+/// per version.
+pub(super) struct AdaptedEnvelopes<'a> {
+    canonical: &'a str,
+    adapted: Vec<(u32, String)>,
+}
+
+impl<'a> AdaptedEnvelopes<'a> {
+    pub(super) fn new(canonical: &'a str) -> Self {
+        Self {
+            canonical,
+            adapted: Vec::new(),
+        }
+    }
+
+    pub(super) fn for_version(&mut self, version: u32) -> &str {
+        if version == CANONICAL_SCHEMA_VERSION {
+            return self.canonical;
+        }
+        let index = match self
+            .adapted
+            .iter()
+            .position(|(adapted_version, _)| *adapted_version == version)
+        {
+            Some(index) => index,
+            None => {
+                let adapted = adapt_envelope(self.canonical, version).into_owned();
+                self.adapted.push((version, adapted));
+                self.adapted.len() - 1
+            }
+        };
+        &self.adapted[index].1
+    }
+}
@@ -36,6 +36,8 @@ pub(super) async fn sweep_sessions(state: &SharedRelayState) {
         }
     }
 
+    prune_penalty_records(&mut relay, &state.config, now);
+
     let mut did_mutate = false;
     let mut closed_session_ids = Vec::new();
     for (session_id, reason) in close_ids {
@@ -77,11 +79,20 @@ pub(super) async fn sweep_sessions(state: &SharedRelayState) {
     if relay.rate_buckets.len() != rate_bucket_count_before {
         did_mutate = true;
     }
+    relay
+        .unknown_token_cache
+        .retain(|_, expires_at_ms| now < *expires_at_ms);
+    let RelayState {
+        sessions,
+        session_refreshed_at_ms,
+        ..
+    } = &mut *relay;
+    session_refreshed_at_ms.retain(|session_id, _| sessions.contains_key(session_id));
     drop(relay);
 
     if did_mutate {
         for session_id in closed_session_ids {
-            let payload = json!({ "type": "disconnect", "reason": "session_expired" }).to_string();
+            let payload = disconnect_payload("session_expired");
             publish_cross_instance_session(state, &session_id, "desktop", None, payload.clone());
             publish_cross_instance_session(state, &session_id, "mobile", None, payload);
             persist_session_if_needed(state, &session_id).await;
@@ -125,20 +136,19 @@ pub(super) fn desktop_status_payload(session: &SessionRecord) -> String {
     serde_json::to_string(&payload).unwrap_or_else(|_| "{}".to_string())
 }
 
-pub(super) fn send_desktop_status(session: &SessionRecord) -> (String, u64, u64) {
+/// Status frames use the control lane, so a mobile whose bulk queue is backed up still learns
+/// that the desktop went away; a full control lane only drops the frame.
+pub(super) fn send_desktop_status(session: &SessionRecord) -> (String, u64) {
     let payload = desktop_status_payload(session);
     let mut outbound_send_failures = 0_u64;
-    let mut slow_consumer_disconnects = 0_u64;
 
     for mobile in session.mobile_sockets.values() {
         if !try_send_payload(&mobile.tx, payload.clone()) {
             outbound_send_failures = outbound_send_failures.saturating_add(1);
-            slow_consumer_disconnects = slow_consumer_disconnects.saturating_add(1);
-            request_socket_disconnect(mobile, "slow_consumer");
         }
     }
 
-    (payload, outbound_send_failures, slow_consumer_disconnects)
+    (payload, outbound_send_failures)
 }
 
 pub(super) fn send_device_count(session: &SessionRecord) {
@@ -147,9 +157,7 @@ pub(super) fn send_device_count(session: &SessionRecord) {
         return;
     };
 
-    if !try_send_payload(&desktop.tx, payload) {
-        request_socket_disconnect(desktop, "slow_consumer");
-    }
+    let _ = try_send_payload(&desktop.tx, payload);
 }
 
 pub(super) fn device_count_payload(session: &SessionRecord) -> String {
@@ -189,19 +197,39 @@ pub(super) async fn is_rate_limited(state: &SharedRelayState, ip: &str) -> bool
     bucket.count > state.config.max_pair_requests_per_minute
 }
 
+/// Closes a session this instance decided to end and reports the matching lifecycle event.
 pub(super) fn close_session(relay: &mut RelayState, session_id: &str, reason: &str) {
-    let Some(session) = relay.sessions.remove(session_id) else {
+    let Some(created_at_ms) = remove_session(relay, session_id, reason) else {
         return;
     };
+    if let Some(kind) = lifecycle_event_for_close_reason(reason) {
+        emit_lifecycle_event(
+            relay,
+            LifecycleEvent::new(kind, session_id)
+                .with_reason(reason)
+                .with_occurrence(created_at_ms),
+        );
+    }
+}
+
+/// Closes the local copy of a session another instance ended. That instance reports the
+/// lifecycle event.
+pub(super) fn close_mirrored_session(relay: &mut RelayState, session_id: &str, reason: &str) {
+    remove_session(relay, session_id, reason);
+}
+
+fn remove_session(relay: &mut RelayState, session_id: &str, reason: &str) -> Option<i64> {
+    let session = relay.sessions.remove(session_id)?;
     relay.bus_subscribed_sessions.remove(session_id);
     if let Some(task) = relay.bus_subscription_tasks.remove(session_id) {
         task.abort();
     }
 
-    if let Some(pending) = session.pending_join_request {
+    for (_, pending) in session.pending_join_requests {
         let _ = pending.decision_tx.send(JoinDecision {
             approved: false,
             reason: "session_closed".to_string(),
+            scopes: Vec::new(),
         });
     }
 
@@ -224,6 +252,7 @@ pub(super) fn close_session(relay: &mut RelayState, session_id: &str, reason: &s
         "[relay-rs] closed session={} reason={reason}",
         session_log_id(session_id)
     );
+    Some(session.created_at_ms)
 }
 
 pub async fn drain_sessions_for_shutdown(state: &SharedRelayState) {
@@ -237,8 +266,7 @@ pub async fn drain_sessions_for_shutdown(state: &SharedRelayState) {
     };
 
     for session_id in session_ids {
-        let disconnect_payload =
-            json!({ "type": "disconnect", "reason": "server_shutdown" }).to_string();
+        let disconnect_payload = disconnect_payload("server_shutdown");
         publish_cross_instance_session(
             state,
             &session_id,
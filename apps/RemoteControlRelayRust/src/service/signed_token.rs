@@ -0,0 +1,176 @@
+use super::*;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+const SIGNED_DEVICE_TOKEN_PREFIX: &str = "rdt1";
+const MAX_SIGNED_DEVICE_TOKEN_CHARS: usize = 512;
+
+#[derive(Serialize, Deserialize)]
+pub(super) struct SignedDeviceTokenClaims {
+    #[serde(rename = "sid")]
+    pub(super) session_id: String,
+    #[serde(rename = "did")]
+    pub(super) device_id: String,
+    #[serde(rename = "gen")]
+    pub(super) generation: u64,
+    #[serde(rename = "exp")]
+    pub(super) expires_at_ms: i64,
+}
+
+pub(super) enum SignedDeviceTokenResolution {
+    Resolved(AuthContext),
+    SessionStale { session_id: String },
+    Rejected(&'static str),
+}
+
+/// Signed device tokens have the shape `rdt1.<keyID>.<claims>.<signature>` where claims and
+/// signature are unpadded base64url. They are recognised by shape alone so opaque tokens keep
+/// using the token index.
+pub(super) fn is_signed_device_token(value: &str) -> bool {
+    if value.len() > MAX_SIGNED_DEVICE_TOKEN_CHARS {
+        return false;
+    }
+    let mut parts = value.split('.');
+    if parts.next() != Some(SIGNED_DEVICE_TOKEN_PREFIX) {
+        return false;
+    }
+    let rest = parts.collect::<Vec<_>>();
+    rest.len() == 3
+        && rest.iter().all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
+        })
+}
+
+pub(super) fn is_device_session_token_candidate(value: &str) -> bool {
+    is_opaque_token(value, 22) || is_signed_device_token(value)
+}
+
+fn signed_device_token_mac(secret: &str, signing_input: &str) -> Option<Hmac<Sha256>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).ok()?;
+    mac.update(signing_input.as_bytes());
+    Some(mac)
+}
+
+pub(super) fn sign_device_token(
+    config: &RelayConfig,
+    claims: &SignedDeviceTokenClaims,
+) -> Option<String> {
+    let (key_id, secret) = config.active_device_token_signing_key()?;
+    let encoded_claims =
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).ok()?);
+    let signing_input = format!("{SIGNED_DEVICE_TOKEN_PREFIX}.{key_id}.{encoded_claims}");
+    let signature = signed_device_token_mac(secret, &signing_input)?
+        .finalize()
+        .into_bytes();
+    Some(format!(
+        "{signing_input}.{}",
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(signature)
+    ))
+}
+
+pub(super) fn verify_signed_device_token(
+    config: &RelayConfig,
+    token: &str,
+    now: i64,
+) -> Result<SignedDeviceTokenClaims, &'static str> {
+    if !is_signed_device_token(token) {
+        return Err("signed_token_malformed");
+    }
+    let Some((signing_input, encoded_signature)) = token.rsplit_once('.') else {
+        return Err("signed_token_malformed");
+    };
+    let mut parts = signing_input.splitn(3, '.');
+    let (Some(_), Some(key_id), Some(encoded_claims)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err("signed_token_malformed");
+    };
+
+    let Some(secret) = config.device_token_signing_key(key_id) else {
+        return Err("signed_token_unknown_key");
+    };
+    let Ok(signature) = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(encoded_signature)
+    else {
+        return Err("signed_token_malformed");
+    };
+    let Some(mac) = signed_device_token_mac(secret, signing_input) else {
+        return Err("signed_token_malformed");
+    };
+    if mac.verify_slice(&signature).is_err() {
+        return Err("signed_token_invalid_signature");
+    }
+
+    let claims = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(encoded_claims)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<SignedDeviceTokenClaims>(&bytes).ok())
+        .ok_or("signed_token_malformed")?;
+    if now >= claims.expires_at_ms {
+        return Err("signed_token_expired");
+    }
+
+    Ok(claims)
+}
+
+/// Mints the next device session token: signed when a signing key is configured, otherwise an
+/// opaque random token that is resolved through the device token index.
+pub(super) fn issue_device_session_token(
+    config: &RelayConfig,
+    session_id: &str,
+    device_id: &str,
+    generation: u64,
+    now: i64,
+) -> String {
+    let claims = SignedDeviceTokenClaims {
+        session_id: session_id.to_string(),
+        device_id: device_id.to_string(),
+        generation,
+        expires_at_ms: now
+            .saturating_add(i64::try_from(config.signed_device_token_ttl_ms).unwrap_or(i64::MAX)),
+    };
+    sign_device_token(config, &claims).unwrap_or_else(|| random_token(32))
+}
+
+pub(super) fn resolve_signed_device_token(
+    relay: &RelayState,
+    config: &RelayConfig,
+    token: &str,
+    now: i64,
+) -> SignedDeviceTokenResolution {
+    let claims = match verify_signed_device_token(config, token, now) {
+        Ok(claims) => claims,
+        Err(reason) => return SignedDeviceTokenResolution::Rejected(reason),
+    };
+
+    let Some(device) = relay
+        .sessions
+        .get(&claims.session_id)
+        .and_then(|session| session.devices.get(&claims.device_id))
+    else {
+        return SignedDeviceTokenResolution::SessionStale {
+            session_id: claims.session_id,
+        };
+    };
+
+    let is_current_generation = claims.generation == device.token_generation;
+    let is_retired_in_grace = claims.generation < device.token_generation
+        && device
+            .retired_session_tokens
+            .iter()
+            .any(|retired| now < retired.expires_at_ms && safe_token_equals(&retired.token, token));
+    if claims.generation > device.token_generation {
+        return SignedDeviceTokenResolution::SessionStale {
+            session_id: claims.session_id,
+        };
+    }
+    if !is_current_generation && !is_retired_in_grace {
+        return SignedDeviceTokenResolution::Rejected("signed_token_revoked");
+    }
+
+    SignedDeviceTokenResolution::Resolved(AuthContext::Mobile {
+        session_id: claims.session_id,
+        device_id: claims.device_id,
+    })
+}
@@ -0,0 +1,190 @@
+use super::*;
+
+/// Most recent desktop `snapshot` envelope seen for a session, kept verbatim so it can be
+/// replayed to mobiles while the desktop cannot answer a snapshot request.
+#[derive(Clone, Serialize, Deserialize)]
+pub(super) struct CachedDesktopSnapshot {
+    pub(super) payload: String,
+    pub(super) captured_at_ms: i64,
+}
+
+pub(super) fn is_desktop_snapshot_payload(parsed: Option<&Value>) -> bool {
+    parsed
+        .and_then(|payload| payload.pointer("/payload/type"))
+        .and_then(Value::as_str)
+        == Some("snapshot")
+}
+
+/// Replaces the cached snapshot when caching is enabled and the payload fits under
+/// `SNAPSHOT_CACHE_MAX_BYTES`. Oversized snapshots leave the previous entry in place.
+pub(super) fn cache_desktop_snapshot(
+    session: &mut SessionRecord,
+    config: &RelayConfig,
+    raw: &str,
+    now: i64,
+) -> Option<CachedDesktopSnapshot> {
+    if config.snapshot_cache_max_bytes == 0 || raw.len() > config.snapshot_cache_max_bytes {
+        return None;
+    }
+    let snapshot = CachedDesktopSnapshot {
+        payload: raw.to_string(),
+        captured_at_ms: now,
+    };
+    session.latest_snapshot = Some(snapshot.clone());
+    Some(snapshot)
+}
+
+/// Marks a cached snapshot as stale and stamps its capture time, leaving the desktop payload
+/// untouched.
+pub(super) fn stale_snapshot_payload(snapshot: &CachedDesktopSnapshot) -> String {
+    let Ok(mut value) = serde_json::from_str::<Value>(&snapshot.payload) else {
+        return snapshot.payload.clone();
+    };
+
+    if let Value::Object(ref mut map) = value {
+        map.insert("relaySnapshotStale".to_string(), Value::Bool(true));
+        map.insert(
+            "relaySnapshotCapturedAt".to_string(),
+            Value::String(iso_from_millis(snapshot.captured_at_ms)),
+        );
+    }
+
+    serde_json::to_string(&value).unwrap_or_else(|_| snapshot.payload.clone())
+}
+
+impl RelayStatePersistence {
+    pub(super) fn snapshot_key(&self, session_id: &str) -> String {
+        format!("{}:{session_id}", self.snapshot_key_prefix)
+    }
+
+    async fn save_snapshot(
+        &self,
+        session_id: &str,
+        snapshot: &CachedDesktopSnapshot,
+        ttl_ms: u64,
+    ) -> Result<(), String> {
+        let payload = serde_json::to_string(snapshot)
+            .map_err(|error| format!("persisted snapshot encode failed: {error}"))?;
+        let mut connection = self
+            .redis_client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|error| format!("redis connection failed: {error}"))?;
+
+        connection
+            .pset_ex::<_, _, ()>(self.snapshot_key(session_id), payload, ttl_ms)
+            .await
+            .map_err(|error| format!("redis set snapshot failed: {error}"))
+    }
+
+    async fn load_snapshot(
+        &self,
+        session_id: &str,
+    ) -> Result<Option<CachedDesktopSnapshot>, String> {
+        let mut connection = self
+            .redis_client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|error| format!("redis connection failed: {error}"))?;
+
+        let payload: Option<String> = connection
+            .get(self.snapshot_key(session_id))
+            .await
+            .map_err(|error| format!("redis get snapshot failed: {error}"))?;
+        payload
+            .map(|payload| {
+                serde_json::from_str::<CachedDesktopSnapshot>(&payload)
+                    .map_err(|error| format!("malformed persisted snapshot: {error}"))
+            })
+            .transpose()
+    }
+}
+
+/// Writes a freshly cached snapshot through to the session store when `SNAPSHOT_CACHE_PERSIST`
+/// is enabled. The key expires with the session's idle timeout.
+pub(super) fn persist_desktop_snapshot(
+    state: &SharedRelayState,
+    session_id: &str,
+    snapshot: CachedDesktopSnapshot,
+    idle_timeout_seconds: u64,
+) {
+    if !state.config.snapshot_cache_persist {
+        return;
+    }
+    let Some(persistence) = state.persistence.clone() else {
+        return;
+    };
+
+    let session_id = session_id.to_string();
+    let ttl_ms = idle_timeout_seconds.saturating_mul(1_000).max(1_000);
+    tokio::spawn(async move {
+        if let Err(error) = persistence
+            .save_snapshot(&session_id, &snapshot, ttl_ms)
+            .await
+        {
+            warn!(
+                "[relay-rs] failed to persist desktop snapshot session={}: {error}",
+                session_log_id(&session_id)
+            );
+        }
+    });
+}
+
+/// Returns the locally cached snapshot, falling back to the session store so an instance that
+/// never saw the desktop can still serve one.
+async fn cached_desktop_snapshot(
+    state: &SharedRelayState,
+    session_id: &str,
+) -> Option<CachedDesktopSnapshot> {
+    {
+        let relay = state.inner.lock().await;
+        let session = relay.sessions.get(session_id)?;
+        if session.latest_snapshot.is_some() || !state.config.snapshot_cache_persist {
+            return session.latest_snapshot.clone();
+        }
+    }
+
+    let persistence = state.persistence.as_ref()?;
+    let snapshot = match persistence.load_snapshot(session_id).await {
+        Ok(snapshot) => snapshot?,
+        Err(error) => {
+            warn!(
+                "[relay-rs] failed to load persisted snapshot session={}: {error}",
+                session_log_id(session_id)
+            );
+            return None;
+        }
+    };
+
+    let mut relay = state.inner.lock().await;
+    let session = relay.sessions.get_mut(session_id)?;
+    if session
+        .latest_snapshot
+        .as_ref()
+        .is_none_or(|cached| cached.captured_at_ms < snapshot.captured_at_ms)
+    {
+        session.latest_snapshot = Some(snapshot);
+    }
+    session.latest_snapshot.clone()
+}
+
+/// Sends the cached snapshot, marked stale, to a mobile socket speaking `schema_version`. Returns
+/// false when there is nothing cached or the socket's bulk lane is full.
+pub(super) async fn serve_cached_desktop_snapshot(
+    state: &SharedRelayState,
+    session_id: &str,
+    tx: &SocketSender,
+    schema_version: u32,
+) -> bool {
+    let Some(snapshot) = cached_desktop_snapshot(state, session_id).await else {
+        return false;
+    };
+
+    let payload = stale_snapshot_payload(&snapshot);
+    if try_send_bulk_payload(tx, adapt_envelope(&payload, schema_version).into_owned()) {
+        return true;
+    }
+    let mut relay = state.inner.lock().await;
+    relay.outbound_send_failures = relay.outbound_send_failures.saturating_add(1);
+    false
+}
@@ -1,9 +1,16 @@
 use super::*;
 use hmac::{Hmac, Mac};
-use sha2::Sha256;
+use sha2::{Digest, Sha256};
 
-const BUS_SUBSCRIBE_RETRY_DELAY: Duration = Duration::from_secs(1);
+pub(super) const BUS_SUBSCRIBE_RETRY_DELAY: Duration = Duration::from_secs(1);
+const PERSISTENCE_RECONCILE_INTERVAL: Duration = Duration::from_secs(60);
+const PERSISTENCE_RECONCILE_BATCH: usize = 256;
 const MIN_CROSS_INSTANCE_NONCE_CHARS: usize = 8;
+pub(super) const UNKNOWN_TOKEN_CACHE_TTL_MS: i64 = 5_000;
+pub(super) const MAX_UNKNOWN_TOKEN_CACHE_ENTRIES: usize = 10_000;
+const SESSION_REFRESH_THROTTLE_MS: i64 = 1_000;
+const CROSS_INSTANCE_PUBLISH_LANES: usize = 16;
+pub(super) const CROSS_INSTANCE_PUBLISH_LANE_CAPACITY: usize = 1_024;
 
 #[derive(Clone)]
 pub struct SharedRelayState {
@@ -11,6 +18,14 @@ pub struct SharedRelayState {
     pub inner: Arc<Mutex<RelayState>>,
     pub(super) persistence: Option<RelayStatePersistence>,
     pub(super) cross_instance_bus: Option<RelayCrossInstanceBus>,
+    pub(super) presence: Option<RelayPresenceRegistry>,
+    pub(super) envelope_keyring: Arc<std::sync::RwLock<NatsHmacKeyring>>,
+    pub(super) webhooks: Option<RelayWebhooks>,
+    pub(super) penalty_store: Option<RelayPenaltyStore>,
+    pub(super) ip_access_rules: Arc<std::sync::RwLock<IpAccessRules>>,
+    /// Woken whenever an async pair request may have been resolved, locally or by a refresh
+    /// from persistence. `/pair/status` long-polls wait on it.
+    pub(super) async_pair_updates: Arc<Notify>,
 }
 
 pub struct RelayState {
@@ -30,11 +45,23 @@ pub struct RelayState {
     pub(super) ws_auth_attempts: u64,
     pub(super) ws_auth_successes: u64,
     pub(super) ws_auth_failure_reasons: HashMap<String, u64>,
-    pub(super) last_persistence_refresh_at_ms: i64,
+    pub(super) unknown_token_cache: HashMap<String, i64>,
+    /// When each local session was last reloaded for an HTTP request, for the per-session
+    /// refresh throttle.
+    pub(super) session_refreshed_at_ms: HashMap<String, i64>,
     pub(super) persistence_versions: HashMap<String, u64>,
     pub(super) seen_cross_instance_nonces: HashMap<String, i64>,
+    pub(super) envelope_signature_failures: HashMap<String, u64>,
     pub(super) bus_subscribed_sessions: HashSet<String>,
     pub(super) bus_subscription_tasks: HashMap<String, tokio::task::JoinHandle<()>>,
+    pub(super) presence_claims: HashMap<String, PresenceClaim>,
+    pub(super) lifecycle_events: Option<mpsc::Sender<LifecycleEvent>>,
+    pub(super) webhook_stats: WebhookDeliveryStats,
+    pub(super) penalty_records: HashMap<String, PenaltyRecord>,
+    pub(super) penalty_blocks_issued: u64,
+    pub(super) penalty_box_rejections: u64,
+    pub(super) ip_access_rejections: HashMap<String, u64>,
+    pub(super) byte_quota_drops: u64,
 }
 
 pub(super) struct SessionRecord {
@@ -55,12 +82,23 @@ pub(super) struct SessionRecord {
     pub(super) session_command_rate_bucket: Option<RateBucket>,
     pub(super) snapshot_request_rate_buckets: HashMap<String, RateBucket>,
     pub(super) command_sequence_by_connection_id: HashMap<String, u64>,
-    pub(super) pending_join_request: Option<PendingJoinRequest>,
+    pub(super) pending_join_requests: HashMap<String, PendingJoinRequest>,
+    pub(super) latest_snapshot: Option<CachedDesktopSnapshot>,
+    pub(super) traffic: SessionTraffic,
+    /// Runtime request IDs the approval webhook already fired for. An ID leaves the set when the
+    /// request is answered or resolved.
+    pub(super) notified_runtime_requests: HashSet<String>,
+    pub(super) transfer_grant: Option<SessionTransferGrant>,
+    pub(super) max_devices: Option<usize>,
+    pub(super) device_eviction_policy: DeviceEvictionPolicy,
+    pub(super) invites: HashMap<String, SessionInvite>,
+    pub(super) async_pair_requests: HashMap<String, AsyncPairRequest>,
+    pub(super) desktop_schema_version: u32,
 }
 
 #[derive(Clone)]
 pub(super) struct SocketHandle {
-    pub(super) tx: mpsc::Sender<Message>,
+    pub(super) tx: SocketSender,
     pub(super) shutdown: watch::Sender<bool>,
     pub(super) device_id: Option<String>,
 }
@@ -73,6 +111,40 @@ pub(super) struct DeviceRecord {
     pub(super) name: String,
     pub(super) joined_at_ms: i64,
     pub(super) last_seen_at_ms: i64,
+    #[serde(default)]
+    pub(super) token_generation: u64,
+    #[serde(default)]
+    pub(super) nickname: Option<String>,
+    #[serde(default)]
+    pub(super) platform: Option<String>,
+    #[serde(default)]
+    pub(super) app_version: Option<String>,
+    #[serde(default)]
+    pub(super) last_ip: Option<String>,
+    #[serde(default)]
+    pub(super) connection_history: std::collections::VecDeque<DeviceConnectionEvent>,
+    #[serde(default)]
+    pub(super) scopes: Vec<String>,
+    #[serde(default = "canonical_schema_version")]
+    pub(super) schema_version: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum DeviceConnectionEventKind {
+    Connected,
+    Disconnected,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(super) struct DeviceConnectionEvent {
+    pub(super) kind: DeviceConnectionEventKind,
+    pub(super) at_ms: i64,
+    pub(super) remote_ip: String,
+    #[serde(default)]
+    pub(super) reason: Option<String>,
+    #[serde(default)]
+    pub(super) duration_ms: Option<i64>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -99,6 +171,16 @@ pub(super) struct PendingJoinRequest {
 pub(super) struct JoinDecision {
     pub(super) approved: bool,
     pub(super) reason: String,
+    pub(super) scopes: Vec<String>,
+}
+
+/// Single-use grant that lets a new desktop install take over a session, see
+/// `/session/transfer/issue`.
+#[derive(Clone, Serialize, Deserialize)]
+pub(super) struct SessionTransferGrant {
+    pub(super) token: String,
+    pub(super) expires_at_ms: i64,
+    pub(super) issued_by: String,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -113,20 +195,30 @@ pub(super) struct RelayStatePersistence {
     pub(super) session_index_key: String,
     pub(super) session_key_prefix: String,
     pub(super) session_version_key_prefix: String,
+    pub(super) token_key_prefix: String,
+    pub(super) session_tokens_key_prefix: String,
+    pub(super) session_changes_channel: String,
+    pub(super) snapshot_key_prefix: String,
+    pub(super) transfer_key_prefix: String,
 }
 
 #[derive(Clone)]
 pub(super) struct RelayCrossInstanceBus {
-    pub(super) client: async_nats::Client,
+    pub(super) transport: Arc<dyn CrossInstanceBus>,
     pub(super) instance_id: String,
     pub(super) subject_prefix: String,
-    pub(super) publish_tx: mpsc::UnboundedSender<QueuedCrossInstancePublish>,
+    /// Bounded publish queues, one per worker. A subject always maps to the same lane, so its
+    /// publishes stay in order while a slow subject only holds up the subjects sharing its lane.
+    pub(super) publish_lanes: Arc<[mpsc::Sender<QueuedCrossInstancePublish>]>,
+    /// Publishes dropped because their lane was full. Reported as `crossInstancePublishDrops`.
+    pub(super) publish_drops: Arc<std::sync::atomic::AtomicU64>,
 }
 
 #[derive(Clone)]
 pub(super) struct QueuedCrossInstancePublish {
     pub(super) subject: String,
     pub(super) payload: Vec<u8>,
+    pub(super) presence_route: Option<PresenceRoute>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -143,6 +235,14 @@ pub(super) struct CrossInstanceEnvelope {
     pub(super) nonce: String,
     #[serde(default)]
     pub(super) signature: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) key_id: Option<String>,
+    /// Set by the publishing relay for frames it authored, so they keep control-lane priority on
+    /// the receiving instance. Forwarded client traffic leaves it unset and stays on the bulk
+    /// lane whatever its JSON says. Like `key_id` it is not covered by the signature, which keeps
+    /// mixed fleets verifying each other's envelopes.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(super) control_lane: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -158,6 +258,18 @@ pub(super) struct PersistedSessionRecord {
     pub(super) created_at_ms: i64,
     pub(super) last_activity_at_ms: i64,
     pub(super) devices: HashMap<String, DeviceRecord>,
+    #[serde(default)]
+    pub(super) transfer_grant: Option<SessionTransferGrant>,
+    #[serde(default)]
+    pub(super) max_devices: Option<usize>,
+    #[serde(default)]
+    pub(super) device_eviction_policy: DeviceEvictionPolicy,
+    #[serde(default)]
+    pub(super) invites: HashMap<String, SessionInvite>,
+    #[serde(default)]
+    pub(super) async_pair_requests: HashMap<String, AsyncPairRequest>,
+    #[serde(default = "canonical_schema_version")]
+    pub(super) desktop_schema_version: u32,
 }
 
 pub(super) enum AuthContext {
@@ -184,6 +296,12 @@ impl PersistedSessionRecord {
             created_at_ms: session.created_at_ms,
             last_activity_at_ms: session.last_activity_at_ms,
             devices: session.devices.clone(),
+            transfer_grant: session.transfer_grant.clone(),
+            max_devices: session.max_devices,
+            device_eviction_policy: session.device_eviction_policy,
+            invites: session.invites.clone(),
+            async_pair_requests: session.async_pair_requests.clone(),
+            desktop_schema_version: session.desktop_schema_version,
         }
     }
 
@@ -209,7 +327,16 @@ impl PersistedSessionRecord {
             session_command_rate_bucket: None,
             snapshot_request_rate_buckets: HashMap::new(),
             command_sequence_by_connection_id: HashMap::new(),
-            pending_join_request: None,
+            pending_join_requests: HashMap::new(),
+            latest_snapshot: None,
+            traffic: SessionTraffic::default(),
+            notified_runtime_requests: HashSet::new(),
+            transfer_grant: self.transfer_grant,
+            max_devices: self.max_devices,
+            device_eviction_policy: self.device_eviction_policy,
+            invites: self.invites,
+            async_pair_requests: self.async_pair_requests,
+            desktop_schema_version: self.desktop_schema_version,
         })
     }
 }
@@ -223,6 +350,21 @@ impl RelayStatePersistence {
         format!("{}:{session_id}", self.session_version_key_prefix)
     }
 
+    pub(super) fn token_key(&self, token: &str) -> String {
+        format!("{}:{}", self.token_key_prefix, persisted_token_hash(token))
+    }
+
+    pub(super) fn session_tokens_key(&self, session_id: &str) -> String {
+        format!("{}:{session_id}", self.session_tokens_key_prefix)
+    }
+
+    fn session_token_keys(&self, session: &SessionRecord, now: i64) -> Vec<String> {
+        session_lookup_tokens(session, now)
+            .into_iter()
+            .map(|token| self.token_key(token))
+            .collect()
+    }
+
     async fn load_sessions(
         &self,
     ) -> Result<(HashMap<String, SessionRecord>, HashMap<String, u64>), String> {
@@ -273,11 +415,107 @@ impl RelayStatePersistence {
         Ok((sessions, persistence_versions))
     }
 
+    async fn load_session(&self, session_id: &str) -> Result<Option<(SessionRecord, u64)>, String> {
+        let mut connection = self
+            .redis_client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|error| format!("redis connection failed: {error}"))?;
+
+        let (persisted_version, payload): (Option<u64>, Option<String>) = redis::pipe()
+            .get(self.session_version_key(session_id))
+            .get(self.session_key(session_id))
+            .query_async(&mut connection)
+            .await
+            .map_err(|error| format!("redis get session failed: {error}"))?;
+        let Some(payload) = payload else {
+            return Ok(None);
+        };
+
+        let parsed = serde_json::from_str::<PersistedSessionRecord>(&payload)
+            .map_err(|error| format!("malformed persisted session: {error}"))?;
+        let Some(runtime) = parsed.into_runtime() else {
+            return Err("unsupported persisted session".to_string());
+        };
+        Ok(Some((runtime, persisted_version.unwrap_or(0))))
+    }
+
+    /// Persisted version of each session, in order. Deletes also bump the version.
+    async fn persisted_session_versions(
+        &self,
+        session_ids: &[String],
+    ) -> Result<Vec<Option<u64>>, String> {
+        let mut connection = self
+            .redis_client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|error| format!("redis connection failed: {error}"))?;
+
+        let version_keys = session_ids
+            .iter()
+            .map(|session_id| self.session_version_key(session_id))
+            .collect::<Vec<_>>();
+        redis::cmd("MGET")
+            .arg(version_keys)
+            .query_async(&mut connection)
+            .await
+            .map_err(|error| format!("redis session version lookup failed: {error}"))
+    }
+
+    async fn lookup_token_session_id(&self, token: &str) -> Result<Option<String>, String> {
+        let mut connection = self
+            .redis_client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|error| format!("redis connection failed: {error}"))?;
+
+        connection
+            .get(self.token_key(token))
+            .await
+            .map_err(|error| format!("redis get token failed: {error}"))
+    }
+
+    /// Adds token index entries for sessions written before the index existed. Entries are only
+    /// ever added here; stale ones are pruned by the next save of that session.
+    async fn backfill_token_index(
+        &self,
+        sessions: &HashMap<String, SessionRecord>,
+    ) -> Result<(), String> {
+        let mut connection = self
+            .redis_client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|error| format!("redis connection failed: {error}"))?;
+
+        let now = now_ms();
+        let mut pipe = redis::pipe();
+        for (session_id, session) in sessions {
+            let token_keys = self.session_token_keys(session, now);
+            if token_keys.is_empty() {
+                continue;
+            }
+            for token_key in &token_keys {
+                pipe.cmd("SET")
+                    .arg(token_key)
+                    .arg(session_id)
+                    .arg("NX")
+                    .ignore();
+            }
+            pipe.sadd(self.session_tokens_key(session_id), token_keys)
+                .ignore();
+        }
+        pipe.query_async::<()>(&mut connection)
+            .await
+            .map_err(|error| format!("redis token index backfill failed: {error}"))
+    }
+
     async fn save_session(&self, session: &SessionRecord, version: u64) -> Result<(), String> {
         let payload = serde_json::to_string(&PersistedSessionRecord::from_session(session))
             .map_err(|error| format!("persisted session encode failed: {error}"))?;
         let key = self.session_key(&session.session_id);
         let version_key = self.session_version_key(&session.session_id);
+        let session_tokens_key = self.session_tokens_key(&session.session_id);
+        let token_keys = self.session_token_keys(session, now_ms());
 
         let mut connection = self
             .redis_client
@@ -285,6 +523,9 @@ impl RelayStatePersistence {
             .await
             .map_err(|error| format!("redis connection failed: {error}"))?;
 
+        // KEYS[5..] are the token index keys that should resolve to this session after the save.
+        // Keys left over from rotated or revoked tokens are dropped, but only while they still
+        // point at this session.
         let script = redis::Script::new(
             r#"
             local current_version = redis.call("GET", KEYS[2])
@@ -292,19 +533,41 @@ impl RelayStatePersistence {
                 redis.call("SET", KEYS[1], ARGV[2])
                 redis.call("SADD", KEYS[3], ARGV[3])
                 redis.call("SET", KEYS[2], ARGV[1])
+                local next_token_keys = {}
+                for index = 5, #KEYS do
+                    next_token_keys[KEYS[index]] = true
+                end
+                for _, token_key in ipairs(redis.call("SMEMBERS", KEYS[4])) do
+                    if (not next_token_keys[token_key]) and redis.call("GET", token_key) == ARGV[3] then
+                        redis.call("DEL", token_key)
+                    end
+                end
+                redis.call("DEL", KEYS[4])
+                for index = 5, #KEYS do
+                    redis.call("SET", KEYS[index], ARGV[3])
+                    redis.call("SADD", KEYS[4], KEYS[index])
+                end
+                redis.call("PUBLISH", ARGV[4], ARGV[1] .. ":" .. ARGV[3])
                 return 1
             end
             return 0
             "#,
         );
 
-        script
+        let mut invocation = script.prepare_invoke();
+        invocation
             .key(&key)
             .key(&version_key)
             .key(&self.session_index_key)
+            .key(&session_tokens_key);
+        for token_key in &token_keys {
+            invocation.key(token_key);
+        }
+        invocation
             .arg(version)
             .arg(payload)
             .arg(&session.session_id)
+            .arg(&self.session_changes_channel)
             .invoke_async::<i32>(&mut connection)
             .await
             .map_err(|error| format!("redis save session script failed: {error}"))?;
@@ -315,6 +578,7 @@ impl RelayStatePersistence {
     async fn delete_session(&self, session_id: &str, version: u64) -> Result<(), String> {
         let key = self.session_key(session_id);
         let version_key = self.session_version_key(session_id);
+        let session_tokens_key = self.session_tokens_key(session_id);
         let mut connection = self
             .redis_client
             .get_multiplexed_async_connection()
@@ -327,6 +591,14 @@ impl RelayStatePersistence {
                 redis.call("DEL", KEYS[1])
                 redis.call("SREM", KEYS[3], ARGV[2])
                 redis.call("SET", KEYS[2], ARGV[1])
+                for _, token_key in ipairs(redis.call("SMEMBERS", KEYS[4])) do
+                    if redis.call("GET", token_key) == ARGV[2] then
+                        redis.call("DEL", token_key)
+                    end
+                end
+                redis.call("DEL", KEYS[4])
+                redis.call("DEL", KEYS[5])
+                redis.call("PUBLISH", ARGV[3], ARGV[1] .. ":" .. ARGV[2])
                 return 1
             end
             return 0
@@ -336,8 +608,11 @@ impl RelayStatePersistence {
             .key(&key)
             .key(&version_key)
             .key(&self.session_index_key)
+            .key(&session_tokens_key)
+            .key(self.snapshot_key(session_id))
             .arg(version)
             .arg(session_id)
+            .arg(&self.session_changes_channel)
             .invoke_async::<i32>(&mut connection)
             .await
             .map_err(|error| format!("redis delete session script failed: {error}"))?;
@@ -347,9 +622,30 @@ impl RelayStatePersistence {
 }
 
 pub async fn new_state(config: RelayConfig) -> SharedRelayState {
-    let cross_instance_bus = build_cross_instance_bus(&config).await;
+    let transport = connect_nats_cross_instance_bus(&config).await;
+    build_state(config, transport).await
+}
+
+/// Builds relay state that routes cross-instance traffic over `bus` instead of connecting to
+/// `NATS_URL`, so several instances in one process can share an [`InProcessCrossInstanceBus`].
+pub async fn new_state_with_cross_instance_bus(
+    config: RelayConfig,
+    bus: Arc<dyn CrossInstanceBus>,
+) -> SharedRelayState {
+    build_state(config, Some(bus)).await
+}
+
+async fn build_state(
+    config: RelayConfig,
+    transport: Option<Arc<dyn CrossInstanceBus>>,
+) -> SharedRelayState {
+    let presence = transport
+        .as_ref()
+        .and_then(|_| build_presence_registry(&config));
+    let cross_instance_bus =
+        transport.map(|transport| build_cross_instance_bus(&config, transport, presence.clone()));
     let persistence = build_persistence(&config);
-    let runtime = if let Some(persistence) = &persistence {
+    let mut runtime = if let Some(persistence) = &persistence {
         match persistence.load_sessions().await {
             Ok((sessions, persistence_versions)) => {
                 let token_count = sessions
@@ -361,6 +657,9 @@ pub async fn new_state(config: RelayConfig) -> SharedRelayState {
                     sessions.len(),
                     token_count
                 );
+                if let Err(error) = persistence.backfill_token_index(&sessions).await {
+                    warn!("[relay-rs] failed to backfill persisted token index: {error}");
+                }
                 RelayState {
                     desktop_token_index: build_desktop_token_index(&sessions),
                     device_token_index: build_device_token_index(&sessions),
@@ -378,11 +677,21 @@ pub async fn new_state(config: RelayConfig) -> SharedRelayState {
                     ws_auth_attempts: 0,
                     ws_auth_successes: 0,
                     ws_auth_failure_reasons: HashMap::new(),
-                    last_persistence_refresh_at_ms: now_ms(),
+                    unknown_token_cache: HashMap::new(),
+                    session_refreshed_at_ms: HashMap::new(),
                     persistence_versions,
                     seen_cross_instance_nonces: HashMap::new(),
+                    envelope_signature_failures: HashMap::new(),
                     bus_subscribed_sessions: HashSet::new(),
                     bus_subscription_tasks: HashMap::new(),
+                    presence_claims: HashMap::new(),
+                    lifecycle_events: None,
+                    webhook_stats: WebhookDeliveryStats::default(),
+                    penalty_records: HashMap::new(),
+                    penalty_blocks_issued: 0,
+                    penalty_box_rejections: 0,
+                    ip_access_rejections: HashMap::new(),
+                    byte_quota_drops: 0,
                 }
             }
             Err(error) => {
@@ -404,11 +713,21 @@ pub async fn new_state(config: RelayConfig) -> SharedRelayState {
                     ws_auth_attempts: 0,
                     ws_auth_successes: 0,
                     ws_auth_failure_reasons: HashMap::new(),
-                    last_persistence_refresh_at_ms: 0,
+                    unknown_token_cache: HashMap::new(),
+                    session_refreshed_at_ms: HashMap::new(),
                     persistence_versions: HashMap::new(),
                     seen_cross_instance_nonces: HashMap::new(),
+                    envelope_signature_failures: HashMap::new(),
                     bus_subscribed_sessions: HashSet::new(),
                     bus_subscription_tasks: HashMap::new(),
+                    presence_claims: HashMap::new(),
+                    lifecycle_events: None,
+                    webhook_stats: WebhookDeliveryStats::default(),
+                    penalty_records: HashMap::new(),
+                    penalty_blocks_issued: 0,
+                    penalty_box_rejections: 0,
+                    ip_access_rejections: HashMap::new(),
+                    byte_quota_drops: 0,
                 }
             }
         }
@@ -430,23 +749,68 @@ pub async fn new_state(config: RelayConfig) -> SharedRelayState {
             ws_auth_attempts: 0,
             ws_auth_successes: 0,
             ws_auth_failure_reasons: HashMap::new(),
-            last_persistence_refresh_at_ms: 0,
+            unknown_token_cache: HashMap::new(),
+            session_refreshed_at_ms: HashMap::new(),
             persistence_versions: HashMap::new(),
             seen_cross_instance_nonces: HashMap::new(),
+            envelope_signature_failures: HashMap::new(),
             bus_subscribed_sessions: HashSet::new(),
             bus_subscription_tasks: HashMap::new(),
+            presence_claims: HashMap::new(),
+            lifecycle_events: None,
+            webhook_stats: WebhookDeliveryStats::default(),
+            penalty_records: HashMap::new(),
+            penalty_blocks_issued: 0,
+            penalty_box_rejections: 0,
+            ip_access_rejections: HashMap::new(),
+            byte_quota_drops: 0,
         }
     };
 
+    let envelope_keyring = config.load_nats_hmac_keyring().unwrap_or_else(|error| {
+        warn!("[relay-rs] failed to load NATS HMAC keyring: {error}");
+        NatsHmacKeyring::default()
+    });
+    let ip_access_rules = config.load_ip_access_rules().unwrap_or_else(|error| {
+        warn!("[relay-rs] failed to load IP access rules: {error}");
+        IpAccessRules::default()
+    });
+    let webhooks = build_webhooks(&config);
+    let penalty_store = build_penalty_store(&config);
+    let lifecycle_events = if webhooks
+        .as_ref()
+        .is_some_and(|webhooks| !webhooks.lifecycle.is_empty())
+    {
+        let (sender, receiver) = mpsc::channel(LIFECYCLE_EVENT_QUEUE_CAPACITY);
+        runtime.lifecycle_events = Some(sender);
+        Some(receiver)
+    } else {
+        None
+    };
     let state = SharedRelayState {
         config,
         inner: Arc::new(Mutex::new(runtime)),
         persistence,
         cross_instance_bus,
+        presence,
+        envelope_keyring: Arc::new(std::sync::RwLock::new(envelope_keyring)),
+        webhooks,
+        penalty_store,
+        ip_access_rules: Arc::new(std::sync::RwLock::new(ip_access_rules)),
+        async_pair_updates: Arc::new(Notify::new()),
     };
 
     start_session_sweeper(state.clone());
     start_control_subscription(state.clone());
+    start_persistence_invalidation_subscription(state.clone());
+    start_envelope_keyring_reloader(state.clone());
+    start_ip_access_rules_reloader(state.clone());
+    start_presence_heartbeat(state.clone());
+    start_presence_change_subscription(state.clone());
+    start_instance_subscription(state.clone());
+    if let Some(lifecycle_events) = lifecycle_events {
+        start_lifecycle_webhook_worker(state.clone(), lifecycle_events);
+    }
     state
 }
 
@@ -465,48 +829,139 @@ pub(super) fn build_persistence(config: &RelayConfig) -> Option<RelayStatePersis
         session_index_key: format!("{}:sessions:index:v1", config.redis_key_prefix),
         session_key_prefix: format!("{}:session:v1", config.redis_key_prefix),
         session_version_key_prefix: format!("{}:session:version:v1", config.redis_key_prefix),
+        token_key_prefix: format!("{}:token:v1", config.redis_key_prefix),
+        session_tokens_key_prefix: format!("{}:session:tokens:v1", config.redis_key_prefix),
+        session_changes_channel: format!("{}:session:changes:v1", config.redis_key_prefix),
+        snapshot_key_prefix: format!("{}:session:snapshot:v1", config.redis_key_prefix),
+        transfer_key_prefix: format!("{}:session:transfer:v1", config.redis_key_prefix),
     })
 }
 
-pub(super) async fn build_cross_instance_bus(
+pub(super) fn build_cross_instance_bus(
     config: &RelayConfig,
-) -> Option<RelayCrossInstanceBus> {
-    let nats_url = config.nats_url.as_ref()?;
-    let client = match async_nats::connect(nats_url).await {
-        Ok(client) => client,
-        Err(error) => {
-            warn!("[relay-rs] failed to connect to NATS; cross-instance bus disabled: {error}");
-            return None;
-        }
-    };
-
-    let instance_id = random_token(10);
-    let redacted_nats_url = redact_url_for_logs(nats_url);
+    transport: Arc<dyn CrossInstanceBus>,
+    presence: Option<RelayPresenceRegistry>,
+) -> RelayCrossInstanceBus {
+    let instance_id = config
+        .instance_id
+        .clone()
+        .unwrap_or_else(|| random_token(10));
     info!(
-        "[relay-rs] connected to NATS for cross-instance routing: url={} subject_prefix={} instance={}",
-        redacted_nats_url, config.nats_subject_prefix, instance_id
+        "[relay-rs] cross-instance routing enabled: subject_prefix={} instance={}",
+        config.nats_subject_prefix, instance_id
     );
 
-    let (publish_tx, mut publish_rx) = mpsc::unbounded_channel::<QueuedCrossInstancePublish>();
-    let publish_client = client.clone();
-    tokio::spawn(async move {
-        while let Some(message) = publish_rx.recv().await {
-            if let Err(error) = publish_client
-                .publish(message.subject, message.payload.into())
-                .await
-            {
+    let mut publish_lanes = Vec::with_capacity(CROSS_INSTANCE_PUBLISH_LANES);
+    for _ in 0..CROSS_INSTANCE_PUBLISH_LANES {
+        let (publish_tx, publish_rx) =
+            mpsc::channel::<QueuedCrossInstancePublish>(CROSS_INSTANCE_PUBLISH_LANE_CAPACITY);
+        publish_lanes.push(publish_tx);
+        tokio::spawn(run_cross_instance_publish_lane(
+            publish_rx,
+            transport.clone(),
+            presence.clone(),
+            config.nats_subject_prefix.clone(),
+            instance_id.clone(),
+        ));
+    }
+
+    RelayCrossInstanceBus {
+        transport,
+        instance_id,
+        subject_prefix: config.nats_subject_prefix.clone(),
+        publish_lanes: publish_lanes.into(),
+        publish_drops: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+    }
+}
+
+async fn run_cross_instance_publish_lane(
+    mut publish_rx: mpsc::Receiver<QueuedCrossInstancePublish>,
+    transport: Arc<dyn CrossInstanceBus>,
+    presence: Option<RelayPresenceRegistry>,
+    subject_prefix: String,
+    instance_id: String,
+) {
+    while let Some(message) = publish_rx.recv().await {
+        let subjects = match (&presence, &message.presence_route) {
+            (Some(presence), Some(route)) => {
+                match presence_route_subjects(presence, &subject_prefix, &instance_id, route).await
+                {
+                    Ok(subjects) => subjects,
+                    Err(error) => {
+                        warn!(
+                            "[relay-rs] failed to resolve presence for session={}: {error}",
+                            session_log_id(&route.session_id)
+                        );
+                        continue;
+                    }
+                }
+            }
+            _ => vec![message.subject],
+        };
+        for subject in subjects {
+            if let Err(error) = transport.publish(subject, message.payload.clone()).await {
                 warn!("[relay-rs] failed to publish cross-instance payload: {error}");
             }
         }
-        warn!("[relay-rs] cross-instance publish queue closed");
-    });
+    }
+    warn!("[relay-rs] cross-instance publish queue closed");
+}
 
-    Some(RelayCrossInstanceBus {
-        client,
-        instance_id,
-        subject_prefix: config.nats_subject_prefix.clone(),
-        publish_tx,
-    })
+impl RelayCrossInstanceBus {
+    pub(super) fn publish_lane(&self, subject: &str) -> &mpsc::Sender<QueuedCrossInstancePublish> {
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        subject.hash(&mut hasher);
+        &self.publish_lanes[(hasher.finish() % self.publish_lanes.len() as u64) as usize]
+    }
+
+    /// Queues a publish without waiting. When the subject's lane is full the publish is dropped
+    /// and counted rather than buffered without bound.
+    pub(super) fn enqueue_publish(&self, message: QueuedCrossInstancePublish) {
+        match self.publish_lane(&message.subject).try_send(message) {
+            Ok(()) => {}
+            Err(TrySendError::Full(message)) => {
+                let drops = self
+                    .publish_drops
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+                    + 1;
+                // Logged at powers of two so a saturated lane does not flood the log.
+                if !drops.is_power_of_two() {
+                    return;
+                }
+                warn!(
+                    "[relay-rs] cross_instance_publish_dropped reason=queue_full subject={} drops={drops}",
+                    message.subject
+                );
+            }
+            Err(TrySendError::Closed(_)) => {
+                warn!("[relay-rs] failed to enqueue cross-instance payload: publish queue closed");
+            }
+        }
+    }
+}
+
+pub(super) async fn subscribe_cross_instance_subject(
+    bus: &RelayCrossInstanceBus,
+    subject: &str,
+    consumer_name: &str,
+) -> Result<futures_util::stream::BoxStream<'static, CrossInstanceBusMessage>, String> {
+    bus.transport
+        .subscribe(subject.to_string(), consumer_name.to_string())
+        .await
+}
+
+pub(super) fn release_cross_instance_consumer(bus: &RelayCrossInstanceBus, consumer_name: String) {
+    bus.transport.release_subscription(consumer_name);
+}
+
+pub(super) fn nats_session_consumer_name(bus: &RelayCrossInstanceBus, session_id: &str) -> String {
+    format!("{}-session-{session_id}", bus.instance_id)
+}
+
+pub(super) fn nats_control_consumer_name(bus: &RelayCrossInstanceBus) -> String {
+    format!("{}-control", bus.instance_id)
 }
 
 pub(super) fn nats_session_subject(bus: &RelayCrossInstanceBus, session_id: &str) -> String {
@@ -550,26 +1005,117 @@ fn envelope_signature(secret: &str, envelope: &CrossInstanceEnvelope) -> Option<
     Some(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
 }
 
-fn envelope_signature_valid(state: &SharedRelayState, envelope: &CrossInstanceEnvelope) -> bool {
-    let Some(secret) = state.config.nats_hmac_secret.as_ref() else {
-        return true;
+fn read_envelope_keyring(
+    state: &SharedRelayState,
+) -> std::sync::RwLockReadGuard<'_, NatsHmacKeyring> {
+    state
+        .envelope_keyring
+        .read()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+pub(super) fn envelope_signing_enabled(state: &SharedRelayState) -> bool {
+    state.config.nats_hmac_secret.is_some() || !read_envelope_keyring(state).keys.is_empty()
+}
+
+/// Signs with the active keyring key and records its ID on the envelope, or with the legacy
+/// `NATS_HMAC_SECRET` without a key ID so pods that predate the keyring still verify it.
+pub(super) fn sign_cross_instance_envelope(
+    state: &SharedRelayState,
+    envelope: &mut CrossInstanceEnvelope,
+) {
+    let keyring = read_envelope_keyring(state);
+    let (key_id, secret) = match keyring.active_key() {
+        Some((key_id, secret)) => (Some(key_id.to_string()), secret),
+        None => match state.config.nats_hmac_secret.as_deref() {
+            Some(secret) => (None, secret),
+            None => return,
+        },
+    };
+    envelope.key_id = key_id;
+    envelope.signature = envelope_signature(secret, envelope);
+}
+
+/// The key ID is only a hint for choosing the verification key and is not covered by the
+/// signature. Envelopes naming a key this pod does not know are checked against the legacy
+/// secret, which keeps mixed fleets working during a rollout. On failure the error is the key
+/// label reported in metrics: the key ID, `legacy`, or `unknown`.
+fn envelope_signature_valid(
+    state: &SharedRelayState,
+    envelope: &CrossInstanceEnvelope,
+) -> Result<(), String> {
+    if !envelope_signing_enabled(state) {
+        return Ok(());
+    }
+
+    let keyring = read_envelope_keyring(state);
+    let keyring_secret = envelope
+        .key_id
+        .as_deref()
+        .and_then(|key_id| keyring.key(key_id).map(|secret| (key_id, secret)));
+    let (key_label, secret) = match keyring_secret {
+        Some((key_id, secret)) => (key_id.to_string(), secret),
+        None => match state.config.nats_hmac_secret.as_deref() {
+            Some(secret) => ("legacy".to_string(), secret),
+            None => return Err("unknown".to_string()),
+        },
     };
+
     let Some(encoded_signature) = envelope.signature.as_deref() else {
-        return false;
+        return Err(key_label);
     };
     let Ok(signature_bytes) =
         base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(encoded_signature)
     else {
-        return false;
+        return Err(key_label);
     };
     let Some(material) = envelope_signature_material(envelope) else {
-        return false;
+        return Err(key_label);
     };
     let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
-        return false;
+        return Err(key_label);
     };
     mac.update(&material);
-    mac.verify_slice(&signature_bytes).is_ok()
+    mac.verify_slice(&signature_bytes).map_err(|_| key_label)
+}
+
+/// Re-reads `NATS_HMAC_KEYS_FILE` periodically so keys can be added, promoted and retired across
+/// a live fleet. A file that fails to load keeps the previous keyring in place.
+pub(super) fn start_envelope_keyring_reloader(state: SharedRelayState) {
+    if state.config.nats_hmac_keys_file.is_none() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let reload_interval = Duration::from_millis(state.config.nats_hmac_keys_reload_interval_ms);
+        loop {
+            sleep(reload_interval).await;
+            let keyring = match state.config.load_nats_hmac_keyring() {
+                Ok(keyring) => keyring,
+                Err(error) => {
+                    warn!("[relay-rs] failed to reload NATS HMAC keyring: {error}");
+                    continue;
+                }
+            };
+            let mut current = state
+                .envelope_keyring
+                .write()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            if *current != keyring {
+                info!(
+                    "[relay-rs] reloaded NATS HMAC keyring active_key_id={} key_ids={}",
+                    keyring.active_key_id.as_deref().unwrap_or("-"),
+                    keyring
+                        .keys
+                        .iter()
+                        .map(|(key_id, _)| key_id.as_str())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                );
+                *current = keyring;
+            }
+        }
+    });
 }
 
 fn cross_instance_nonce_key(envelope: &CrossInstanceEnvelope) -> Option<String> {
@@ -632,14 +1178,20 @@ async fn envelope_is_valid_for_processing(
     if envelope.schema_version != 1 {
         return false;
     }
-    if !envelope_signature_valid(state, envelope) {
+    if let Err(key_label) = envelope_signature_valid(state, envelope) {
+        let mut relay = state.inner.lock().await;
+        let count = relay
+            .envelope_signature_failures
+            .entry(key_label)
+            .or_insert(0);
+        *count = count.saturating_add(1);
         return false;
     }
     if envelope.source_instance_id == local_instance_id {
         return false;
     }
 
-    if state.config.nats_hmac_secret.is_some() {
+    if envelope_signing_enabled(state) {
         let now = now_ms();
         let mut relay = state.inner.lock().await;
         register_cross_instance_nonce(
@@ -661,53 +1213,196 @@ pub(super) fn start_control_subscription(state: SharedRelayState) {
 
     tokio::spawn(async move {
         let subject = nats_control_subject(&bus);
+        let consumer_name = nats_control_consumer_name(&bus);
+        loop {
+            let mut subscription =
+                match subscribe_cross_instance_subject(&bus, &subject, &consumer_name).await {
+                    Ok(subscription) => subscription,
+                    Err(error) => {
+                        warn!("[relay-rs] failed to subscribe control subject {subject}: {error}");
+                        sleep(BUS_SUBSCRIBE_RETRY_DELAY).await;
+                        continue;
+                    }
+                };
+
+            while let Some(message) = subscription.next().await {
+                handle_control_envelope(&state, &bus.instance_id, message.payload()).await;
+                message.ack().await;
+            }
+
+            warn!("[relay-rs] control subscription ended; retrying");
+            sleep(BUS_SUBSCRIBE_RETRY_DELAY).await;
+        }
+    });
+}
+
+async fn handle_control_envelope(
+    state: &SharedRelayState,
+    local_instance_id: &str,
+    payload: &[u8],
+) {
+    let Ok(envelope) = serde_json::from_slice::<CrossInstanceEnvelope>(payload) else {
+        return;
+    };
+    if !envelope_is_valid_for_processing(state, &envelope, local_instance_id).await {
+        return;
+    }
+    match envelope.target.as_str() {
+        "pair_decision" => {
+            apply_pair_decision_from_envelope(state, &envelope).await;
+        }
+        "session_refresh" => {
+            refresh_session_from_persistence(state, &envelope.session_id).await;
+        }
+        "desktop_status_probe" => {
+            handle_desktop_status_probe_from_envelope(state, &envelope).await;
+        }
+        "desktop_status_response" => {
+            apply_desktop_status_response_from_envelope(state, &envelope, local_instance_id).await;
+        }
+        _ => {}
+    }
+}
+
+/// Every successful save or delete publishes `<version>:<sessionID>` on the session changes
+/// channel. Instances reload only sessions they hold locally, and skip changes they already have,
+/// which also covers their own writes. Pub/sub drops messages while the subscriber is away, so
+/// local sessions are reconciled against their persisted versions after every (re)subscribe and
+/// every `PERSISTENCE_RECONCILE_INTERVAL` after that.
+pub(super) fn start_persistence_invalidation_subscription(state: SharedRelayState) {
+    let Some(persistence) = state.persistence.clone() else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let channel = persistence.session_changes_channel.clone();
         loop {
-            let mut subscription = match bus.client.subscribe(subject.clone()).await {
-                Ok(subscription) => subscription,
+            let mut pubsub = match persistence.redis_client.get_async_pubsub().await {
+                Ok(pubsub) => pubsub,
                 Err(error) => {
-                    warn!("[relay-rs] failed to subscribe control subject {subject}: {error}");
+                    warn!("[relay-rs] failed to connect redis pubsub for {channel}: {error}");
                     sleep(BUS_SUBSCRIBE_RETRY_DELAY).await;
                     continue;
                 }
             };
+            if let Err(error) = pubsub.subscribe(&channel).await {
+                warn!("[relay-rs] failed to subscribe redis channel {channel}: {error}");
+                sleep(BUS_SUBSCRIBE_RETRY_DELAY).await;
+                continue;
+            }
+            // Changes published while the subscription was down are gone for good.
+            reconcile_local_sessions_with_persistence(&state).await;
 
-            while let Some(message) = subscription.next().await {
-                let Ok(envelope) =
-                    serde_json::from_slice::<CrossInstanceEnvelope>(&message.payload)
-                else {
-                    continue;
-                };
-                if !envelope_is_valid_for_processing(&state, &envelope, &bus.instance_id).await {
-                    continue;
-                }
-                match envelope.target.as_str() {
-                    "pair_decision" => {
-                        apply_pair_decision_from_envelope(&state, &envelope).await;
-                    }
-                    "session_refresh" => {
-                        refresh_sessions_from_persistence(&state, true).await;
+            let mut messages = pubsub.into_on_message();
+            let mut reconcile = interval(PERSISTENCE_RECONCILE_INTERVAL);
+            reconcile.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            reconcile.tick().await;
+            loop {
+                tokio::select! {
+                    message = messages.next() => {
+                        let Some(message) = message else {
+                            break;
+                        };
+                        let Ok(payload) = message.get_payload::<String>() else {
+                            continue;
+                        };
+                        apply_persisted_session_change(&state, &payload).await;
                     }
-                    "desktop_status_probe" => {
-                        handle_desktop_status_probe_from_envelope(&state, &envelope).await;
+                    _ = reconcile.tick() => {
+                        reconcile_local_sessions_with_persistence(&state).await;
                     }
-                    "desktop_status_response" => {
-                        apply_desktop_status_response_from_envelope(
-                            &state,
-                            &envelope,
-                            &bus.instance_id,
-                        )
-                        .await;
-                    }
-                    _ => continue,
                 }
             }
 
-            warn!("[relay-rs] control subscription ended; retrying");
+            warn!("[relay-rs] redis invalidation subscription ended; retrying");
             sleep(BUS_SUBSCRIBE_RETRY_DELAY).await;
         }
     });
 }
 
+/// Reloads every local session whose persisted version moved past the local one, which covers
+/// saves and deletes made elsewhere. Sessions that are current only cost a version read.
+pub(super) async fn reconcile_local_sessions_with_persistence(state: &SharedRelayState) {
+    let Some(persistence) = state.persistence.as_ref().cloned() else {
+        return;
+    };
+    let local_versions = {
+        let relay = state.inner.lock().await;
+        relay
+            .sessions
+            .keys()
+            .map(|session_id| {
+                (
+                    session_id.clone(),
+                    relay
+                        .persistence_versions
+                        .get(session_id)
+                        .copied()
+                        .unwrap_or(0),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut stale_session_ids = Vec::new();
+    for batch in local_versions.chunks(PERSISTENCE_RECONCILE_BATCH) {
+        let session_ids = batch
+            .iter()
+            .map(|(session_id, _)| session_id.clone())
+            .collect::<Vec<_>>();
+        let persisted = match persistence.persisted_session_versions(&session_ids).await {
+            Ok(persisted) => persisted,
+            Err(error) => {
+                warn!("[relay-rs] failed to reconcile sessions with persistence: {error}");
+                return;
+            }
+        };
+        for ((session_id, local_version), persisted_version) in batch.iter().zip(persisted) {
+            if persisted_version.is_some_and(|version| version > *local_version) {
+                stale_session_ids.push(session_id.clone());
+            }
+        }
+    }
+
+    if !stale_session_ids.is_empty() {
+        info!(
+            "[relay-rs] persistence_reconcile stale_sessions={}",
+            stale_session_ids.len()
+        );
+    }
+    for session_id in stale_session_ids {
+        refresh_session_from_persistence(state, &session_id).await;
+    }
+}
+
+pub(super) fn parse_persisted_session_change(payload: &str) -> Option<(u64, &str)> {
+    let (version, session_id) = payload.split_once(':')?;
+    let version = version.parse::<u64>().ok()?;
+    is_opaque_token(session_id, 16).then_some((version, session_id))
+}
+
+pub(super) async fn apply_persisted_session_change(state: &SharedRelayState, payload: &str) {
+    let Some((version, session_id)) = parse_persisted_session_change(payload) else {
+        return;
+    };
+
+    {
+        let relay = state.inner.lock().await;
+        if !relay.sessions.contains_key(session_id) {
+            return;
+        }
+        if relay
+            .persistence_versions
+            .get(session_id)
+            .is_some_and(|local_version| *local_version >= version)
+        {
+            return;
+        }
+    }
+
+    refresh_session_from_persistence(state, session_id).await;
+}
+
 pub(super) fn publish_cross_instance_session(
     state: &SharedRelayState,
     session_id: &str,
@@ -722,6 +1417,26 @@ pub(super) fn publish_cross_instance_session(
         target,
         target_device_id,
         payload,
+        OutboundLane::Control,
+    );
+}
+
+/// Desktop or mobile traffic forwarded to the peers held by other instances. It goes out on
+/// their bulk lane, like local forwarding.
+pub(super) fn publish_cross_instance_forwarded(
+    state: &SharedRelayState,
+    session_id: &str,
+    target: &str,
+    payload: String,
+) {
+    publish_cross_instance_envelope(
+        state,
+        nats_session_subject,
+        session_id,
+        target,
+        None,
+        payload,
+        OutboundLane::Bulk,
     );
 }
 
@@ -737,6 +1452,7 @@ pub(super) fn publish_cross_instance_control_pair_decision(
         "pair_decision",
         None,
         payload,
+        OutboundLane::Control,
     );
 }
 
@@ -755,6 +1471,28 @@ pub(super) fn publish_cross_instance_control_session_refresh(
             "sessionID": session_id,
         })
         .to_string(),
+        OutboundLane::Control,
+    );
+}
+
+/// Tells the instance holding the desktop that `replaced_desktop_token` was given up in a session
+/// transfer, so it drops that desktop without closing the session for the mobiles.
+pub(super) fn publish_cross_instance_desktop_replaced(
+    state: &SharedRelayState,
+    session_id: &str,
+    replaced_desktop_token: &str,
+) {
+    publish_cross_instance_session(
+        state,
+        session_id,
+        "desktop_replaced",
+        None,
+        json!({
+            "type": "relay.desktop_replaced",
+            "sessionID": session_id,
+            "replacedDesktopTokenHash": persisted_token_hash(replaced_desktop_token),
+        })
+        .to_string(),
     );
 }
 
@@ -778,6 +1516,7 @@ pub(super) fn publish_cross_instance_control_desktop_status_probe(
             "requestingInstanceID": bus.instance_id,
         })
         .to_string(),
+        OutboundLane::Control,
     );
 }
 
@@ -800,6 +1539,7 @@ pub(super) fn publish_cross_instance_control_desktop_status_response(
             "desktopConnected": desktop_connected,
         })
         .to_string(),
+        OutboundLane::Control,
     );
 }
 
@@ -810,6 +1550,7 @@ pub(super) fn publish_cross_instance_envelope(
     target: &str,
     target_device_id: Option<String>,
     payload: String,
+    lane: OutboundLane,
 ) {
     let Some(bus) = state.cross_instance_bus.clone() else {
         return;
@@ -825,13 +1566,11 @@ pub(super) fn publish_cross_instance_envelope(
         issued_at_ms: now_ms(),
         nonce: random_token(10),
         signature: None,
+        key_id: None,
+        control_lane: lane == OutboundLane::Control,
     };
     let mut signed_envelope = envelope;
-    signed_envelope.signature = state
-        .config
-        .nats_hmac_secret
-        .as_ref()
-        .and_then(|secret| envelope_signature(secret, &signed_envelope));
+    sign_cross_instance_envelope(state, &mut signed_envelope);
     let subject = subject_builder(&bus, session_id);
     let encoded = match serde_json::to_vec(&signed_envelope) {
         Ok(encoded) => encoded,
@@ -840,18 +1579,30 @@ pub(super) fn publish_cross_instance_envelope(
             return;
         }
     };
-    if let Err(error) = bus.publish_tx.send(QueuedCrossInstancePublish {
+    let presence_target = match target {
+        "desktop" | "desktop_replaced" => Some("desktop"),
+        "mobile" => Some("mobile"),
+        _ => None,
+    };
+    let presence_route = presence_target.map(|presence_target| PresenceRoute {
+        session_id: session_id.to_string(),
+        target: presence_target.to_string(),
+    });
+    bus.enqueue_publish(QueuedCrossInstancePublish {
         subject,
         payload: encoded,
-    }) {
-        warn!("[relay-rs] failed to enqueue cross-instance payload: {error}");
-    }
+        presence_route,
+    });
 }
 
 pub(super) async fn sync_session_bus_subscription(state: &SharedRelayState, session_id: &str) {
     let Some(bus) = state.cross_instance_bus.clone() else {
         return;
     };
+    if let Some(presence) = &state.presence {
+        sync_session_presence(state, presence, &bus.instance_id, session_id).await;
+        return;
+    }
 
     let mut relay = state.inner.lock().await;
     let should_subscribe = relay
@@ -867,20 +1618,27 @@ pub(super) async fn sync_session_bus_subscription(state: &SharedRelayState, sess
         let state_clone = state.clone();
         let bus_clone = bus.clone();
         let local_instance_id = bus.instance_id.clone();
+        let consumer_name = nats_session_consumer_name(&bus, &session_id_owned);
         let handle = tokio::spawn(async move {
             loop {
-                let mut subscription = match bus_clone.client.subscribe(subject.clone()).await {
-                    Ok(subscription) => subscription,
-                    Err(error) => {
-                        warn!("[relay-rs] failed to subscribe session subject {subject}: {error}");
-                        sleep(BUS_SUBSCRIBE_RETRY_DELAY).await;
-                        continue;
-                    }
-                };
+                let mut subscription =
+                    match subscribe_cross_instance_subject(&bus_clone, &subject, &consumer_name)
+                        .await
+                    {
+                        Ok(subscription) => subscription,
+                        Err(error) => {
+                            warn!(
+                                "[relay-rs] failed to subscribe session subject {subject}: {error}"
+                            );
+                            sleep(BUS_SUBSCRIBE_RETRY_DELAY).await;
+                            continue;
+                        }
+                    };
 
                 while let Some(message) = subscription.next().await {
-                    handle_session_envelope(&state_clone, &local_instance_id, &message.payload)
+                    handle_session_envelope(&state_clone, &local_instance_id, message.payload())
                         .await;
+                    message.ack().await;
                 }
 
                 warn!("[relay-rs] session subscription ended subject={subject}; retrying");
@@ -902,6 +1660,7 @@ pub(super) async fn sync_session_bus_subscription(state: &SharedRelayState, sess
         if let Some(task) = relay.bus_subscription_tasks.remove(session_id) {
             task.abort();
         }
+        release_cross_instance_consumer(&bus, nats_session_consumer_name(&bus, session_id));
         info!(
             "[relay-rs] unsubscribed session={} from cross-instance routing",
             session_log_id(session_id)
@@ -921,11 +1680,18 @@ pub(super) async fn handle_session_envelope(
         return;
     }
 
+    let lane = if envelope.control_lane {
+        OutboundLane::Control
+    } else {
+        OutboundLane::Bulk
+    };
     let mut relay = state.inner.lock().await;
     let mut revoked_device_id: Option<String> = None;
+    let mut replaced_desktop_token: Option<String> = None;
     let mut close_reason: Option<String> = None;
     let mut outbound_send_failures = 0_u64;
     let mut slow_consumer_disconnects = 0_u64;
+    let mut byte_quota_drops = 0_u64;
 
     {
         let Some(session) = relay.sessions.get_mut(&envelope.session_id) else {
@@ -949,16 +1715,44 @@ pub(super) async fn handle_session_envelope(
         match envelope.target.as_str() {
             "desktop" => {
                 if let Some(desktop) = &session.desktop_socket {
-                    if !try_send_payload(&desktop.tx, envelope.payload.clone()) {
+                    let payload = adapt_envelope(&envelope.payload, session.desktop_schema_version);
+                    let delivered = try_send_on_lane(
+                        &desktop.tx,
+                        lane,
+                        Message::Text(payload.into_owned().into()),
+                    );
+                    if !delivered {
                         outbound_send_failures = outbound_send_failures.saturating_add(1);
-                        slow_consumer_disconnects = slow_consumer_disconnects.saturating_add(1);
-                        request_socket_disconnect(desktop, "slow_consumer");
+                        if lane == OutboundLane::Bulk {
+                            slow_consumer_disconnects = slow_consumer_disconnects.saturating_add(1);
+                            request_socket_disconnect(desktop, "slow_consumer");
+                        }
                     }
                 }
                 if let Some(reason) = disconnect_reason {
                     close_reason = Some(reason);
                 }
             }
+            "desktop_replaced" => {
+                // Only the desktop authenticated with the replaced token is dropped; a desktop
+                // that already reconnected here with the new token is left alone.
+                let replaced_token_hash = serde_json::from_str::<Value>(&envelope.payload)
+                    .ok()
+                    .and_then(|value| {
+                        value
+                            .get("replacedDesktopTokenHash")
+                            .and_then(Value::as_str)
+                            .map(ToOwned::to_owned)
+                    });
+                if replaced_token_hash.as_deref()
+                    == Some(persisted_token_hash(&session.desktop_session_token).as_str())
+                {
+                    if let Some(desktop) = session.desktop_socket.take() {
+                        request_socket_disconnect(&desktop, "session_transferred");
+                    }
+                    replaced_desktop_token = Some(session.desktop_session_token.clone());
+                }
+            }
             "mobile" => {
                 if let Some(desktop_connected) = serde_json::from_str::<Value>(&envelope.payload)
                     .ok()
@@ -972,24 +1766,68 @@ pub(super) async fn handle_session_envelope(
                 }
 
                 if let Some(target_device_id) = envelope.target_device_id.as_deref() {
+                    // The removing instance names the reason (`device_revoked`, `device_evicted`)
+                    // in the disconnect payload it targets at the device.
                     close_existing_mobile_socket_for_device(
                         session,
                         target_device_id,
-                        "device_revoked",
+                        disconnect_reason.as_deref().unwrap_or("device_revoked"),
                     );
                     session.command_rate_buckets.remove(target_device_id);
                     session
                         .snapshot_request_rate_buckets
                         .remove(target_device_id);
                     session.devices.remove(target_device_id);
+                    session.traffic.remove_device(target_device_id);
                     send_device_count(session);
                     revoked_device_id = Some(target_device_id.to_string());
                 } else {
+                    // The desktop's instance persists snapshots; this copy only serves local
+                    // mobiles if the desktop later drops.
+                    if lane == OutboundLane::Bulk && state.config.snapshot_cache_max_bytes > 0 {
+                        let parsed = serde_json::from_str::<Value>(&envelope.payload).ok();
+                        if is_desktop_snapshot_payload(parsed.as_ref()) {
+                            cache_desktop_snapshot(
+                                session,
+                                &state.config,
+                                &envelope.payload,
+                                now_ms(),
+                            );
+                        }
+                    }
+                    let now = now_ms();
+                    let mut adapted = AdaptedEnvelopes::new(&envelope.payload);
                     for mobile in session.mobile_sockets.values() {
-                        if !try_send_payload(&mobile.tx, envelope.payload.clone()) {
+                        if lane == OutboundLane::Bulk {
+                            if let ByteQuotaDecision::Exceeded { notify } =
+                                session.traffic.admit_outbound(
+                                    &state.config,
+                                    mobile.device_id.as_deref(),
+                                    envelope.payload.len() as u64,
+                                    now,
+                                )
+                            {
+                                byte_quota_drops = byte_quota_drops.saturating_add(1);
+                                if notify {
+                                    send_byte_quota_error(&mobile.tx);
+                                }
+                                continue;
+                            }
+                        }
+                        let schema_version =
+                            device_schema_version(&session.devices, mobile.device_id.as_deref());
+                        let delivered = try_send_on_lane(
+                            &mobile.tx,
+                            lane,
+                            Message::Text(adapted.for_version(schema_version).to_string().into()),
+                        );
+                        if !delivered {
                             outbound_send_failures = outbound_send_failures.saturating_add(1);
-                            slow_consumer_disconnects = slow_consumer_disconnects.saturating_add(1);
-                            request_socket_disconnect(mobile, "slow_consumer");
+                            if lane == OutboundLane::Bulk {
+                                slow_consumer_disconnects =
+                                    slow_consumer_disconnects.saturating_add(1);
+                                request_socket_disconnect(mobile, "slow_consumer");
+                            }
                         }
                     }
                     if let Some(reason) = disconnect_reason {
@@ -1001,6 +1839,9 @@ pub(super) async fn handle_session_envelope(
         }
     }
 
+    if let Some(token) = replaced_desktop_token {
+        relay.desktop_token_index.remove(&token);
+    }
     if let Some(device_id) = revoked_device_id {
         relay.device_token_index.retain(|_, token| {
             !(token.session_id == envelope.session_id && token.device_id == device_id)
@@ -1012,8 +1853,9 @@ pub(super) async fn handle_session_envelope(
     relay.slow_consumer_disconnects = relay
         .slow_consumer_disconnects
         .saturating_add(slow_consumer_disconnects);
+    relay.byte_quota_drops = relay.byte_quota_drops.saturating_add(byte_quota_drops);
     if let Some(reason) = close_reason {
-        close_session(&mut relay, &envelope.session_id, &reason);
+        close_mirrored_session(&mut relay, &envelope.session_id, &reason);
     }
 }
 
@@ -1100,16 +1942,20 @@ pub(super) fn build_device_token_index(
     let mut token_index = HashMap::new();
     for (session_id, session) in sessions {
         for (device_id, device) in &session.devices {
-            token_index.insert(
-                device.current_session_token.clone(),
-                DeviceTokenContext {
-                    session_id: session_id.clone(),
-                    device_id: device_id.clone(),
-                    expires_at_ms: None,
-                },
-            );
+            if !is_signed_device_token(&device.current_session_token) {
+                token_index.insert(
+                    device.current_session_token.clone(),
+                    DeviceTokenContext {
+                        session_id: session_id.clone(),
+                        device_id: device_id.clone(),
+                        expires_at_ms: None,
+                    },
+                );
+            }
             for retired_token in &device.retired_session_tokens {
-                if now_ms() >= retired_token.expires_at_ms {
+                if now_ms() >= retired_token.expires_at_ms
+                    || is_signed_device_token(&retired_token.token)
+                {
                     continue;
                 }
                 token_index
@@ -1125,6 +1971,28 @@ pub(super) fn build_device_token_index(
     token_index
 }
 
+/// Tokens that the persisted token index maps back to their session: the desktop token plus each
+/// device's opaque current and in-grace retired tokens. Signed tokens carry their own session.
+pub(super) fn session_lookup_tokens(session: &SessionRecord, now: i64) -> Vec<&str> {
+    let mut tokens = vec![session.desktop_session_token.as_str()];
+    for device in session.devices.values() {
+        tokens.push(device.current_session_token.as_str());
+        tokens.extend(
+            device
+                .retired_session_tokens
+                .iter()
+                .filter(|retired| now < retired.expires_at_ms)
+                .map(|retired| retired.token.as_str()),
+        );
+    }
+    tokens.retain(|token| !is_signed_device_token(token));
+    tokens
+}
+
+pub(super) fn persisted_token_hash(token: &str) -> String {
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
 pub(super) fn build_desktop_token_index(
     sessions: &HashMap<String, SessionRecord>,
 ) -> HashMap<String, String> {
@@ -1197,131 +2065,287 @@ pub(super) async fn persist_session_if_needed(state: &SharedRelayState, session_
     }
 }
 
-pub(super) async fn refresh_sessions_from_persistence(state: &SharedRelayState, force: bool) {
+/// Resolves a token that missed the in-memory indexes by looking up its owning session in the
+/// persisted token index and reloading only that session. Tokens that are not found are cached
+/// briefly so repeated bad tokens do not hit Redis on every attempt.
+pub(super) async fn refresh_session_for_token(state: &SharedRelayState, token: &str) {
     let Some(persistence) = state.persistence.as_ref().cloned() else {
         return;
     };
 
-    let now = now_ms();
-    if !force {
-        let relay = state.inner.lock().await;
-        if now - relay.last_persistence_refresh_at_ms < 1_000 {
-            return;
+    let token_hash = persisted_token_hash(token);
+    if is_cached_unknown_token(&*state.inner.lock().await, &token_hash, now_ms()) {
+        return;
+    }
+
+    match persistence.lookup_token_session_id(token).await {
+        Ok(Some(session_id)) => {
+            refresh_session_from_persistence(state, &session_id).await;
+        }
+        Ok(None) => {
+            remember_unknown_token(&mut *state.inner.lock().await, token_hash, now_ms());
+        }
+        Err(error) => {
+            warn!("[relay-rs] failed to look up persisted token: {error}");
         }
     }
+}
+
+pub(super) fn is_cached_unknown_token(relay: &RelayState, token_hash: &str, now: i64) -> bool {
+    relay
+        .unknown_token_cache
+        .get(token_hash)
+        .is_some_and(|expires_at_ms| now < *expires_at_ms)
+}
+
+/// Caches a token the persisted index does not know. A full cache is first pruned of expired
+/// entries; if it is still full the miss is not cached.
+pub(super) fn remember_unknown_token(relay: &mut RelayState, token_hash: String, now: i64) {
+    if relay.unknown_token_cache.len() >= MAX_UNKNOWN_TOKEN_CACHE_ENTRIES {
+        relay
+            .unknown_token_cache
+            .retain(|_, expires_at_ms| now < *expires_at_ms);
+    }
+    if relay.unknown_token_cache.len() < MAX_UNKNOWN_TOKEN_CACHE_ENTRIES {
+        relay
+            .unknown_token_cache
+            .insert(token_hash, now.saturating_add(UNKNOWN_TOKEN_CACHE_TTL_MS));
+    }
+}
 
-    let (loaded_sessions, loaded_versions) = match persistence.load_sessions().await {
-        Ok(sessions) => sessions,
+/// Reloads a session before an HTTP handler reads it. A session this instance already holds is
+/// reloaded at most once a second, since change notifications keep it current in between; a
+/// session it does not hold is always looked up. A record missing from Redis never closes the
+/// local copy here. Only change notifications and token lookups do that.
+pub(super) async fn refresh_session_for_request(state: &SharedRelayState, session_id: &str) {
+    if state.persistence.is_none() {
+        return;
+    }
+    {
+        let now = now_ms();
+        let mut relay = state.inner.lock().await;
+        if relay.sessions.contains_key(session_id) {
+            if relay
+                .session_refreshed_at_ms
+                .get(session_id)
+                .is_some_and(|refreshed_at_ms| now - refreshed_at_ms < SESSION_REFRESH_THROTTLE_MS)
+            {
+                return;
+            }
+            relay
+                .session_refreshed_at_ms
+                .insert(session_id.to_string(), now);
+        }
+    }
+    reload_session_from_persistence(state, session_id, false).await;
+}
+
+pub(super) async fn refresh_session_from_persistence(state: &SharedRelayState, session_id: &str) {
+    reload_session_from_persistence(state, session_id, true).await;
+}
+
+async fn reload_session_from_persistence(
+    state: &SharedRelayState,
+    session_id: &str,
+    close_if_missing: bool,
+) {
+    let Some(persistence) = state.persistence.as_ref().cloned() else {
+        return;
+    };
+
+    let loaded = match persistence.load_session(session_id).await {
+        Ok(loaded) => loaded,
         Err(error) => {
-            warn!("[relay-rs] failed to refresh sessions from persistence: {error}");
+            warn!(
+                "[relay-rs] failed to refresh session {} from persistence: {error}",
+                session_log_id(session_id)
+            );
             return;
         }
     };
-    let persisted_session_ids = loaded_sessions.keys().cloned().collect::<HashSet<_>>();
 
+    let now = now_ms();
     let mut relay = state.inner.lock().await;
-    relay.last_persistence_refresh_at_ms = now;
-
-    for (session_id, mut loaded_session) in loaded_sessions {
-        let loaded_version = loaded_versions.get(&session_id).copied().unwrap_or(0);
-        for device in loaded_session.devices.values_mut() {
-            device
-                .retired_session_tokens
-                .retain(|token| now < token.expires_at_ms);
-        }
-        let mut replaced_desktop_token: Option<String> = None;
-        match relay.sessions.get_mut(&session_id) {
-            Some(existing) => {
-                if existing.desktop_socket.is_none() && existing.mobile_sockets.is_empty() {
-                    replaced_desktop_token = Some(existing.desktop_session_token.clone());
-                    *existing = loaded_session;
-                }
+    match loaded {
+        Some((loaded_session, loaded_version)) => {
+            let has_async_pair_requests = !loaded_session.async_pair_requests.is_empty();
+            merge_persisted_session(&mut relay, session_id, loaded_session, loaded_version, now);
+            if has_async_pair_requests {
+                state.async_pair_updates.notify_waiters();
             }
-            None => {
-                relay.sessions.insert(session_id.clone(), loaded_session);
+        }
+        None if close_if_missing => {
+            let is_idle_local_copy = relay.sessions.get(session_id).is_some_and(|session| {
+                session.desktop_socket.is_none() && session.mobile_sockets.is_empty()
+            });
+            if is_idle_local_copy {
+                close_mirrored_session(&mut relay, session_id, "removed_from_persistence");
             }
         }
+        None => {}
+    }
+}
+
+/// Moves the state that only lives in this instance's memory into a record loaded from
+/// persistence: waiters on pending joins, byte counters, the snapshot cache and rate buckets.
+fn carry_runtime_session_fields(existing: &mut SessionRecord, loaded_session: &mut SessionRecord) {
+    loaded_session.desktop_connected = existing.desktop_connected;
+    loaded_session.pending_join_requests = std::mem::take(&mut existing.pending_join_requests);
+    loaded_session.traffic = std::mem::take(&mut existing.traffic);
+    loaded_session.notified_runtime_requests =
+        std::mem::take(&mut existing.notified_runtime_requests);
+    if existing.latest_snapshot.is_some() {
+        loaded_session.latest_snapshot = existing.latest_snapshot.take();
+    }
+    loaded_session.command_rate_buckets = std::mem::take(&mut existing.command_rate_buckets);
+    loaded_session.session_command_rate_bucket = existing.session_command_rate_bucket.take();
+    loaded_session.snapshot_request_rate_buckets =
+        std::mem::take(&mut existing.snapshot_request_rate_buckets);
+}
 
-        if let Some(token) = replaced_desktop_token {
-            relay.desktop_token_index.remove(&token);
+/// Copies the persisted fields of a newer session write into a session with live local sockets,
+/// keeping the socket handles. Mobile sockets of devices that are gone are disconnected.
+fn apply_persisted_session_fields(existing: &mut SessionRecord, loaded_session: SessionRecord) {
+    existing.join_token = loaded_session.join_token;
+    existing.join_token_expires_at_ms = loaded_session.join_token_expires_at_ms;
+    existing.join_token_used_at_ms = loaded_session.join_token_used_at_ms;
+    if existing.desktop_session_token != loaded_session.desktop_session_token {
+        // The desktop token only changes in a session transfer; a desktop still connected here
+        // authenticated with the old one.
+        if let Some(desktop) = existing.desktop_socket.take() {
+            request_socket_disconnect(&desktop, "session_transferred");
         }
+    }
+    existing.desktop_session_token = loaded_session.desktop_session_token;
+    existing.idle_timeout_seconds = loaded_session.idle_timeout_seconds;
+    existing.transfer_grant = loaded_session.transfer_grant;
+    existing.max_devices = loaded_session.max_devices;
+    existing.device_eviction_policy = loaded_session.device_eviction_policy;
+    existing.invites = loaded_session.invites;
+    existing.async_pair_requests = loaded_session.async_pair_requests;
+    existing.desktop_schema_version = loaded_session.desktop_schema_version;
+    existing.last_activity_at_ms = existing
+        .last_activity_at_ms
+        .max(loaded_session.last_activity_at_ms);
+
+    let removed_device_ids = existing
+        .devices
+        .keys()
+        .filter(|device_id| !loaded_session.devices.contains_key(*device_id))
+        .cloned()
+        .collect::<Vec<_>>();
+    existing.devices = loaded_session.devices;
+    for device_id in removed_device_ids {
+        close_existing_mobile_socket_for_device(existing, &device_id, "device_revoked");
+    }
+}
 
-        relay
-            .device_token_index
-            .retain(|_, context| context.session_id != session_id);
+pub(super) fn merge_persisted_session(
+    relay: &mut RelayState,
+    session_id: &str,
+    mut loaded_session: SessionRecord,
+    loaded_version: u64,
+    now: i64,
+) {
+    for device in loaded_session.devices.values_mut() {
+        device
+            .retired_session_tokens
+            .retain(|token| now < token.expires_at_ms);
+    }
+    let local_version = relay
+        .persistence_versions
+        .get(session_id)
+        .copied()
+        .unwrap_or(0);
+    let mut replaced_desktop_token: Option<String> = None;
+    match relay.sessions.get_mut(session_id) {
+        Some(existing) => {
+            if loaded_version <= local_version {
+                // Already at this write or newer; the token indexes are still rebuilt below.
+            } else if existing.desktop_socket.is_none() && existing.mobile_sockets.is_empty() {
+                replaced_desktop_token = Some(existing.desktop_session_token.clone());
+                carry_runtime_session_fields(existing, &mut loaded_session);
+                *existing = loaded_session;
+            } else {
+                replaced_desktop_token = Some(existing.desktop_session_token.clone());
+                apply_persisted_session_fields(existing, loaded_session);
+            }
+        }
+        None => {
+            relay
+                .sessions
+                .insert(session_id.to_string(), loaded_session);
+        }
+    }
 
-        let device_tokens = relay
-            .sessions
-            .get(&session_id)
-            .map(|session| {
-                session
-                    .devices
-                    .iter()
-                    .flat_map(|(device_id, device)| {
-                        let mut tokens = vec![(
-                            device.current_session_token.clone(),
-                            DeviceTokenContext {
-                                session_id: session_id.clone(),
-                                device_id: device_id.clone(),
-                                expires_at_ms: None,
-                            },
-                        )];
-                        tokens.extend(device.retired_session_tokens.iter().filter_map(|token| {
-                            (now < token.expires_at_ms).then(|| {
+    if let Some(token) = replaced_desktop_token {
+        relay.desktop_token_index.remove(&token);
+    }
+
+    relay
+        .device_token_index
+        .retain(|_, context| context.session_id != session_id);
+
+    let device_tokens = relay
+        .sessions
+        .get(session_id)
+        .map(|session| {
+            session
+                .devices
+                .iter()
+                .flat_map(|(device_id, device)| {
+                    let mut tokens = vec![(
+                        device.current_session_token.clone(),
+                        DeviceTokenContext {
+                            session_id: session_id.to_string(),
+                            device_id: device_id.clone(),
+                            expires_at_ms: None,
+                        },
+                    )];
+                    tokens.extend(
+                        device
+                            .retired_session_tokens
+                            .iter()
+                            .filter(|token| now < token.expires_at_ms)
+                            .map(|token| {
                                 (
                                     token.token.clone(),
                                     DeviceTokenContext {
-                                        session_id: session_id.clone(),
+                                        session_id: session_id.to_string(),
                                         device_id: device_id.clone(),
                                         expires_at_ms: Some(token.expires_at_ms),
                                     },
                                 )
-                            })
-                        }));
-                        tokens
-                    })
-                    .collect::<Vec<_>>()
-            })
-            .unwrap_or_default();
+                            }),
+                    );
+                    tokens
+                })
+                .filter(|(token, _)| !is_signed_device_token(token))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
 
-        for (token, context) in device_tokens {
-            relay.device_token_index.insert(token, context);
-        }
+    for (token, context) in device_tokens {
+        relay.device_token_index.insert(token, context);
+    }
 
+    relay
+        .desktop_token_index
+        .retain(|_, indexed_session_id| *indexed_session_id != session_id);
+    if let Some(desktop_token) = relay
+        .sessions
+        .get(session_id)
+        .map(|session| session.desktop_session_token.clone())
+    {
         relay
             .desktop_token_index
-            .retain(|_, indexed_session_id| *indexed_session_id != session_id);
-        if let Some(desktop_token) = relay
-            .sessions
-            .get(&session_id)
-            .map(|session| session.desktop_session_token.clone())
-        {
-            relay
-                .desktop_token_index
-                .insert(desktop_token, session_id.clone());
-        }
-        relay
-            .persistence_versions
-            .entry(session_id.clone())
-            .and_modify(|existing| *existing = (*existing).max(loaded_version))
-            .or_insert(loaded_version);
-    }
-
-    if force {
-        let stale_session_ids = relay
-            .sessions
-            .iter()
-            .filter(|(session_id, session)| {
-                !persisted_session_ids.contains(*session_id)
-                    && session.desktop_socket.is_none()
-                    && session.mobile_sockets.is_empty()
-            })
-            .map(|(session_id, _)| session_id.clone())
-            .collect::<Vec<_>>();
-        for session_id in stale_session_ids {
-            close_session(&mut relay, &session_id, "removed_from_persistence");
-        }
+            .insert(desktop_token, session_id.to_string());
     }
+    relay
+        .persistence_versions
+        .entry(session_id.to_string())
+        .and_modify(|existing| *existing = (*existing).max(loaded_version))
+        .or_insert(loaded_version);
 }
 
 pub(super) fn build_cors_layer(config: &RelayConfig) -> CorsLayer {
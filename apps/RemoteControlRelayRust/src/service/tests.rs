@@ -26,14 +26,30 @@ fn make_test_state_with_session(session: SessionRecord) -> SharedRelayState {
             ws_auth_attempts: 0,
             ws_auth_successes: 0,
             ws_auth_failure_reasons: HashMap::new(),
-            last_persistence_refresh_at_ms: 0,
+            unknown_token_cache: HashMap::new(),
+            session_refreshed_at_ms: HashMap::new(),
             persistence_versions: HashMap::new(),
             seen_cross_instance_nonces: HashMap::new(),
+            envelope_signature_failures: HashMap::new(),
             bus_subscribed_sessions: HashSet::new(),
             bus_subscription_tasks: HashMap::new(),
+            presence_claims: HashMap::new(),
+            lifecycle_events: None,
+            webhook_stats: WebhookDeliveryStats::default(),
+            penalty_records: HashMap::new(),
+            penalty_blocks_issued: 0,
+            penalty_box_rejections: 0,
+            ip_access_rejections: HashMap::new(),
+            byte_quota_drops: 0,
         })),
         persistence: None,
         cross_instance_bus: None,
+        presence: None,
+        envelope_keyring: Arc::new(std::sync::RwLock::new(NatsHmacKeyring::default())),
+        webhooks: None,
+        penalty_store: None,
+        ip_access_rules: Arc::new(std::sync::RwLock::new(IpAccessRules::default())),
+        async_pair_updates: Arc::new(Notify::new()),
     }
 }
 
@@ -59,13 +75,30 @@ fn make_test_session(session_id: &str, device_id: &str, token: &str) -> SessionR
                 name: "Test Phone".to_string(),
                 joined_at_ms: now_ms(),
                 last_seen_at_ms: now_ms(),
+                token_generation: 0,
+                nickname: None,
+                platform: None,
+                app_version: None,
+                last_ip: None,
+                connection_history: Default::default(),
+                scopes: Vec::new(),
+                schema_version: CANONICAL_SCHEMA_VERSION,
             },
         )]),
         command_rate_buckets: HashMap::new(),
         session_command_rate_bucket: None,
         snapshot_request_rate_buckets: HashMap::new(),
         command_sequence_by_connection_id: HashMap::new(),
-        pending_join_request: None,
+        pending_join_requests: HashMap::new(),
+        latest_snapshot: None,
+        traffic: SessionTraffic::default(),
+        notified_runtime_requests: HashSet::new(),
+        transfer_grant: None,
+        max_devices: None,
+        device_eviction_policy: DeviceEvictionPolicy::default(),
+        invites: HashMap::new(),
+        async_pair_requests: HashMap::new(),
+        desktop_schema_version: CANONICAL_SCHEMA_VERSION,
     }
 }
 
@@ -98,13 +131,30 @@ fn persisted_runtime_round_trip_preserves_sessions_and_tokens() {
                     name: "Test Phone".to_string(),
                     joined_at_ms: 150,
                     last_seen_at_ms: 190,
+                    token_generation: 0,
+                    nickname: None,
+                    platform: None,
+                    app_version: None,
+                    last_ip: None,
+                    connection_history: Default::default(),
+                    scopes: Vec::new(),
+                    schema_version: CANONICAL_SCHEMA_VERSION,
                 },
             )]),
             command_rate_buckets: HashMap::new(),
             session_command_rate_bucket: None,
             snapshot_request_rate_buckets: HashMap::new(),
             command_sequence_by_connection_id: HashMap::new(),
-            pending_join_request: None,
+            pending_join_requests: HashMap::new(),
+            latest_snapshot: None,
+            traffic: SessionTraffic::default(),
+            notified_runtime_requests: HashSet::new(),
+            transfer_grant: None,
+            max_devices: None,
+            device_eviction_policy: DeviceEvictionPolicy::default(),
+            invites: HashMap::new(),
+            async_pair_requests: HashMap::new(),
+            desktop_schema_version: CANONICAL_SCHEMA_VERSION,
         },
     );
 
@@ -117,7 +167,7 @@ fn persisted_runtime_round_trip_preserves_sessions_and_tokens() {
     assert_eq!(restored_session.devices.len(), 1);
     assert!(restored_session.desktop_socket.is_none());
     assert!(restored_session.mobile_sockets.is_empty());
-    assert!(restored_session.pending_join_request.is_none());
+    assert!(restored_session.pending_join_requests.is_empty());
 
     let mut restored_sessions = HashMap::new();
     restored_sessions.insert("session-1".to_string(), restored_session);
@@ -210,6 +260,8 @@ async fn cross_instance_targeted_revoke_removes_device_tokens_locally() {
         issued_at_ms: now_ms(),
         nonce: "nonce-token-1".to_string(),
         signature: None,
+        key_id: None,
+        control_lane: true,
     };
     let payload = serde_json::to_vec(&envelope).expect("encode envelope");
     handle_session_envelope(&state, "local-instance", &payload).await;
@@ -220,6 +272,46 @@ async fn cross_instance_targeted_revoke_removes_device_tokens_locally() {
     assert!(relay.device_token_index.is_empty());
 }
 
+#[tokio::test]
+async fn cross_instance_targeted_eviction_closes_local_socket_with_eviction_reason() {
+    let session_id = "session-1";
+    let mut session = make_test_session(session_id, "device-1", "device-token-1");
+    let (tx, mut rx) = socket_channel(4);
+    let (shutdown_tx, _shutdown_rx) = watch::channel(false);
+    session.mobile_sockets.insert(
+        "connection-1".to_string(),
+        SocketHandle {
+            tx,
+            shutdown: shutdown_tx,
+            device_id: Some("device-1".to_string()),
+        },
+    );
+    let state = make_test_state_with_session(session);
+
+    let envelope = CrossInstanceEnvelope {
+        schema_version: 1,
+        session_id: session_id.to_string(),
+        source_instance_id: "remote-instance".to_string(),
+        target: "mobile".to_string(),
+        target_device_id: Some("device-1".to_string()),
+        payload: disconnect_payload("device_evicted"),
+        issued_at_ms: now_ms(),
+        nonce: "nonce-eviction-1".to_string(),
+        signature: None,
+        key_id: None,
+        control_lane: true,
+    };
+    let payload = serde_json::to_vec(&envelope).expect("encode envelope");
+    handle_session_envelope(&state, "local-instance", &payload).await;
+
+    let Some(Message::Text(payload)) = rx.recv().await else {
+        panic!("expected disconnect payload");
+    };
+    assert!(payload.contains("device_evicted"));
+    let relay = state.inner.lock().await;
+    assert!(relay.sessions[session_id].mobile_sockets.is_empty());
+}
+
 #[tokio::test]
 async fn cross_instance_disconnect_closes_local_stale_session() {
     let session_id = "session-1";
@@ -236,68 +328,1186 @@ async fn cross_instance_disconnect_closes_local_stale_session() {
             "type": "disconnect",
             "reason": "stopped_by_desktop"
         })
-        .to_string(),
-        issued_at_ms: now_ms(),
-        nonce: "nonce-token-2".to_string(),
-        signature: None,
-    };
-    let payload = serde_json::to_vec(&envelope).expect("encode envelope");
-    handle_session_envelope(&state, "local-instance", &payload).await;
+        .to_string(),
+        issued_at_ms: now_ms(),
+        nonce: "nonce-token-2".to_string(),
+        signature: None,
+        key_id: None,
+        control_lane: true,
+    };
+    let payload = serde_json::to_vec(&envelope).expect("encode envelope");
+    handle_session_envelope(&state, "local-instance", &payload).await;
+
+    let relay = state.inner.lock().await;
+    assert!(!relay.sessions.contains_key(session_id));
+    assert!(relay.device_token_index.is_empty());
+}
+
+#[tokio::test]
+async fn cross_instance_envelope_without_required_signature_is_ignored() {
+    let session_id = "session-1";
+    let mut state =
+        make_test_state_with_session(make_test_session(session_id, "device-1", "device-token-1"));
+    state.config.nats_hmac_secret = Some("01234567890123456789012345678901".to_string());
+
+    let envelope = CrossInstanceEnvelope {
+        schema_version: 1,
+        session_id: session_id.to_string(),
+        source_instance_id: "remote-instance".to_string(),
+        target: "mobile".to_string(),
+        target_device_id: None,
+        payload: json!({
+            "type": "disconnect",
+            "reason": "stopped_by_desktop"
+        })
+        .to_string(),
+        issued_at_ms: now_ms(),
+        nonce: "nonce-token-3".to_string(),
+        signature: None,
+        key_id: None,
+        control_lane: true,
+    };
+    let payload = serde_json::to_vec(&envelope).expect("encode envelope");
+    handle_session_envelope(&state, "local-instance", &payload).await;
+
+    let relay = state.inner.lock().await;
+    assert!(
+        relay.sessions.contains_key(session_id),
+        "session should remain when signature verification fails"
+    );
+}
+
+#[tokio::test]
+async fn cross_instance_consumer_names_survive_a_restart_with_the_same_instance_id() {
+    let mut config = RelayConfig::from_env();
+    config.instance_id = Some("relay-0".to_string());
+    let transport: Arc<dyn CrossInstanceBus> = Arc::new(InProcessCrossInstanceBus::new());
+    let first = build_cross_instance_bus(&config, transport.clone(), None);
+    let restarted = build_cross_instance_bus(&config, transport, None);
+
+    assert_eq!(first.instance_id, "relay-0");
+    assert_eq!(
+        nats_session_consumer_name(&first, "session-1"),
+        nats_session_consumer_name(&restarted, "session-1")
+    );
+    assert_eq!(nats_control_consumer_name(&restarted), "relay-0-control");
+    assert!(!restarted.transport.is_degraded());
+}
+
+/// Transport whose publishes to one subject never complete, like a JetStream ack that never
+/// arrives.
+struct StallingCrossInstanceBus {
+    stalled_subject: String,
+    published: Arc<Mutex<Vec<String>>>,
+}
+
+impl CrossInstanceBus for StallingCrossInstanceBus {
+    fn publish(
+        &self,
+        subject: String,
+        _payload: Vec<u8>,
+    ) -> futures_util::future::BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            if subject == self.stalled_subject {
+                std::future::pending::<()>().await;
+            }
+            self.published.lock().await.push(subject);
+            Ok(())
+        })
+    }
+
+    fn subscribe(
+        &self,
+        _subject: String,
+        _consumer_name: String,
+    ) -> futures_util::future::BoxFuture<
+        '_,
+        Result<futures_util::stream::BoxStream<'static, CrossInstanceBusMessage>, String>,
+    > {
+        Box::pin(async { Err("not supported".to_string()) })
+    }
+}
+
+#[tokio::test]
+async fn stalled_cross_instance_publish_only_holds_up_its_own_lane() {
+    let config = RelayConfig::from_env();
+    let published = Arc::new(Mutex::new(Vec::new()));
+    let transport: Arc<dyn CrossInstanceBus> = Arc::new(StallingCrossInstanceBus {
+        stalled_subject: "relay.session.stalled".to_string(),
+        published: published.clone(),
+    });
+    let bus = build_cross_instance_bus(&config, transport, None);
+    let queued = |subject: &str| QueuedCrossInstancePublish {
+        subject: subject.to_string(),
+        payload: b"{}".to_vec(),
+        presence_route: None,
+    };
+
+    bus.enqueue_publish(queued("relay.session.stalled"));
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    for _ in 0..CROSS_INSTANCE_PUBLISH_LANE_CAPACITY + 5 {
+        bus.enqueue_publish(queued("relay.session.stalled"));
+    }
+    assert_eq!(
+        bus.publish_drops.load(std::sync::atomic::Ordering::Relaxed),
+        5
+    );
+
+    let stalled_lane = bus.publish_lane("relay.session.stalled");
+    let other_subject = (0..)
+        .map(|index| format!("relay.session.other-{index}"))
+        .find(|subject| !bus.publish_lane(subject).same_channel(stalled_lane))
+        .expect("subject on another lane");
+    bus.enqueue_publish(queued(&other_subject));
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(1);
+    while !published.lock().await.contains(&other_subject) {
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "publish on another lane should not wait for the stalled subject"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+fn make_desktop_status_envelope(session_id: &str, nonce: &str) -> CrossInstanceEnvelope {
+    CrossInstanceEnvelope {
+        schema_version: 1,
+        session_id: session_id.to_string(),
+        source_instance_id: "remote-instance".to_string(),
+        target: "mobile".to_string(),
+        target_device_id: None,
+        payload: json!({
+            "type": "relay.desktop_status",
+            "desktopConnected": true
+        })
+        .to_string(),
+        issued_at_ms: now_ms(),
+        nonce: nonce.to_string(),
+        signature: None,
+        key_id: None,
+        control_lane: true,
+    }
+}
+
+#[tokio::test]
+async fn cross_instance_envelope_keyring_accepts_rotated_keys_and_counts_failures_by_key_id() {
+    let session_id = "session-1";
+    let state =
+        make_test_state_with_session(make_test_session(session_id, "device-1", "device-token-1"));
+    let set_active_key = |key_id: &str| {
+        *state.envelope_keyring.write().expect("keyring lock") = NatsHmacKeyring {
+            active_key_id: Some(key_id.to_string()),
+            keys: vec![
+                ("k1".to_string(), "a".repeat(32)),
+                ("k2".to_string(), "b".repeat(32)),
+            ],
+        };
+    };
+
+    set_active_key("k2");
+    let mut signed_with_previous_key = make_desktop_status_envelope(session_id, "nonce-token-k2");
+    sign_cross_instance_envelope(&state, &mut signed_with_previous_key);
+    assert_eq!(signed_with_previous_key.key_id.as_deref(), Some("k2"));
+
+    set_active_key("k1");
+    let payload = serde_json::to_vec(&signed_with_previous_key).expect("encode envelope");
+    handle_session_envelope(&state, "local-instance", &payload).await;
+    assert!(
+        state
+            .inner
+            .lock()
+            .await
+            .sessions
+            .get(session_id)
+            .is_some_and(|session| session.desktop_connected),
+        "envelope signed with a non-active key in the ring should be accepted"
+    );
+
+    let mut tampered = make_desktop_status_envelope(session_id, "nonce-token-k1");
+    sign_cross_instance_envelope(&state, &mut tampered);
+    tampered.payload =
+        json!({ "type": "relay.desktop_status", "desktopConnected": false }).to_string();
+    let payload = serde_json::to_vec(&tampered).expect("encode envelope");
+    handle_session_envelope(&state, "local-instance", &payload).await;
+
+    let mut unknown_key = make_desktop_status_envelope(session_id, "nonce-token-k9");
+    unknown_key.key_id = Some("k9".to_string());
+    unknown_key.signature = Some("c2lnbmF0dXJl".to_string());
+    let payload = serde_json::to_vec(&unknown_key).expect("encode envelope");
+    handle_session_envelope(&state, "local-instance", &payload).await;
+
+    let relay = state.inner.lock().await;
+    assert!(relay
+        .sessions
+        .get(session_id)
+        .is_some_and(|session| session.desktop_connected));
+    assert_eq!(relay.envelope_signature_failures.get("k1"), Some(&1));
+    assert_eq!(relay.envelope_signature_failures.get("unknown"), Some(&1));
+}
+
+#[test]
+fn try_send_bulk_payload_returns_false_when_queue_is_full() {
+    let (tx, mut rx) = socket_channel(1);
+
+    assert!(try_send_bulk_payload(&tx, "first".to_string()));
+    assert!(
+        !try_send_bulk_payload(&tx, "second".to_string()),
+        "second payload should be rejected when queue is full"
+    );
+
+    let first = rx
+        .bulk
+        .try_recv()
+        .expect("first payload should remain in queue");
+    assert_eq!(first, Message::Text("first".to_string().into()));
+}
+
+#[tokio::test]
+async fn control_frames_bypass_a_full_bulk_lane_and_are_written_first() {
+    let (tx, mut rx) = socket_channel(2);
+
+    assert!(try_send_bulk_payload(&tx, "bulk-1".to_string()));
+    assert!(try_send_bulk_payload(&tx, "bulk-2".to_string()));
+    assert!(!try_send_bulk_payload(&tx, "bulk-3".to_string()));
+    assert!(try_send_payload(&tx, "control-1".to_string()));
+    assert!(try_send_message(&tx, Message::Ping(Vec::new().into())));
+    drop(tx);
+
+    let mut written = Vec::new();
+    while let Some(message) = rx.recv().await {
+        written.push(match message {
+            Message::Text(payload) => payload.to_string(),
+            Message::Ping(_) => "ping".to_string(),
+            _ => "other".to_string(),
+        });
+    }
+    assert_eq!(written, vec!["control-1", "ping", "bulk-1", "bulk-2"]);
+}
+
+#[tokio::test]
+async fn cross_instance_lane_comes_from_the_envelope_not_the_client_payload() {
+    let session_id = "session-1";
+    let mut session = make_test_session(session_id, "device-1", "device-token-1");
+    let (tx, mut rx) = socket_channel(4);
+    let (shutdown_tx, _shutdown_rx) = watch::channel(false);
+    session.mobile_sockets.insert(
+        "connection-1".to_string(),
+        SocketHandle {
+            tx,
+            shutdown: shutdown_tx,
+            device_id: Some("device-1".to_string()),
+        },
+    );
+    let state = make_test_state_with_session(session);
+
+    // Forwarded desktop traffic that labels itself as a relay frame stays on the bulk lane.
+    let mut forwarded = make_desktop_status_envelope(session_id, "nonce-lane-1");
+    forwarded.control_lane = false;
+    let payload = serde_json::to_vec(&forwarded).expect("encode envelope");
+    handle_session_envelope(&state, "local-instance", &payload).await;
+    assert!(rx.control.try_recv().is_err());
+    assert!(matches!(rx.bulk.try_recv(), Ok(Message::Text(_))));
+
+    let relay_authored = make_desktop_status_envelope(session_id, "nonce-lane-2");
+    let payload = serde_json::to_vec(&relay_authored).expect("encode envelope");
+    handle_session_envelope(&state, "local-instance", &payload).await;
+    assert!(matches!(rx.control.try_recv(), Ok(Message::Text(_))));
+    assert!(rx.bulk.try_recv().is_err());
+}
+
+#[test]
+fn desktop_snapshot_cache_respects_size_cap_and_marks_replays_stale() {
+    let mut config = RelayConfig::from_env();
+    config.snapshot_cache_max_bytes = 128;
+    let mut session = make_test_session("session-1", "device-1", "token-1");
+
+    let snapshot = r#"{"schemaVersion":2,"seq":3,"payload":{"type":"snapshot","payload":{}}}"#;
+    let parsed = serde_json::from_str::<Value>(snapshot).ok();
+    assert!(is_desktop_snapshot_payload(parsed.as_ref()));
+    assert!(cache_desktop_snapshot(&mut session, &config, snapshot, 1_000).is_some());
+
+    let oversized = format!(
+        r#"{{"schemaVersion":2,"seq":4,"payload":{{"type":"snapshot","payload":{{"pad":"{}"}}}}}}"#,
+        "x".repeat(256)
+    );
+    assert!(cache_desktop_snapshot(&mut session, &config, &oversized, 2_000).is_none());
+    let cached = session.latest_snapshot.as_ref().expect("cached snapshot");
+    assert_eq!(cached.captured_at_ms, 1_000);
+
+    let replay: Value =
+        serde_json::from_str(&stale_snapshot_payload(cached)).expect("stale snapshot json");
+    assert_eq!(replay.get("relaySnapshotStale"), Some(&Value::Bool(true)));
+    assert_eq!(
+        replay
+            .get("relaySnapshotCapturedAt")
+            .and_then(Value::as_str),
+        Some(iso_from_millis(1_000).as_str())
+    );
+    assert_eq!(replay.get("seq"), Some(&json!(3)));
+
+    config.snapshot_cache_max_bytes = 0;
+    session.latest_snapshot = None;
+    assert!(cache_desktop_snapshot(&mut session, &config, snapshot, 3_000).is_none());
+    assert!(session.latest_snapshot.is_none());
+}
+
+#[test]
+fn pending_runtime_request_reads_kind_and_builds_thread_deep_link() {
+    let approval = serde_json::from_str::<Value>(
+        r#"{"payload":{"type":"event","payload":{"name":"runtime_request.requested","threadID":"t 1","requestID":"r-1"}}}"#,
+    )
+    .ok();
+    assert_eq!(
+        pending_runtime_request(approval.as_ref()),
+        Some(PendingRuntimeRequest {
+            kind: "approval".to_string(),
+            request_id: Some("r-1".to_string()),
+            thread_id: Some("t 1".to_string()),
+        })
+    );
+
+    let question = serde_json::from_str::<Value>(
+        r#"{"payload":{"type":"event","payload":{"name":"runtime_request.requested","runtimeRequestKind":"question"}}}"#,
+    )
+    .ok();
+    let question = pending_runtime_request(question.as_ref()).expect("question request");
+    assert_eq!(question.kind, "question");
+    assert_eq!(question.thread_id, None);
+
+    let other_event = serde_json::from_str::<Value>(
+        r#"{"payload":{"type":"event","payload":{"name":"thread.updated"}}}"#,
+    )
+    .ok();
+    assert_eq!(pending_runtime_request(other_event.as_ref()), None);
+
+    assert_eq!(
+        runtime_request_deep_link("https://remote.example.com/app", Some("t 1")),
+        "https://remote.example.com/app#view=thread&tid=t+1&pid=all"
+    );
+    assert_eq!(
+        runtime_request_deep_link("https://remote.example.com/app#stale", None),
+        "https://remote.example.com/app#view=home&pid=all"
+    );
+}
+
+#[tokio::test]
+async fn presence_route_owners_are_served_from_cache_until_expiry() {
+    let mut config = RelayConfig::from_env();
+    config.presence_registry_enabled = true;
+    config.redis_url = Some("redis://127.0.0.1:1".to_string());
+    config.presence_route_cache_ttl_ms = 1_000;
+    let presence = build_presence_registry(&config).expect("presence registry");
+    let key = ("session-1".to_string(), "desktop".to_string());
+    presence.route_cache.lock().await.insert(
+        key.clone(),
+        CachedPresenceOwners {
+            owners: vec!["instance-b".to_string()],
+            expires_at_ms: now_ms() + 1_000,
+        },
+    );
+
+    let route = PresenceRoute {
+        session_id: "session-1".to_string(),
+        target: "desktop".to_string(),
+    };
+    assert_eq!(
+        presence_route_subjects(&presence, "relay", "instance-a", &route)
+            .await
+            .expect("cached owners"),
+        vec![nats_instance_subject("relay", "instance-b")]
+    );
+
+    presence
+        .route_cache
+        .lock()
+        .await
+        .get_mut(&key)
+        .expect("cached entry")
+        .expires_at_ms = now_ms() - 1;
+    assert!(
+        presence_route_subjects(&presence, "relay", "instance-a", &route)
+            .await
+            .is_err()
+    );
+    assert!(presence.route_cache.lock().await.is_empty());
+}
+
+#[tokio::test]
+async fn presence_change_notification_drops_cached_owner_within_ttl() {
+    let mut config = RelayConfig::from_env();
+    config.presence_registry_enabled = true;
+    config.redis_url = Some("redis://127.0.0.1:1".to_string());
+    config.presence_route_cache_ttl_ms = 60_000;
+    let presence = build_presence_registry(&config).expect("presence registry");
+    for target in ["desktop", "mobile"] {
+        presence.route_cache.lock().await.insert(
+            ("session-1".to_string(), target.to_string()),
+            CachedPresenceOwners {
+                owners: vec!["instance-b".to_string()],
+                expires_at_ms: now_ms() + 60_000,
+            },
+        );
+    }
+
+    presence.apply_change("desktop:session-1").await;
+    presence.apply_change("malformed").await;
+
+    let desktop_route = PresenceRoute {
+        session_id: "session-1".to_string(),
+        target: "desktop".to_string(),
+    };
+    assert!(
+        presence_route_subjects(&presence, "relay", "instance-a", &desktop_route)
+            .await
+            .is_err(),
+        "the moved desktop is looked up again instead of using the cached owner"
+    );
+    let mobile_route = PresenceRoute {
+        session_id: "session-1".to_string(),
+        target: "mobile".to_string(),
+    };
+    assert_eq!(
+        presence_route_subjects(&presence, "relay", "instance-a", &mobile_route)
+            .await
+            .expect("cached mobile owners"),
+        vec![nats_instance_subject("relay", "instance-b")]
+    );
+}
+
+#[test]
+fn runtime_request_notifications_are_claimed_once_until_resolved() {
+    let mut session = make_test_session("session-1", "device-1", "token-1");
+    let request = PendingRuntimeRequest {
+        kind: "approval".to_string(),
+        request_id: Some("r-1".to_string()),
+        thread_id: None,
+    };
+    assert!(claim_runtime_request_notification(&mut session, &request));
+    assert!(!claim_runtime_request_notification(&mut session, &request));
+
+    let anonymous = PendingRuntimeRequest {
+        kind: "approval".to_string(),
+        request_id: None,
+        thread_id: None,
+    };
+    assert!(claim_runtime_request_notification(&mut session, &anonymous));
+    assert!(claim_runtime_request_notification(&mut session, &anonymous));
+
+    let resolved = serde_json::from_str::<Value>(
+        r#"{"payload":{"type":"event","payload":{"name":"runtime_request.resolved","requestId":" r-1 "}}}"#,
+    )
+    .ok();
+    assert_eq!(
+        resolved_runtime_request_id(resolved.as_ref()).as_deref(),
+        Some("r-1")
+    );
+    let requested = serde_json::from_str::<Value>(
+        r#"{"payload":{"type":"event","payload":{"name":"runtime_request.requested","requestID":"r-1"}}}"#,
+    )
+    .ok();
+    assert_eq!(resolved_runtime_request_id(requested.as_ref()), None);
+
+    session.notified_runtime_requests.remove("r-1");
+    assert!(claim_runtime_request_notification(&mut session, &request));
+}
+
+#[tokio::test]
+async fn close_session_queues_lifecycle_events_for_stop_and_expiry_reasons() {
+    let state = make_test_state_with_session(make_test_session("session-1", "device-1", "t-1"));
+    let (sender, mut receiver) = mpsc::channel(1);
+    let mut relay = state.inner.lock().await;
+    relay.lifecycle_events = Some(sender);
+    let created_at_ms = relay.sessions["session-1"].created_at_ms;
+
+    close_session(&mut relay, "session-1", "idle_timeout");
+    let event = receiver.try_recv().expect("session_expired event");
+    assert_eq!(event.kind, LifecycleEventKind::SessionExpired);
+    assert_eq!(event.session_id, "session-1");
+    assert_eq!(event.reason.as_deref(), Some("idle_timeout"));
+    let reported_elsewhere = LifecycleEvent::new(LifecycleEventKind::SessionExpired, "session-1")
+        .with_reason("idle_timeout")
+        .with_occurrence(created_at_ms);
+    assert_eq!(event.delivery_id(), reported_elsewhere.delivery_id());
+    assert_ne!(
+        event.delivery_id(),
+        LifecycleEvent::new(LifecycleEventKind::SessionStopped, "session-1")
+            .with_occurrence(created_at_ms)
+            .delivery_id()
+    );
+
+    relay.sessions.insert(
+        "session-2".to_string(),
+        make_test_session("session-2", "device-2", "t-2"),
+    );
+    close_session(&mut relay, "session-2", "server_shutdown");
+    assert!(receiver.try_recv().is_err());
+
+    relay.sessions.insert(
+        "session-4".to_string(),
+        make_test_session("session-4", "device-4", "t-4"),
+    );
+    close_mirrored_session(&mut relay, "session-4", "stopped_by_desktop");
+    assert!(!relay.sessions.contains_key("session-4"));
+    assert!(receiver.try_recv().is_err());
+
+    emit_lifecycle_event(
+        &mut relay,
+        LifecycleEvent::new(LifecycleEventKind::SessionStarted, "session-3"),
+    );
+    emit_lifecycle_event(
+        &mut relay,
+        LifecycleEvent::new(LifecycleEventKind::DeviceJoined, "session-3")
+            .with_device("device-3", Some("Phone")),
+    );
+    assert_eq!(relay.webhook_stats.dead_lettered, 1);
+    assert_eq!(
+        relay
+            .webhook_stats
+            .dead_letters_by_event
+            .get("device_joined"),
+        Some(&1)
+    );
+}
+
+#[test]
+fn pairing_uri_keeps_join_token_in_fragment_and_renders_qr_images() {
+    let mut config = RelayConfig::from_env();
+    config.pairing_join_base_url = "https://remote.example.com/app?stale=1".to_string();
+
+    let uri = pairing_uri(
+        &config,
+        "session-1",
+        "join token",
+        "wss://relay.example.com/ws",
+    );
+    assert_eq!(
+        uri,
+        "https://remote.example.com/app#sid=session-1&jt=join+token&relay=https%3A%2F%2Frelay.example.com"
+    );
+    let parsed = Url::parse(&uri).expect("pairing uri");
+    assert!(parsed.query().is_none());
+
+    let svg = render_pairing_qr(&uri, PairingQrFormat::Svg).expect("svg qr");
+    assert!(String::from_utf8(svg).expect("svg utf8").contains("<svg"));
+    let png = render_pairing_qr(&uri, PairingQrFormat::Png).expect("png qr");
+    assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+    assert_eq!(PairingQrFormat::parse(None), Some(PairingQrFormat::Svg));
+    assert_eq!(PairingQrFormat::parse(Some("gif")), None);
+}
+
+#[test]
+fn local_penalty_box_blocks_after_threshold_and_doubles_block_per_strike() {
+    let mut config = RelayConfig::from_env();
+    config.penalty_box_failure_threshold = 3;
+    config.penalty_box_subnet_failure_threshold = 10;
+    config.penalty_box_window_ms = 60_000;
+    config.penalty_box_base_block_ms = 1_000;
+    config.penalty_box_max_block_ms = 3_000;
+    let state = make_test_state_with_session(make_test_session("session-1", "device-1", "token"));
+    let mut relay = state.inner.try_lock().expect("relay state lock");
+
+    assert_eq!(
+        penalty_keys(&config, "203.0.113.9"),
+        vec![
+            ("ip:203.0.113.9".to_string(), 3),
+            ("subnet:203.0.113.0/24".to_string(), 10),
+        ]
+    );
+    assert_eq!(
+        penalty_keys(&config, "2001:db8:1:2:3:4:5:6")[1].0,
+        "subnet:2001:db8:1:2::/64"
+    );
+
+    let key = "ip:203.0.113.9";
+    let now = 1_000_000;
+    assert_eq!(
+        record_local_penalty_failure(&mut relay, &config, key, 3, now),
+        None
+    );
+    assert_eq!(
+        record_local_penalty_failure(&mut relay, &config, key, 3, now),
+        None
+    );
+    assert_eq!(
+        record_local_penalty_failure(&mut relay, &config, key, 3, now),
+        Some((now + 1_000, 1))
+    );
+    for _ in 0..2 {
+        record_local_penalty_failure(&mut relay, &config, key, 3, now);
+    }
+    assert_eq!(
+        record_local_penalty_failure(&mut relay, &config, key, 3, now),
+        Some((now + 2_000, 2))
+    );
+    assert_eq!(penalty_block_duration_ms(&config, 3), 3_000);
+    assert_eq!(penalty_block_duration_ms(&config, 40), 3_000);
+
+    prune_penalty_records(&mut relay, &config, now + PENALTY_STRIKE_TTL_MS + 1);
+    assert!(relay.penalty_records.is_empty());
+}
+
+#[test]
+fn session_traffic_counts_bytes_and_enforces_quota_windows() {
+    let mut config = RelayConfig::from_env();
+    config.session_byte_quota_per_instance = 100;
+    config.device_byte_quota_per_instance = 50;
+    config.byte_quota_window_ms = 1_000;
+    let mut traffic = SessionTraffic::default();
+
+    assert_eq!(
+        traffic.admit_inbound(&config, None, 60, 0),
+        ByteQuotaDecision::Allowed
+    );
+    assert_eq!(
+        traffic.admit_inbound(&config, None, 60, 10),
+        ByteQuotaDecision::Exceeded { notify: true }
+    );
+    assert_eq!(
+        traffic.admit_inbound(&config, None, 60, 20),
+        ByteQuotaDecision::Exceeded { notify: false }
+    );
+    assert_eq!(traffic.totals.bytes_in, 180);
+
+    assert_eq!(
+        traffic.admit_outbound(&config, Some("device-1"), 40, 20),
+        ByteQuotaDecision::Allowed
+    );
+    assert_eq!(
+        traffic.admit_outbound(&config, Some("device-1"), 40, 30),
+        ByteQuotaDecision::Exceeded { notify: true }
+    );
+    assert_eq!(
+        traffic.device("device-1"),
+        TrafficCounters {
+            bytes_in: 0,
+            bytes_out: 40,
+        }
+    );
+
+    assert_eq!(
+        traffic.admit_inbound(&config, Some("device-1"), 30, 1_000),
+        ByteQuotaDecision::Allowed
+    );
+    assert_eq!(traffic.device("device-1").bytes_in, 30);
+    traffic.remove_device("device-1");
+    assert_eq!(traffic.device("device-1"), TrafficCounters::default());
+
+    config.session_byte_quota_per_instance = 0;
+    config.device_byte_quota_per_instance = 0;
+    assert_eq!(
+        traffic.admit_inbound(&config, None, u64::MAX, 1_010),
+        ByteQuotaDecision::Allowed
+    );
+}
+
+#[test]
+fn device_client_info_prefers_reported_values_and_falls_back_to_user_agent() {
+    let safari_ipad = "Mozilla/5.0 (iPad; CPU OS 17_4 like Mac OS X) AppleWebKit/605.1.15";
+    assert_eq!(
+        DeviceClientInfo::from_request(None, None, Some(safari_ipad)),
+        DeviceClientInfo {
+            platform: Some("ipados".to_string()),
+            app_version: None,
+        }
+    );
+    assert_eq!(
+        DeviceClientInfo::from_request(
+            None,
+            None,
+            Some("CodexChat/3.1.0 (Linux; Android 14) okhttp/4.12")
+        ),
+        DeviceClientInfo {
+            platform: Some("android".to_string()),
+            app_version: Some("3.1.0".to_string()),
+        }
+    );
+    assert_eq!(
+        DeviceClientInfo::from_request(Some(" ios "), Some("2.4.0"), Some(safari_ipad)),
+        DeviceClientInfo {
+            platform: Some("ios".to_string()),
+            app_version: Some("2.4.0".to_string()),
+        }
+    );
+
+    let mut session = make_test_session("session-1", "device-1", "token");
+    let device = session.devices.get_mut("device-1").expect("device");
+    device.app_version = Some("1.0.0".to_string());
+    DeviceClientInfo::from_request(Some("ios"), None, None).apply(device, "198.51.100.4");
+    assert_eq!(device.platform.as_deref(), Some("ios"));
+    assert_eq!(device.app_version.as_deref(), Some("1.0.0"));
+    assert_eq!(device.last_ip.as_deref(), Some("198.51.100.4"));
+
+    let legacy_record = serde_json::from_value::<DeviceRecord>(json!({
+        "current_session_token": "token",
+        "name": "Old Phone",
+        "joined_at_ms": 1,
+        "last_seen_at_ms": 2,
+    }))
+    .expect("records persisted before metadata fields still load");
+    assert!(legacy_record.nickname.is_none() && legacy_record.platform.is_none());
+}
+
+#[test]
+fn device_connection_history_is_bounded_and_summarized() {
+    let mut config = RelayConfig::from_env();
+    config.device_connection_history_limit = 2;
+    let mut session = make_test_session("session-1", "device-1", "token");
+    let device = session.devices.get_mut("device-1").expect("device");
+
+    for at_ms in 1..=3 {
+        record_device_connection_event(
+            device,
+            &config,
+            DeviceConnectionEvent {
+                kind: DeviceConnectionEventKind::Connected,
+                at_ms,
+                remote_ip: "198.51.100.4".to_string(),
+                reason: None,
+                duration_ms: None,
+            },
+        );
+    }
+    record_device_connection_event(
+        device,
+        &config,
+        DeviceConnectionEvent {
+            kind: DeviceConnectionEventKind::Disconnected,
+            at_ms: 4,
+            remote_ip: "198.51.100.4".to_string(),
+            reason: Some("heartbeat_timeout".to_string()),
+            duration_ms: Some(1),
+        },
+    );
+    let at_ms = device
+        .connection_history
+        .iter()
+        .map(|event| event.at_ms)
+        .collect::<Vec<_>>();
+    assert_eq!(at_ms, vec![3, 4]);
+
+    let summary = device_connection_event_summary(&device.connection_history[1]);
+    assert_eq!(summary.event, "disconnected");
+    assert_eq!(summary.reason.as_deref(), Some("heartbeat_timeout"));
+    assert_eq!(summary.duration_ms, Some(1));
+
+    config.device_connection_history_limit = 0;
+    device.connection_history.clear();
+    record_device_connection_event(
+        device,
+        &config,
+        DeviceConnectionEvent {
+            kind: DeviceConnectionEventKind::Connected,
+            at_ms: 5,
+            remote_ip: "198.51.100.4".to_string(),
+            reason: None,
+            duration_ms: None,
+        },
+    );
+    assert!(device.connection_history.is_empty());
+
+    let legacy_record = serde_json::from_value::<DeviceRecord>(json!({
+        "current_session_token": "token",
+        "name": "Old Phone",
+        "joined_at_ms": 1,
+        "last_seen_at_ms": 2,
+    }))
+    .expect("records persisted before connection history still load");
+    assert!(legacy_record.connection_history.is_empty());
+}
+
+#[tokio::test]
+async fn session_transfer_issuer_requires_desktop_token_or_scoped_device() {
+    assert_eq!(
+        sanitize_device_scopes(&[
+            " session_transfer ".to_string(),
+            "admin".to_string(),
+            "session_transfer".to_string(),
+        ]),
+        vec![SESSION_TRANSFER_SCOPE.to_string()]
+    );
+
+    let device_token = random_token(32);
+    let state =
+        make_test_state_with_session(make_test_session("session-1", "device-1", &device_token));
+    let mut relay = state.inner.lock().await;
+    let issue_request = |desktop: Option<&str>, device: Option<&str>| SessionTransferIssueRequest {
+        schema_version: Some(2),
+        session_id: "session-1".to_string(),
+        desktop_session_token: desktop.map(str::to_string),
+        device_session_token: device.map(str::to_string),
+    };
+    let authorize = |relay: &RelayState, request: &SessionTransferIssueRequest| {
+        let session = relay.sessions.get("session-1").expect("session");
+        authorize_transfer_issuer(relay, &state.config, session, request, now_ms())
+            .map_err(|(status, code, _)| (status, code))
+    };
+
+    assert_eq!(
+        authorize(&relay, &issue_request(Some("desktop-token"), None)),
+        Ok("desktop".to_string())
+    );
+    assert_eq!(
+        authorize(&relay, &issue_request(Some("wrong-desktop-token"), None)),
+        Err((StatusCode::FORBIDDEN, "invalid_desktop_session_token"))
+    );
+    assert_eq!(
+        authorize(
+            &relay,
+            &issue_request(Some("desktop-token"), Some(&device_token))
+        ),
+        Err((StatusCode::BAD_REQUEST, "invalid_session_transfer"))
+    );
+    assert_eq!(
+        authorize(&relay, &issue_request(None, Some(&device_token))),
+        Err((StatusCode::FORBIDDEN, "transfer_scope_required"))
+    );
+    assert_eq!(
+        authorize(&relay, &issue_request(None, Some(&random_token(32)))),
+        Err((StatusCode::FORBIDDEN, "invalid_device_session_token"))
+    );
+
+    relay
+        .sessions
+        .get_mut("session-1")
+        .and_then(|session| session.devices.get_mut("device-1"))
+        .expect("device")
+        .scopes = vec![SESSION_TRANSFER_SCOPE.to_string()];
+    assert_eq!(
+        authorize(&relay, &issue_request(None, Some(&device_token))),
+        Ok("device:device-1".to_string())
+    );
+}
+
+#[test]
+fn device_cap_decision_honours_session_cap_and_evicts_least_recently_seen() {
+    let mut config = RelayConfig::from_env();
+    config.max_devices_per_session = 3;
+    let mut session = make_test_session("session-1", "device-1", "token-1");
+    let mut second = session.devices["device-1"].clone();
+    second.current_session_token = "token-2".to_string();
+    second.last_seen_at_ms = session.devices["device-1"].last_seen_at_ms + 1_000;
+    session.devices.insert("device-2".to_string(), second);
+
+    assert_eq!(session_device_cap(&config, &session), 3);
+    assert_eq!(
+        device_cap_decision(&config, &session),
+        DeviceCapDecision::Room
+    );
+
+    session.max_devices = Some(2);
+    assert_eq!(
+        device_cap_decision(&config, &session),
+        DeviceCapDecision::Full(2)
+    );
+
+    session.device_eviction_policy = DeviceEvictionPolicy::LeastRecentlySeen;
+    assert_eq!(
+        device_cap_decision(&config, &session),
+        DeviceCapDecision::Evict("device-1".to_string())
+    );
+
+    config.max_devices_per_session = 1;
+    session.max_devices = Some(5);
+    assert_eq!(session_device_cap(&config, &session), 1);
+
+    assert_eq!(
+        DeviceEvictionPolicy::parse(None),
+        Some(DeviceEvictionPolicy::Reject)
+    );
+    assert_eq!(
+        DeviceEvictionPolicy::parse(Some("least_recently_seen")),
+        Some(DeviceEvictionPolicy::LeastRecentlySeen)
+    );
+    assert_eq!(DeviceEvictionPolicy::parse(Some("newest")), None);
+}
+
+#[test]
+fn join_credentials_resolve_join_token_and_invites_with_limits() {
+    let now = now_ms();
+    let mut session = make_test_session("session-1", "device-1", "token-1");
+    session.join_token_used_at_ms = None;
+    session.invites.insert(
+        "invite-1".to_string(),
+        SessionInvite {
+            token: "invite-token".to_string(),
+            max_redemptions: 1,
+            redemptions: 0,
+            created_at_ms: now,
+            expires_at_ms: now + 60_000,
+            scopes: vec![SESSION_TRANSFER_SCOPE.to_string()],
+            auto_approve: true,
+        },
+    );
+    session.invites.insert(
+        "invite-2".to_string(),
+        SessionInvite {
+            token: "expired-invite-token".to_string(),
+            max_redemptions: 5,
+            redemptions: 0,
+            created_at_ms: now - 120_000,
+            expires_at_ms: now - 60_000,
+            scopes: Vec::new(),
+            auto_approve: false,
+        },
+    );
+
+    assert_eq!(
+        resolve_join_credential(&session, "join-token", now),
+        Ok(JoinCredential::JoinToken)
+    );
+    let invite = resolve_join_credential(&session, "invite-token", now).expect("invite");
+    assert!(invite.auto_approve());
+    assert_eq!(invite.expires_at_ms(&session), Some(now + 60_000));
+    assert_eq!(
+        JoinCredential::JoinToken.expires_at_ms(&session),
+        Some(session.join_token_expires_at_ms)
+    );
+    assert_eq!(
+        resolve_join_credential(&session, "expired-invite-token", now).map_err(|error| error.1),
+        Err("invite_expired")
+    );
+    assert_eq!(
+        resolve_join_credential(&session, "unknown-token", now).map_err(|error| error.1),
+        Err("invalid_join_token")
+    );
+
+    assert_eq!(
+        redeem_join_credential(&mut session, &invite, now),
+        vec![SESSION_TRANSFER_SCOPE.to_string()]
+    );
+    assert_eq!(
+        resolve_join_credential(&session, "invite-token", now).map_err(|error| error.1),
+        Err("invite_exhausted")
+    );
+    assert!(redeem_join_credential(&mut session, &JoinCredential::JoinToken, now).is_empty());
+    assert_eq!(
+        resolve_join_credential(&session, "join-token", now).map_err(|error| error.1),
+        Err("join_token_already_used")
+    );
+
+    prune_expired_invites(&mut session, now);
+    assert_eq!(session.invites.keys().collect::<Vec<_>>(), vec!["invite-1"]);
+}
+
+#[test]
+fn schema_negotiation_picks_highest_common_version() {
+    assert_eq!(negotiate_schema_version(&[2, 3]), Some(3));
+    assert_eq!(negotiate_schema_version(&[3, 2, 7]), Some(3));
+    assert_eq!(negotiate_schema_version(&[2]), Some(2));
+    assert_eq!(negotiate_schema_version(&[1, 4]), None);
+    assert_eq!(negotiate_schema_version(&[]), None);
+    assert_eq!(resolve_schema_version(None), Some(CANONICAL_SCHEMA_VERSION));
+}
+
+#[test]
+fn schema_adapters_round_trip_envelopes_between_v2_and_v3() {
+    let canonical = json!({
+        "schemaVersion": 2,
+        "sessionID": "session-1",
+        "seq": 4,
+        "timestamp": "2026-01-01T00:00:00Z",
+        "payload": {
+            "type": "snapshot",
+            "payload": { "threads": [] },
+            "reason": "resync"
+        },
+        "relaySnapshotStale": true
+    })
+    .to_string();
+
+    let v3: Value = serde_json::from_str(&adapt_envelope(&canonical, 3)).expect("v3 json");
+    assert_eq!(
+        v3,
+        json!({
+            "schemaVersion": 3,
+            "sessionID": "session-1",
+            "seq": 4,
+            "timestamp": "2026-01-01T00:00:00Z",
+            "type": "snapshot",
+            "body": { "threads": [] },
+            "meta": { "reason": "resync" },
+            "relaySnapshotStale": true
+        })
+    );
+    assert_eq!(
+        canonicalize_envelope(&v3),
+        Some(serde_json::from_str::<Value>(&canonical).expect("canonical json"))
+    );
 
-    let relay = state.inner.lock().await;
-    assert!(!relay.sessions.contains_key(session_id));
-    assert!(relay.device_token_index.is_empty());
+    let control = json!({ "type": "relay.device_count", "connectedDeviceCount": 1 }).to_string();
+    assert!(matches!(adapt_envelope(&control, 3), Cow::Borrowed(_)));
+    assert!(canonicalize_envelope(&serde_json::from_str(&control).expect("control")).is_none());
+    assert!(matches!(adapt_envelope(&canonical, 2), Cow::Borrowed(_)));
+
+    let mut adapted = AdaptedEnvelopes::new(&canonical);
+    assert_eq!(adapted.for_version(2), canonical);
+    assert!(adapted.for_version(3).contains("\"body\""));
 }
 
-#[tokio::test]
-async fn cross_instance_envelope_without_required_signature_is_ignored() {
-    let session_id = "session-1";
-    let mut state =
-        make_test_state_with_session(make_test_session(session_id, "device-1", "device-token-1"));
-    state.config.nats_hmac_secret = Some("01234567890123456789012345678901".to_string());
+fn make_pending_join_request(request_id: &str, requester_ip: &str, now: i64) -> PendingJoinRequest {
+    let (decision_tx, _) = oneshot::channel();
+    PendingJoinRequest {
+        request_id: request_id.to_string(),
+        requester_ip: requester_ip.to_string(),
+        requested_at_ms: now,
+        expires_at_ms: now + 45_000,
+        decision_tx,
+    }
+}
 
-    let envelope = CrossInstanceEnvelope {
-        schema_version: 1,
-        session_id: session_id.to_string(),
-        source_instance_id: "remote-instance".to_string(),
-        target: "mobile".to_string(),
-        target_device_id: None,
-        payload: json!({
-            "type": "disconnect",
-            "reason": "stopped_by_desktop"
-        })
-        .to_string(),
-        issued_at_ms: now_ms(),
-        nonce: "nonce-token-3".to_string(),
-        signature: None,
-    };
-    let payload = serde_json::to_vec(&envelope).expect("encode envelope");
-    handle_session_envelope(&state, "local-instance", &payload).await;
+#[test]
+fn pending_join_slot_bounds_requests_per_session_and_requester_ip() {
+    let now = now_ms();
+    let mut config = RelayConfig::from_env();
+    config.max_pending_pair_requests_per_session = 2;
+    config.max_pending_pair_requests_per_ip = 1;
+    let mut session = make_test_session("session-1", "device-1", "token-1");
 
-    let relay = state.inner.lock().await;
-    assert!(
-        relay.sessions.contains_key(session_id),
-        "session should remain when signature verification fails"
+    let mut stale = make_pending_join_request("request-0", "10.0.0.1", now - 60_000);
+    stale.expires_at_ms = now - 15_000;
+    session
+        .pending_join_requests
+        .insert("request-0".to_string(), stale);
+    assert_eq!(
+        pending_join_slot(&config, &session, "10.0.0.1", now),
+        PendingJoinSlot::Available
+    );
+
+    session.pending_join_requests.insert(
+        "request-1".to_string(),
+        make_pending_join_request("request-1", "10.0.0.1", now),
+    );
+    assert_eq!(
+        pending_join_slot(&config, &session, "10.0.0.1", now),
+        PendingJoinSlot::RequesterBusy {
+            request_id: "request-1".to_string(),
+            expires_at_ms: now + 45_000,
+        }
+    );
+    assert_eq!(
+        pending_join_slot(&config, &session, "10.0.0.2", now),
+        PendingJoinSlot::Available
+    );
+
+    session.pending_join_requests.insert(
+        "request-2".to_string(),
+        make_pending_join_request("request-2", "10.0.0.2", now),
+    );
+    assert_eq!(
+        pending_join_slot(&config, &session, "10.0.0.3", now),
+        PendingJoinSlot::SessionFull(2)
     );
 }
 
-#[test]
-fn try_send_payload_returns_false_when_queue_is_full() {
-    let (tx, mut rx) = mpsc::channel::<Message>(1);
+fn make_async_pair_request(status_token: &str, requested_at_ms: i64) -> AsyncPairRequest {
+    AsyncPairRequest {
+        status_token: status_token.to_string(),
+        state: AsyncPairRequestState::Pending,
+        requested_at_ms,
+        expires_at_ms: requested_at_ms + 60_000,
+        completed_at_ms: None,
+        device_id: None,
+        device_session_token: None,
+        error: None,
+        message: None,
+    }
+}
 
-    assert!(try_send_payload(&tx, "first".to_string()));
-    assert!(
-        !try_send_payload(&tx, "second".to_string()),
-        "second payload should be rejected when queue is full"
+fn make_pair_status_request(request_id: &str, status_token: &str) -> PairStatusRequest {
+    PairStatusRequest {
+        schema_version: None,
+        session_id: "session-1".to_string(),
+        request_id: request_id.to_string(),
+        status_token: status_token.to_string(),
+        wait_ms: None,
+    }
+}
+
+#[test]
+fn async_pair_requests_report_status_and_are_pruned_after_retention() {
+    let now = now_ms();
+    let mut session = make_test_session("session-1", "device-1", "token-1");
+    session.async_pair_requests.insert(
+        "pending-1".to_string(),
+        make_async_pair_request("status-1", now),
+    );
+    let mut approved = make_async_pair_request("status-2", now);
+    approved.state = AsyncPairRequestState::Approved;
+    approved.completed_at_ms = Some(now);
+    approved.device_id = Some("device-2".to_string());
+    approved.device_session_token = Some("device-token-2".to_string());
+    session
+        .async_pair_requests
+        .insert("approved-1".to_string(), approved);
+    let mut stale = make_async_pair_request("status-3", now - 600_000);
+    stale.state = AsyncPairRequestState::Rejected;
+    stale.completed_at_ms = Some(now - 500_000);
+    session
+        .async_pair_requests
+        .insert("stale-1".to_string(), stale);
+
+    let PairStatusLookup::Found(pending) = lookup_pair_status(
+        &session,
+        &make_pair_status_request("pending-1", "status-1"),
+        now,
+    ) else {
+        panic!("pending request should be found");
+    };
+    assert_eq!(pending.status, "pending");
+    assert!(pending.device_session_token.is_none());
+    assert!(pending.ws_url.is_none());
+
+    let PairStatusLookup::Found(expired) = lookup_pair_status(
+        &session,
+        &make_pair_status_request("pending-1", "status-1"),
+        now + 60_000,
+    ) else {
+        panic!("expired request should be found");
+    };
+    assert_eq!(expired.status, "expired");
+
+    let PairStatusLookup::Found(approved) = lookup_pair_status(
+        &session,
+        &make_pair_status_request("approved-1", "status-2"),
+        now,
+    ) else {
+        panic!("approved request should be found");
+    };
+    assert_eq!(approved.status, "approved");
+    assert_eq!(approved.device_id.as_deref(), Some("device-2"));
+    assert_eq!(
+        approved.ws_url.as_deref(),
+        Some(session.relay_web_socket_url.as_str())
     );
 
-    let first = rx.try_recv().expect("first payload should remain in queue");
-    assert_eq!(first, Message::Text("first".to_string().into()));
+    assert!(matches!(
+        lookup_pair_status(
+            &session,
+            &make_pair_status_request("approved-1", "status-1"),
+            now
+        ),
+        PairStatusLookup::InvalidToken
+    ));
+    assert!(matches!(
+        lookup_pair_status(
+            &session,
+            &make_pair_status_request("unknown-1", "status-1"),
+            now
+        ),
+        PairStatusLookup::NotFound
+    ));
+
+    prune_async_pair_requests(&mut session, now);
+    let mut remaining = session.async_pair_requests.keys().collect::<Vec<_>>();
+    remaining.sort();
+    assert_eq!(remaining, vec!["approved-1", "pending-1"]);
 }
 
 #[tokio::test]
 async fn request_socket_disconnect_sends_disconnect_and_shutdown_signal() {
-    let (tx, mut rx) = mpsc::channel::<Message>(4);
+    let (tx, mut rx) = socket_channel(4);
     let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
     let handle = SocketHandle {
         tx,
@@ -319,6 +1529,10 @@ async fn request_socket_disconnect_sends_disconnect_and_shutdown_signal() {
 
     shutdown_rx.changed().await.expect("shutdown change");
     assert!(*shutdown_rx.borrow());
+    assert_eq!(
+        handle.tx.disconnect_reason.get().map(String::as_str),
+        Some("slow_consumer")
+    );
 }
 
 #[tokio::test]
@@ -362,6 +1576,93 @@ async fn close_session_removes_all_device_tokens_for_closed_session() {
     );
 }
 
+#[tokio::test]
+async fn unknown_token_cache_entries_expire_and_are_swept() {
+    let state =
+        make_test_state_with_session(make_test_session("session-1", "device-1", "device-token-1"));
+    let now = now_ms();
+    {
+        let mut relay = state.inner.lock().await;
+        remember_unknown_token(&mut relay, persisted_token_hash("bad-token"), now);
+        assert!(is_cached_unknown_token(
+            &relay,
+            &persisted_token_hash("bad-token"),
+            now + UNKNOWN_TOKEN_CACHE_TTL_MS - 1
+        ));
+        assert!(!is_cached_unknown_token(
+            &relay,
+            &persisted_token_hash("bad-token"),
+            now + UNKNOWN_TOKEN_CACHE_TTL_MS
+        ));
+        assert!(!is_cached_unknown_token(
+            &relay,
+            &persisted_token_hash("other-token"),
+            now
+        ));
+        relay
+            .unknown_token_cache
+            .insert(persisted_token_hash("stale-token"), now - 1);
+    }
+
+    sweep_sessions(&state).await;
+
+    let relay = state.inner.lock().await;
+    assert!(relay
+        .unknown_token_cache
+        .contains_key(&persisted_token_hash("bad-token")));
+    assert!(!relay
+        .unknown_token_cache
+        .contains_key(&persisted_token_hash("stale-token")));
+}
+
+#[test]
+fn full_unknown_token_cache_drops_expired_entries_before_caching() {
+    let state =
+        make_test_state_with_session(make_test_session("session-1", "device-1", "device-token-1"));
+    let now = now_ms();
+    let mut relay = state.inner.try_lock().expect("relay state");
+    for index in 0..MAX_UNKNOWN_TOKEN_CACHE_ENTRIES {
+        relay
+            .unknown_token_cache
+            .insert(format!("expired-{index}"), now - 1);
+    }
+
+    remember_unknown_token(&mut relay, "fresh".to_string(), now);
+
+    assert_eq!(relay.unknown_token_cache.len(), 1);
+    assert!(is_cached_unknown_token(&relay, "fresh", now));
+}
+
+#[tokio::test]
+async fn request_refresh_of_a_local_session_is_throttled_and_keeps_it_when_redis_has_none() {
+    let mut state =
+        make_test_state_with_session(make_test_session("session-1", "device-1", "device-token-1"));
+    let mut config = RelayConfig::from_env();
+    config.redis_url = Some("redis://127.0.0.1:1".to_string());
+    state.persistence = build_persistence(&config);
+
+    refresh_session_for_request(&state, "session-1").await;
+    let first_refresh = state
+        .inner
+        .lock()
+        .await
+        .session_refreshed_at_ms
+        .get("session-1")
+        .copied()
+        .expect("refresh recorded");
+
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    refresh_session_for_request(&state, "session-1").await;
+
+    let relay = state.inner.lock().await;
+    assert_eq!(
+        relay.session_refreshed_at_ms.get("session-1"),
+        Some(&first_refresh),
+        "a second request within the throttle window does not reload"
+    );
+    assert!(relay.sessions.contains_key("session-1"));
+}
+
 #[tokio::test]
 async fn sweep_sessions_prunes_expired_retired_device_tokens() {
     let session_id = "session-1";
@@ -404,6 +1705,128 @@ async fn sweep_sessions_prunes_expired_retired_device_tokens() {
     );
 }
 
+#[test]
+fn session_lookup_tokens_include_opaque_tokens_in_grace_only() {
+    let now = now_ms();
+    let mut session = make_test_session("session-1", "device-1", "current-token");
+    let device = session.devices.get_mut("device-1").expect("device");
+    device.retired_session_tokens = vec![
+        RetiredDeviceToken {
+            token: "grace-token".to_string(),
+            expires_at_ms: now + 60_000,
+        },
+        RetiredDeviceToken {
+            token: "expired-token".to_string(),
+            expires_at_ms: now - 1,
+        },
+        RetiredDeviceToken {
+            token: "rdt1.kid.claims.signature".to_string(),
+            expires_at_ms: now + 60_000,
+        },
+    ];
+
+    let mut tokens = session_lookup_tokens(&session, now);
+    tokens.sort_unstable();
+    assert_eq!(
+        tokens,
+        vec!["current-token", "desktop-token", "grace-token"]
+    );
+    assert_ne!(
+        persisted_token_hash("current-token"),
+        persisted_token_hash("grace-token")
+    );
+    assert!(!persisted_token_hash("current-token").contains("current"));
+}
+
+#[test]
+fn persisted_session_change_payload_requires_version_and_session_id() {
+    assert_eq!(
+        parse_persisted_session_change("42:session-abcdef-1234"),
+        Some((42, "session-abcdef-1234"))
+    );
+    assert_eq!(parse_persisted_session_change("session-abcdef-1234"), None);
+    assert_eq!(
+        parse_persisted_session_change("x:session-abcdef-1234"),
+        None
+    );
+    assert_eq!(parse_persisted_session_change("42:short"), None);
+}
+
+#[tokio::test]
+async fn newer_persisted_write_updates_live_session_and_disconnects_removed_device() {
+    let mut session = make_test_session("session-1", "device-1", "token-1");
+    let (tx, mut rx) = socket_channel(4);
+    let (shutdown_tx, _shutdown_rx) = watch::channel(false);
+    session.mobile_sockets.insert(
+        "connection-1".to_string(),
+        SocketHandle {
+            tx,
+            shutdown: shutdown_tx,
+            device_id: Some("device-1".to_string()),
+        },
+    );
+    let state = make_test_state_with_session(session);
+    let mut relay = state.inner.lock().await;
+    relay
+        .persistence_versions
+        .insert("session-1".to_string(), 3);
+
+    let stale_write = make_test_session("session-1", "device-2", "token-2");
+    merge_persisted_session(&mut relay, "session-1", stale_write, 3, now_ms());
+    assert!(relay.device_token_index.contains_key("token-1"));
+    assert!(!relay.device_token_index.contains_key("token-2"));
+
+    let newer_write = make_test_session("session-1", "device-2", "token-2");
+    merge_persisted_session(&mut relay, "session-1", newer_write, 4, now_ms());
+    let session = relay.sessions.get("session-1").expect("session");
+    assert!(session.devices.contains_key("device-2"));
+    assert!(!session.devices.contains_key("device-1"));
+    assert!(session.mobile_sockets.is_empty());
+    assert!(!relay.device_token_index.contains_key("token-1"));
+    assert!(relay.device_token_index.contains_key("token-2"));
+    assert_eq!(relay.persistence_versions.get("session-1"), Some(&4));
+
+    let Some(Message::Text(payload)) = rx.recv().await else {
+        panic!("expected disconnect payload");
+    };
+    assert!(payload.contains("device_revoked"));
+}
+
+#[tokio::test]
+async fn persisted_write_without_local_sockets_keeps_pending_join_requests() {
+    let now = now_ms();
+    let mut session = make_test_session("session-1", "device-1", "token-1");
+    let (decision_tx, mut decision_rx) = oneshot::channel();
+    session.pending_join_requests.insert(
+        "request-1".to_string(),
+        PendingJoinRequest {
+            decision_tx,
+            ..make_pending_join_request("request-1", "203.0.113.10", now)
+        },
+    );
+    let state = make_test_state_with_session(session);
+    let mut relay = state.inner.lock().await;
+    relay
+        .persistence_versions
+        .insert("session-1".to_string(), 3);
+
+    let same_write = make_test_session("session-1", "device-2", "token-2");
+    merge_persisted_session(&mut relay, "session-1", same_write, 3, now);
+    let session = relay.sessions.get("session-1").expect("session");
+    assert!(session.devices.contains_key("device-1"));
+    assert_eq!(session.pending_join_requests.len(), 1);
+
+    let newer_write = make_test_session("session-1", "device-2", "token-2");
+    merge_persisted_session(&mut relay, "session-1", newer_write, 4, now);
+    let session = relay.sessions.get("session-1").expect("session");
+    assert!(session.devices.contains_key("device-2"));
+    assert!(session.pending_join_requests.contains_key("request-1"));
+    assert!(matches!(
+        decision_rx.try_recv(),
+        Err(oneshot::error::TryRecvError::Empty)
+    ));
+}
+
 #[tokio::test]
 async fn drain_sessions_for_shutdown_closes_active_sessions() {
     let session_id = "session-1";
@@ -437,11 +1860,21 @@ fn replay_invariant_rejects_duplicate_cross_instance_nonce() {
         ws_auth_attempts: 0,
         ws_auth_successes: 0,
         ws_auth_failure_reasons: HashMap::new(),
-        last_persistence_refresh_at_ms: 0,
+        unknown_token_cache: HashMap::new(),
+        session_refreshed_at_ms: HashMap::new(),
         persistence_versions: HashMap::new(),
         seen_cross_instance_nonces: HashMap::new(),
+        envelope_signature_failures: HashMap::new(),
         bus_subscribed_sessions: HashSet::new(),
         bus_subscription_tasks: HashMap::new(),
+        presence_claims: HashMap::new(),
+        lifecycle_events: None,
+        webhook_stats: WebhookDeliveryStats::default(),
+        penalty_records: HashMap::new(),
+        penalty_blocks_issued: 0,
+        penalty_box_rejections: 0,
+        ip_access_rejections: HashMap::new(),
+        byte_quota_drops: 0,
     };
     let now = now_ms();
     let envelope = CrossInstanceEnvelope {
@@ -454,6 +1887,8 @@ fn replay_invariant_rejects_duplicate_cross_instance_nonce() {
         issued_at_ms: now,
         nonce: "nonce-token-4".to_string(),
         signature: None,
+        key_id: None,
+        control_lane: false,
     };
 
     assert!(register_cross_instance_nonce(
@@ -465,6 +1900,118 @@ fn replay_invariant_rejects_duplicate_cross_instance_nonce() {
     );
 }
 
+fn make_signing_config(active_key_id: &str) -> RelayConfig {
+    let mut config = RelayConfig::from_env();
+    config.device_token_signing_keys = vec![
+        (
+            "k1".to_string(),
+            "signing-secret-one-0123456789abcdef".to_string(),
+        ),
+        (
+            "k2".to_string(),
+            "signing-secret-two-0123456789abcdef".to_string(),
+        ),
+    ];
+    config.device_token_signing_key_id = Some(active_key_id.to_string());
+    config
+}
+
+#[test]
+fn signed_device_token_round_trips_and_rejects_tampering() {
+    let config = make_signing_config("k1");
+    let now = now_ms();
+    let token = issue_device_session_token(&config, "session-1", "device-1", 3, now);
+    assert!(is_signed_device_token(&token));
+    assert!(is_device_session_token_candidate(&token));
+
+    let claims = verify_signed_device_token(&config, &token, now).ok();
+    assert_eq!(
+        claims.map(|claims| (claims.session_id, claims.device_id, claims.generation)),
+        Some(("session-1".to_string(), "device-1".to_string(), 3))
+    );
+
+    let forged_claims = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(
+        serde_json::to_vec(&SignedDeviceTokenClaims {
+            session_id: "session-2".to_string(),
+            device_id: "device-1".to_string(),
+            generation: 3,
+            expires_at_ms: now + 60_000,
+        })
+        .expect("encode claims"),
+    );
+    let mut parts = token.split('.').map(ToOwned::to_owned).collect::<Vec<_>>();
+    parts[2] = forged_claims;
+    let forged = parts.join(".");
+    assert_eq!(
+        verify_signed_device_token(&config, &forged, now).err(),
+        Some("signed_token_invalid_signature")
+    );
+    assert_eq!(
+        verify_signed_device_token(&config, &token, now + 31 * 24 * 60 * 60 * 1_000).err(),
+        Some("signed_token_expired")
+    );
+}
+
+#[test]
+fn signed_device_token_from_previous_key_id_verifies_after_rotation() {
+    let now = now_ms();
+    let token = issue_device_session_token(&make_signing_config("k1"), "s", "d", 0, now);
+
+    let rotated = make_signing_config("k2");
+    assert!(verify_signed_device_token(&rotated, &token, now).is_ok());
+
+    let mut retired = make_signing_config("k2");
+    retired
+        .device_token_signing_keys
+        .retain(|(key_id, _)| key_id != "k1");
+    assert_eq!(
+        verify_signed_device_token(&retired, &token, now).err(),
+        Some("signed_token_unknown_key")
+    );
+}
+
+#[tokio::test]
+async fn signed_device_token_generation_bump_revokes_previous_token() {
+    let config = make_signing_config("k1");
+    let now = now_ms();
+    let first = issue_device_session_token(&config, "session-1", "device-1", 0, now);
+    let mut state =
+        make_test_state_with_session(make_test_session("session-1", "device-1", &first));
+    state.config = config.clone();
+
+    {
+        let relay = state.inner.lock().await;
+        assert!(matches!(
+            resolve_signed_device_token(&relay, &config, &first, now),
+            SignedDeviceTokenResolution::Resolved(AuthContext::Mobile { .. })
+        ));
+        assert!(relay.device_token_index.is_empty());
+    }
+
+    let second = issue_device_session_token(&config, "session-1", "device-1", 1, now);
+    let mut relay = state.inner.lock().await;
+    assert!(matches!(
+        resolve_signed_device_token(&relay, &config, &second, now),
+        SignedDeviceTokenResolution::SessionStale { .. }
+    ));
+
+    let device = relay
+        .sessions
+        .get_mut("session-1")
+        .and_then(|session| session.devices.get_mut("device-1"))
+        .expect("device exists");
+    device.token_generation = 1;
+    device.current_session_token = second.clone();
+    assert!(matches!(
+        resolve_signed_device_token(&relay, &config, &first, now),
+        SignedDeviceTokenResolution::Rejected("signed_token_revoked")
+    ));
+    assert!(matches!(
+        resolve_signed_device_token(&relay, &config, &second, now),
+        SignedDeviceTokenResolution::Resolved(_)
+    ));
+}
+
 fn make_protocol_validation_config() -> RelayConfig {
     let mut config = RelayConfig::from_env();
     config.max_remote_commands_per_minute = 60;
@@ -498,7 +2045,7 @@ fn protocol_invariant_rejects_replayed_command_sequences() {
 
     let first_result = validate_mobile_payload(
         &mut session,
-        Some(&payload),
+        &decode_inbound_frame(&payload.to_string()).message,
         "session-1",
         "conn-1",
         "device-1",
@@ -508,7 +2055,7 @@ fn protocol_invariant_rejects_replayed_command_sequences() {
 
     let replay_result = validate_mobile_payload(
         &mut session,
-        Some(&payload),
+        &decode_inbound_frame(&payload.to_string()).message,
         "session-1",
         "conn-1",
         "device-1",
@@ -533,7 +2080,7 @@ fn protocol_invariant_rejects_invalid_snapshot_last_sequence() {
 
     let result = validate_mobile_payload(
         &mut session,
-        Some(&payload),
+        &decode_inbound_frame(&payload.to_string()).message,
         "session-1",
         "conn-1",
         "device-1",
@@ -545,6 +2092,19 @@ fn protocol_invariant_rejects_invalid_snapshot_last_sequence() {
     );
 }
 
+fn decoded_command(raw: &str) -> CommandEnvelope {
+    match decode_inbound_frame(raw).message {
+        InboundMessage::Command(envelope) => *envelope,
+        _ => panic!("frame should decode as a command"),
+    }
+}
+
+fn plain_command() -> CommandEnvelope {
+    decoded_command(
+        r#"{"schemaVersion":2,"sessionID":"session-1","seq":1,"payload":{"type":"command","payload":{"name":"thread.select","commandID":"cmd-1","threadID":"thread-1"}}}"#,
+    )
+}
+
 #[test]
 fn protocol_invariant_overwrites_spoofed_mobile_metadata() {
     let raw = json!({
@@ -564,7 +2124,12 @@ fn protocol_invariant_overwrites_spoofed_mobile_metadata() {
     })
     .to_string();
 
-    let injected = inject_mobile_metadata(&raw, "conn-actual", "device-actual");
+    let injected = forwarded_command_payload(
+        &raw,
+        Some(&decoded_command(&raw)),
+        "conn-actual",
+        "device-actual",
+    );
     let parsed: Value = serde_json::from_str(&injected).expect("injected payload");
 
     assert_eq!(
@@ -577,6 +2142,114 @@ fn protocol_invariant_overwrites_spoofed_mobile_metadata() {
     );
 }
 
+#[test]
+fn forwarded_command_payload_appends_metadata_without_reserializing() {
+    let raw = r#"{ "seq": 1, "sessionID": "session-1", "payload": {"type": "command"} }"#;
+
+    let forwarded =
+        forwarded_command_payload(raw, Some(&plain_command()), "conn-1", "device-\"1\"");
+
+    assert!(forwarded
+        .starts_with(r#"{ "seq": 1, "sessionID": "session-1", "payload": {"type": "command"} ,"#));
+    let parsed: Value = serde_json::from_str(&forwarded).expect("forwarded payload");
+    assert_eq!(parsed["relayConnectionID"], json!("conn-1"));
+    assert_eq!(parsed["relayDeviceID"], json!("device-\"1\""));
+    assert_eq!(parsed["seq"], json!(1));
+
+    let forwarded = forwarded_command_payload("{}", Some(&plain_command()), "conn-1", "device-1");
+    assert_eq!(
+        forwarded,
+        r#"{"relayConnectionID":"conn-1","relayDeviceID":"device-1"}"#
+    );
+}
+
+#[test]
+fn forwarded_command_payload_rewrites_escaped_metadata_keys() {
+    let raw = r#"{"schemaVersion":2,"sessionID":"session-1","seq":1,"\u0072elayDeviceID":"spoofed-device","payload":{"type":"command","payload":{"name":"thread.select","commandID":"cmd-1","threadID":"thread-1"}}}"#;
+    let envelope = decoded_command(raw);
+    assert_eq!(envelope.relay_device_id.as_deref(), Some("spoofed-device"));
+
+    let forwarded = forwarded_command_payload(raw, Some(&envelope), "conn-1", "device-1");
+
+    assert!(!forwarded.contains("spoofed-device"));
+    assert_eq!(forwarded.matches("relayDeviceID").count(), 1);
+    let parsed: Value = serde_json::from_str(&forwarded).expect("forwarded payload");
+    assert_eq!(parsed["relayDeviceID"], json!("device-1"));
+    assert_eq!(parsed["relayConnectionID"], json!("conn-1"));
+}
+
+#[test]
+fn desktop_frames_with_malformed_routing_fields_keep_their_session_id() {
+    let frame = decode_inbound_frame(r#"{"sessionID":"session-2","payload":"not-an-object"}"#);
+    assert_eq!(frame.session_id.as_deref(), Some("session-2"));
+    assert!(matches!(frame.message, InboundMessage::Invalid(_)));
+}
+
+#[test]
+fn inbound_frames_decode_into_typed_messages() {
+    let auth = decode_inbound_frame(r#"{"type":"relay.auth","token":"token-1"}"#);
+    assert!(
+        matches!(auth.message, InboundMessage::Auth(ref message) if message.token == "token-1")
+    );
+
+    let decision = decode_inbound_frame(
+        r#"{"type":"relay.pair_decision","sessionID":"session-1","requestID":"request-1","approved":true}"#,
+    );
+    assert!(matches!(decision.message, InboundMessage::PairDecision(_)));
+    assert_eq!(decision.session_id.as_deref(), Some("session-1"));
+
+    let snapshot_request = decode_inbound_frame(
+        r#"{"type":"relay.snapshot_request","sessionID":"session-1","lastSeq":"12"}"#,
+    );
+    assert!(matches!(
+        snapshot_request.message,
+        InboundMessage::SnapshotRequest(_)
+    ));
+
+    let command_raw = make_valid_command_payload("session-1", 3).to_string();
+    let command = decode_inbound_frame(&command_raw);
+    assert!(matches!(command.raw, Cow::Borrowed(_)));
+    assert!(matches!(command.message, InboundMessage::Command(ref envelope) if envelope.seq == 3));
+
+    let snapshot = decode_inbound_frame(
+        r#"{"schemaVersion":2,"sessionID":"session-1","seq":4,"payload":{"type":"snapshot","payload":{"threads":[]}}}"#,
+    );
+    assert!(matches!(snapshot.raw, Cow::Borrowed(_)));
+    assert_eq!(
+        snapshot.message.passthrough_payload_type(),
+        Some("snapshot")
+    );
+    assert_eq!(snapshot.session_id.as_deref(), Some("session-1"));
+
+    let v3_command = decode_inbound_frame(
+        r#"{"schemaVersion":3,"sessionID":"session-1","seq":5,"type":"command","body":{"name":"thread.select","commandID":"cmd-5","threadID":"thread-1"}}"#,
+    );
+    assert!(matches!(v3_command.raw, Cow::Owned(_)));
+    assert!(matches!(
+        v3_command.message,
+        InboundMessage::Command(ref envelope) if envelope.schema_version == 2
+    ));
+
+    for (raw, code) in [
+        ("not json", "invalid_payload"),
+        ("[1,2]", "invalid_payload"),
+        (r#"{"type":"relay.auth"}"#, "invalid_auth_payload"),
+        (
+            r#"{"type":"relay.snapshot_request","sessionID":"session-1","extra":1}"#,
+            "invalid_snapshot_request",
+        ),
+        (
+            r#"{"schemaVersion":2,"sessionID":"session-1","seq":1,"payload":{"type":"command","payload":{"name":"thread.delete","commandID":"cmd-1"}}}"#,
+            "invalid_command",
+        ),
+    ] {
+        match decode_inbound_frame(raw).message {
+            InboundMessage::Invalid(error) => assert_eq!(error.code, code, "{raw}"),
+            _ => panic!("{raw} should not decode"),
+        }
+    }
+}
+
 #[test]
 fn protocol_invariant_rejects_missing_command_id() {
     let config = make_protocol_validation_config();
@@ -596,7 +2269,7 @@ fn protocol_invariant_rejects_missing_command_id() {
 
     let result = validate_mobile_payload(
         &mut session,
-        Some(&payload),
+        &decode_inbound_frame(&payload.to_string()).message,
         "session-1",
         "conn-1",
         "device-1",
@@ -633,7 +2306,7 @@ fn protocol_invariant_accepts_runtime_request_response_commands() {
 
     let result = validate_mobile_payload(
         &mut session,
-        Some(&payload),
+        &decode_inbound_frame(&payload.to_string()).message,
         "session-1",
         "conn-1",
         "device-1",
@@ -642,6 +2315,54 @@ fn protocol_invariant_accepts_runtime_request_response_commands() {
     assert!(result.is_ok());
 }
 
+#[test]
+fn message_schemas_match_protocol_validator() {
+    let schemas = message_schemas();
+    let definitions = &schemas["inbound"]["command.v2"]["$defs"];
+
+    let config = make_protocol_validation_config();
+    let mut session = make_test_session("session-1", "device-1", "token-1");
+    let command_names: Vec<String> = definitions["RemoteCommandName"]["enum"]
+        .as_array()
+        .map(|names| {
+            names
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    assert_eq!(command_names.len(), 4);
+    for (index, name) in command_names.iter().enumerate() {
+        let payload = json!({
+            "schemaVersion": 2,
+            "sessionID": "session-1",
+            "seq": index + 1,
+            "payload": {
+                "type": "command",
+                "payload": {
+                    "name": name,
+                    "commandID": format!("cmd-{index}"),
+                    "threadID": "thread-1",
+                    "projectID": "project-1",
+                    "text": "hello",
+                    "runtimeRequestID": "42",
+                    "runtimeRequestResponse": { "decision": "accept" }
+                }
+            }
+        });
+        let result = validate_mobile_payload(
+            &mut session,
+            &decode_inbound_frame(&payload.to_string()).message,
+            "session-1",
+            "conn-1",
+            "device-1",
+            &config,
+        );
+        assert!(result.is_ok(), "{name} should be accepted");
+    }
+}
+
 #[test]
 fn protocol_invariant_rejects_legacy_approval_response_commands() {
     let config = make_protocol_validation_config();
@@ -663,7 +2384,7 @@ fn protocol_invariant_rejects_legacy_approval_response_commands() {
 
     let result = validate_mobile_payload(
         &mut session,
-        Some(&payload),
+        &decode_inbound_frame(&payload.to_string()).message,
         "session-1",
         "conn-1",
         "device-1",
@@ -693,7 +2414,7 @@ fn protocol_invariant_rejects_runtime_request_responses_without_fields() {
 
     let result = validate_mobile_payload(
         &mut session,
-        Some(&payload),
+        &decode_inbound_frame(&payload.to_string()).message,
         "session-1",
         "conn-1",
         "device-1",
@@ -719,7 +2440,7 @@ proptest! {
 
         let result = validate_mobile_payload(
             &mut session,
-            Some(&payload),
+            &decode_inbound_frame(&payload.to_string()).message,
             "session-1",
             "conn-1",
             "device-1",
@@ -742,7 +2463,7 @@ proptest! {
 
         let result = validate_mobile_payload(
             &mut session,
-            Some(&payload),
+            &decode_inbound_frame(&payload.to_string()).message,
             "session-1",
             "conn-1",
             "device-1",
@@ -756,7 +2477,7 @@ proptest! {
     }
 
     #[test]
-    fn property_inject_mobile_metadata_overwrites_spoofed_values(
+    fn property_forwarded_command_payload_overwrites_spoofed_values(
         mut fields in hash_map("[a-z]{1,8}", any::<i32>(), 0..12)
     ) {
         fields.insert("relayConnectionID".to_string(), 7);
@@ -768,7 +2489,7 @@ proptest! {
         }
 
         let raw = Value::Object(payload).to_string();
-        let injected = inject_mobile_metadata(&raw, "conn-prop", "device-prop");
+        let injected = forwarded_command_payload(&raw, Some(&plain_command()), "conn-prop", "device-prop");
         let parsed: Value = serde_json::from_str(&injected).expect("valid injected json");
 
         prop_assert_eq!(
@@ -0,0 +1,139 @@
+use super::*;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct TrafficCounters {
+    pub(super) bytes_in: u64,
+    pub(super) bytes_out: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ByteQuotaWindow {
+    window_started_at_ms: i64,
+    bytes: u64,
+    notified: bool,
+}
+
+/// Outcome of charging bytes against the session and device quotas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ByteQuotaDecision {
+    Allowed,
+    /// Over quota; `notify` is set for the first drop in a window so the client gets a single
+    /// `relay.error` instead of one per frame.
+    Exceeded {
+        notify: bool,
+    },
+}
+
+impl ByteQuotaWindow {
+    fn roll(&mut self, window_ms: u64, now: i64) {
+        if now - self.window_started_at_ms >= i64::try_from(window_ms).unwrap_or(i64::MAX) {
+            *self = Self {
+                window_started_at_ms: now,
+                ..Self::default()
+            };
+        }
+    }
+
+    fn has_room(&self, quota: u64, bytes: u64) -> bool {
+        quota == 0 || self.bytes.saturating_add(bytes) <= quota
+    }
+
+    fn exceeded(&mut self) -> ByteQuotaDecision {
+        let notify = !self.notified;
+        self.notified = true;
+        ByteQuotaDecision::Exceeded { notify }
+    }
+}
+
+/// Bytes relayed for one session, in total and per paired device. Counters are runtime-only
+/// and start from zero when a session is restored from persistence.
+#[derive(Default)]
+pub(super) struct SessionTraffic {
+    pub(super) totals: TrafficCounters,
+    pub(super) devices: HashMap<String, TrafficCounters>,
+    session_window: ByteQuotaWindow,
+    device_windows: HashMap<String, ByteQuotaWindow>,
+}
+
+impl SessionTraffic {
+    pub(super) fn device(&self, device_id: &str) -> TrafficCounters {
+        self.devices.get(device_id).copied().unwrap_or_default()
+    }
+
+    pub(super) fn remove_device(&mut self, device_id: &str) {
+        self.devices.remove(device_id);
+        self.device_windows.remove(device_id);
+    }
+
+    /// Counts a frame received from the desktop (`device_id` is `None`) or a mobile device and
+    /// charges it to `SESSION_BYTE_QUOTA_PER_INSTANCE` and, for mobiles, `DEVICE_BYTE_QUOTA_PER_INSTANCE`.
+    pub(super) fn admit_inbound(
+        &mut self,
+        config: &RelayConfig,
+        device_id: Option<&str>,
+        bytes: u64,
+        now: i64,
+    ) -> ByteQuotaDecision {
+        self.totals.bytes_in = self.totals.bytes_in.saturating_add(bytes);
+        if let Some(device_id) = device_id {
+            let counters = self.devices.entry(device_id.to_string()).or_default();
+            counters.bytes_in = counters.bytes_in.saturating_add(bytes);
+        }
+
+        let window_ms = config.byte_quota_window_ms;
+        self.session_window.roll(window_ms, now);
+        if !self
+            .session_window
+            .has_room(config.session_byte_quota_per_instance, bytes)
+        {
+            return self.session_window.exceeded();
+        }
+        if let Some(device_id) = device_id {
+            let device_window = self
+                .device_windows
+                .entry(device_id.to_string())
+                .or_default();
+            device_window.roll(window_ms, now);
+            if !device_window.has_room(config.device_byte_quota_per_instance, bytes) {
+                return device_window.exceeded();
+            }
+            device_window.bytes = device_window.bytes.saturating_add(bytes);
+        }
+        self.session_window.bytes = self.session_window.bytes.saturating_add(bytes);
+        ByteQuotaDecision::Allowed
+    }
+
+    /// Charges a frame about to be delivered to a mobile device against `DEVICE_BYTE_QUOTA_PER_INSTANCE`
+    /// and counts it as sent when allowed.
+    pub(super) fn admit_outbound(
+        &mut self,
+        config: &RelayConfig,
+        device_id: Option<&str>,
+        bytes: u64,
+        now: i64,
+    ) -> ByteQuotaDecision {
+        if let Some(device_id) = device_id {
+            let device_window = self
+                .device_windows
+                .entry(device_id.to_string())
+                .or_default();
+            device_window.roll(config.byte_quota_window_ms, now);
+            if !device_window.has_room(config.device_byte_quota_per_instance, bytes) {
+                return device_window.exceeded();
+            }
+            device_window.bytes = device_window.bytes.saturating_add(bytes);
+            let counters = self.devices.entry(device_id.to_string()).or_default();
+            counters.bytes_out = counters.bytes_out.saturating_add(bytes);
+        }
+        self.totals.bytes_out = self.totals.bytes_out.saturating_add(bytes);
+        ByteQuotaDecision::Allowed
+    }
+}
+
+pub(super) fn send_byte_quota_error(tx: &SocketSender) {
+    send_relay_error(
+        tx,
+        "byte_quota_exceeded",
+        "Byte quota exceeded on this relay instance for this time window. Messages are dropped until it resets.",
+    );
+}
@@ -0,0 +1,170 @@
+use super::*;
+
+/// Device scope a desktop can grant in `relay.pair_decision` so the phone may issue session
+/// transfer tokens on its behalf.
+pub(super) const SESSION_TRANSFER_SCOPE: &str = "session_transfer";
+
+const KNOWN_DEVICE_SCOPES: &[&str] = &[SESSION_TRANSFER_SCOPE];
+
+/// Keeps the known scopes from a pair decision, trimmed and without duplicates.
+pub(super) fn sanitize_device_scopes(scopes: &[String]) -> Vec<String> {
+    let mut sanitized: Vec<String> = Vec::new();
+    for scope in scopes.iter().map(|scope| scope.trim()) {
+        if KNOWN_DEVICE_SCOPES.contains(&scope) && !sanitized.iter().any(|known| known == scope) {
+            sanitized.push(scope.to_string());
+        }
+    }
+    sanitized
+}
+
+/// Checks the credential presented to `/session/transfer/issue`: the current desktop token, or
+/// the token of a paired device holding the `session_transfer` scope. Returns the issuer label
+/// recorded on the grant.
+pub(super) fn authorize_transfer_issuer(
+    relay: &RelayState,
+    config: &RelayConfig,
+    session: &SessionRecord,
+    request: &SessionTransferIssueRequest,
+    now: i64,
+) -> Result<String, (StatusCode, &'static str, &'static str)> {
+    match (
+        request.desktop_session_token.as_deref(),
+        request.device_session_token.as_deref(),
+    ) {
+        (Some(desktop_session_token), None) => {
+            if safe_token_equals(&session.desktop_session_token, desktop_session_token) {
+                Ok("desktop".to_string())
+            } else {
+                Err((
+                    StatusCode::FORBIDDEN,
+                    "invalid_desktop_session_token",
+                    "Desktop session token is invalid.",
+                ))
+            }
+        }
+        (None, Some(device_session_token)) => {
+            let auth_context = if is_signed_device_token(device_session_token) {
+                match resolve_signed_device_token(relay, config, device_session_token, now) {
+                    SignedDeviceTokenResolution::Resolved(auth_context) => Some(auth_context),
+                    _ => None,
+                }
+            } else {
+                resolve_auth_context(relay, device_session_token)
+            };
+            let device_id = match auth_context {
+                Some(AuthContext::Mobile {
+                    session_id,
+                    device_id,
+                }) if session_id == session.session_id => device_id,
+                _ => {
+                    return Err((
+                        StatusCode::FORBIDDEN,
+                        "invalid_device_session_token",
+                        "Device session token is invalid.",
+                    ))
+                }
+            };
+            let has_scope = session.devices.get(&device_id).is_some_and(|device| {
+                device
+                    .scopes
+                    .iter()
+                    .any(|scope| scope == SESSION_TRANSFER_SCOPE)
+            });
+            if !has_scope {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    "transfer_scope_required",
+                    "This device was not granted the session_transfer scope.",
+                ));
+            }
+            Ok(format!("device:{device_id}"))
+        }
+        _ => Err((
+            StatusCode::BAD_REQUEST,
+            "invalid_session_transfer",
+            "Exactly one of desktopSessionToken or deviceSessionToken is required.",
+        )),
+    }
+}
+
+pub(super) enum TransferTokenClaim {
+    Claimed,
+    AlreadyUsed,
+    DesktopTokenInUse,
+}
+
+impl RelayStatePersistence {
+    fn transfer_key(&self, session_id: &str) -> String {
+        format!("{}:{session_id}", self.transfer_key_prefix)
+    }
+
+    /// Stores the hash of the outstanding transfer token, replacing any earlier one.
+    pub(super) async fn save_transfer_token(
+        &self,
+        session_id: &str,
+        transfer_token: &str,
+        ttl_ms: u64,
+    ) -> Result<(), String> {
+        let mut connection = self
+            .redis_client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|error| format!("redis connection failed: {error}"))?;
+
+        connection
+            .pset_ex::<_, _, ()>(
+                self.transfer_key(session_id),
+                persisted_token_hash(transfer_token),
+                ttl_ms.max(1),
+            )
+            .await
+            .map_err(|error| format!("redis set transfer token failed: {error}"))
+    }
+
+    /// Consumes the transfer token and claims the token index entry of the new desktop token in
+    /// one step, so only one instance can redeem a token and the new desktop token cannot already
+    /// belong to a persisted session.
+    pub(super) async fn claim_transfer_token(
+        &self,
+        session_id: &str,
+        transfer_token: &str,
+        desktop_session_token: &str,
+    ) -> Result<TransferTokenClaim, String> {
+        let mut connection = self
+            .redis_client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|error| format!("redis connection failed: {error}"))?;
+
+        // The claimed index key joins the session's token set, so the next save of the session
+        // keeps it and a later save drops it again once the token is replaced.
+        let script = redis::Script::new(
+            r#"
+            if redis.call("EXISTS", KEYS[2]) == 1 then
+                return -1
+            end
+            if redis.call("GET", KEYS[1]) ~= ARGV[1] then
+                return 0
+            end
+            redis.call("DEL", KEYS[1])
+            redis.call("SET", KEYS[2], ARGV[2])
+            redis.call("SADD", KEYS[3], KEYS[2])
+            return 1
+            "#,
+        );
+        let claimed = script
+            .key(self.transfer_key(session_id))
+            .key(self.token_key(desktop_session_token))
+            .key(self.session_tokens_key(session_id))
+            .arg(persisted_token_hash(transfer_token))
+            .arg(session_id)
+            .invoke_async::<i32>(&mut connection)
+            .await
+            .map_err(|error| format!("redis claim transfer token failed: {error}"))?;
+        Ok(match claimed {
+            1 => TransferTokenClaim::Claimed,
+            -1 => TransferTokenClaim::DesktopTokenInUse,
+            _ => TransferTokenClaim::AlreadyUsed,
+        })
+    }
+}
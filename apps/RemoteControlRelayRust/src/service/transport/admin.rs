@@ -0,0 +1,101 @@
+use super::*;
+
+/// Admin routes require `Authorization: Bearer <ADMIN_API_TOKEN>` and are hidden (`404`) when
+/// no token is configured.
+fn admin_rejection(config: &RelayConfig, headers: &HeaderMap) -> Option<axum::response::Response> {
+    let Some(admin_api_token) = config.admin_api_token.as_deref() else {
+        return Some(error_response(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            "Admin API is not enabled.",
+        ));
+    };
+
+    let presented = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim);
+    if presented.is_some_and(|token| safe_token_equals(token, admin_api_token)) {
+        return None;
+    }
+    Some(error_response(
+        StatusCode::UNAUTHORIZED,
+        "invalid_admin_token",
+        "Admin token is missing or invalid.",
+    ))
+}
+
+pub(super) async fn penalty_box_list(
+    State(state): State<SharedRelayState>,
+    headers: HeaderMap,
+) -> axum::response::Response {
+    if let Some(response) = admin_rejection(&state.config, &headers) {
+        return response;
+    }
+
+    match list_penalty_blocks(&state).await {
+        Ok(blocks) => (
+            StatusCode::OK,
+            Json(PenaltyBoxListResponse {
+                blocks: blocks
+                    .into_iter()
+                    .map(|block| PenaltyBlockSummary {
+                        key: block.key,
+                        blocked_until: iso_from_millis(block.blocked_until_ms),
+                    })
+                    .collect(),
+            }),
+        )
+            .into_response(),
+        Err(error) => {
+            warn!("[relay-rs] penalty box list failed: {error}");
+            error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "penalty_box_unavailable",
+                "Penalty box state could not be loaded.",
+            )
+        }
+    }
+}
+
+pub(super) async fn penalty_box_clear(
+    State(state): State<SharedRelayState>,
+    headers: HeaderMap,
+    Json(request): Json<PenaltyBoxClearRequest>,
+) -> axum::response::Response {
+    if let Some(response) = admin_rejection(&state.config, &headers) {
+        return response;
+    }
+
+    let key = request.key.trim();
+    if !(key.starts_with("ip:") || key.starts_with("subnet:")) || key.len() > 128 {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_penalty_key",
+            "key must be an ip: or subnet: penalty box key.",
+        );
+    }
+
+    match clear_penalty_block(&state, key).await {
+        Ok(cleared) => {
+            info!("[relay-rs] penalty_box_clear key={key} cleared={cleared}");
+            (
+                StatusCode::OK,
+                Json(PenaltyBoxClearResponse {
+                    cleared,
+                    key: key.to_string(),
+                }),
+            )
+                .into_response()
+        }
+        Err(error) => {
+            warn!("[relay-rs] penalty box clear failed key={key}: {error}");
+            error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "penalty_box_unavailable",
+                "Penalty box state could not be updated.",
+            )
+        }
+    }
+}
@@ -2,12 +2,12 @@ use super::*;
 
 fn validate_schema_version(schema_version: Option<u32>) -> Option<axum::response::Response> {
     match schema_version {
-        Some(2) | None => None,
-        Some(_) => Some(error_response(
+        Some(version) if !is_supported_schema_version(version) => Some(error_response(
             StatusCode::BAD_REQUEST,
             "unsupported_schema_version",
-            "Only schemaVersion 2 is supported.",
+            &unsupported_schema_version_message(),
         )),
+        _ => None,
     }
 }
 
@@ -82,11 +82,49 @@ pub(super) async fn pair_start(
         );
     }
 
+    if request
+        .max_devices
+        .is_some_and(|cap| cap == 0 || cap > state.config.max_devices_per_session)
+    {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_pair_start",
+            &format!(
+                "maxDevices must be between 1 and {}.",
+                state.config.max_devices_per_session
+            ),
+        );
+    }
+    let Some(device_eviction_policy) =
+        DeviceEvictionPolicy::parse(request.device_eviction_policy.as_deref())
+    else {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_pair_start",
+            "deviceEvictionPolicy must be reject or least_recently_seen.",
+        );
+    };
+    let Some(desktop_schema_version) =
+        resolve_schema_version(request.supported_schema_versions.as_deref())
+    else {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "unsupported_schema_version",
+            &unsupported_schema_version_message(),
+        );
+    };
+
     let relay_web_socket_url = request
         .relay_web_socket_url
         .as_deref()
         .and_then(normalize_relay_web_socket_url)
         .unwrap_or_else(|| state.config.websocket_url());
+    let pairing_uri = pairing_uri(
+        &state.config,
+        &request.session_id,
+        &request.join_token,
+        &relay_web_socket_url,
+    );
     let desktop_session_token = request.desktop_session_token.clone();
     let idle_timeout_seconds = request
         .idle_timeout_seconds
@@ -144,12 +182,25 @@ pub(super) async fn pair_start(
             session_command_rate_bucket: None,
             snapshot_request_rate_buckets: HashMap::new(),
             command_sequence_by_connection_id: HashMap::new(),
-            pending_join_request: None,
+            pending_join_requests: HashMap::new(),
+            latest_snapshot: None,
+            traffic: SessionTraffic::default(),
+            notified_runtime_requests: HashSet::new(),
+            transfer_grant: None,
+            max_devices: request.max_devices,
+            device_eviction_policy,
+            invites: HashMap::new(),
+            async_pair_requests: HashMap::new(),
+            desktop_schema_version,
         },
     );
     relay
         .desktop_token_index
         .insert(desktop_session_token, request.session_id.clone());
+    emit_lifecycle_event(
+        &mut relay,
+        LifecycleEvent::new(LifecycleEventKind::SessionStarted, &request.session_id),
+    );
 
     info!(
         "[relay-rs] pair_start session={}",
@@ -157,8 +208,7 @@ pub(super) async fn pair_start(
     );
     drop(relay);
     if replaced_existing_session {
-        let disconnect_payload =
-            json!({ "type": "disconnect", "reason": "replaced_by_new_pair_start" }).to_string();
+        let disconnect_payload = disconnect_payload("replaced_by_new_pair_start");
         publish_cross_instance_session(
             &state,
             &request.session_id,
@@ -187,11 +237,25 @@ pub(super) async fn pair_start(
             accepted: true,
             session_id: request.session_id,
             ws_url: relay_web_socket_url,
+            pairing_uri,
+            max_devices: request
+                .max_devices
+                .unwrap_or(state.config.max_devices_per_session),
+            device_eviction_policy: device_eviction_policy.as_str().to_string(),
+            schema_version: desktop_schema_version,
         }),
     )
         .into_response()
 }
 
+fn device_cap_reached(cap: usize) -> PairJoinFailure {
+    PairJoinFailure::new(
+        StatusCode::CONFLICT,
+        "device_cap_reached",
+        format!("This session allows at most {cap} connected devices."),
+    )
+}
+
 pub(super) async fn pair_join(
     State(state): State<SharedRelayState>,
     headers: HeaderMap,
@@ -212,6 +276,10 @@ pub(super) async fn pair_join(
     }
 
     let client_ip = client_ip(&state.config, &headers, addr);
+    if let Some(response) = penalty_box_rejection(&state, &client_ip).await {
+        warn!("[relay-rs] pair_join_failure code=ip_blocked remote_ip={client_ip}");
+        return response;
+    }
     if is_rate_limited(&state, &client_ip).await {
         return pair_join_failure_response(
             StatusCode::TOO_MANY_REQUESTS,
@@ -228,7 +296,16 @@ pub(super) async fn pair_join(
         );
     }
 
-    refresh_sessions_from_persistence(&state, false).await;
+    let Some(schema_version) = resolve_schema_version(request.supported_schema_versions.as_deref())
+    else {
+        return pair_join_failure_response(
+            StatusCode::BAD_REQUEST,
+            "unsupported_schema_version",
+            &unsupported_schema_version_message(),
+        );
+    };
+
+    refresh_session_for_request(&state, &request.session_id).await;
 
     let requested_device_name = request
         .device_name
@@ -236,17 +313,28 @@ pub(super) async fn pair_join(
         .map(str::trim)
         .filter(|value| !value.is_empty())
         .map(|value| value.chars().take(64).collect::<String>());
+    let device_name = sanitize_device_name(requested_device_name.as_deref());
+    let client_info = DeviceClientInfo::from_request(
+        request.platform.as_deref(),
+        request.app_version.as_deref(),
+        headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok()),
+    );
+    let status_token = random_token(32);
 
     let (
         decision_rx,
         request_id,
         pair_request_payload,
         outbound_send_failures,
-        slow_consumer_disconnects,
         pair_approval_timeout_ms,
+        join_requested_at_ms,
     ) = {
         let mut relay = state.inner.lock().await;
-        if relay.pending_join_waiters >= state.config.max_pending_join_waiters {
+        if !request.async_join
+            && relay.pending_join_waiters >= state.config.max_pending_join_waiters
+        {
             return pair_join_failure_response(
                 StatusCode::SERVICE_UNAVAILABLE,
                 "pairing_backpressure",
@@ -258,13 +346,9 @@ pub(super) async fn pair_join(
         let request_id = random_token(10);
         let (tx, rx) = oneshot::channel::<JoinDecision>();
 
-        let (
-            pair_request_payload,
-            pair_request_outbound_send_failures,
-            pair_request_slow_consumer_disconnects,
-            pair_request_timeout_ms,
-        ) = {
+        let (pair_request_payload, pair_request_outbound_send_failures, pair_request_timeout_ms) = {
             let Some(session) = relay.sessions.get_mut(&request.session_id) else {
+                spawn_penalty_failure(&state, &client_ip, "pair_join");
                 return pair_join_failure_response(
                     StatusCode::NOT_FOUND,
                     "session_not_found",
@@ -272,225 +356,369 @@ pub(super) async fn pair_join(
                 );
             };
 
-            if now >= session.join_token_expires_at_ms {
-                return pair_join_failure_response(
-                    StatusCode::GONE,
-                    "join_token_expired",
-                    "Join token has expired.",
-                );
-            }
-
-            if session.join_token_used_at_ms.is_some() {
-                return pair_join_failure_response(
-                    StatusCode::CONFLICT,
-                    "join_token_already_used",
-                    "Join token has already been redeemed. Start a new session from desktop.",
-                );
-            }
-
-            if !safe_token_equals(&session.join_token, &request.join_token) {
-                return pair_join_failure_response(
-                    StatusCode::FORBIDDEN,
-                    "invalid_join_token",
-                    "Join token is invalid.",
-                );
-            }
+            let credential = match resolve_join_credential(session, &request.join_token, now) {
+                Ok(credential) => credential,
+                Err((status, code, message)) => {
+                    if status == StatusCode::FORBIDDEN {
+                        spawn_penalty_failure(&state, &client_ip, "pair_join");
+                    }
+                    return pair_join_failure_response(status, code, message);
+                }
+            };
 
-            if session.devices.len() >= state.config.max_devices_per_session {
-                return pair_join_failure_response(
-                    StatusCode::CONFLICT,
-                    "device_cap_reached",
-                    &format!(
-                        "This session allows at most {} connected devices.",
-                        state.config.max_devices_per_session
-                    ),
-                );
-            }
+            let evicts_device_id = match device_cap_decision(&state.config, session) {
+                DeviceCapDecision::Room => None,
+                DeviceCapDecision::Evict(device_id) => Some(device_id),
+                DeviceCapDecision::Full(cap) => return device_cap_reached(cap).into_response(),
+            };
 
-            if session.desktop_socket.is_none() && state.cross_instance_bus.is_none() {
-                return pair_join_failure_response(
-                    StatusCode::CONFLICT,
-                    "desktop_not_connected",
-                    "Desktop is not connected to relay. Re-open Remote Control on desktop and retry.",
-                );
-            }
+            let approval = if credential.auto_approve() {
+                let _ = tx.send(JoinDecision {
+                    approved: true,
+                    reason: "auto_approved".to_string(),
+                    scopes: Vec::new(),
+                });
+                (None, 0, state.config.pair_approval_timeout_ms)
+            } else {
+                if session.desktop_socket.is_none() && state.cross_instance_bus.is_none() {
+                    return pair_join_failure_response(
+                        StatusCode::CONFLICT,
+                        "desktop_not_connected",
+                        "Desktop is not connected to relay. Re-open Remote Control on desktop and retry.",
+                    );
+                }
 
-            if let Some(pending) = &session.pending_join_request {
-                warn!(
-                    "[relay-rs] pair_join_failure code=pair_request_in_progress status={}",
-                    StatusCode::CONFLICT.as_u16()
-                );
-                return (
-                    StatusCode::CONFLICT,
-                    Json(json!({
-                        "error": "pair_request_in_progress",
-                        "message": "A pairing approval request is already pending on desktop.",
-                        "requestID": pending.request_id,
-                        "expiresAt": iso_from_millis(pending.expires_at_ms),
-                    })),
-                )
-                    .into_response();
-            }
+                match pending_join_slot(&state.config, session, &client_ip, now) {
+                    PendingJoinSlot::Available => {}
+                    PendingJoinSlot::RequesterBusy {
+                        request_id,
+                        expires_at_ms,
+                    } => {
+                        warn!(
+                            "[relay-rs] pair_join_failure code=pair_request_in_progress status={}",
+                            StatusCode::CONFLICT.as_u16()
+                        );
+                        return (
+                            StatusCode::CONFLICT,
+                            Json(json!({
+                                "error": "pair_request_in_progress",
+                                "message": "A pairing approval request from this device is already pending on desktop.",
+                                "requestID": request_id,
+                                "expiresAt": iso_from_millis(expires_at_ms),
+                            })),
+                        )
+                            .into_response();
+                    }
+                    PendingJoinSlot::SessionFull(limit) => {
+                        return pair_join_failure_response(
+                            StatusCode::CONFLICT,
+                            "pair_requests_full",
+                            &format!(
+                                "{limit} pairing requests are already waiting on desktop approval. Retry shortly."
+                            ),
+                        );
+                    }
+                }
 
-            let join_remaining_ms = (session.join_token_expires_at_ms - now).max(0);
-            let timeout_ms = state
-                .config
-                .pair_approval_timeout_ms
-                .min(join_remaining_ms as u64)
-                .max(5_000);
-
-            let pending = PendingJoinRequest {
-                request_id: request_id.clone(),
-                requester_ip: client_ip.clone(),
-                requested_at_ms: now,
-                expires_at_ms: now + timeout_ms as i64,
-                decision_tx: tx,
-            };
+                let credential_remaining_ms = credential
+                    .expires_at_ms(session)
+                    .map_or(0, |expires_at_ms| (expires_at_ms - now).max(0));
+                let timeout_ms = state
+                    .config
+                    .pair_approval_timeout_ms
+                    .min(credential_remaining_ms as u64)
+                    .max(5_000);
+
+                let pending = PendingJoinRequest {
+                    request_id: request_id.clone(),
+                    requester_ip: client_ip.clone(),
+                    requested_at_ms: now,
+                    expires_at_ms: now + timeout_ms as i64,
+                    decision_tx: tx,
+                };
+
+                let payload = RelayPairRequest {
+                    message_type: "relay.pair_request".to_string(),
+                    session_id: session.session_id.clone(),
+                    request_id: pending.request_id.clone(),
+                    device_name: requested_device_name.clone(),
+                    requester_ip: pending.requester_ip.clone(),
+                    requested_at: iso_from_millis(pending.requested_at_ms),
+                    expires_at: iso_from_millis(pending.expires_at_ms),
+                    evicts_device_id,
+                };
+                let encoded_payload =
+                    serde_json::to_string(&payload).unwrap_or_else(|_| "{}".to_string());
+
+                let mut publish_remote_payload = None;
+                let mut delivered_to_local_desktop = false;
+                let mut outbound_send_failures = 0_u64;
+                if let Some(desktop) = &session.desktop_socket {
+                    if try_send_payload(&desktop.tx, encoded_payload.clone()) {
+                        delivered_to_local_desktop = true;
+                    } else {
+                        outbound_send_failures = outbound_send_failures.saturating_add(1);
+                    }
+                }
 
-            let payload = RelayPairRequest {
-                message_type: "relay.pair_request".to_string(),
-                session_id: session.session_id.clone(),
-                request_id: pending.request_id.clone(),
-                device_name: requested_device_name.clone(),
-                requester_ip: pending.requester_ip.clone(),
-                requested_at: iso_from_millis(pending.requested_at_ms),
-                expires_at: iso_from_millis(pending.expires_at_ms),
-            };
-            let encoded_payload =
-                serde_json::to_string(&payload).unwrap_or_else(|_| "{}".to_string());
+                if !delivered_to_local_desktop && state.cross_instance_bus.is_some() {
+                    publish_remote_payload = Some(encoded_payload);
+                }
 
-            let mut publish_remote_payload = None;
-            let mut delivered_to_local_desktop = false;
-            let mut outbound_send_failures = 0_u64;
-            let mut slow_consumer_disconnects = 0_u64;
-            if let Some(desktop) = &session.desktop_socket {
-                if try_send_payload(&desktop.tx, encoded_payload.clone()) {
-                    delivered_to_local_desktop = true;
-                } else {
-                    outbound_send_failures = outbound_send_failures.saturating_add(1);
-                    slow_consumer_disconnects = slow_consumer_disconnects.saturating_add(1);
-                    request_socket_disconnect(desktop, "slow_consumer");
+                if publish_remote_payload.is_none() && !delivered_to_local_desktop {
+                    return pair_join_failure_response(
+                        StatusCode::CONFLICT,
+                        "desktop_not_connected",
+                        "Desktop is not connected to relay. Re-open Remote Control on desktop and retry.",
+                    );
                 }
-            }
 
-            if !delivered_to_local_desktop && state.cross_instance_bus.is_some() {
-                publish_remote_payload = Some(encoded_payload);
-            }
+                session
+                    .pending_join_requests
+                    .insert(request_id.clone(), pending);
+                (publish_remote_payload, outbound_send_failures, timeout_ms)
+            };
 
-            if publish_remote_payload.is_none() && !delivered_to_local_desktop {
-                return pair_join_failure_response(
-                    StatusCode::CONFLICT,
-                    "desktop_not_connected",
-                    "Desktop is not connected to relay. Re-open Remote Control on desktop and retry.",
+            if request.async_join {
+                prune_async_pair_requests(session, now);
+                session.async_pair_requests.insert(
+                    request_id.clone(),
+                    AsyncPairRequest {
+                        status_token: status_token.clone(),
+                        state: AsyncPairRequestState::Pending,
+                        requested_at_ms: now,
+                        expires_at_ms: now.saturating_add(approval.2 as i64),
+                        completed_at_ms: None,
+                        device_id: None,
+                        device_session_token: None,
+                        error: None,
+                        message: None,
+                    },
                 );
             }
-
-            session.pending_join_request = Some(pending);
-            (
-                publish_remote_payload,
-                outbound_send_failures,
-                slow_consumer_disconnects,
-                timeout_ms,
-            )
+            approval
         };
 
-        relay.pending_join_waiters += 1;
+        if !request.async_join {
+            relay.pending_join_waiters += 1;
+        }
         (
             rx,
             request_id,
             pair_request_payload,
             pair_request_outbound_send_failures,
-            pair_request_slow_consumer_disconnects,
             pair_request_timeout_ms,
+            now,
         )
     };
 
-    if outbound_send_failures > 0 || slow_consumer_disconnects > 0 {
+    if outbound_send_failures > 0 {
         let mut relay = state.inner.lock().await;
         relay.outbound_send_failures = relay
             .outbound_send_failures
             .saturating_add(outbound_send_failures);
-        relay.slow_consumer_disconnects = relay
-            .slow_consumer_disconnects
-            .saturating_add(slow_consumer_disconnects);
     }
 
     if let Some(payload) = pair_request_payload {
         publish_cross_instance_session(&state, &request.session_id, "desktop", None, payload);
     }
 
+    let attempt = PairJoinAttempt {
+        session_id: request.session_id.clone(),
+        join_token: request.join_token.clone(),
+        request_id: request_id.clone(),
+        device_name,
+        client_info,
+        client_ip,
+        schema_version,
+        holds_waiter_slot: !request.async_join,
+    };
+    if request.async_join {
+        tokio::spawn({
+            let state = state.clone();
+            let session_id = request.session_id.clone();
+            let request_id = request_id.clone();
+            async move {
+                let outcome =
+                    match finish_pair_join(&state, attempt, decision_rx, pair_approval_timeout_ms)
+                        .await
+                    {
+                        Ok(joined) => AsyncPairOutcome::Approved {
+                            device_id: joined.device_id,
+                            device_session_token: joined.device_session_token,
+                        },
+                        Err(failure) => {
+                            warn!(
+                                "[relay-rs] pair_join_failure code={} status={} async=true",
+                                failure.code,
+                                failure.status.as_u16()
+                            );
+                            AsyncPairOutcome::Rejected {
+                                code: failure.code.to_string(),
+                                message: failure.message,
+                            }
+                        }
+                    };
+                record_async_pair_outcome(&state, &session_id, &request_id, outcome).await;
+            }
+        });
+
+        info!(
+            "[relay-rs] pair_join_pending session={} request={request_id}",
+            session_log_id(&request.session_id)
+        );
+        persist_session_if_needed(&state, &request.session_id).await;
+        publish_cross_instance_control_session_refresh(&state, &request.session_id);
+        return (
+            StatusCode::ACCEPTED,
+            Json(PairJoinPendingResponse {
+                accepted: true,
+                status: "pending".to_string(),
+                session_id: request.session_id,
+                request_id,
+                status_token,
+                expires_at: iso_from_millis(
+                    join_requested_at_ms.saturating_add(pair_approval_timeout_ms as i64),
+                ),
+            }),
+        )
+            .into_response();
+    }
+
+    match finish_pair_join(&state, attempt, decision_rx, pair_approval_timeout_ms).await {
+        Ok(joined) => (StatusCode::OK, Json(joined)).into_response(),
+        Err(failure) => failure.into_response(),
+    }
+}
+
+struct PairJoinAttempt {
+    session_id: String,
+    join_token: String,
+    request_id: String,
+    device_name: String,
+    client_info: DeviceClientInfo,
+    client_ip: String,
+    schema_version: u32,
+    holds_waiter_slot: bool,
+}
+
+struct PairJoinFailure {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+impl PairJoinFailure {
+    fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn into_response(self) -> axum::response::Response {
+        pair_join_failure_response(self.status, self.code, &self.message)
+    }
+}
+
+/// Waits for the desktop decision on a join and links the device. Shared by blocking joins and
+/// the background task behind `"async": true` joins.
+async fn finish_pair_join(
+    state: &SharedRelayState,
+    attempt: PairJoinAttempt,
+    decision_rx: oneshot::Receiver<JoinDecision>,
+    pair_approval_timeout_ms: u64,
+) -> Result<PairJoinResponse, PairJoinFailure> {
     let decision = match timeout(Duration::from_millis(pair_approval_timeout_ms), decision_rx).await
     {
         Ok(Ok(result)) => result,
         Ok(Err(_)) => JoinDecision {
             approved: false,
             reason: "desktop_disconnected".to_string(),
+            scopes: Vec::new(),
         },
         Err(_) => JoinDecision {
             approved: false,
             reason: "approval_timeout".to_string(),
+            scopes: Vec::new(),
         },
     };
 
+    let PairJoinAttempt {
+        session_id,
+        join_token,
+        request_id,
+        device_name,
+        client_info,
+        client_ip,
+        schema_version,
+        holds_waiter_slot,
+    } = attempt;
     let mut relay = state.inner.lock().await;
-    relay.pending_join_waiters = relay.pending_join_waiters.saturating_sub(1);
+    if holds_waiter_slot {
+        relay.pending_join_waiters = relay.pending_join_waiters.saturating_sub(1);
+    }
 
-    let device_name = sanitize_device_name(requested_device_name.as_deref());
-    let (device_id, device_session_token, ws_url, session_id_for_token) = {
-        let Some(session) = relay.sessions.get_mut(&request.session_id) else {
-            return pair_join_failure_response(
+    let (device_id, device_session_token, ws_url, session_id_for_token, eviction) = {
+        let Some(session) = relay.sessions.get_mut(&session_id) else {
+            return Err(PairJoinFailure::new(
                 StatusCode::CONFLICT,
                 "desktop_not_connected",
                 "Desktop disconnected before pairing could be approved.",
-            );
+            ));
         };
 
-        if let Some(pending) = &session.pending_join_request {
-            if pending.request_id == request_id {
-                session.pending_join_request = None;
-            }
-        }
+        session.pending_join_requests.remove(&request_id);
 
         if !decision.approved {
-            return match decision.reason.as_str() {
-                "approval_timeout" => pair_join_failure_response(
+            return Err(match decision.reason.as_str() {
+                "approval_timeout" => PairJoinFailure::new(
                     StatusCode::REQUEST_TIMEOUT,
                     "pair_request_timed_out",
                     "Desktop pairing approval timed out.",
                 ),
-                "desktop_disconnected" | "session_closed" => pair_join_failure_response(
+                "desktop_disconnected" | "session_closed" => PairJoinFailure::new(
                     StatusCode::CONFLICT,
                     "desktop_not_connected",
                     "Desktop disconnected before pairing could be approved.",
                 ),
-                _ => pair_join_failure_response(
+                _ => PairJoinFailure::new(
                     StatusCode::FORBIDDEN,
                     "pair_request_denied",
                     "Desktop denied this pairing request.",
                 ),
-            };
+            });
         }
 
-        if now_ms() >= session.join_token_expires_at_ms {
-            return pair_join_failure_response(
-                StatusCode::GONE,
-                "join_token_expired",
-                "Join token has expired.",
-            );
-        }
+        let credential = match resolve_join_credential(session, &join_token, now_ms()) {
+            Ok(credential) => credential,
+            Err((status, code, message)) => {
+                return Err(PairJoinFailure::new(status, code, message));
+            }
+        };
 
-        if !safe_token_equals(&session.join_token, &request.join_token) {
-            return pair_join_failure_response(
-                StatusCode::FORBIDDEN,
-                "invalid_join_token",
-                "Join token is invalid.",
-            );
-        }
+        let evicted_device = match device_cap_decision(&state.config, session) {
+            DeviceCapDecision::Room => None,
+            DeviceCapDecision::Evict(evicted_device_id) => {
+                remove_session_device(session, &evicted_device_id, "device_evicted")
+                    .map(|record| (evicted_device_id, record.name))
+            }
+            DeviceCapDecision::Full(cap) => return Err(device_cap_reached(cap)),
+        };
 
         let device_id = random_token(12);
-        let device_session_token = random_token(32);
         let now = now_ms();
-        session.join_token_used_at_ms = Some(now);
+        let device_session_token =
+            issue_device_session_token(&state.config, &session.session_id, &device_id, 0, now);
+        let invite_scopes = redeem_join_credential(session, &credential, now);
+        if let JoinCredential::Invite { invite_id, .. } = &credential {
+            info!(
+                "[relay-rs] invite_redeemed session={} invite={invite_id} auto_approved={}",
+                session_log_id(&session.session_id),
+                credential.auto_approve()
+            );
+        }
         session.last_activity_at_ms = now;
         session.devices.insert(
             device_id.clone(),
@@ -500,49 +728,170 @@ pub(super) async fn pair_join(
                 name: device_name.clone(),
                 joined_at_ms: now,
                 last_seen_at_ms: now,
+                token_generation: 0,
+                nickname: None,
+                platform: client_info.platform,
+                app_version: client_info.app_version,
+                last_ip: Some(client_ip.clone()),
+                connection_history: Default::default(),
+                scopes: sanitize_device_scopes(&[decision.scopes, invite_scopes].concat()),
+                schema_version,
             },
         );
 
+        let device_evicted_payload = evicted_device.as_ref().map(|(evicted_id, evicted_name)| {
+            let payload = serde_json::to_string(&RelayDeviceEvicted {
+                message_type: "relay.device_evicted".to_string(),
+                session_id: session.session_id.clone(),
+                device_id: evicted_id.clone(),
+                device_name: evicted_name.clone(),
+                replaced_by_device_id: device_id.clone(),
+                reason: "device_cap_reached".to_string(),
+            })
+            .unwrap_or_else(|_| "{}".to_string());
+            if let Some(desktop) = &session.desktop_socket {
+                let _ = try_send_payload(&desktop.tx, payload.clone());
+            }
+            payload
+        });
+
         (
             device_id,
             device_session_token,
             session.relay_web_socket_url.clone(),
             session.session_id.clone(),
+            evicted_device.zip(device_evicted_payload),
         )
     };
 
-    relay.device_token_index.insert(
-        device_session_token.clone(),
-        DeviceTokenContext {
-            session_id: session_id_for_token,
-            device_id: device_id.clone(),
-            expires_at_ms: None,
-        },
+    if !is_signed_device_token(&device_session_token) {
+        relay.device_token_index.insert(
+            device_session_token.clone(),
+            DeviceTokenContext {
+                session_id: session_id_for_token,
+                device_id: device_id.clone(),
+                expires_at_ms: None,
+            },
+        );
+    }
+
+    if let Some(((evicted_device_id, evicted_device_name), _)) = &eviction {
+        relay.device_token_index.retain(|_, token| {
+            !(token.session_id == session_id && token.device_id == *evicted_device_id)
+        });
+        emit_lifecycle_event(
+            &mut relay,
+            LifecycleEvent::new(LifecycleEventKind::DeviceRevoked, &session_id)
+                .with_device(evicted_device_id, Some(evicted_device_name))
+                .with_reason("device_evicted"),
+        );
+        info!(
+            "[relay-rs] device_evicted session={} device={} replaced_by={}",
+            session_log_id(&session_id),
+            evicted_device_id,
+            device_id
+        );
+    }
+    emit_lifecycle_event(
+        &mut relay,
+        LifecycleEvent::new(LifecycleEventKind::DeviceJoined, &session_id)
+            .with_device(&device_id, Some(&device_name)),
     );
 
     info!(
         "[relay-rs] pair_join session={}",
-        session_log_id(&request.session_id)
+        session_log_id(&session_id)
     );
     drop(relay);
-    persist_session_if_needed(&state, &request.session_id).await;
-    publish_cross_instance_control_session_refresh(&state, &request.session_id);
+    if let Some(((evicted_device_id, _), device_evicted_payload)) = eviction {
+        publish_cross_instance_session(
+            state,
+            &session_id,
+            "mobile",
+            Some(evicted_device_id),
+            disconnect_payload("device_evicted"),
+        );
+        publish_cross_instance_session(state, &session_id, "desktop", None, device_evicted_payload);
+    }
+    persist_session_if_needed(state, &session_id).await;
+    publish_cross_instance_control_session_refresh(state, &session_id);
     {
         let mut relay = state.inner.lock().await;
         relay.pair_join_successes = relay.pair_join_successes.saturating_add(1);
     }
 
-    (
-        StatusCode::OK,
-        Json(PairJoinResponse {
-            accepted: true,
-            session_id: request.session_id,
-            device_id,
-            device_session_token,
-            ws_url,
-        }),
-    )
-        .into_response()
+    Ok(PairJoinResponse {
+        accepted: true,
+        session_id,
+        device_id,
+        device_session_token,
+        ws_url,
+        schema_version,
+    })
+}
+
+pub(super) async fn pair_status(
+    State(state): State<SharedRelayState>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(request): Json<PairStatusRequest>,
+) -> axum::response::Response {
+    if let Some(response) = validate_schema_version(request.schema_version) {
+        return response;
+    }
+
+    if !origin_allowed(&state.config, &headers) {
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "origin_not_allowed",
+            "Origin is not allowed.",
+        );
+    }
+
+    let client_ip = client_ip(&state.config, &headers, addr);
+    if let Some(response) = penalty_box_rejection(&state, &client_ip).await {
+        return response;
+    }
+    if is_rate_limited(&state, &client_ip).await {
+        return error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+            "Too many pairing attempts. Try again in a minute.",
+        );
+    }
+
+    if !is_opaque_token(&request.session_id, 16)
+        || !is_opaque_token(&request.request_id, 8)
+        || !is_opaque_token(&request.status_token, 22)
+    {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_pair_status",
+            "sessionID, requestID and statusToken are required.",
+        );
+    }
+
+    match wait_for_pair_status(&state, &request).await {
+        Some(PairStatusLookup::Found(response)) => {
+            (StatusCode::OK, Json(*response)).into_response()
+        }
+        Some(PairStatusLookup::InvalidToken) => {
+            spawn_penalty_failure(&state, &client_ip, "pair_status");
+            error_response(
+                StatusCode::FORBIDDEN,
+                "invalid_status_token",
+                "Status token is invalid.",
+            )
+        }
+        Some(PairStatusLookup::NotFound) | None => {
+            spawn_penalty_failure(&state, &client_ip, "pair_status");
+            error_response(
+                StatusCode::NOT_FOUND,
+                "pair_request_not_found",
+                "Pair request not found.",
+            )
+        }
+    }
 }
 
 pub(super) async fn pair_refresh(
@@ -588,7 +937,7 @@ pub(super) async fn pair_refresh(
         );
     }
 
-    refresh_sessions_from_persistence(&state, false).await;
+    refresh_session_for_request(&state, &request.session_id).await;
 
     let Ok(expires_at) = DateTime::parse_from_rfc3339(&request.join_token_expires_at) else {
         return error_response(
@@ -640,6 +989,12 @@ pub(super) async fn pair_refresh(
         let mut relay = state.inner.lock().await;
         relay.pair_refresh_successes = relay.pair_refresh_successes.saturating_add(1);
     }
+    let pairing_uri = pairing_uri(
+        &state.config,
+        &request.session_id,
+        &request.join_token,
+        &ws_url,
+    );
 
     (
         StatusCode::OK,
@@ -647,16 +1002,19 @@ pub(super) async fn pair_refresh(
             accepted: true,
             session_id: request.session_id,
             ws_url,
+            pairing_uri,
         }),
     )
         .into_response()
 }
 
-pub(super) async fn pair_stop(
+/// Renders the current pairing URI as a QR code for the desktop that owns the session. The
+/// image embeds the join token, so it is never cached.
+pub(super) async fn pair_qr(
     State(state): State<SharedRelayState>,
     headers: HeaderMap,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    Json(request): Json<PairStopRequest>,
+    Json(request): Json<PairQrRequest>,
 ) -> axum::response::Response {
     if let Some(response) = validate_schema_version(request.schema_version) {
         return response;
@@ -671,6 +1029,9 @@ pub(super) async fn pair_stop(
     }
 
     let client_ip = client_ip(&state.config, &headers, addr);
+    if let Some(response) = penalty_box_rejection(&state, &client_ip).await {
+        return response;
+    }
     if is_rate_limited(&state, &client_ip).await {
         return error_response(
             StatusCode::TOO_MANY_REQUESTS,
@@ -684,39 +1045,154 @@ pub(super) async fn pair_stop(
     {
         return error_response(
             StatusCode::BAD_REQUEST,
-            "invalid_pair_stop",
+            "invalid_pair_qr",
             "sessionID and desktopSessionToken are required.",
         );
     }
+    let Some(format) = PairingQrFormat::parse(request.format.as_deref()) else {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_pair_qr",
+            "format must be svg or png.",
+        );
+    };
 
-    refresh_sessions_from_persistence(&state, false).await;
+    refresh_session_for_request(&state, &request.session_id).await;
+
+    let uri = {
+        let relay = state.inner.lock().await;
+        let Some(session) = relay.sessions.get(&request.session_id) else {
+            spawn_penalty_failure(&state, &client_ip, "pair_qr");
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "session_not_found",
+                "Remote session not found.",
+            );
+        };
 
-    let mut relay = state.inner.lock().await;
-    if let Some(session) = relay.sessions.get(&request.session_id) {
         if !safe_token_equals(
             &session.desktop_session_token,
             &request.desktop_session_token,
         ) {
+            spawn_penalty_failure(&state, &client_ip, "pair_qr");
             return error_response(
                 StatusCode::FORBIDDEN,
                 "invalid_desktop_session_token",
                 "Desktop session token is invalid.",
             );
         }
+
+        if session.join_token_used_at_ms.is_some() {
+            return error_response(
+                StatusCode::CONFLICT,
+                "join_token_already_used",
+                "Join token has already been redeemed. Refresh pairing to get a new code.",
+            );
+        }
+        if now_ms() >= session.join_token_expires_at_ms {
+            return error_response(
+                StatusCode::GONE,
+                "join_token_expired",
+                "Join token has expired.",
+            );
+        }
+
+        pairing_uri(
+            &state.config,
+            &session.session_id,
+            &session.join_token,
+            &session.relay_web_socket_url,
+        )
+    };
+
+    match render_pairing_qr(&uri, format) {
+        Ok(image) => (
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, format.content_type()),
+                (header::CACHE_CONTROL, "no-store"),
+            ],
+            image,
+        )
+            .into_response(),
+        Err(error) => {
+            warn!(
+                "[relay-rs] pair_qr render failed session={}: {error}",
+                session_log_id(&request.session_id)
+            );
+            error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "pair_qr_failed",
+                "Pairing QR code could not be rendered.",
+            )
+        }
     }
+}
 
-    close_session(&mut relay, &request.session_id, "stopped_by_desktop");
-    info!(
-        "[relay-rs] pair_stop session={}",
-        session_log_id(&request.session_id)
-    );
-    drop(relay);
-    let disconnect_payload =
-        json!({ "type": "disconnect", "reason": "stopped_by_desktop" }).to_string();
-    publish_cross_instance_session(
-        &state,
-        &request.session_id,
-        "desktop",
+pub(super) async fn pair_stop(
+    State(state): State<SharedRelayState>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(request): Json<PairStopRequest>,
+) -> axum::response::Response {
+    if let Some(response) = validate_schema_version(request.schema_version) {
+        return response;
+    }
+
+    if !origin_allowed(&state.config, &headers) {
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "origin_not_allowed",
+            "Origin is not allowed.",
+        );
+    }
+
+    let client_ip = client_ip(&state.config, &headers, addr);
+    if is_rate_limited(&state, &client_ip).await {
+        return error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+            "Too many relay management requests. Try again in a minute.",
+        );
+    }
+
+    if !is_opaque_token(&request.session_id, 16)
+        || !is_opaque_token(&request.desktop_session_token, 22)
+    {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_pair_stop",
+            "sessionID and desktopSessionToken are required.",
+        );
+    }
+
+    refresh_session_for_request(&state, &request.session_id).await;
+
+    let mut relay = state.inner.lock().await;
+    if let Some(session) = relay.sessions.get(&request.session_id) {
+        if !safe_token_equals(
+            &session.desktop_session_token,
+            &request.desktop_session_token,
+        ) {
+            return error_response(
+                StatusCode::FORBIDDEN,
+                "invalid_desktop_session_token",
+                "Desktop session token is invalid.",
+            );
+        }
+    }
+
+    close_session(&mut relay, &request.session_id, "stopped_by_desktop");
+    info!(
+        "[relay-rs] pair_stop session={}",
+        session_log_id(&request.session_id)
+    );
+    drop(relay);
+    let disconnect_payload = disconnect_payload("stopped_by_desktop");
+    publish_cross_instance_session(
+        &state,
+        &request.session_id,
+        "desktop",
         None,
         disconnect_payload.clone(),
     );
@@ -776,7 +1252,7 @@ pub(super) async fn devices_list(
         );
     }
 
-    refresh_sessions_from_persistence(&state, false).await;
+    refresh_session_for_request(&state, &request.session_id).await;
 
     let mut relay = state.inner.lock().await;
     let Some(session) = relay.sessions.get_mut(&request.session_id) else {
@@ -802,16 +1278,7 @@ pub(super) async fn devices_list(
     let mut devices = session
         .devices
         .iter()
-        .map(|(device_id, record)| DeviceSummary {
-            device_id: device_id.clone(),
-            device_name: record.name.clone(),
-            connected: session
-                .mobile_sockets
-                .values()
-                .any(|socket| socket.device_id.as_deref() == Some(device_id.as_str())),
-            joined_at: iso_from_millis(record.joined_at_ms),
-            last_seen_at: iso_from_millis(record.last_seen_at_ms),
-        })
+        .map(|(device_id, record)| device_summary(session, device_id, record))
         .collect::<Vec<_>>();
     devices.sort_by(|lhs, rhs| lhs.joined_at.cmp(&rhs.joined_at));
 
@@ -821,16 +1288,20 @@ pub(super) async fn devices_list(
             accepted: true,
             session_id: request.session_id,
             devices,
+            session_bytes_in: session.traffic.totals.bytes_in,
+            session_bytes_out: session.traffic.totals.bytes_out,
         }),
     )
         .into_response()
 }
 
-pub(super) async fn device_revoke(
+/// Sets or clears the desktop-supplied nickname for a paired device. The device keeps the name
+/// it reported at `pair/join`.
+pub(super) async fn device_rename(
     State(state): State<SharedRelayState>,
     headers: HeaderMap,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    Json(request): Json<DeviceRevokeRequest>,
+    Json(request): Json<DeviceRenameRequest>,
 ) -> axum::response::Response {
     if let Some(response) = validate_schema_version(request.schema_version) {
         return response;
@@ -859,17 +1330,16 @@ pub(super) async fn device_revoke(
     {
         return error_response(
             StatusCode::BAD_REQUEST,
-            "invalid_device_revoke",
+            "invalid_device_rename",
             "sessionID, desktopSessionToken, and deviceID are required.",
         );
     }
 
-    refresh_sessions_from_persistence(&state, false).await;
+    refresh_session_for_request(&state, &request.session_id).await;
 
-    let mut relay = state.inner.lock().await;
-    let session_id = request.session_id.clone();
-    let device_count_event = {
-        let Some(session) = relay.sessions.get_mut(&session_id) else {
+    let device = {
+        let mut relay = state.inner.lock().await;
+        let Some(session) = relay.sessions.get_mut(&request.session_id) else {
             return error_response(
                 StatusCode::NOT_FOUND,
                 "session_not_found",
@@ -888,34 +1358,842 @@ pub(super) async fn device_revoke(
             );
         }
 
-        let Some(_removed_device) = session.devices.remove(&request.device_id) else {
+        let Some(record) = session.devices.get_mut(&request.device_id) else {
             return error_response(
                 StatusCode::NOT_FOUND,
                 "device_not_found",
                 "Device is not linked to this session.",
             );
         };
-        session.command_rate_buckets.remove(&request.device_id);
-        session
-            .snapshot_request_rate_buckets
-            .remove(&request.device_id);
-
+        record.nickname = sanitize_device_nickname(request.nickname.as_deref());
         session.last_activity_at_ms = now_ms();
-        close_existing_mobile_socket_for_device(session, &request.device_id, "device_revoked");
-        send_device_count(session);
-        device_count_payload(session)
+        let record = session.devices[&request.device_id].clone();
+        device_summary(session, &request.device_id, &record)
     };
 
-    relay.device_token_index.retain(|_, token| {
-        !(token.session_id == session_id && token.device_id == request.device_id)
-    });
+    info!(
+        "[relay-rs] device_rename session={} device={}",
+        session_log_id(&request.session_id),
+        request.device_id
+    );
+    persist_session_if_needed(&state, &request.session_id).await;
+    publish_cross_instance_control_session_refresh(&state, &request.session_id);
+
+    (
+        StatusCode::OK,
+        Json(DeviceRenameResponse {
+            accepted: true,
+            session_id: request.session_id,
+            device,
+        }),
+    )
+        .into_response()
+}
+
+pub(super) async fn invite_create(
+    State(state): State<SharedRelayState>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(request): Json<InviteCreateRequest>,
+) -> axum::response::Response {
+    if let Some(response) = validate_schema_version(request.schema_version) {
+        return response;
+    }
+
+    if !origin_allowed(&state.config, &headers) {
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "origin_not_allowed",
+            "Origin is not allowed.",
+        );
+    }
+
+    let client_ip = client_ip(&state.config, &headers, addr);
+    if is_rate_limited(&state, &client_ip).await {
+        return error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+            "Too many relay management requests. Try again in a minute.",
+        );
+    }
+
+    if !is_opaque_token(&request.session_id, 16)
+        || !is_opaque_token(&request.desktop_session_token, 22)
+    {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_invite",
+            "sessionID and desktopSessionToken are required.",
+        );
+    }
+
+    let max_redemptions = request.max_redemptions.unwrap_or(1);
+    if max_redemptions == 0 || max_redemptions > MAX_INVITE_REDEMPTIONS {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_invite",
+            &format!("maxRedemptions must be between 1 and {MAX_INVITE_REDEMPTIONS}."),
+        );
+    }
+
+    let now = now_ms();
+    let expires_at_ms = match DateTime::parse_from_rfc3339(&request.expires_at) {
+        Ok(expires_at) => expires_at.timestamp_millis(),
+        Err(_) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "invalid_invite",
+                "expiresAt must be a valid RFC3339 timestamp.",
+            );
+        }
+    };
+    if expires_at_ms <= now || expires_at_ms > now.saturating_add(MAX_INVITE_TTL_MS) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_invite",
+            "expiresAt must be in the future and at most 7 days away.",
+        );
+    }
+
+    refresh_session_for_request(&state, &request.session_id).await;
+
+    let (invite_id, invite_token, pairing_uri, invite) = {
+        let mut relay = state.inner.lock().await;
+        let Some(session) = relay.sessions.get_mut(&request.session_id) else {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "session_not_found",
+                "Remote session not found.",
+            );
+        };
+
+        if !safe_token_equals(
+            &session.desktop_session_token,
+            &request.desktop_session_token,
+        ) {
+            return error_response(
+                StatusCode::FORBIDDEN,
+                "invalid_desktop_session_token",
+                "Desktop session token is invalid.",
+            );
+        }
+
+        prune_expired_invites(session, now);
+        if session.invites.len() >= MAX_INVITES_PER_SESSION {
+            return error_response(
+                StatusCode::CONFLICT,
+                "invite_limit_reached",
+                &format!(
+                    "This session allows at most {MAX_INVITES_PER_SESSION} active invites. Revoke one first."
+                ),
+            );
+        }
+
+        let invite_id = random_token(12);
+        let invite_token = random_token(32);
+        let invite = SessionInvite {
+            token: invite_token.clone(),
+            max_redemptions,
+            redemptions: 0,
+            created_at_ms: now,
+            expires_at_ms,
+            scopes: sanitize_device_scopes(&request.scopes),
+            auto_approve: request.auto_approve,
+        };
+        let pairing_uri = pairing_uri(
+            &state.config,
+            &session.session_id,
+            &invite_token,
+            &session.relay_web_socket_url,
+        );
+        session.invites.insert(invite_id.clone(), invite.clone());
+        session.last_activity_at_ms = now;
+        (invite_id, invite_token, pairing_uri, invite)
+    };
+
+    info!(
+        "[relay-rs] invite_created session={} invite={invite_id} max_redemptions={max_redemptions} auto_approve={}",
+        session_log_id(&request.session_id),
+        invite.auto_approve
+    );
+    persist_session_if_needed(&state, &request.session_id).await;
+    publish_cross_instance_control_session_refresh(&state, &request.session_id);
+
+    (
+        StatusCode::OK,
+        Json(InviteCreateResponse {
+            accepted: true,
+            session_id: request.session_id,
+            invite_token,
+            pairing_uri,
+            invite: invite_summary(&invite_id, &invite),
+        }),
+    )
+        .into_response()
+}
+
+pub(super) async fn invites_list(
+    State(state): State<SharedRelayState>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(request): Json<InvitesListRequest>,
+) -> axum::response::Response {
+    if let Some(response) = validate_schema_version(request.schema_version) {
+        return response;
+    }
+
+    if !origin_allowed(&state.config, &headers) {
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "origin_not_allowed",
+            "Origin is not allowed.",
+        );
+    }
+
+    let client_ip = client_ip(&state.config, &headers, addr);
+    if is_rate_limited(&state, &client_ip).await {
+        return error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+            "Too many relay management requests. Try again in a minute.",
+        );
+    }
+
+    if !is_opaque_token(&request.session_id, 16)
+        || !is_opaque_token(&request.desktop_session_token, 22)
+    {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_invites_list",
+            "sessionID and desktopSessionToken are required.",
+        );
+    }
+
+    refresh_session_for_request(&state, &request.session_id).await;
+
+    let invites = {
+        let mut relay = state.inner.lock().await;
+        let Some(session) = relay.sessions.get_mut(&request.session_id) else {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "session_not_found",
+                "Remote session not found.",
+            );
+        };
+
+        if !safe_token_equals(
+            &session.desktop_session_token,
+            &request.desktop_session_token,
+        ) {
+            return error_response(
+                StatusCode::FORBIDDEN,
+                "invalid_desktop_session_token",
+                "Desktop session token is invalid.",
+            );
+        }
+
+        prune_expired_invites(session, now_ms());
+        let mut invites = session
+            .invites
+            .iter()
+            .map(|(invite_id, invite)| invite_summary(invite_id, invite))
+            .collect::<Vec<_>>();
+        invites.sort_by(|left, right| {
+            left.created_at
+                .cmp(&right.created_at)
+                .then_with(|| left.invite_id.cmp(&right.invite_id))
+        });
+        invites
+    };
+
+    (
+        StatusCode::OK,
+        Json(InvitesListResponse {
+            accepted: true,
+            session_id: request.session_id,
+            invites,
+        }),
+    )
+        .into_response()
+}
+
+pub(super) async fn invite_revoke(
+    State(state): State<SharedRelayState>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(request): Json<InviteRevokeRequest>,
+) -> axum::response::Response {
+    if let Some(response) = validate_schema_version(request.schema_version) {
+        return response;
+    }
+
+    if !origin_allowed(&state.config, &headers) {
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "origin_not_allowed",
+            "Origin is not allowed.",
+        );
+    }
+
+    let client_ip = client_ip(&state.config, &headers, addr);
+    if is_rate_limited(&state, &client_ip).await {
+        return error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+            "Too many relay management requests. Try again in a minute.",
+        );
+    }
+
+    if !is_opaque_token(&request.session_id, 16)
+        || !is_opaque_token(&request.desktop_session_token, 22)
+        || !is_opaque_token(&request.invite_id, 8)
+    {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_invite_revoke",
+            "sessionID, desktopSessionToken, and inviteID are required.",
+        );
+    }
+
+    refresh_session_for_request(&state, &request.session_id).await;
+
+    {
+        let mut relay = state.inner.lock().await;
+        let Some(session) = relay.sessions.get_mut(&request.session_id) else {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "session_not_found",
+                "Remote session not found.",
+            );
+        };
+
+        if !safe_token_equals(
+            &session.desktop_session_token,
+            &request.desktop_session_token,
+        ) {
+            return error_response(
+                StatusCode::FORBIDDEN,
+                "invalid_desktop_session_token",
+                "Desktop session token is invalid.",
+            );
+        }
+
+        if session.invites.remove(&request.invite_id).is_none() {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "invite_not_found",
+                "Invite does not exist for this session.",
+            );
+        }
+        session.last_activity_at_ms = now_ms();
+    }
+
+    info!(
+        "[relay-rs] invite_revoked session={} invite={}",
+        session_log_id(&request.session_id),
+        request.invite_id
+    );
+    persist_session_if_needed(&state, &request.session_id).await;
+    publish_cross_instance_control_session_refresh(&state, &request.session_id);
+
+    (
+        StatusCode::OK,
+        Json(InviteRevokeResponse {
+            accepted: true,
+            session_id: request.session_id,
+            invite_id: request.invite_id,
+        }),
+    )
+        .into_response()
+}
+
+pub(super) async fn session_transfer_issue(
+    State(state): State<SharedRelayState>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(request): Json<SessionTransferIssueRequest>,
+) -> axum::response::Response {
+    if let Some(response) = validate_schema_version(request.schema_version) {
+        return response;
+    }
+
+    if !origin_allowed(&state.config, &headers) {
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "origin_not_allowed",
+            "Origin is not allowed.",
+        );
+    }
+
+    let client_ip = client_ip(&state.config, &headers, addr);
+    if let Some(response) = penalty_box_rejection(&state, &client_ip).await {
+        return response;
+    }
+    if is_rate_limited(&state, &client_ip).await {
+        return error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+            "Too many relay management requests. Try again in a minute.",
+        );
+    }
+
+    if !is_opaque_token(&request.session_id, 16) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_session_transfer",
+            "sessionID is required.",
+        );
+    }
+
+    refresh_session_for_request(&state, &request.session_id).await;
+
+    let (transfer_token, expires_at_ms, issued_by) = {
+        let mut relay = state.inner.lock().await;
+        let now = now_ms();
+        let Some(session) = relay.sessions.get(&request.session_id) else {
+            spawn_penalty_failure(&state, &client_ip, "session_transfer");
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "session_not_found",
+                "Remote session not found.",
+            );
+        };
+        let issued_by =
+            match authorize_transfer_issuer(&relay, &state.config, session, &request, now) {
+                Ok(issued_by) => issued_by,
+                Err((status, code, message)) => {
+                    if status == StatusCode::FORBIDDEN {
+                        spawn_penalty_failure(&state, &client_ip, "session_transfer");
+                    }
+                    return error_response(status, code, message);
+                }
+            };
+
+        let Some(session) = relay.sessions.get_mut(&request.session_id) else {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "session_not_found",
+                "Remote session not found.",
+            );
+        };
+        let transfer_token = random_token(32);
+        let expires_at_ms = now.saturating_add(state.config.session_transfer_token_ttl_ms as i64);
+        session.transfer_grant = Some(SessionTransferGrant {
+            token: transfer_token.clone(),
+            expires_at_ms,
+            issued_by: issued_by.clone(),
+        });
+        session.last_activity_at_ms = now;
+        (transfer_token, expires_at_ms, issued_by)
+    };
+
+    if let Some(persistence) = state.persistence.as_ref() {
+        if let Err(error) = persistence
+            .save_transfer_token(
+                &request.session_id,
+                &transfer_token,
+                state.config.session_transfer_token_ttl_ms,
+            )
+            .await
+        {
+            warn!(
+                "[relay-rs] session_transfer_issue_failed session={} error={error}",
+                session_log_id(&request.session_id)
+            );
+            return error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "session_transfer_unavailable",
+                "Relay could not store the transfer token. Retry shortly.",
+            );
+        }
+    }
+
+    info!(
+        "[relay-rs] session_transfer_issued session={} issued_by={issued_by}",
+        session_log_id(&request.session_id)
+    );
+    persist_session_if_needed(&state, &request.session_id).await;
+    publish_cross_instance_control_session_refresh(&state, &request.session_id);
+
+    (
+        StatusCode::OK,
+        Json(SessionTransferIssueResponse {
+            accepted: true,
+            session_id: request.session_id,
+            transfer_token,
+            expires_at: iso_from_millis(expires_at_ms),
+        }),
+    )
+        .into_response()
+}
+
+pub(super) async fn session_transfer_redeem(
+    State(state): State<SharedRelayState>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(request): Json<SessionTransferRedeemRequest>,
+) -> axum::response::Response {
+    if let Some(response) = validate_schema_version(request.schema_version) {
+        return response;
+    }
+
+    if !origin_allowed(&state.config, &headers) {
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "origin_not_allowed",
+            "Origin is not allowed.",
+        );
+    }
+
+    let client_ip = client_ip(&state.config, &headers, addr);
+    if let Some(response) = penalty_box_rejection(&state, &client_ip).await {
+        return response;
+    }
+    if is_rate_limited(&state, &client_ip).await {
+        return error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+            "Too many relay management requests. Try again in a minute.",
+        );
+    }
+
+    if !is_opaque_token(&request.session_id, 16)
+        || !is_opaque_token(&request.transfer_token, 22)
+        || !is_opaque_token(&request.desktop_session_token, 22)
+    {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_session_transfer",
+            "sessionID, transferToken, and desktopSessionToken must be high-entropy opaque identifiers.",
+        );
+    }
+
+    refresh_session_for_request(&state, &request.session_id).await;
+
+    let issued_by = {
+        let relay = state.inner.lock().await;
+        let desktop_token_in_use = relay
+            .desktop_token_index
+            .contains_key(&request.desktop_session_token);
+        let Some(session) = relay.sessions.get(&request.session_id) else {
+            spawn_penalty_failure(&state, &client_ip, "session_transfer");
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "session_not_found",
+                "Remote session not found.",
+            );
+        };
+
+        let Some(grant) = session
+            .transfer_grant
+            .as_ref()
+            .filter(|grant| safe_token_equals(&grant.token, &request.transfer_token))
+        else {
+            spawn_penalty_failure(&state, &client_ip, "session_transfer");
+            return session_transfer_token_used();
+        };
+        if now_ms() >= grant.expires_at_ms {
+            return error_response(
+                StatusCode::GONE,
+                "transfer_token_expired",
+                "Transfer token has expired.",
+            );
+        }
+        if desktop_token_in_use {
+            return session_transfer_desktop_token_in_use();
+        }
+        grant.issued_by.clone()
+    };
+
+    // Every instance holding the session sees the same grant, so with Redis the token is only
+    // spent once the claim there succeeds.
+    if let Some(persistence) = state.persistence.as_ref() {
+        match persistence
+            .claim_transfer_token(
+                &request.session_id,
+                &request.transfer_token,
+                &request.desktop_session_token,
+            )
+            .await
+        {
+            Ok(TransferTokenClaim::Claimed) => {}
+            Ok(TransferTokenClaim::AlreadyUsed) => return session_transfer_token_used(),
+            Ok(TransferTokenClaim::DesktopTokenInUse) => {
+                return session_transfer_desktop_token_in_use();
+            }
+            Err(error) => {
+                warn!(
+                    "[relay-rs] session_transfer_redeem_failed session={} error={error}",
+                    session_log_id(&request.session_id)
+                );
+                return error_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "session_transfer_unavailable",
+                    "Relay could not confirm the transfer token. Retry shortly.",
+                );
+            }
+        }
+    }
+
+    let (ws_url, device_count, previous_desktop_token) = {
+        let mut relay = state.inner.lock().await;
+        if relay
+            .desktop_token_index
+            .contains_key(&request.desktop_session_token)
+        {
+            return session_transfer_desktop_token_in_use();
+        }
+        let Some(session) = relay.sessions.get_mut(&request.session_id) else {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "session_not_found",
+                "Remote session not found.",
+            );
+        };
+        // A concurrent redeem on this instance may have spent the grant while the lock was free.
+        if !session
+            .transfer_grant
+            .as_ref()
+            .is_some_and(|grant| safe_token_equals(&grant.token, &request.transfer_token))
+        {
+            return session_transfer_token_used();
+        }
+
+        session.transfer_grant = None;
+        let previous_desktop_token = std::mem::replace(
+            &mut session.desktop_session_token,
+            request.desktop_session_token.clone(),
+        );
+        if let Some(desktop) = session.desktop_socket.take() {
+            request_socket_disconnect(&desktop, "session_transferred");
+        }
+        session.last_activity_at_ms = now_ms();
+        let ws_url = session.relay_web_socket_url.clone();
+        let device_count = session.devices.len();
+
+        relay.desktop_token_index.remove(&previous_desktop_token);
+        relay.desktop_token_index.insert(
+            request.desktop_session_token.clone(),
+            request.session_id.clone(),
+        );
+        (ws_url, device_count, previous_desktop_token)
+    };
+
+    info!(
+        "[relay-rs] session_transfer_redeemed session={} issued_by={issued_by} devices={device_count}",
+        session_log_id(&request.session_id)
+    );
+    publish_cross_instance_desktop_replaced(&state, &request.session_id, &previous_desktop_token);
+    persist_session_if_needed(&state, &request.session_id).await;
+    publish_cross_instance_control_session_refresh(&state, &request.session_id);
+
+    (
+        StatusCode::OK,
+        Json(SessionTransferRedeemResponse {
+            accepted: true,
+            session_id: request.session_id,
+            ws_url,
+            device_count,
+        }),
+    )
+        .into_response()
+}
+
+fn session_transfer_token_used() -> axum::response::Response {
+    error_response(
+        StatusCode::FORBIDDEN,
+        "invalid_transfer_token",
+        "Transfer token is invalid or has already been used.",
+    )
+}
+
+fn session_transfer_desktop_token_in_use() -> axum::response::Response {
+    error_response(
+        StatusCode::CONFLICT,
+        "desktop_session_token_in_use",
+        "desktopSessionToken is already in use. Generate a new one.",
+    )
+}
+
+pub(super) async fn device_history(
+    State(state): State<SharedRelayState>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(request): Json<DeviceHistoryRequest>,
+) -> axum::response::Response {
+    if let Some(response) = validate_schema_version(request.schema_version) {
+        return response;
+    }
+
+    if !origin_allowed(&state.config, &headers) {
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "origin_not_allowed",
+            "Origin is not allowed.",
+        );
+    }
+
+    let client_ip = client_ip(&state.config, &headers, addr);
+    if is_rate_limited(&state, &client_ip).await {
+        return error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+            "Too many relay management requests. Try again in a minute.",
+        );
+    }
+
+    if !is_opaque_token(&request.session_id, 16)
+        || !is_opaque_token(&request.desktop_session_token, 22)
+        || !is_opaque_token(&request.device_id, 8)
+    {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_device_history",
+            "sessionID, desktopSessionToken, and deviceID are required.",
+        );
+    }
+
+    refresh_session_for_request(&state, &request.session_id).await;
+
+    let events = {
+        let relay = state.inner.lock().await;
+        let Some(session) = relay.sessions.get(&request.session_id) else {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "session_not_found",
+                "Remote session not found.",
+            );
+        };
+
+        if !safe_token_equals(
+            &session.desktop_session_token,
+            &request.desktop_session_token,
+        ) {
+            return error_response(
+                StatusCode::FORBIDDEN,
+                "invalid_desktop_session_token",
+                "Desktop session token is invalid.",
+            );
+        }
+
+        let Some(record) = session.devices.get(&request.device_id) else {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "device_not_found",
+                "Device is not linked to this session.",
+            );
+        };
+        record
+            .connection_history
+            .iter()
+            .map(device_connection_event_summary)
+            .collect::<Vec<_>>()
+    };
+
+    (
+        StatusCode::OK,
+        Json(DeviceHistoryResponse {
+            accepted: true,
+            session_id: request.session_id,
+            device_id: request.device_id,
+            events,
+        }),
+    )
+        .into_response()
+}
+
+pub(super) async fn device_revoke(
+    State(state): State<SharedRelayState>,
+    headers: HeaderMap,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(request): Json<DeviceRevokeRequest>,
+) -> axum::response::Response {
+    if let Some(response) = validate_schema_version(request.schema_version) {
+        return response;
+    }
+
+    if !origin_allowed(&state.config, &headers) {
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "origin_not_allowed",
+            "Origin is not allowed.",
+        );
+    }
+
+    let client_ip = client_ip(&state.config, &headers, addr);
+    if is_rate_limited(&state, &client_ip).await {
+        return error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+            "Too many relay management requests. Try again in a minute.",
+        );
+    }
+
+    if !is_opaque_token(&request.session_id, 16)
+        || !is_opaque_token(&request.desktop_session_token, 22)
+        || !is_opaque_token(&request.device_id, 8)
+    {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "invalid_device_revoke",
+            "sessionID, desktopSessionToken, and deviceID are required.",
+        );
+    }
+
+    refresh_session_for_request(&state, &request.session_id).await;
+
+    let mut relay = state.inner.lock().await;
+    let session_id = request.session_id.clone();
+    let (device_count_event, removed_device_name) = {
+        let Some(session) = relay.sessions.get_mut(&session_id) else {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "session_not_found",
+                "Remote session not found.",
+            );
+        };
+
+        if !safe_token_equals(
+            &session.desktop_session_token,
+            &request.desktop_session_token,
+        ) {
+            return error_response(
+                StatusCode::FORBIDDEN,
+                "invalid_desktop_session_token",
+                "Desktop session token is invalid.",
+            );
+        }
+
+        let Some(removed_device) =
+            remove_session_device(session, &request.device_id, "device_revoked")
+        else {
+            return error_response(
+                StatusCode::NOT_FOUND,
+                "device_not_found",
+                "Device is not linked to this session.",
+            );
+        };
+
+        session.last_activity_at_ms = now_ms();
+        send_device_count(session);
+        (device_count_payload(session), removed_device.name)
+    };
+
+    relay.device_token_index.retain(|_, token| {
+        !(token.session_id == session_id && token.device_id == request.device_id)
+    });
+    emit_lifecycle_event(
+        &mut relay,
+        LifecycleEvent::new(LifecycleEventKind::DeviceRevoked, &session_id)
+            .with_device(&request.device_id, Some(&removed_device_name)),
+    );
     drop(relay);
     publish_cross_instance_session(
         &state,
         &request.session_id,
         "mobile",
         Some(request.device_id.clone()),
-        json!({ "type": "disconnect", "reason": "device_revoked" }).to_string(),
+        disconnect_payload("device_revoked"),
     );
     publish_cross_instance_session(
         &state,
@@ -1,5 +1,6 @@
 use super::*;
 
+mod admin;
 mod http;
 mod websocket;
 
@@ -7,9 +8,7 @@ pub fn build_router(state: SharedRelayState) -> Router {
     let max_json_bytes = state.config.max_json_bytes;
     let cors_layer = build_cors_layer(&state.config);
 
-    Router::new()
-        .route("/healthz", axum::routing::get(healthz))
-        .route("/metricsz", axum::routing::get(metricsz))
+    let pairing_routes = Router::new()
         .route(
             "/pair/start",
             axum::routing::post(http::pair_start).options(http::pair_options),
@@ -18,10 +17,18 @@ pub fn build_router(state: SharedRelayState) -> Router {
             "/pair/join",
             axum::routing::post(http::pair_join).options(http::pair_options),
         )
+        .route(
+            "/pair/status",
+            axum::routing::post(http::pair_status).options(http::pair_options),
+        )
         .route(
             "/pair/refresh",
             axum::routing::post(http::pair_refresh).options(http::pair_options),
         )
+        .route(
+            "/pair/qr",
+            axum::routing::post(http::pair_qr).options(http::pair_options),
+        )
         .route(
             "/pair/stop",
             axum::routing::post(http::pair_stop).options(http::pair_options),
@@ -30,11 +37,70 @@ pub fn build_router(state: SharedRelayState) -> Router {
             "/devices/list",
             axum::routing::post(http::devices_list).options(http::pair_options),
         )
+        .route(
+            "/invites/create",
+            axum::routing::post(http::invite_create).options(http::pair_options),
+        )
+        .route(
+            "/invites/list",
+            axum::routing::post(http::invites_list).options(http::pair_options),
+        )
+        .route(
+            "/invites/revoke",
+            axum::routing::post(http::invite_revoke).options(http::pair_options),
+        )
+        .route(
+            "/session/transfer/issue",
+            axum::routing::post(http::session_transfer_issue).options(http::pair_options),
+        )
+        .route(
+            "/session/transfer/redeem",
+            axum::routing::post(http::session_transfer_redeem).options(http::pair_options),
+        )
+        .route(
+            "/devices/history",
+            axum::routing::post(http::device_history).options(http::pair_options),
+        )
+        .route(
+            "/devices/rename",
+            axum::routing::post(http::device_rename).options(http::pair_options),
+        )
         .route(
             "/devices/revoke",
             axum::routing::post(http::device_revoke).options(http::pair_options),
         )
+        .route_layer(axum::middleware::from_fn_with_state(
+            (state.clone(), RouteGroup::Pairing),
+            enforce_ip_access,
+        ));
+    let admin_routes = Router::new()
+        .route(
+            "/admin/penalty-box",
+            axum::routing::get(admin::penalty_box_list),
+        )
+        .route(
+            "/admin/penalty-box/clear",
+            axum::routing::post(admin::penalty_box_clear),
+        )
+        .route_layer(axum::middleware::from_fn_with_state(
+            (state.clone(), RouteGroup::Admin),
+            enforce_ip_access,
+        ));
+    let websocket_routes = Router::new()
         .route("/ws", axum::routing::get(websocket::ws_upgrade))
+        .route_layer(axum::middleware::from_fn_with_state(
+            (state.clone(), RouteGroup::WebSocket),
+            enforce_ip_access,
+        ));
+
+    Router::new()
+        .route("/healthz", axum::routing::get(healthz))
+        .route("/metricsz", axum::routing::get(metricsz))
+        .route("/openapi.json", axum::routing::get(openapi_json))
+        .route("/schemas", axum::routing::get(schemas_json))
+        .merge(pairing_routes)
+        .merge(admin_routes)
+        .merge(websocket_routes)
         .with_state(state)
         .layer(DefaultBodyLimit::max(max_json_bytes))
         .layer(cors_layer)
@@ -16,6 +16,11 @@ pub(super) async fn ws_upgrade(
     } else {
         None
     };
+    if let Some(response) =
+        penalty_box_rejection(&state, &client_ip(&state.config, &headers, addr)).await
+    {
+        return response;
+    }
     let max_message_size = state.config.max_ws_message_bytes;
 
     ws.max_message_size(max_message_size)
@@ -23,6 +28,7 @@ pub(super) async fn ws_upgrade(
         .on_upgrade(move |socket| async move {
             handle_socket(state, socket, headers, addr, origin, legacy_query_token).await;
         })
+        .into_response()
 }
 
 pub(super) async fn handle_socket(
@@ -34,7 +40,7 @@ pub(super) async fn handle_socket(
     legacy_query_token: Option<String>,
 ) {
     let (mut writer, mut reader) = socket.split();
-    let (tx, mut rx) = mpsc::channel::<Message>(state.config.max_socket_outbound_queue.max(8));
+    let (tx, mut rx) = socket_channel(state.config.max_socket_outbound_queue.max(8));
     let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
     let client_ip = client_ip(&state.config, &headers, addr);
     let user_agent = headers
@@ -55,11 +61,14 @@ pub(super) async fn handle_socket(
         }
     });
 
-    let auth_message = if let Some(token) = legacy_query_token {
-        Some(RelayAuthMessage {
+    let auth_frame = if let Some(token) = legacy_query_token {
+        Some(InboundMessage::Auth(RelayAuthMessage {
             message_type: "relay.auth".to_string(),
             token,
-        })
+            platform: None,
+            app_version: None,
+            supported_schema_versions: None,
+        }))
     } else {
         match timeout(
             Duration::from_millis(state.config.ws_auth_timeout_ms),
@@ -67,12 +76,15 @@ pub(super) async fn handle_socket(
         )
         .await
         {
-            Ok(Some(Ok(Message::Text(raw)))) => serde_json::from_str::<RelayAuthMessage>(&raw).ok(),
+            Ok(Some(Ok(Message::Text(raw)))) => match decode_inbound_frame(&raw).message {
+                InboundMessage::Invalid(_) => None,
+                message => Some(message),
+            },
             _ => None,
         }
     };
 
-    let Some(auth_message) = auth_message else {
+    let Some(auth_frame) = auth_frame else {
         {
             let mut relay = state.inner.lock().await;
             record_ws_auth_failure_reason(&mut relay, "auth_timeout_or_missing_payload");
@@ -87,23 +99,50 @@ pub(super) async fn handle_socket(
         return;
     };
 
-    if auth_message.message_type != "relay.auth" || !is_opaque_token(&auth_message.token, 22) {
+    let auth_message = match auth_frame {
+        InboundMessage::Auth(auth_message)
+            if is_device_session_token_candidate(&auth_message.token) =>
+        {
+            auth_message
+        }
+        _ => {
+            {
+                let mut relay = state.inner.lock().await;
+                record_ws_auth_failure_reason(&mut relay, "invalid_auth_payload");
+            }
+            warn!(
+                "[relay-rs] ws_auth_failure reason=invalid_auth_payload remote_ip={} user_agent={}",
+                client_ip,
+                user_agent.as_deref().unwrap_or("-")
+            );
+            record_penalty_failure(&state, &client_ip, "ws_auth").await;
+            close_writer_task(writer_task, tx).await;
+            return;
+        }
+    };
+
+    if resolve_schema_version(auth_message.supported_schema_versions.as_deref()).is_none() {
         {
             let mut relay = state.inner.lock().await;
-            record_ws_auth_failure_reason(&mut relay, "invalid_auth_payload");
+            record_ws_auth_failure_reason(&mut relay, "unsupported_schema_version");
         }
         warn!(
-            "[relay-rs] ws_auth_failure reason=invalid_auth_payload remote_ip={} user_agent={}",
+            "[relay-rs] ws_auth_failure reason=unsupported_schema_version remote_ip={} user_agent={}",
             client_ip,
             user_agent.as_deref().unwrap_or("-")
         );
+        send_relay_error(
+            &tx,
+            "unsupported_schema_version",
+            &unsupported_schema_version_message(),
+        );
         close_writer_task(writer_task, tx).await;
         return;
     }
 
     let auth = authenticate_socket(
         &state,
-        &auth_message.token,
+        &auth_message,
         origin.as_deref(),
         &client_ip,
         user_agent.as_deref(),
@@ -114,6 +153,7 @@ pub(super) async fn handle_socket(
     let auth = match auth {
         Ok(auth) => auth,
         Err(SocketAuthFailure::SessionExpired) => {
+            record_penalty_failure(&state, &client_ip, "ws_auth").await;
             close_writer_task_with_policy_violation(writer_task, tx, "session_expired").await;
             return;
         }
@@ -123,6 +163,8 @@ pub(super) async fn handle_socket(
         }
     };
 
+    let connected_at_ms = now_ms();
+    let mut disconnect_reason = "client_closed".to_string();
     let mut ws_message_rate_bucket = RateBucket {
         count: 0,
         window_ends_at_ms: now_ms() + 60_000,
@@ -141,6 +183,11 @@ pub(super) async fn handle_socket(
         tokio::select! {
             changed = shutdown_rx.changed() => {
                 if changed.is_err() || *shutdown_rx.borrow() {
+                    disconnect_reason = tx
+                        .disconnect_reason
+                        .get()
+                        .cloned()
+                        .unwrap_or_else(|| "relay_closed".to_string());
                     break;
                 }
             }
@@ -149,15 +196,13 @@ pub(super) async fn handle_socket(
                 if now - last_heartbeat_at_ms > heartbeat_timeout_ms as i64 {
                     let _ = try_send_payload(
                         &tx,
-                        json!({
-                            "type": "disconnect",
-                            "reason": "heartbeat_timeout"
-                        })
-                        .to_string(),
+                        disconnect_payload("heartbeat_timeout"),
                     );
+                    disconnect_reason = "heartbeat_timeout".to_string();
                     break;
                 }
                 if !try_send_message(&tx, Message::Ping(Vec::new().into())) {
+                    disconnect_reason = "send_failed".to_string();
                     break;
                 }
             }
@@ -187,12 +232,9 @@ pub(super) async fn handle_socket(
                 if raw.len() > state.config.max_ws_message_bytes {
                     let _ = try_send_payload(
                         &tx,
-                        json!({
-                            "type": "disconnect",
-                            "reason": "message_too_large"
-                        })
-                        .to_string(),
+                        disconnect_payload("message_too_large"),
                     );
+                    disconnect_reason = "message_too_large".to_string();
                     break;
                 }
 
@@ -202,23 +244,41 @@ pub(super) async fn handle_socket(
                 ) {
                     let _ = try_send_payload(
                         &tx,
-                        json!({
-                            "type": "disconnect",
-                            "reason": "socket_rate_limited"
-                        })
-                        .to_string(),
+                        disconnect_payload("socket_rate_limited"),
                     );
+                    disconnect_reason = "socket_rate_limited".to_string();
                     break;
                 }
 
-                let parsed = serde_json::from_str::<Value>(&raw).ok();
+                let InboundFrame {
+                    raw,
+                    session_id: frame_session_id,
+                    message,
+                } = decode_inbound_frame(&raw);
+                // Runtime request events are only parsed when the approval webhook needs them,
+                // and before the relay lock is taken.
+                let approval_event = (matches!(auth.auth, SocketAuth::Desktop)
+                    && message.passthrough_payload_type() == Some("event")
+                    && state
+                        .webhooks
+                        .as_ref()
+                        .is_some_and(|webhooks| webhooks.approval.is_some()))
+                .then(|| serde_json::from_str::<Value>(&raw).ok())
+                .flatten();
+                let resolved_request_id = resolved_runtime_request_id(approval_event.as_ref());
+                let mut raised_request = pending_runtime_request(approval_event.as_ref());
                 let mut publish_target: Option<(&'static str, String)> = None;
                 let mut publish_pair_decision = false;
                 let mut outbound_send_failures = 0_u64;
                 let mut slow_consumer_disconnects = 0_u64;
-                let mut mobile_targets: Vec<SocketHandle> = Vec::new();
-                let mut desktop_target: Option<SocketHandle> = None;
+                let mut byte_quota_drops = 0_u64;
+                let mut byte_quota_notices: Vec<SocketSender> = Vec::new();
+                let mut mobile_targets: Vec<(SocketHandle, u32)> = Vec::new();
+                let mut desktop_target: Option<(SocketHandle, u32)> = None;
                 let mut relay_error: Option<(String, String)> = None;
+                let mut serve_cached_snapshot: Option<u32> = None;
+                let mut cached_snapshot: Option<(CachedDesktopSnapshot, u64)> = None;
+                let mut unattended_runtime_request: Option<PendingRuntimeRequest> = None;
                 let mut should_continue = false;
                 let mut should_break = false;
 
@@ -234,36 +294,107 @@ pub(super) async fn handle_socket(
                         } else {
                             session.last_activity_at_ms = now_ms();
 
-                            if let Some(parsed) = parsed.as_ref() {
-                                if parsed
-                                    .get("sessionID")
-                                    .and_then(Value::as_str)
-                                    .is_some_and(|id| id != auth.session_id())
-                                {
-                                    should_continue = true;
-                                }
+                            if frame_session_id
+                                .as_deref()
+                                .is_some_and(|id| id != auth.session_id())
+                            {
+                                should_continue = true;
                             }
 
                             if !should_continue {
                                 match &auth.auth {
                                     SocketAuth::Desktop => {
-                                        if let Ok(pair_decision) =
-                                            serde_json::from_str::<RelayPairDecision>(&raw)
-                                        {
-                                            if pair_decision.message_type == "relay.pair_decision" {
+                                        match &message {
+                                            InboundMessage::PairDecision(pair_decision) => {
                                                 apply_pair_decision(
                                                     session,
-                                                    &pair_decision,
+                                                    pair_decision,
                                                     Some(&tx),
                                                 );
                                                 publish_pair_decision = true;
                                                 should_continue = true;
                                             }
+                                            InboundMessage::Auth(_) => {
+                                                relay_error = Some((
+                                                    "invalid_payload".to_string(),
+                                                    "Socket is already authenticated.".to_string(),
+                                                ));
+                                                should_continue = true;
+                                            }
+                                            _ => {}
                                         }
 
                                         if !should_continue {
-                                            mobile_targets =
-                                                session.mobile_sockets.values().cloned().collect();
+                                            if let ByteQuotaDecision::Exceeded { notify } =
+                                                session.traffic.admit_inbound(
+                                                    &state.config,
+                                                    None,
+                                                    raw.len() as u64,
+                                                    now_ms(),
+                                                )
+                                            {
+                                                byte_quota_drops = 1;
+                                                if notify {
+                                                    byte_quota_notices.push(tx.clone());
+                                                }
+                                                should_continue = true;
+                                            }
+                                        }
+
+                                        if !should_continue {
+                                            let payload_type = message.passthrough_payload_type();
+                                            if payload_type == Some("snapshot") {
+                                                cached_snapshot = cache_desktop_snapshot(
+                                                    session,
+                                                    &state.config,
+                                                    &raw,
+                                                    now_ms(),
+                                                )
+                                                .map(|snapshot| {
+                                                    (snapshot, session.idle_timeout_seconds)
+                                                });
+                                            }
+                                            if let Some(request_id) = &resolved_request_id {
+                                                session
+                                                    .notified_runtime_requests
+                                                    .remove(request_id);
+                                            }
+                                            if session.mobile_sockets.is_empty() {
+                                                unattended_runtime_request = raised_request
+                                                    .take()
+                                                    .filter(|request| {
+                                                        claim_runtime_request_notification(
+                                                            session, request,
+                                                        )
+                                                    });
+                                            }
+                                            let now = now_ms();
+                                            for mobile in session.mobile_sockets.values() {
+                                                match session.traffic.admit_outbound(
+                                                    &state.config,
+                                                    mobile.device_id.as_deref(),
+                                                    raw.len() as u64,
+                                                    now,
+                                                ) {
+                                                    ByteQuotaDecision::Allowed => {
+                                                        mobile_targets.push((
+                                                            mobile.clone(),
+                                                            device_schema_version(
+                                                                &session.devices,
+                                                                mobile.device_id.as_deref(),
+                                                            ),
+                                                        ));
+                                                    }
+                                                    ByteQuotaDecision::Exceeded { notify } => {
+                                                        byte_quota_drops =
+                                                            byte_quota_drops.saturating_add(1);
+                                                        if notify {
+                                                            byte_quota_notices
+                                                                .push(mobile.tx.clone());
+                                                        }
+                                                    }
+                                                }
+                                            }
                                             publish_target = Some(("mobile", raw.to_string()));
                                         }
                                     }
@@ -271,16 +402,17 @@ pub(super) async fn handle_socket(
                                         device_id,
                                         connection_id,
                                     } => {
-                                        let is_command_or_snapshot =
-                                            parsed.as_ref().is_some_and(|payload| {
-                                                payload
-                                                    .pointer("/payload/type")
-                                                    .and_then(Value::as_str)
-                                                    .is_some_and(|value| value == "command")
-                                                    || payload.get("type").and_then(Value::as_str)
-                                                        == Some("relay.snapshot_request")
-                                            });
-                                        if is_command_or_snapshot && !desktop_connected(session) {
+                                        let is_command =
+                                            matches!(message, InboundMessage::Command(_));
+                                        let is_snapshot_request =
+                                            matches!(message, InboundMessage::SnapshotRequest(_));
+                                        if is_snapshot_request && !desktop_connected(session) {
+                                            serve_cached_snapshot = Some(device_schema_version(
+                                                &session.devices,
+                                                Some(device_id),
+                                            ));
+                                            should_continue = true;
+                                        } else if is_command && !desktop_connected(session) {
                                             relay_error = Some((
                                                 "desktop_offline".to_string(),
                                                 "Mac is offline. Reconnect desktop and try again."
@@ -289,10 +421,27 @@ pub(super) async fn handle_socket(
                                             should_continue = true;
                                         }
 
+                                        if !should_continue {
+                                            if let ByteQuotaDecision::Exceeded { notify } =
+                                                session.traffic.admit_inbound(
+                                                    &state.config,
+                                                    Some(device_id),
+                                                    raw.len() as u64,
+                                                    now_ms(),
+                                                )
+                                            {
+                                                byte_quota_drops = 1;
+                                                if notify {
+                                                    byte_quota_notices.push(tx.clone());
+                                                }
+                                                should_continue = true;
+                                            }
+                                        }
+
                                         if !should_continue {
                                             match validate_mobile_payload(
                                                 session,
-                                                parsed.as_ref(),
+                                                &message,
                                                 auth.session_id(),
                                                 connection_id,
                                                 device_id,
@@ -310,12 +459,39 @@ pub(super) async fn handle_socket(
                                         }
 
                                         if !should_continue {
-                                            let forwarded = inject_mobile_metadata(
+                                            if let InboundMessage::Command(command) = &message {
+                                                if let Some(runtime_request_id) =
+                                                    &command.payload.payload.runtime_request_id
+                                                {
+                                                    session
+                                                        .notified_runtime_requests
+                                                        .remove(runtime_request_id);
+                                                }
+                                            }
+                                            let envelope = match &message {
+                                                InboundMessage::Command(envelope) => {
+                                                    Some(envelope.as_ref())
+                                                }
+                                                _ => None,
+                                            };
+                                            let forwarded = forwarded_command_payload(
                                                 &raw,
+                                                envelope,
                                                 connection_id,
                                                 device_id,
                                             );
-                                            desktop_target = session.desktop_socket.clone();
+                                            desktop_target =
+                                                session.desktop_socket.clone().map(|desktop| {
+                                                    (desktop, session.desktop_schema_version)
+                                                });
+                                            if desktop_target.is_some() {
+                                                session.traffic.admit_outbound(
+                                                    &state.config,
+                                                    None,
+                                                    forwarded.len() as u64,
+                                                    now_ms(),
+                                                );
+                                            }
                                             publish_target = Some(("desktop", forwarded));
                                         }
                                     }
@@ -325,9 +501,56 @@ pub(super) async fn handle_socket(
                     }
                 }
 
+                if byte_quota_drops > 0 {
+                    if !byte_quota_notices.is_empty() {
+                        let sender = match &auth.auth {
+                            SocketAuth::Desktop => "desktop",
+                            SocketAuth::Mobile { device_id, .. } => device_id.as_str(),
+                        };
+                        warn!(
+                            "[relay-rs] byte_quota_exceeded session={} sender={sender} notices={}",
+                            session_log_id(auth.session_id()),
+                            byte_quota_notices.len()
+                        );
+                    }
+                    for notice in &byte_quota_notices {
+                        send_byte_quota_error(notice);
+                    }
+                    let mut relay = state.inner.lock().await;
+                    relay.byte_quota_drops = relay.byte_quota_drops.saturating_add(byte_quota_drops);
+                }
                 if should_break {
+                    disconnect_reason = "replaced".to_string();
                     break 'socket_loop;
                 }
+                if let Some(schema_version) = serve_cached_snapshot {
+                    if !serve_cached_desktop_snapshot(
+                        &state,
+                        auth.session_id(),
+                        &tx,
+                        schema_version,
+                    )
+                    .await
+                    {
+                        send_relay_error(
+                            &tx,
+                            "desktop_offline",
+                            "Mac is offline. Reconnect desktop and try again.",
+                        );
+                    }
+                    continue;
+                }
+                if let Some((snapshot, idle_timeout_seconds)) = cached_snapshot {
+                    persist_desktop_snapshot(
+                        &state,
+                        auth.session_id(),
+                        snapshot,
+                        idle_timeout_seconds,
+                    );
+                }
+                if let Some(request) = unattended_runtime_request {
+                    notify_pending_runtime_request(&state, auth.session_id(), request);
+                }
                 if let Some((error_code, error_message)) = relay_error {
                     send_relay_error(&tx, &error_code, &error_message);
                     continue;
@@ -344,17 +567,20 @@ pub(super) async fn handle_socket(
                     continue;
                 }
 
-                for mobile in &mobile_targets {
-                    if !try_send_payload(&mobile.tx, raw.to_string()) {
+                let mut adapted = AdaptedEnvelopes::new(&raw);
+                for (mobile, schema_version) in &mobile_targets {
+                    let payload = adapted.for_version(*schema_version).to_string();
+                    if !try_send_bulk_payload(&mobile.tx, payload) {
                         outbound_send_failures = outbound_send_failures.saturating_add(1);
                         slow_consumer_disconnects = slow_consumer_disconnects.saturating_add(1);
                         request_socket_disconnect(mobile, "slow_consumer");
                     }
                 }
 
-                if let Some(desktop) = &desktop_target {
+                if let Some((desktop, schema_version)) = &desktop_target {
                     if let Some((_, payload)) = publish_target.as_ref() {
-                        if !try_send_payload(&desktop.tx, payload.clone()) {
+                        let payload = adapt_envelope(payload, *schema_version).into_owned();
+                        if !try_send_bulk_payload(&desktop.tx, payload) {
                             outbound_send_failures = outbound_send_failures.saturating_add(1);
                             slow_consumer_disconnects = slow_consumer_disconnects.saturating_add(1);
                             request_socket_disconnect(desktop, "slow_consumer");
@@ -363,13 +589,7 @@ pub(super) async fn handle_socket(
                 }
 
                 if let Some((target, payload)) = publish_target {
-                    publish_cross_instance_session(
-                        &state,
-                        auth.session_id(),
-                        target,
-                        None,
-                        payload,
-                    );
+                    publish_cross_instance_forwarded(&state, auth.session_id(), target, payload);
                 }
 
                 if outbound_send_failures > 0 || slow_consumer_disconnects > 0 {
@@ -385,13 +605,20 @@ pub(super) async fn handle_socket(
         }
     }
 
-    disconnect_socket(&state, &auth).await;
+    disconnect_socket(
+        &state,
+        &auth,
+        &client_ip,
+        connected_at_ms,
+        &disconnect_reason,
+    )
+    .await;
     close_writer_task(writer_task, tx).await;
 }
 
 pub(super) async fn close_writer_task(
     mut writer_task: tokio::task::JoinHandle<()>,
-    tx: mpsc::Sender<Message>,
+    tx: SocketSender,
 ) {
     drop(tx);
     if timeout(Duration::from_millis(100), &mut writer_task)
@@ -404,17 +631,10 @@ pub(super) async fn close_writer_task(
 
 async fn close_writer_task_with_policy_violation(
     writer_task: tokio::task::JoinHandle<()>,
-    tx: mpsc::Sender<Message>,
+    tx: SocketSender,
     reason: &'static str,
 ) {
-    let _ = try_send_payload(
-        &tx,
-        json!({
-            "type": "disconnect",
-            "reason": reason,
-        })
-        .to_string(),
-    );
+    let _ = try_send_payload(&tx, disconnect_payload(reason));
     let _ = try_send_message(
         &tx,
         Message::Close(Some(CloseFrame {
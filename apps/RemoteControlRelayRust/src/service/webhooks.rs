@@ -0,0 +1,564 @@
+use super::*;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+const WEBHOOK_MAX_BACKOFF_MS: u64 = 30_000;
+const MAX_NOTIFIED_RUNTIME_REQUESTS: usize = 256;
+pub(super) const LIFECYCLE_EVENT_QUEUE_CAPACITY: usize = 1_024;
+
+/// Outbound webhook delivery shared by every notification the relay sends.
+#[derive(Clone)]
+pub(super) struct RelayWebhooks {
+    pub(super) http: reqwest::Client,
+    pub(super) max_attempts: u64,
+    pub(super) initial_backoff_ms: u64,
+    pub(super) approval: Option<ApprovalWebhook>,
+    pub(super) lifecycle: Vec<WebhookTarget>,
+}
+
+#[derive(Clone)]
+pub(super) struct ApprovalWebhook {
+    pub(super) target: WebhookTarget,
+    pub(super) deep_link_base_url: String,
+}
+
+#[derive(Clone)]
+pub(super) struct WebhookTarget {
+    pub(super) url: String,
+    pub(super) secret: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct PendingRuntimeRequest {
+    pub(super) kind: String,
+    pub(super) request_id: Option<String>,
+    pub(super) thread_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum LifecycleEventKind {
+    SessionStarted,
+    DeviceJoined,
+    DeviceRevoked,
+    SessionStopped,
+    SessionExpired,
+}
+
+impl LifecycleEventKind {
+    pub(super) fn as_str(self) -> &'static str {
+        match self {
+            Self::SessionStarted => "session_started",
+            Self::DeviceJoined => "device_joined",
+            Self::DeviceRevoked => "device_revoked",
+            Self::SessionStopped => "session_stopped",
+            Self::SessionExpired => "session_expired",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct LifecycleEvent {
+    pub(super) kind: LifecycleEventKind,
+    pub(super) session_id: String,
+    pub(super) device_id: Option<String>,
+    pub(super) device_name: Option<String>,
+    pub(super) reason: Option<String>,
+    pub(super) occurred_at_ms: i64,
+    /// Timestamp that identifies this occurrence independently of the instance reporting it,
+    /// such as the session's creation time for the close of that session.
+    pub(super) occurrence_ms: i64,
+}
+
+impl LifecycleEvent {
+    pub(super) fn new(kind: LifecycleEventKind, session_id: &str) -> Self {
+        let occurred_at_ms = now_ms();
+        Self {
+            kind,
+            session_id: session_id.to_string(),
+            device_id: None,
+            device_name: None,
+            reason: None,
+            occurred_at_ms,
+            occurrence_ms: occurred_at_ms,
+        }
+    }
+
+    pub(super) fn with_occurrence(mut self, occurrence_ms: i64) -> Self {
+        self.occurrence_ms = occurrence_ms;
+        self
+    }
+
+    /// Derived from the session, event kind, device and occurrence, so an event reported by
+    /// more than one instance, or retried, reaches receivers under one `deliveryID`.
+    pub(super) fn delivery_id(&self) -> String {
+        let material = format!(
+            "{}|{}|{}|{}",
+            self.session_id,
+            self.kind.as_str(),
+            self.device_id.as_deref().unwrap_or_default(),
+            self.occurrence_ms
+        );
+        base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(&Sha256::digest(material.as_bytes())[..12])
+    }
+
+    pub(super) fn with_device(mut self, device_id: &str, device_name: Option<&str>) -> Self {
+        self.device_id = Some(device_id.to_string());
+        self.device_name = device_name.map(ToOwned::to_owned);
+        self
+    }
+
+    pub(super) fn with_reason(mut self, reason: &str) -> Self {
+        self.reason = Some(reason.to_string());
+        self
+    }
+}
+
+/// Delivery counters reported by `/metricsz`. Dead letters are deliveries that exhausted their
+/// retries, were rejected permanently, or were dropped because the event queue was full.
+#[derive(Default)]
+pub(super) struct WebhookDeliveryStats {
+    pub(super) attempts: u64,
+    pub(super) delivered: u64,
+    pub(super) dead_lettered: u64,
+    pub(super) dead_letters_by_event: HashMap<String, u64>,
+}
+
+impl WebhookDeliveryStats {
+    fn record_dead_letter(&mut self, event: &str) {
+        self.dead_lettered = self.dead_lettered.saturating_add(1);
+        *self
+            .dead_letters_by_event
+            .entry(event.to_string())
+            .or_insert(0) += 1;
+    }
+}
+
+#[derive(Serialize)]
+struct LifecycleEventNotification<'a> {
+    #[serde(rename = "type")]
+    message_type: &'static str,
+    #[serde(rename = "deliveryID")]
+    delivery_id: &'a str,
+    #[serde(rename = "sessionID")]
+    session_id: &'a str,
+    #[serde(rename = "deviceID")]
+    device_id: Option<&'a str>,
+    #[serde(rename = "deviceName")]
+    device_name: Option<&'a str>,
+    reason: Option<&'a str>,
+    #[serde(rename = "occurredAt")]
+    occurred_at: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PendingRuntimeRequestNotification<'a> {
+    #[serde(rename = "type")]
+    message_type: &'static str,
+    #[serde(rename = "deliveryID")]
+    delivery_id: &'a str,
+    #[serde(rename = "sessionID")]
+    session_id: &'a str,
+    request_kind: &'a str,
+    #[serde(rename = "requestID")]
+    request_id: Option<&'a str>,
+    #[serde(rename = "threadID")]
+    thread_id: Option<&'a str>,
+    deep_link: String,
+    occurred_at: String,
+}
+
+pub(super) fn build_webhooks(config: &RelayConfig) -> Option<RelayWebhooks> {
+    let approval = match (
+        config.approval_webhook_url.as_ref(),
+        config.approval_webhook_secret.as_ref(),
+        config.approval_webhook_deep_link_base_url.as_ref(),
+    ) {
+        (Some(url), Some(secret), Some(deep_link_base_url)) => Some(ApprovalWebhook {
+            target: WebhookTarget {
+                url: url.clone(),
+                secret: secret.clone(),
+            },
+            deep_link_base_url: deep_link_base_url.clone(),
+        }),
+        _ => None,
+    };
+    let lifecycle = match config.lifecycle_webhook_secret.as_ref() {
+        Some(secret) => config
+            .lifecycle_webhook_urls
+            .iter()
+            .map(|url| WebhookTarget {
+                url: url.clone(),
+                secret: secret.clone(),
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    if approval.is_none() && lifecycle.is_empty() {
+        return None;
+    }
+
+    let http = match reqwest::Client::builder()
+        .timeout(Duration::from_millis(config.webhook_timeout_ms))
+        .build()
+    {
+        Ok(http) => http,
+        Err(error) => {
+            warn!("[relay-rs] failed to build webhook client; webhooks disabled: {error}");
+            return None;
+        }
+    };
+
+    Some(RelayWebhooks {
+        http,
+        max_attempts: config.webhook_max_attempts.max(1),
+        initial_backoff_ms: config.webhook_initial_backoff_ms,
+        approval,
+        lifecycle,
+    })
+}
+
+/// Signature over `<timestamp>.<body>` so a captured delivery cannot be replayed with a fresh
+/// timestamp. Sent as `X-Relay-Signature: v1=<base64url>`.
+pub(super) fn webhook_signature(secret: &str, timestamp_ms: i64, body: &str) -> Option<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).ok()?;
+    mac.update(timestamp_ms.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    Some(format!(
+        "v1={}",
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    ))
+}
+
+fn webhook_backoff_ms(initial_backoff_ms: u64, attempt: u64) -> u64 {
+    let exponent = u32::try_from(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+    initial_backoff_ms
+        .saturating_mul(2_u64.saturating_pow(exponent))
+        .min(WEBHOOK_MAX_BACKOFF_MS)
+}
+
+/// Posts a signed payload, retrying network errors, 429 and 5xx responses with exponential
+/// backoff. Other 4xx responses are treated as permanent. Returns the number of attempts made
+/// alongside the outcome.
+async fn deliver_webhook(
+    webhooks: &RelayWebhooks,
+    target: &WebhookTarget,
+    event: &str,
+    delivery_id: &str,
+    body: &str,
+) -> (u64, Result<(), String>) {
+    let mut attempt = 1;
+    loop {
+        let timestamp_ms = now_ms();
+        let Some(signature) = webhook_signature(&target.secret, timestamp_ms, body) else {
+            return (attempt, Err("webhook signing failed".to_string()));
+        };
+        let result = webhooks
+            .http
+            .post(&target.url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("X-Relay-Event", event)
+            .header("X-Relay-Delivery", delivery_id)
+            .header("X-Relay-Timestamp", timestamp_ms.to_string())
+            .header("X-Relay-Signature", signature)
+            .body(body.to_string())
+            .send()
+            .await;
+
+        let error = match result {
+            Ok(response) if response.status().is_success() => return (attempt, Ok(())),
+            Ok(response)
+                if response.status().is_client_error()
+                    && response.status() != StatusCode::TOO_MANY_REQUESTS =>
+            {
+                return (
+                    attempt,
+                    Err(format!("rejected with status {}", response.status())),
+                );
+            }
+            Ok(response) => format!("status {}", response.status()),
+            Err(error) => error.to_string(),
+        };
+        if attempt >= webhooks.max_attempts {
+            return (attempt, Err(error));
+        }
+        warn!(
+            "[relay-rs] webhook attempt {attempt} failed event={event} delivery={delivery_id}: {error}"
+        );
+        sleep(Duration::from_millis(webhook_backoff_ms(
+            webhooks.initial_backoff_ms,
+            attempt,
+        )))
+        .await;
+        attempt += 1;
+    }
+}
+
+/// Delivers one webhook in the background and records the outcome. Deliveries that cannot be
+/// completed are counted and logged under their delivery ID; bodies are not logged.
+pub(super) fn dispatch_webhook(
+    state: &SharedRelayState,
+    webhooks: &RelayWebhooks,
+    target: &WebhookTarget,
+    event: &'static str,
+    delivery_id: String,
+    body: String,
+) {
+    let state = state.clone();
+    let webhooks = webhooks.clone();
+    let target = target.clone();
+    tokio::spawn(async move {
+        let (attempts, result) =
+            deliver_webhook(&webhooks, &target, event, &delivery_id, &body).await;
+        let mut relay = state.inner.lock().await;
+        relay.webhook_stats.attempts = relay.webhook_stats.attempts.saturating_add(attempts);
+        match result {
+            Ok(()) => {
+                relay.webhook_stats.delivered = relay.webhook_stats.delivered.saturating_add(1);
+                drop(relay);
+                info!(
+                    "[relay-rs] webhook_delivered event={event} delivery={delivery_id} attempts={attempts}"
+                );
+            }
+            Err(error) => {
+                relay.webhook_stats.record_dead_letter(event);
+                drop(relay);
+                warn!(
+                    "[relay-rs] webhook_dead_letter event={event} delivery={delivery_id} url={} attempts={attempts} error={error}",
+                    redact_url_for_logs(&target.url)
+                );
+            }
+        }
+    });
+}
+
+/// Queues a lifecycle event for the webhook worker. Never blocks the caller, which usually holds
+/// the relay lock; events that do not fit in the queue are dead-lettered.
+pub(super) fn emit_lifecycle_event(relay: &mut RelayState, event: LifecycleEvent) {
+    let Some(sender) = relay.lifecycle_events.as_ref() else {
+        return;
+    };
+    let event = match sender.try_send(event) {
+        Ok(()) => return,
+        Err(TrySendError::Full(event) | TrySendError::Closed(event)) => event,
+    };
+    relay.webhook_stats.record_dead_letter(event.kind.as_str());
+    warn!(
+        "[relay-rs] webhook_dead_letter event={} delivery={} session={} error=queue_full",
+        event.kind.as_str(),
+        event.delivery_id(),
+        session_log_id(&event.session_id)
+    );
+}
+
+/// Maps `close_session` reasons onto lifecycle events. Closes that only mirror another
+/// instance's state (shutdown drains, persistence removals) are not reported.
+pub(super) fn lifecycle_event_for_close_reason(reason: &str) -> Option<LifecycleEventKind> {
+    match reason {
+        "stopped_by_desktop" | "replaced_by_new_pair_start" => {
+            Some(LifecycleEventKind::SessionStopped)
+        }
+        "idle_timeout" | "retention_expired" => Some(LifecycleEventKind::SessionExpired),
+        _ => None,
+    }
+}
+
+pub(super) fn start_lifecycle_webhook_worker(
+    state: SharedRelayState,
+    mut events: mpsc::Receiver<LifecycleEvent>,
+) {
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            let Some(webhooks) = state.webhooks.as_ref() else {
+                continue;
+            };
+            let delivery_id = event.delivery_id();
+            let body = serde_json::to_string(&LifecycleEventNotification {
+                message_type: event.kind.as_str(),
+                delivery_id: &delivery_id,
+                session_id: &event.session_id,
+                device_id: event.device_id.as_deref(),
+                device_name: event.device_name.as_deref(),
+                reason: event.reason.as_deref(),
+                occurred_at: iso_from_millis(event.occurred_at_ms),
+            })
+            .unwrap_or_else(|_| "{}".to_string());
+            for target in &webhooks.lifecycle {
+                dispatch_webhook(
+                    &state,
+                    webhooks,
+                    target,
+                    event.kind.as_str(),
+                    delivery_id.clone(),
+                    body.clone(),
+                );
+            }
+        }
+    });
+}
+
+/// Recognises a desktop `runtime_request.requested` event. Desktops that do not report the
+/// request kind are treated as plain approvals, matching the mobile client.
+pub(super) fn pending_runtime_request(parsed: Option<&Value>) -> Option<PendingRuntimeRequest> {
+    let parsed = parsed?;
+    if parsed.pointer("/payload/type").and_then(Value::as_str) != Some("event") {
+        return None;
+    }
+    let event = parsed.pointer("/payload/payload")?;
+    if event.get("name").and_then(Value::as_str) != Some("runtime_request.requested") {
+        return None;
+    }
+
+    let string_field = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| event.get(*key).and_then(Value::as_str))
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(|value| value.chars().take(128).collect::<String>())
+    };
+    Some(PendingRuntimeRequest {
+        kind: string_field(&["runtimeRequestKind", "kind"])
+            .unwrap_or_else(|| "approval".to_string()),
+        request_id: string_field(&["requestID", "requestId"]),
+        thread_id: string_field(&["threadID"]),
+    })
+}
+
+/// Request ID of a desktop `runtime_request.resolved` or `runtime_request.responded` event.
+pub(super) fn resolved_runtime_request_id(parsed: Option<&Value>) -> Option<String> {
+    let parsed = parsed?;
+    if parsed.pointer("/payload/type").and_then(Value::as_str) != Some("event") {
+        return None;
+    }
+    let event = parsed.pointer("/payload/payload")?;
+    if !matches!(
+        event.get("name").and_then(Value::as_str),
+        Some("runtime_request.resolved" | "runtime_request.responded")
+    ) {
+        return None;
+    }
+    ["requestID", "requestId"]
+        .iter()
+        .find_map(|key| event.get(*key).and_then(Value::as_str))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.chars().take(128).collect())
+}
+
+/// Records that the approval webhook is about to fire for `request`. Returns `false` when it
+/// already fired for the same request ID and the request has not been resolved since. Requests
+/// without an ID are always notified.
+pub(super) fn claim_runtime_request_notification(
+    session: &mut SessionRecord,
+    request: &PendingRuntimeRequest,
+) -> bool {
+    let Some(request_id) = request.request_id.as_ref() else {
+        return true;
+    };
+    if session.notified_runtime_requests.contains(request_id) {
+        return false;
+    }
+    // Desktops that never report resolutions would otherwise grow the set without bound.
+    if session.notified_runtime_requests.len() >= MAX_NOTIFIED_RUNTIME_REQUESTS {
+        session.notified_runtime_requests.clear();
+    }
+    session.notified_runtime_requests.insert(request_id.clone());
+    true
+}
+
+/// Deep link into the mobile web app using its hash routes (`#view=thread&tid=...`).
+pub(super) fn runtime_request_deep_link(base_url: &str, thread_id: Option<&str>) -> String {
+    let mut fragment = url::form_urlencoded::Serializer::new(String::new());
+    match thread_id {
+        Some(thread_id) => {
+            fragment.append_pair("view", "thread");
+            fragment.append_pair("tid", thread_id);
+        }
+        None => {
+            fragment.append_pair("view", "home");
+        }
+    }
+    fragment.append_pair("pid", "all");
+
+    let Ok(mut url) = Url::parse(base_url) else {
+        return base_url.to_string();
+    };
+    url.set_fragment(Some(&fragment.finish()));
+    url.to_string()
+}
+
+/// Fires the approval webhook for a runtime request nobody is around to answer. The caller has
+/// already checked this instance's mobile sockets; with the presence registry other instances'
+/// mobiles are checked too. That check is a Redis round trip, so it runs in the spawned task
+/// rather than on the desktop socket loop.
+pub(super) fn notify_pending_runtime_request(
+    state: &SharedRelayState,
+    session_id: &str,
+    request: PendingRuntimeRequest,
+) {
+    if state
+        .webhooks
+        .as_ref()
+        .is_none_or(|webhooks| webhooks.approval.is_none())
+    {
+        return;
+    }
+
+    let state = state.clone();
+    let session_id = session_id.to_string();
+    tokio::spawn(async move {
+        deliver_pending_runtime_request(&state, &session_id, request).await;
+    });
+}
+
+async fn deliver_pending_runtime_request(
+    state: &SharedRelayState,
+    session_id: &str,
+    request: PendingRuntimeRequest,
+) {
+    let Some(webhooks) = state.webhooks.as_ref() else {
+        return;
+    };
+    let Some(approval) = webhooks.approval.as_ref() else {
+        return;
+    };
+    if let Some(presence) = &state.presence {
+        match presence.owners(session_id, "mobile").await {
+            Ok(owners) if !owners.is_empty() => return,
+            Ok(_) => {}
+            Err(error) => {
+                warn!(
+                    "[relay-rs] failed to check mobile presence for approval webhook session={}: {error}",
+                    session_log_id(session_id)
+                );
+            }
+        }
+    }
+
+    let delivery_id = random_token(12);
+    let body = serde_json::to_string(&PendingRuntimeRequestNotification {
+        message_type: "runtime_request.pending",
+        delivery_id: &delivery_id,
+        session_id,
+        request_kind: &request.kind,
+        request_id: request.request_id.as_deref(),
+        thread_id: request.thread_id.as_deref(),
+        deep_link: runtime_request_deep_link(
+            &approval.deep_link_base_url,
+            request.thread_id.as_deref(),
+        ),
+        occurred_at: Utc::now().to_rfc3339(),
+    })
+    .unwrap_or_else(|_| "{}".to_string());
+
+    dispatch_webhook(
+        state,
+        webhooks,
+        &approval.target,
+        "runtime_request.pending",
+        delivery_id,
+        body,
+    );
+}
@@ -1,9 +1,14 @@
 use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
 use std::time::Duration;
 
 use futures_util::{SinkExt, StreamExt};
 use remote_control_relay_rust::config::RelayConfig;
-use remote_control_relay_rust::service::{build_router, new_state};
+use remote_control_relay_rust::service::{
+    build_router, message_schemas, new_state, new_state_with_cross_instance_bus, openapi_document,
+    CrossInstanceBus, InProcessCrossInstanceBus,
+};
 use reqwest::StatusCode;
 use serde_json::{json, Value};
 use tokio::net::TcpListener;
@@ -21,6 +26,13 @@ async fn spawn_test_server() -> (String, JoinHandle<()>) {
 
 async fn spawn_test_server_with_config(
     configure: impl FnOnce(&mut RelayConfig),
+) -> (String, JoinHandle<()>) {
+    spawn_test_server_with_bus(None, configure).await
+}
+
+async fn spawn_test_server_with_bus(
+    bus: Option<Arc<dyn CrossInstanceBus>>,
+    configure: impl FnOnce(&mut RelayConfig),
 ) -> (String, JoinHandle<()>) {
     let listener = TcpListener::bind("127.0.0.1:0")
         .await
@@ -32,9 +44,13 @@ async fn spawn_test_server_with_config(
     config.port = addr.port();
     config.public_base_url = format!("http://{}:{}", addr.ip(), addr.port());
     config.allowed_origins = ["http://localhost:4173".to_string()].into_iter().collect();
+    config.instance_id = Some(format!("test-{}", random_token(8)));
     configure(&mut config);
 
-    let state = new_state(config).await;
+    let state = match bus {
+        Some(bus) => new_state_with_cross_instance_bus(config, bus).await,
+        None => new_state(config).await,
+    };
     let app = build_router(state);
 
     let task = tokio::spawn(async move {
@@ -107,6 +123,57 @@ async fn expect_policy_close(socket: &mut TestSocket, timeout_ms: u64) {
     }
 }
 
+/// Runs `/pair/join` while the desktop approves it, returning the `relay.pair_request` the
+/// desktop saw and the join response payload.
+async fn join_with_desktop_approval(
+    client: &reqwest::Client,
+    base: &str,
+    desktop_socket: &mut TestSocket,
+    session_id: &str,
+    join_token: &str,
+    device_name: &str,
+) -> (Value, Value) {
+    let join_future = tokio::spawn({
+        let client = client.clone();
+        let url = format!("{base}/pair/join");
+        let body = json!({
+            "sessionID": session_id,
+            "joinToken": join_token,
+            "deviceName": device_name,
+        });
+        async move {
+            client
+                .post(url)
+                .header("Origin", "http://localhost:4173")
+                .json(&body)
+                .send()
+                .await
+                .expect("pair join request")
+        }
+    });
+    let pair_request = next_matching_json_message(desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("relay.pair_request")
+    })
+    .await;
+    desktop_socket
+        .send(Message::Text(
+            json!({
+                "type": "relay.pair_decision",
+                "sessionID": session_id,
+                "requestID": pair_request.get("requestID").and_then(Value::as_str),
+                "approved": true,
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("desktop pair decision send");
+
+    let join_response = join_future.await.expect("join task");
+    assert_eq!(join_response.status(), StatusCode::OK);
+    let join_payload: Value = join_response.json().await.expect("join payload");
+    (pair_request, join_payload)
+}
+
 async fn pair_connected_mobile(
     configure: impl FnOnce(&mut RelayConfig),
 ) -> (
@@ -283,6 +350,101 @@ async fn healthz_reports_ok() {
         body.get("activeWebSockets").and_then(Value::as_u64),
         Some(0)
     );
+    assert_eq!(
+        body.get("crossInstanceBusDegraded")
+            .and_then(Value::as_bool),
+        Some(false)
+    );
+
+    task.abort();
+}
+
+/// Compares a served schema document with the copy committed under `schemas/`. Run the tests
+/// with `UPDATE_SCHEMA_SNAPSHOTS=1` to accept an intentional change.
+fn assert_matches_schema_snapshot(file_name: &str, served: &Value) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("schemas")
+        .join(file_name);
+    let rendered = format!(
+        "{}\n",
+        serde_json::to_string_pretty(served).expect("render schema")
+    );
+    if std::env::var_os("UPDATE_SCHEMA_SNAPSHOTS").is_some() {
+        std::fs::write(&path, &rendered).expect("write schema snapshot");
+        return;
+    }
+    let committed = std::fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        committed == rendered,
+        "{} no longer matches the Rust types; rerun with UPDATE_SCHEMA_SNAPSHOTS=1 and commit the result",
+        path.display()
+    );
+}
+
+#[tokio::test]
+async fn served_openapi_and_message_schemas_match_rust_types_and_committed_snapshots() {
+    let (base, task) = spawn_test_server().await;
+
+    let openapi: Value = reqwest::get(format!("{base}/openapi.json"))
+        .await
+        .expect("openapi request")
+        .json()
+        .await
+        .expect("openapi body");
+    assert_eq!(openapi, openapi_document());
+    assert_eq!(openapi["openapi"], "3.1.0");
+    assert_eq!(
+        openapi.pointer("/components/schemas/PairStartRequest/properties/sessionID/type"),
+        Some(&json!("string"))
+    );
+    assert_matches_schema_snapshot("openapi.json", &openapi);
+
+    let schemas: Value = reqwest::get(format!("{base}/schemas"))
+        .await
+        .expect("schemas request")
+        .json()
+        .await
+        .expect("schemas body");
+    assert_eq!(schemas, message_schemas());
+    assert_eq!(
+        schemas.pointer("/outbound/auth_ok/properties/type/const"),
+        Some(&json!("auth_ok"))
+    );
+    assert_matches_schema_snapshot("messages.json", &schemas);
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn every_route_in_openapi_document_is_served() {
+    let admin_token = random_token(32);
+    let configured_admin_token = admin_token.clone();
+    let (base, task) = spawn_test_server_with_config(move |config| {
+        config.admin_api_token = Some(configured_admin_token);
+    })
+    .await;
+    let client = reqwest::Client::new();
+
+    let document = openapi_document();
+    let paths = document["paths"].as_object().expect("openapi paths");
+    assert!(paths.len() >= 20);
+    for (path, operations) in paths {
+        for method in operations.as_object().expect("path operations").keys() {
+            let request = match method.as_str() {
+                "get" => client.get(format!("{base}{path}")),
+                "post" => client.post(format!("{base}{path}")).json(&json!({})),
+                other => panic!("unexpected method {other} for {path}"),
+            };
+            let status = request
+                .bearer_auth(&admin_token)
+                .send()
+                .await
+                .expect("documented route request")
+                .status();
+            assert_ne!(status, StatusCode::NOT_FOUND, "{method} {path}");
+            assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{method} {path}");
+        }
+    }
 
     task.abort();
 }
@@ -391,9 +553,7 @@ async fn metricsz_reports_ws_auth_failure_reasons() {
 
     socket
         .send(Message::Text(
-            json!({ "type": "relay.auth", "token": random_token(32) })
-                .to_string()
-                .into(),
+            json!({ "type": "relay.auth", "token": random_token(32) }).to_string(),
         ))
         .await
         .expect("auth send");
@@ -1111,136 +1271,91 @@ async fn mobile_receives_desktop_offline_status_and_command_rejection() {
 }
 
 #[tokio::test]
-async fn trusted_mobile_reauths_while_desktop_offline_without_repairing() {
-    let (base, task) = spawn_test_server().await;
-    let client = reqwest::Client::new();
-
-    let session_id = random_token(16);
-    let join_token = random_token(32);
-    let desktop_session_token = random_token(32);
-
-    let start_response = client
-        .post(format!("{base}/pair/start"))
-        .json(&json!({
-            "sessionID": session_id,
-            "joinToken": join_token,
-            "desktopSessionToken": desktop_session_token,
-            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
-            "idleTimeoutSeconds": 1800,
-        }))
-        .send()
-        .await
-        .expect("pair start request");
-    assert_eq!(start_response.status(), StatusCode::OK);
-    let start_payload: Value = start_response.json().await.expect("pair start payload");
-    let ws_url = start_payload
-        .get("wsURL")
-        .and_then(Value::as_str)
-        .expect("ws url")
-        .to_string();
+async fn cached_desktop_snapshot_is_served_stale_while_desktop_offline() {
+    let (
+        base,
+        task,
+        mut desktop_socket,
+        mut mobile_socket,
+        session_id,
+        _device_token,
+        rotated_device_token,
+    ) = pair_connected_mobile(|_| {}).await;
 
-    let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url)
-        .await
-        .expect("desktop websocket");
     desktop_socket
         .send(Message::Text(
-            json!({ "type": "relay.auth", "token": desktop_session_token }).to_string(),
+            json!({
+                "schemaVersion": 2,
+                "sessionID": session_id,
+                "seq": 5,
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+                "payload": {
+                    "type": "snapshot",
+                    "payload": {
+                        "projects": [{ "id": "p1", "name": "General" }],
+                        "threads": [],
+                        "messages": [],
+                        "pendingRuntimeRequests": []
+                    }
+                }
+            })
+            .to_string(),
         ))
         .await
-        .expect("desktop auth send");
-    let _desktop_auth = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
-        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+        .expect("desktop snapshot send");
+    let live_snapshot = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
+        payload.pointer("/payload/type").and_then(Value::as_str) == Some("snapshot")
     })
     .await;
+    assert!(live_snapshot.get("relaySnapshotStale").is_none());
 
-    let join_future = tokio::spawn({
-        let client = client.clone();
-        let base = base.clone();
-        let session_id = session_id.clone();
-        let join_token = join_token.clone();
-        async move {
-            client
-                .post(format!("{base}/pair/join"))
-                .header("Origin", "http://localhost:4173")
-                .json(&json!({ "sessionID": session_id, "joinToken": join_token }))
-                .send()
-                .await
-                .expect("pair join request")
-        }
-    });
-
-    let pair_request = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
-        payload.get("type").and_then(Value::as_str) == Some("relay.pair_request")
+    desktop_socket
+        .close(None)
+        .await
+        .expect("desktop websocket close");
+    let _desktop_offline = next_matching_json_message(&mut mobile_socket, 1_500, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("relay.desktop_status")
+            && payload.get("desktopConnected").and_then(Value::as_bool) == Some(false)
     })
     .await;
-    let request_id = pair_request
-        .get("requestID")
-        .and_then(Value::as_str)
-        .expect("request id")
-        .to_string();
-    desktop_socket
+
+    mobile_socket
         .send(Message::Text(
             json!({
-                "type": "relay.pair_decision",
+                "type": "relay.snapshot_request",
                 "sessionID": session_id,
-                "requestID": request_id,
-                "approved": true,
+                "reason": "manual_refresh",
             })
             .to_string(),
         ))
         .await
-        .expect("pair decision send");
-
-    let join_response = join_future.await.expect("join task");
-    assert_eq!(join_response.status(), StatusCode::OK);
-    let join_payload: Value = join_response.json().await.expect("join payload");
-    let device_token = join_payload
-        .get("deviceSessionToken")
-        .and_then(Value::as_str)
-        .expect("device token")
-        .to_string();
-
-    let mut mobile_request = ws_url
-        .clone()
-        .into_client_request()
-        .expect("mobile request");
-    mobile_request.headers_mut().insert(
-        "Origin",
-        "http://localhost:4173".parse().expect("origin header"),
-    );
-    let (mut mobile_socket, _) = tokio_tungstenite::connect_async(mobile_request)
-        .await
-        .expect("mobile websocket");
-    mobile_socket
-        .send(Message::Text(
-            json!({ "type": "relay.auth", "token": device_token }).to_string(),
-        ))
-        .await
-        .expect("mobile auth send");
-    let mobile_auth = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
-        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+        .expect("snapshot request send");
+    let cached_snapshot = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("relay.error")
+            || payload.pointer("/payload/type").and_then(Value::as_str) == Some("snapshot")
     })
     .await;
-    let rotated_token = mobile_auth
-        .get("nextDeviceSessionToken")
-        .and_then(Value::as_str)
-        .expect("rotated token")
-        .to_string();
     assert_eq!(
-        mobile_auth.get("desktopConnected").and_then(Value::as_bool),
+        cached_snapshot
+            .get("relaySnapshotStale")
+            .and_then(Value::as_bool),
         Some(true)
     );
+    assert!(cached_snapshot
+        .get("relaySnapshotCapturedAt")
+        .and_then(Value::as_str)
+        .is_some_and(|captured_at| chrono::DateTime::parse_from_rfc3339(captured_at).is_ok()));
+    assert_eq!(
+        cached_snapshot.pointer("/payload/payload/projects/0/name"),
+        Some(&json!("General"))
+    );
 
     mobile_socket
         .close(None)
         .await
-        .expect("mobile socket close");
-    desktop_socket
-        .close(None)
-        .await
-        .expect("desktop socket close");
+        .expect("mobile websocket close");
 
-    let mut mobile_reconnect_request = ws_url
+    let mut mobile_reconnect_request = (base.replace("http://", "ws://") + "/ws")
         .into_client_request()
         .expect("mobile reconnect request");
     mobile_reconnect_request.headers_mut().insert(
@@ -1253,7 +1368,7 @@ async fn trusted_mobile_reauths_while_desktop_offline_without_repairing() {
             .expect("mobile reconnect websocket");
     mobile_reconnect_socket
         .send(Message::Text(
-            json!({ "type": "relay.auth", "token": rotated_token }).to_string(),
+            json!({ "type": "relay.auth", "token": rotated_device_token }).to_string(),
         ))
         .await
         .expect("mobile reconnect auth send");
@@ -1262,20 +1377,23 @@ async fn trusted_mobile_reauths_while_desktop_offline_without_repairing() {
             payload.get("type").and_then(Value::as_str) == Some("auth_ok")
         })
         .await;
-    assert_eq!(
-        reconnect_auth.get("role").and_then(Value::as_str),
-        Some("mobile")
-    );
     assert_eq!(
         reconnect_auth
             .get("desktopConnected")
             .and_then(Value::as_bool),
         Some(false)
     );
-    assert!(reconnect_auth
-        .get("nextDeviceSessionToken")
-        .and_then(Value::as_str)
-        .is_some());
+    let reconnect_snapshot =
+        next_matching_json_message(&mut mobile_reconnect_socket, 1_000, |payload| {
+            payload.pointer("/payload/type").and_then(Value::as_str) == Some("snapshot")
+        })
+        .await;
+    assert_eq!(
+        reconnect_snapshot
+            .get("relaySnapshotStale")
+            .and_then(Value::as_bool),
+        Some(true)
+    );
 
     mobile_reconnect_socket
         .close(None)
@@ -1284,64 +1402,247 @@ async fn trusted_mobile_reauths_while_desktop_offline_without_repairing() {
     task.abort();
 }
 
+#[derive(Clone, Default)]
+struct WebhookStub {
+    deliveries: Arc<tokio::sync::Mutex<Vec<(axum::http::HeaderMap, String)>>>,
+    failures_remaining: Arc<std::sync::atomic::AtomicUsize>,
+}
+
+async fn spawn_webhook_stub(failures: usize) -> (String, WebhookStub, JoinHandle<()>) {
+    let stub = WebhookStub::default();
+    stub.failures_remaining
+        .store(failures, std::sync::atomic::Ordering::SeqCst);
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind webhook listener");
+    let addr = listener.local_addr().expect("webhook local addr");
+    let app = axum::Router::new()
+        .route(
+            "/hook",
+            axum::routing::post(
+                |axum::extract::State(stub): axum::extract::State<WebhookStub>,
+                 headers: axum::http::HeaderMap,
+                 body: String| async move {
+                    stub.deliveries.lock().await.push((headers, body));
+                    let should_fail = stub
+                        .failures_remaining
+                        .fetch_update(
+                            std::sync::atomic::Ordering::SeqCst,
+                            std::sync::atomic::Ordering::SeqCst,
+                            |remaining| remaining.checked_sub(1),
+                        )
+                        .is_ok();
+                    if should_fail {
+                        axum::http::StatusCode::SERVICE_UNAVAILABLE
+                    } else {
+                        axum::http::StatusCode::OK
+                    }
+                },
+            ),
+        )
+        .with_state(stub.clone());
+    let task = tokio::spawn(async move {
+        axum::serve(listener, app)
+            .await
+            .expect("serve webhook stub");
+    });
+    (format!("http://{addr}/hook"), stub, task)
+}
+
+fn expected_webhook_signature(secret: &str, timestamp: &str, body: &str) -> String {
+    use base64::Engine;
+    use hmac::Mac;
+
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).expect("hmac key");
+    mac.update(format!("{timestamp}.{body}").as_bytes());
+    format!(
+        "v1={}",
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    )
+}
+
 #[tokio::test]
-async fn stale_mobile_device_token_emits_session_expired_disconnect_and_policy_close() {
+async fn pending_runtime_request_without_mobiles_fires_signed_approval_webhook_with_retry() {
+    let (webhook_url, stub, webhook_task) = spawn_webhook_stub(1).await;
+    let secret = random_token(32);
+    let configured_secret = secret.clone();
     let (
-        base,
+        _base,
         task,
-        _desktop_socket,
+        mut desktop_socket,
         mut mobile_socket,
-        _session_id,
-        first_device_token,
-        _rotated_device_token,
-    ) = pair_connected_mobile(|config| {
-        config.token_rotation_grace_ms = 0;
+        session_id,
+        _device_token,
+        _rotated_device_token,
+    ) = pair_connected_mobile(move |config| {
+        config.approval_webhook_url = Some(webhook_url);
+        config.approval_webhook_secret = Some(configured_secret);
+        config.approval_webhook_deep_link_base_url =
+            Some("https://remote.example.com/app".to_string());
+        config.webhook_initial_backoff_ms = 10;
+    })
+    .await;
+
+    let runtime_request_event = |request_id: &str| {
+        json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "seq": 9,
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "payload": {
+                "type": "event",
+                "payload": {
+                    "name": "runtime_request.requested",
+                    "threadID": "thread-42",
+                    "requestID": request_id,
+                    "body": "Run tests?"
+                }
+            }
+        })
+        .to_string()
+    };
+
+    desktop_socket
+        .send(Message::Text(runtime_request_event("req-attended")))
+        .await
+        .expect("attended runtime request send");
+    let _forwarded = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
+        payload
+            .pointer("/payload/payload/name")
+            .and_then(Value::as_str)
+            == Some("runtime_request.requested")
     })
     .await;
 
     mobile_socket
         .close(None)
         .await
-        .expect("mobile socket close");
+        .expect("mobile websocket close");
+    let _no_mobiles = next_matching_json_message(&mut desktop_socket, 1_500, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("relay.device_count")
+            && payload.get("connectedDeviceCount").and_then(Value::as_u64) == Some(0)
+    })
+    .await;
 
-    let ws_url = format!("{base}/ws").replace("http://", "ws://");
-    let mut stale_reconnect_request = ws_url
-        .into_client_request()
-        .expect("stale reconnect request");
-    stale_reconnect_request.headers_mut().insert(
-        "Origin",
-        "http://localhost:4173".parse().expect("origin header"),
+    desktop_socket
+        .send(Message::Text(runtime_request_event("req-unattended")))
+        .await
+        .expect("unattended runtime request send");
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(3);
+    let deliveries = loop {
+        let deliveries = stub.deliveries.lock().await.clone();
+        if deliveries.len() >= 2 || tokio::time::Instant::now() >= deadline {
+            break deliveries;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    };
+    assert_eq!(deliveries.len(), 2, "one failed attempt plus one retry");
+
+    let header = |headers: &axum::http::HeaderMap, name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .expect("webhook header")
+            .to_string()
+    };
+    assert_eq!(
+        header(&deliveries[0].0, "x-relay-delivery"),
+        header(&deliveries[1].0, "x-relay-delivery")
     );
-    let (mut stale_reconnect_socket, _) = tokio_tungstenite::connect_async(stale_reconnect_request)
+    for (headers, body) in &deliveries {
+        assert_eq!(
+            header(headers, "x-relay-signature"),
+            expected_webhook_signature(&secret, &header(headers, "x-relay-timestamp"), body)
+        );
+    }
+
+    let notification: Value = serde_json::from_str(&deliveries[1].1).expect("webhook body json");
+    assert_eq!(notification["type"], "runtime_request.pending");
+    assert_eq!(notification["sessionID"], json!(session_id));
+    assert_eq!(notification["requestKind"], "approval");
+    assert_eq!(notification["requestID"], "req-unattended");
+    assert_eq!(
+        notification["deepLink"],
+        "https://remote.example.com/app#view=thread&tid=thread-42&pid=all"
+    );
+
+    desktop_socket
+        .send(Message::Text(runtime_request_event("req-unattended")))
         .await
-        .expect("stale reconnect websocket");
-    stale_reconnect_socket
+        .expect("duplicate runtime request send");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(
+        stub.deliveries.lock().await.len(),
+        2,
+        "re-sent request with the same requestID must not notify again"
+    );
+
+    desktop_socket
         .send(Message::Text(
-            json!({ "type": "relay.auth", "token": first_device_token }).to_string(),
+            json!({
+                "schemaVersion": 2,
+                "sessionID": session_id,
+                "seq": 10,
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+                "payload": {
+                    "type": "event",
+                    "payload": {
+                        "name": "runtime_request.resolved",
+                        "threadID": "thread-42",
+                        "requestID": "req-unattended"
+                    }
+                }
+            })
+            .to_string(),
         ))
         .await
-        .expect("stale reconnect auth send");
+        .expect("resolved runtime request send");
+    desktop_socket
+        .send(Message::Text(runtime_request_event("req-unattended")))
+        .await
+        .expect("re-raised runtime request send");
 
-    expect_disconnect_with_reason(&mut stale_reconnect_socket, 1_000, "session_expired").await;
-    expect_policy_close(&mut stale_reconnect_socket, 1_000).await;
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(3);
+    let deliveries = loop {
+        let deliveries = stub.deliveries.lock().await.clone();
+        if deliveries.len() >= 3 || tokio::time::Instant::now() >= deadline {
+            break deliveries;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    };
+    assert_eq!(
+        deliveries.len(),
+        3,
+        "a resolved request notifies again when re-raised"
+    );
 
     task.abort();
+    webhook_task.abort();
 }
 
 #[tokio::test]
-async fn pair_stop_closes_session_and_invalidates_join() {
-    let (base, task) = spawn_test_server().await;
+async fn lifecycle_webhooks_are_signed_and_failed_deliveries_are_dead_lettered() {
+    let (healthy_url, healthy_stub, healthy_task) = spawn_webhook_stub(0).await;
+    let (failing_url, failing_stub, failing_task) = spawn_webhook_stub(usize::MAX).await;
+    let secret = random_token(32);
+    let configured_secret = secret.clone();
+    let (base, task) = spawn_test_server_with_config(move |config| {
+        config.lifecycle_webhook_urls = vec![healthy_url, failing_url];
+        config.lifecycle_webhook_secret = Some(configured_secret);
+        config.webhook_max_attempts = 2;
+        config.webhook_initial_backoff_ms = 10;
+    })
+    .await;
     let client = reqwest::Client::new();
 
     let session_id = random_token(16);
-    let join_token = random_token(32);
     let desktop_session_token = random_token(32);
-
     let start_response = client
         .post(format!("{base}/pair/start"))
         .json(&json!({
             "sessionID": session_id,
-            "joinToken": join_token,
+            "joinToken": random_token(32),
             "desktopSessionToken": desktop_session_token,
             "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
             "idleTimeoutSeconds": 1800,
@@ -1350,7 +1651,6 @@ async fn pair_stop_closes_session_and_invalidates_join() {
         .await
         .expect("pair start request");
     assert_eq!(start_response.status(), StatusCode::OK);
-
     let stop_response = client
         .post(format!("{base}/pair/stop"))
         .json(&json!({
@@ -1363,23 +1663,174 @@ async fn pair_stop_closes_session_and_invalidates_join() {
         .expect("pair stop request");
     assert_eq!(stop_response.status(), StatusCode::OK);
 
-    let join_response = client
-        .post(format!("{base}/pair/join"))
-        .header("Origin", "http://localhost:4173")
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(3);
+    let metrics = loop {
+        let metrics = client
+            .get(format!("{base}/metricsz"))
+            .send()
+            .await
+            .expect("metricsz request")
+            .json::<Value>()
+            .await
+            .expect("metricsz json");
+        let settled = metrics["webhookDeliveriesSucceeded"].as_u64() == Some(2)
+            && metrics["webhookDeliveriesDeadLettered"].as_u64() == Some(2);
+        if settled || tokio::time::Instant::now() >= deadline {
+            break metrics;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    };
+    assert_eq!(metrics["webhookDeliveriesSucceeded"], 2);
+    assert_eq!(metrics["webhookDeliveriesDeadLettered"], 2);
+    assert_eq!(metrics["webhookDeliveryAttempts"], 6);
+    assert_eq!(
+        metrics["webhookDeadLettersByEvent"],
+        json!({ "session_started": 1, "session_stopped": 1 })
+    );
+    assert_eq!(failing_stub.deliveries.lock().await.len(), 4);
+
+    let deliveries = healthy_stub.deliveries.lock().await.clone();
+    let mut events = Vec::new();
+    for (headers, body) in &deliveries {
+        let timestamp = headers
+            .get("x-relay-timestamp")
+            .and_then(|value| value.to_str().ok())
+            .expect("timestamp header");
+        assert_eq!(
+            headers
+                .get("x-relay-signature")
+                .and_then(|value| value.to_str().ok()),
+            Some(expected_webhook_signature(&secret, timestamp, body).as_str())
+        );
+        let event: Value = serde_json::from_str(body).expect("lifecycle body json");
+        assert_eq!(event["sessionID"], json!(session_id));
+        events.push(event);
+    }
+    events.sort_by_key(|event| event["type"].as_str().unwrap_or_default().to_string());
+    assert_eq!(events[0]["type"], "session_started");
+    assert_eq!(events[1]["type"], "session_stopped");
+    assert_eq!(events[1]["reason"], "stopped_by_desktop");
+
+    task.abort();
+    healthy_task.abort();
+    failing_task.abort();
+}
+
+#[tokio::test]
+async fn lifecycle_close_event_is_sent_once_by_the_instance_that_closed_the_session() {
+    let bus: Arc<dyn CrossInstanceBus> = Arc::new(InProcessCrossInstanceBus::new());
+    let (webhook_url, webhook_stub, webhook_task) = spawn_webhook_stub(0).await;
+    let secret = random_token(32);
+    let configure = |config: &mut RelayConfig| {
+        config.lifecycle_webhook_urls = vec![webhook_url.clone()];
+        config.lifecycle_webhook_secret = Some(secret.clone());
+    };
+    let (base_a, task_a) = spawn_test_server_with_bus(Some(bus.clone()), configure).await;
+    let (base_b, task_b) = spawn_test_server_with_bus(Some(bus.clone()), configure).await;
+    let client = reqwest::Client::new();
+
+    let session_id = random_token(16);
+    let join_token = random_token(32);
+    let desktop_session_token = random_token(32);
+    for base in [&base_a, &base_b] {
+        let start_response = client
+            .post(format!("{base}/pair/start"))
+            .json(&json!({
+                "sessionID": session_id,
+                "joinToken": join_token,
+                "desktopSessionToken": desktop_session_token,
+                "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+                "idleTimeoutSeconds": 1800,
+            }))
+            .send()
+            .await
+            .expect("pair start request");
+        assert_eq!(start_response.status(), StatusCode::OK);
+    }
+
+    let ws_url_a = base_a.replace("http://", "ws://") + "/ws";
+    let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url_a)
+        .await
+        .expect("desktop websocket");
+    desktop_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": desktop_session_token }).to_string(),
+        ))
+        .await
+        .expect("desktop auth send");
+    next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+
+    let (_, join_payload) = join_with_desktop_approval(
+        &client,
+        &base_b,
+        &mut desktop_socket,
+        &session_id,
+        &join_token,
+        "Other Pod iPhone",
+    )
+    .await;
+    let device_token = join_payload
+        .get("deviceSessionToken")
+        .and_then(Value::as_str)
+        .expect("device token")
+        .to_string();
+    let mut mobile_request = (base_b.replace("http://", "ws://") + "/ws")
+        .into_client_request()
+        .expect("mobile request");
+    mobile_request.headers_mut().insert(
+        "Origin",
+        "http://localhost:4173".parse().expect("origin header"),
+    );
+    let (mut mobile_socket, _) = tokio_tungstenite::connect_async(mobile_request)
+        .await
+        .expect("mobile websocket");
+    mobile_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": device_token }).to_string(),
+        ))
+        .await
+        .expect("mobile auth send");
+    next_matching_json_message(&mut mobile_socket, 1_500, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+
+    let stop_response = client
+        .post(format!("{base_a}/pair/stop"))
         .json(&json!({
+            "schemaVersion": 2,
             "sessionID": session_id,
-            "joinToken": join_token,
+            "desktopSessionToken": desktop_session_token,
         }))
         .send()
         .await
-        .expect("pair join request");
-    assert_eq!(join_response.status(), StatusCode::NOT_FOUND);
+        .expect("pair stop request");
+    assert_eq!(stop_response.status(), StatusCode::OK);
 
-    task.abort();
+    expect_disconnect_with_reason(&mut mobile_socket, 1_500, "stopped_by_desktop").await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let stopped_events = webhook_stub
+        .deliveries
+        .lock()
+        .await
+        .iter()
+        .map(|(_, body)| serde_json::from_str::<Value>(body).expect("lifecycle body json"))
+        .filter(|event| event["type"] == "session_stopped")
+        .collect::<Vec<_>>();
+    assert_eq!(stopped_events.len(), 1);
+    assert_eq!(stopped_events[0]["reason"], "stopped_by_desktop");
+
+    task_b.abort();
+    task_a.abort();
+    webhook_task.abort();
 }
 
 #[tokio::test]
-async fn pair_stop_forces_repair_by_rejecting_trusted_device_reauth() {
+async fn trusted_mobile_reauths_while_desktop_offline_without_repairing() {
     let (base, task) = spawn_test_server().await;
     let client = reqwest::Client::new();
 
@@ -1390,7 +1841,6 @@ async fn pair_stop_forces_repair_by_rejecting_trusted_device_reauth() {
     let start_response = client
         .post(format!("{base}/pair/start"))
         .json(&json!({
-            "schemaVersion": 2,
             "sessionID": session_id,
             "joinToken": join_token,
             "desktopSessionToken": desktop_session_token,
@@ -1495,27 +1945,20 @@ async fn pair_stop_forces_repair_by_rejecting_trusted_device_reauth() {
         .and_then(Value::as_str)
         .expect("rotated token")
         .to_string();
-
-    let stop_response = client
-        .post(format!("{base}/pair/stop"))
-        .json(&json!({
-            "sessionID": session_id,
-            "desktopSessionToken": desktop_session_token,
-        }))
-        .send()
-        .await
-        .expect("pair stop request");
-    assert_eq!(stop_response.status(), StatusCode::OK);
-
-    let stopped_disconnect = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
-        payload.get("type").and_then(Value::as_str) == Some("disconnect")
-    })
-    .await;
     assert_eq!(
-        stopped_disconnect.get("reason").and_then(Value::as_str),
-        Some("stopped_by_desktop")
+        mobile_auth.get("desktopConnected").and_then(Value::as_bool),
+        Some(true)
     );
 
+    mobile_socket
+        .close(None)
+        .await
+        .expect("mobile socket close");
+    desktop_socket
+        .close(None)
+        .await
+        .expect("desktop socket close");
+
     let mut mobile_reconnect_request = ws_url
         .into_client_request()
         .expect("mobile reconnect request");
@@ -1533,251 +1976,180 @@ async fn pair_stop_forces_repair_by_rejecting_trusted_device_reauth() {
         ))
         .await
         .expect("mobile reconnect auth send");
-    let reconnect_rejected = tokio::time::timeout(Duration::from_millis(1_000), async {
-        while let Some(frame) = mobile_reconnect_socket.next().await {
-            match frame {
-                Ok(Message::Text(raw)) => {
-                    let payload: Value =
-                        serde_json::from_str(raw.as_ref()).unwrap_or_else(|_| json!({}));
-                    if payload.get("type").and_then(Value::as_str) == Some("auth_ok") {
-                        return false;
-                    }
-                    if payload.get("type").and_then(Value::as_str) == Some("disconnect") {
-                        return true;
-                    }
-                }
-                Ok(Message::Close(_)) | Err(_) => return true,
-                _ => {}
-            }
-        }
-        true
-    })
-    .await
-    .expect("expected reconnect attempt to resolve");
-    assert!(
-        reconnect_rejected,
-        "stopped session should require re-pair and reject trusted-device reauth"
+    let reconnect_auth =
+        next_matching_json_message(&mut mobile_reconnect_socket, 1_000, |payload| {
+            payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+        })
+        .await;
+    assert_eq!(
+        reconnect_auth.get("role").and_then(Value::as_str),
+        Some("mobile")
+    );
+    assert_eq!(
+        reconnect_auth
+            .get("desktopConnected")
+            .and_then(Value::as_bool),
+        Some(false)
     );
+    assert!(reconnect_auth
+        .get("nextDeviceSessionToken")
+        .and_then(Value::as_str)
+        .is_some());
 
+    mobile_reconnect_socket
+        .close(None)
+        .await
+        .expect("mobile reconnect close");
     task.abort();
 }
 
 #[tokio::test]
-async fn stale_desktop_session_token_emits_session_expired_disconnect_and_policy_close() {
-    let (base, task) = spawn_test_server().await;
-    let client = reqwest::Client::new();
-
-    let session_id = random_token(16);
-    let join_token = random_token(32);
-    let desktop_session_token = random_token(32);
-
-    let start_response = client
-        .post(format!("{base}/pair/start"))
-        .json(&json!({
-            "schemaVersion": 2,
-            "sessionID": session_id,
-            "joinToken": join_token,
-            "desktopSessionToken": desktop_session_token,
-            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
-            "idleTimeoutSeconds": 1800,
-        }))
-        .send()
-        .await
-        .expect("pair start request");
-    assert_eq!(start_response.status(), StatusCode::OK);
-    let start_payload: Value = start_response.json().await.expect("pair start payload");
-    let ws_url = start_payload
-        .get("wsURL")
-        .and_then(Value::as_str)
-        .expect("ws url")
-        .to_string();
+async fn stale_mobile_device_token_emits_session_expired_disconnect_and_policy_close() {
+    let (
+        base,
+        task,
+        _desktop_socket,
+        mut mobile_socket,
+        _session_id,
+        first_device_token,
+        _rotated_device_token,
+    ) = pair_connected_mobile(|config| {
+        config.token_rotation_grace_ms = 0;
+    })
+    .await;
 
-    let stop_response = client
-        .post(format!("{base}/pair/stop"))
-        .json(&json!({
-            "schemaVersion": 2,
-            "sessionID": session_id,
-            "desktopSessionToken": desktop_session_token,
-        }))
-        .send()
+    mobile_socket
+        .close(None)
         .await
-        .expect("pair stop request");
-    assert_eq!(stop_response.status(), StatusCode::OK);
+        .expect("mobile socket close");
 
-    let (mut stale_desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url)
+    let ws_url = format!("{base}/ws").replace("http://", "ws://");
+    let mut stale_reconnect_request = ws_url
+        .into_client_request()
+        .expect("stale reconnect request");
+    stale_reconnect_request.headers_mut().insert(
+        "Origin",
+        "http://localhost:4173".parse().expect("origin header"),
+    );
+    let (mut stale_reconnect_socket, _) = tokio_tungstenite::connect_async(stale_reconnect_request)
         .await
-        .expect("stale desktop websocket");
-    stale_desktop_socket
+        .expect("stale reconnect websocket");
+    stale_reconnect_socket
         .send(Message::Text(
-            json!({ "type": "relay.auth", "token": desktop_session_token }).to_string(),
+            json!({ "type": "relay.auth", "token": first_device_token }).to_string(),
         ))
         .await
-        .expect("stale desktop auth send");
+        .expect("stale reconnect auth send");
 
-    expect_disconnect_with_reason(&mut stale_desktop_socket, 1_000, "session_expired").await;
-    expect_policy_close(&mut stale_desktop_socket, 1_000).await;
+    expect_disconnect_with_reason(&mut stale_reconnect_socket, 1_000, "session_expired").await;
+    expect_policy_close(&mut stale_reconnect_socket, 1_000).await;
 
     task.abort();
 }
 
 #[tokio::test]
-async fn pair_refresh_rotates_join_token_without_stopping_session() {
-    let (base, task) = spawn_test_server().await;
-    let client = reqwest::Client::new();
-
-    let session_id = random_token(16);
-    let original_join_token = random_token(32);
-    let refreshed_join_token = random_token(32);
-    let desktop_session_token = random_token(32);
-
-    let start_response = client
-        .post(format!("{base}/pair/start"))
-        .json(&json!({
-            "schemaVersion": 2,
-            "sessionID": session_id,
-            "joinToken": original_join_token,
-            "desktopSessionToken": desktop_session_token,
-            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
-            "idleTimeoutSeconds": 1800,
-        }))
-        .send()
-        .await
-        .expect("pair start request");
-    assert_eq!(start_response.status(), StatusCode::OK);
-    let start_payload: Value = start_response.json().await.expect("pair start payload");
-    let ws_url = start_payload
-        .get("wsURL")
-        .and_then(Value::as_str)
-        .expect("ws url")
-        .to_string();
+async fn signed_device_tokens_authenticate_without_index_and_revoke_prior_generation() {
+    let (
+        base,
+        task,
+        _desktop_socket,
+        mut mobile_socket,
+        _session_id,
+        first_device_token,
+        rotated_device_token,
+    ) = pair_connected_mobile(|config| {
+        config.token_rotation_grace_ms = 0;
+        config.device_token_signing_keys = vec![(
+            "k1".to_string(),
+            "integration-signing-secret-0123456789".to_string(),
+        )];
+        config.device_token_signing_key_id = Some("k1".to_string());
+    })
+    .await;
+    assert!(first_device_token.starts_with("rdt1.k1."));
+    assert!(rotated_device_token.starts_with("rdt1.k1."));
 
-    let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url)
-        .await
-        .expect("desktop websocket");
-    desktop_socket
-        .send(Message::Text(
-            json!({ "type": "relay.auth", "token": desktop_session_token }).to_string(),
-        ))
-        .await
-        .expect("desktop auth send");
-    let _desktop_auth = desktop_socket
-        .next()
+    let metrics: Value = reqwest::get(format!("{base}/metricsz"))
         .await
-        .expect("desktop auth message")
-        .expect("desktop auth frame");
-
-    let refresh_response = client
-        .post(format!("{base}/pair/refresh"))
-        .json(&json!({
-            "schemaVersion": 2,
-            "sessionID": session_id,
-            "joinToken": refreshed_join_token,
-            "desktopSessionToken": desktop_session_token,
-            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
-        }))
-        .send()
+        .expect("metricsz request")
+        .json()
         .await
-        .expect("pair refresh request");
-    assert_eq!(refresh_response.status(), StatusCode::OK);
+        .expect("metricsz body");
+    assert_eq!(metrics.get("deviceTokens").and_then(Value::as_u64), Some(0));
 
-    let old_join_response = client
-        .post(format!("{base}/pair/join"))
-        .header("Origin", "http://localhost:4173")
-        .json(&json!({
-            "sessionID": session_id,
-            "joinToken": original_join_token,
-        }))
-        .send()
+    mobile_socket
+        .close(None)
         .await
-        .expect("pair join old token");
-    assert_eq!(old_join_response.status(), StatusCode::FORBIDDEN);
+        .expect("mobile socket close");
 
-    let join_future = tokio::spawn({
-        let client = client.clone();
-        let base = base.clone();
-        let session_id = session_id.clone();
-        let refreshed_join_token = refreshed_join_token.clone();
-        async move {
-            client
-                .post(format!("{base}/pair/join"))
-                .header("Origin", "http://localhost:4173")
-                .json(&json!({
-                    "sessionID": session_id,
-                    "joinToken": refreshed_join_token,
-                }))
-                .send()
-                .await
-                .expect("pair join refreshed token")
-        }
-    });
+    let ws_url = format!("{base}/ws").replace("http://", "ws://");
+    let connect_mobile = || async {
+        let mut request = ws_url
+            .clone()
+            .into_client_request()
+            .expect("mobile reconnect request");
+        request.headers_mut().insert(
+            "Origin",
+            "http://localhost:4173".parse().expect("origin header"),
+        );
+        let (socket, _) = tokio_tungstenite::connect_async(request)
+            .await
+            .expect("mobile reconnect websocket");
+        socket
+    };
 
-    let pair_request = desktop_socket
-        .next()
+    let mut stale_socket = connect_mobile().await;
+    stale_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": first_device_token }).to_string(),
+        ))
         .await
-        .expect("pair request frame")
-        .expect("pair request message");
-    let pair_request_json: Value =
-        serde_json::from_str(pair_request.to_text().expect("pair request text"))
-            .expect("pair request json");
-    let request_id = pair_request_json
-        .get("requestID")
-        .and_then(Value::as_str)
-        .expect("requestID")
-        .to_string();
+        .expect("stale auth send");
+    expect_disconnect_with_reason(&mut stale_socket, 1_000, "session_expired").await;
 
-    desktop_socket
+    let mut reconnect_socket = connect_mobile().await;
+    reconnect_socket
         .send(Message::Text(
-            json!({
-                "type": "relay.pair_decision",
-                "sessionID": session_id,
-                "requestID": request_id,
-                "approved": true,
-            })
-            .to_string(),
+            json!({ "type": "relay.auth", "token": rotated_device_token }).to_string(),
         ))
         .await
-        .expect("desktop pair decision send");
-
-    let join_response = join_future.await.expect("join task");
-    assert_eq!(join_response.status(), StatusCode::OK);
-
-    task.abort();
-}
-
-#[tokio::test]
-async fn pairing_endpoints_include_cors_headers_for_allowed_origin() {
-    let (base, task) = spawn_test_server().await;
-    let client = reqwest::Client::new();
+        .expect("rotated auth send");
+    let auth_ok = next_matching_json_message(&mut reconnect_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+    assert!(auth_ok
+        .get("nextDeviceSessionToken")
+        .and_then(Value::as_str)
+        .is_some_and(|token| token.starts_with("rdt1.k1.")));
 
-    let response = client
-        .request(reqwest::Method::OPTIONS, format!("{base}/pair/join"))
-        .header("Origin", "http://localhost:4173")
-        .header("Access-Control-Request-Method", "POST")
-        .send()
+    let metrics: Value = reqwest::get(format!("{base}/metricsz"))
         .await
-        .expect("options request");
-
-    assert!(response.status().is_success());
+        .expect("metricsz request")
+        .json()
+        .await
+        .expect("metricsz body");
     assert_eq!(
-        response
-            .headers()
-            .get("access-control-allow-origin")
-            .and_then(|value| value.to_str().ok()),
-        Some("http://localhost:4173")
+        metrics
+            .pointer("/wsAuthFailureReasons/signed_token_revoked")
+            .and_then(Value::as_u64),
+        Some(1)
     );
 
     task.abort();
 }
 
 #[tokio::test]
-async fn devices_list_and_revoke_remove_trusted_device() {
-    let (base, task) = spawn_test_server().await;
+async fn pair_start_returns_pairing_uri_and_pair_qr_renders_it_for_the_desktop() {
+    let (base, task) = spawn_test_server_with_config(|config| {
+        config.pairing_join_base_url = "https://remote.example.com/".to_string();
+    })
+    .await;
     let client = reqwest::Client::new();
 
     let session_id = random_token(16);
     let join_token = random_token(32);
     let desktop_session_token = random_token(32);
-
     let start_response = client
         .post(format!("{base}/pair/start"))
         .json(&json!({
@@ -1785,246 +2157,547 @@ async fn devices_list_and_revoke_remove_trusted_device() {
             "joinToken": join_token,
             "desktopSessionToken": desktop_session_token,
             "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
-            "idleTimeoutSeconds": 1800,
         }))
         .send()
         .await
         .expect("pair start request");
     assert_eq!(start_response.status(), StatusCode::OK);
-    let start_payload: Value = start_response.json().await.expect("pair start payload");
-    let ws_url = start_payload
-        .get("wsURL")
-        .and_then(Value::as_str)
-        .expect("ws url")
-        .to_string();
-
-    let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url)
+    let start_body = start_response
+        .json::<Value>()
         .await
-        .expect("desktop websocket");
-    desktop_socket
-        .send(Message::Text(
-            json!({ "type": "relay.auth", "token": desktop_session_token }).to_string(),
-        ))
-        .await
-        .expect("desktop auth send");
-    let _desktop_auth = desktop_socket
-        .next()
-        .await
-        .expect("desktop auth message")
-        .expect("desktop auth frame");
+        .expect("pair start json");
+    let pairing_uri = start_body["pairingURI"].as_str().expect("pairingURI");
+    let expected_relay = base.replace(':', "%3A").replace('/', "%2F");
+    assert_eq!(
+        pairing_uri,
+        format!(
+            "https://remote.example.com/#sid={session_id}&jt={join_token}&relay={expected_relay}"
+        )
+    );
 
-    let join_future = tokio::spawn({
-        let client = client.clone();
-        let base = base.clone();
-        let session_id = session_id.clone();
-        let join_token = join_token.clone();
-        async move {
-            client
-                .post(format!("{base}/pair/join"))
-                .header("Origin", "http://localhost:4173")
-                .json(&json!({
-                    "sessionID": session_id,
-                    "joinToken": join_token,
-                    "deviceName": "Bikram iPhone"
-                }))
-                .send()
-                .await
-                .expect("pair join request")
-        }
-    });
-
-    let pair_request = desktop_socket
-        .next()
-        .await
-        .expect("pair request frame")
-        .expect("pair request message");
-    let pair_request_json: Value =
-        serde_json::from_str(pair_request.to_text().expect("pair request text"))
-            .expect("pair request json");
-    let request_id = pair_request_json
-        .get("requestID")
-        .and_then(Value::as_str)
-        .expect("requestID")
-        .to_string();
-
-    desktop_socket
-        .send(Message::Text(
-            json!({
-                "type": "relay.pair_decision",
+    for (format, content_type) in [("svg", "image/svg+xml"), ("png", "image/png")] {
+        let qr_response = client
+            .post(format!("{base}/pair/qr"))
+            .json(&json!({
                 "sessionID": session_id,
-                "requestID": request_id,
-                "approved": true,
-            })
-            .to_string(),
-        ))
+                "desktopSessionToken": desktop_session_token,
+                "format": format,
+            }))
+            .send()
+            .await
+            .expect("pair qr request");
+        assert_eq!(qr_response.status(), StatusCode::OK);
+        assert_eq!(
+            qr_response
+                .headers()
+                .get("content-type")
+                .and_then(|value| value.to_str().ok()),
+            Some(content_type)
+        );
+        assert_eq!(
+            qr_response
+                .headers()
+                .get("cache-control")
+                .and_then(|value| value.to_str().ok()),
+            Some("no-store")
+        );
+        assert!(!qr_response.bytes().await.expect("qr body").is_empty());
+    }
+
+    let forbidden_response = client
+        .post(format!("{base}/pair/qr"))
+        .json(&json!({
+            "sessionID": session_id,
+            "desktopSessionToken": random_token(32),
+        }))
+        .send()
         .await
-        .expect("desktop pair decision send");
+        .expect("pair qr forbidden request");
+    assert_eq!(forbidden_response.status(), StatusCode::FORBIDDEN);
 
-    let join_response = join_future.await.expect("join task");
-    assert_eq!(join_response.status(), StatusCode::OK);
-    let join_payload: Value = join_response.json().await.expect("join payload");
-    let device_id = join_payload
-        .get("deviceID")
-        .and_then(Value::as_str)
-        .expect("deviceID")
-        .to_string();
-    let device_session_token = join_payload
-        .get("deviceSessionToken")
-        .and_then(Value::as_str)
-        .expect("deviceSessionToken")
-        .to_string();
+    task.abort();
+}
 
-    let mut mobile_request = ws_url
-        .clone()
-        .into_client_request()
-        .expect("mobile request");
-    mobile_request.headers_mut().insert(
-        "Origin",
-        "http://localhost:4173".parse().expect("origin header"),
-    );
-    let (mut mobile_socket, _) = tokio_tungstenite::connect_async(mobile_request)
-        .await
-        .expect("mobile websocket");
-    mobile_socket
-        .send(Message::Text(
-            json!({ "type": "relay.auth", "token": device_session_token }).to_string(),
-        ))
-        .await
-        .expect("mobile auth send");
-    let mobile_auth = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
-        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+#[tokio::test]
+async fn repeated_bad_desktop_tokens_on_pair_qr_put_ip_in_penalty_box() {
+    let (base, task) = spawn_test_server_with_config(|config| {
+        config.penalty_box_failure_threshold = 3;
+        config.penalty_box_subnet_failure_threshold = 0;
+        config.penalty_box_base_block_ms = 60_000;
     })
     .await;
-    let rotated_device_token = mobile_auth
-        .get("nextDeviceSessionToken")
-        .and_then(Value::as_str)
-        .expect("rotated device token")
-        .to_string();
+    let client = reqwest::Client::new();
 
-    let rejected_list_response = client
-        .post(format!("{base}/devices/list"))
-        .header("Origin", "https://evil.example")
+    let session_id = random_token(16);
+    let start_response = client
+        .post(format!("{base}/pair/start"))
         .json(&json!({
-            "schemaVersion": 2,
             "sessionID": session_id,
-            "desktopSessionToken": desktop_session_token,
+            "joinToken": random_token(32),
+            "desktopSessionToken": random_token(32),
+            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
         }))
         .send()
         .await
-        .expect("devices list disallowed origin");
-    assert_eq!(rejected_list_response.status(), StatusCode::FORBIDDEN);
-    let rejected_list_payload: Value = rejected_list_response
+        .expect("pair start request");
+    assert_eq!(start_response.status(), StatusCode::OK);
+
+    let bad_qr = || {
+        client
+            .post(format!("{base}/pair/qr"))
+            .json(&json!({
+                "sessionID": session_id,
+                "desktopSessionToken": random_token(32),
+            }))
+            .send()
+    };
+
+    for _ in 0..3 {
+        let response = bad_qr().await.expect("pair qr request");
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    let mut blocked = None;
+    for _ in 0..50 {
+        let response = bad_qr().await.expect("pair qr request");
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            blocked = Some(response);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let payload: Value = blocked
+        .expect("penalty box should block the client ip")
         .json()
         .await
-        .expect("devices list disallowed origin payload");
+        .expect("blocked payload");
     assert_eq!(
-        rejected_list_payload.get("error").and_then(Value::as_str),
-        Some("origin_not_allowed")
+        payload.get("error").and_then(Value::as_str),
+        Some("ip_blocked")
     );
 
-    let list_response = client
-        .post(format!("{base}/devices/list"))
-        .json(&json!({
-            "schemaVersion": 2,
-            "sessionID": session_id,
-            "desktopSessionToken": desktop_session_token,
-        }))
+    task.abort();
+}
+
+#[tokio::test]
+async fn repeated_pair_join_failures_put_ip_in_penalty_box_until_admin_clears_it() {
+    let admin_token = random_token(32);
+    let configured_admin_token = admin_token.clone();
+    let (base, task) = spawn_test_server_with_config(move |config| {
+        config.penalty_box_failure_threshold = 3;
+        config.penalty_box_subnet_failure_threshold = 0;
+        config.penalty_box_base_block_ms = 60_000;
+        config.admin_api_token = Some(configured_admin_token);
+    })
+    .await;
+    let client = reqwest::Client::new();
+
+    let bad_join = || {
+        client
+            .post(format!("{base}/pair/join"))
+            .header("Origin", "http://localhost:4173")
+            .json(&json!({
+                "sessionID": random_token(16),
+                "joinToken": random_token(32),
+            }))
+            .send()
+    };
+
+    for _ in 0..3 {
+        let response = bad_join().await.expect("pair join request");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    let mut blocked = None;
+    for _ in 0..50 {
+        let response = bad_join().await.expect("pair join request");
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            blocked = Some(response);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let blocked = blocked.expect("penalty box should block the client ip");
+    let retry_after = blocked
+        .headers()
+        .get("retry-after")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .expect("retry-after header");
+    assert!(retry_after > 0 && retry_after <= 60);
+    let payload: Value = blocked.json().await.expect("blocked payload");
+    assert_eq!(
+        payload.get("error").and_then(Value::as_str),
+        Some("ip_blocked")
+    );
+
+    let unauthorized = client
+        .get(format!("{base}/admin/penalty-box"))
+        .bearer_auth(random_token(32))
         .send()
         .await
-        .expect("devices list");
-    assert_eq!(list_response.status(), StatusCode::OK);
-    let list_payload: Value = list_response.json().await.expect("list payload");
-    let devices = list_payload
-        .get("devices")
+        .expect("admin list request");
+    assert_eq!(unauthorized.status(), StatusCode::UNAUTHORIZED);
+
+    let listed: Value = client
+        .get(format!("{base}/admin/penalty-box"))
+        .bearer_auth(&admin_token)
+        .send()
+        .await
+        .expect("admin list request")
+        .json()
+        .await
+        .expect("admin list payload");
+    let blocks = listed
+        .get("blocks")
         .and_then(Value::as_array)
-        .expect("devices array");
-    assert_eq!(devices.len(), 1);
-    assert_eq!(
-        devices[0].get("deviceID").and_then(Value::as_str),
-        Some(device_id.as_str())
-    );
+        .expect("blocks array");
+    assert_eq!(blocks.len(), 1);
     assert_eq!(
-        devices[0].get("deviceName").and_then(Value::as_str),
-        Some("Bikram iPhone")
+        blocks[0].get("key").and_then(Value::as_str),
+        Some("ip:127.0.0.1")
     );
 
-    let rejected_revoke_response = client
-        .post(format!("{base}/devices/revoke"))
-        .header("Origin", "https://evil.example")
-        .json(&json!({
-            "schemaVersion": 2,
-            "sessionID": session_id,
-            "desktopSessionToken": desktop_session_token,
-            "deviceID": device_id,
-        }))
+    let cleared: Value = client
+        .post(format!("{base}/admin/penalty-box/clear"))
+        .bearer_auth(&admin_token)
+        .json(&json!({ "key": "ip:127.0.0.1" }))
         .send()
         .await
-        .expect("device revoke disallowed origin");
-    assert_eq!(rejected_revoke_response.status(), StatusCode::FORBIDDEN);
-    let rejected_revoke_payload: Value = rejected_revoke_response
+        .expect("admin clear request")
         .json()
         .await
-        .expect("device revoke disallowed origin payload");
-    assert_eq!(
-        rejected_revoke_payload.get("error").and_then(Value::as_str),
-        Some("origin_not_allowed")
-    );
+        .expect("admin clear payload");
+    assert_eq!(cleared.get("cleared").and_then(Value::as_bool), Some(true));
 
-    let revoke_response = client
-        .post(format!("{base}/devices/revoke"))
-        .json(&json!({
-            "schemaVersion": 2,
-            "sessionID": session_id,
-            "desktopSessionToken": desktop_session_token,
-            "deviceID": device_id,
-        }))
+    let response = bad_join().await.expect("pair join request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let metrics: Value = client
+        .get(format!("{base}/metricsz"))
         .send()
         .await
-        .expect("device revoke");
-    assert_eq!(revoke_response.status(), StatusCode::OK);
-    let revoke_disconnect = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
-        payload.get("type").and_then(Value::as_str) == Some("disconnect")
+        .expect("metrics request")
+        .json()
+        .await
+        .expect("metrics payload");
+    assert_eq!(
+        metrics
+            .get("penaltyBoxBlocksIssued")
+            .and_then(Value::as_u64),
+        Some(1)
+    );
+    assert!(
+        metrics
+            .get("penaltyBoxRejections")
+            .and_then(Value::as_u64)
+            .unwrap_or_default()
+            >= 1
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn cidr_lists_reject_route_groups_and_reload_from_rules_file() {
+    let rules_path = std::env::temp_dir().join(format!(
+        "relay-ip-access-integration-{}.json",
+        random_token(8)
+    ));
+    std::fs::write(&rules_path, r#"{"ws":{"deny":["127.0.0.0/8"]}}"#).expect("write rules file");
+    let configured_rules_path = rules_path.to_string_lossy().to_string();
+    let (base, task) = spawn_test_server_with_config(move |config| {
+        config.pairing_allow_cidrs = vec!["10.0.0.0/8".to_string()];
+        config.ip_access_rules_file = Some(configured_rules_path);
+        config.ip_access_rules_reload_interval_ms = 50;
     })
     .await;
+    let client = reqwest::Client::new();
+
+    let pair_start = || {
+        client
+            .post(format!("{base}/pair/start"))
+            .json(&json!({
+                "sessionID": random_token(16),
+                "joinToken": random_token(32),
+                "desktopSessionToken": random_token(32),
+                "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+                "idleTimeoutSeconds": 1800,
+            }))
+            .send()
+    };
+
+    let rejected = pair_start().await.expect("pair start request");
+    assert_eq!(rejected.status(), StatusCode::FORBIDDEN);
+    let payload: Value = rejected.json().await.expect("rejection payload");
     assert_eq!(
-        revoke_disconnect.get("reason").and_then(Value::as_str),
-        Some("device_revoked")
+        payload.get("error").and_then(Value::as_str),
+        Some("ip_not_allowed")
     );
 
-    let list_after_revoke = client
-        .post(format!("{base}/devices/list"))
-        .json(&json!({
-            "schemaVersion": 2,
-            "sessionID": session_id,
-            "desktopSessionToken": desktop_session_token,
-        }))
+    let ws_url = format!("{}/ws", base.replacen("http://", "ws://", 1));
+    let ws_error = tokio_tungstenite::connect_async(ws_url.as_str())
+        .await
+        .expect_err("websocket upgrade should be rejected");
+    match ws_error {
+        tokio_tungstenite::tungstenite::Error::Http(response) => {
+            assert_eq!(response.status().as_u16(), 403);
+        }
+        other => panic!("expected http rejection, got {other:?}"),
+    }
+
+    let health = client
+        .get(format!("{base}/healthz"))
         .send()
         .await
-        .expect("devices list after revoke");
-    assert_eq!(list_after_revoke.status(), StatusCode::OK);
-    let list_after_payload: Value = list_after_revoke
-        .json()
+        .expect("healthz request");
+    assert_eq!(health.status(), StatusCode::OK);
+
+    std::fs::write(&rules_path, r#"{"pairing":{"allow":["127.0.0.1/32"]}}"#)
+        .expect("rewrite rules file");
+    let mut allowed = false;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        if pair_start().await.expect("pair start request").status() == StatusCode::OK {
+            allowed = true;
+            break;
+        }
+    }
+    let _ = std::fs::remove_file(&rules_path);
+    assert!(
+        allowed,
+        "reloaded rules should allow pairing from 127.0.0.1"
+    );
+    let (mut socket, _) = tokio_tungstenite::connect_async(ws_url.as_str())
         .await
-        .expect("list payload after revoke");
-    let devices_after = list_after_payload
-        .get("devices")
-        .and_then(Value::as_array)
-        .expect("devices array after revoke");
-    assert_eq!(devices_after.len(), 0);
+        .expect("websocket connects once the deny list is removed");
+    let _ = socket.close(None).await;
 
-    let mut reconnect_request = ws_url
-        .into_client_request()
-        .expect("mobile reconnect request");
-    reconnect_request.headers_mut().insert(
-        "Origin",
-        "http://localhost:4173".parse().expect("origin header"),
-    );
-    let (mut mobile_reconnect_socket, _) = tokio_tungstenite::connect_async(reconnect_request)
+    let metrics: Value = client
+        .get(format!("{base}/metricsz"))
+        .send()
         .await
-        .expect("mobile reconnect websocket");
-    mobile_reconnect_socket
-        .send(Message::Text(
-            json!({ "type": "relay.auth", "token": rotated_device_token }).to_string(),
+        .expect("metrics request")
+        .json()
+        .await
+        .expect("metrics payload");
+    let rejections = metrics
+        .get("ipAccessRejectionsByRouteGroup")
+        .expect("ip access rejection metrics");
+    assert!(
+        rejections
+            .get("pairing")
+            .and_then(Value::as_u64)
+            .unwrap_or_default()
+            >= 1
+    );
+    assert_eq!(rejections.get("ws").and_then(Value::as_u64), Some(1));
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn pair_stop_closes_session_and_invalidates_join() {
+    let (base, task) = spawn_test_server().await;
+    let client = reqwest::Client::new();
+
+    let session_id = random_token(16);
+    let join_token = random_token(32);
+    let desktop_session_token = random_token(32);
+
+    let start_response = client
+        .post(format!("{base}/pair/start"))
+        .json(&json!({
+            "sessionID": session_id,
+            "joinToken": join_token,
+            "desktopSessionToken": desktop_session_token,
+            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+            "idleTimeoutSeconds": 1800,
+        }))
+        .send()
+        .await
+        .expect("pair start request");
+    assert_eq!(start_response.status(), StatusCode::OK);
+
+    let stop_response = client
+        .post(format!("{base}/pair/stop"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+        }))
+        .send()
+        .await
+        .expect("pair stop request");
+    assert_eq!(stop_response.status(), StatusCode::OK);
+
+    let join_response = client
+        .post(format!("{base}/pair/join"))
+        .header("Origin", "http://localhost:4173")
+        .json(&json!({
+            "sessionID": session_id,
+            "joinToken": join_token,
+        }))
+        .send()
+        .await
+        .expect("pair join request");
+    assert_eq!(join_response.status(), StatusCode::NOT_FOUND);
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn pair_stop_forces_repair_by_rejecting_trusted_device_reauth() {
+    let (base, task) = spawn_test_server().await;
+    let client = reqwest::Client::new();
+
+    let session_id = random_token(16);
+    let join_token = random_token(32);
+    let desktop_session_token = random_token(32);
+
+    let start_response = client
+        .post(format!("{base}/pair/start"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "joinToken": join_token,
+            "desktopSessionToken": desktop_session_token,
+            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+            "idleTimeoutSeconds": 1800,
+        }))
+        .send()
+        .await
+        .expect("pair start request");
+    assert_eq!(start_response.status(), StatusCode::OK);
+    let start_payload: Value = start_response.json().await.expect("pair start payload");
+    let ws_url = start_payload
+        .get("wsURL")
+        .and_then(Value::as_str)
+        .expect("ws url")
+        .to_string();
+
+    let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url)
+        .await
+        .expect("desktop websocket");
+    desktop_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": desktop_session_token }).to_string(),
+        ))
+        .await
+        .expect("desktop auth send");
+    let _desktop_auth = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+
+    let join_future = tokio::spawn({
+        let client = client.clone();
+        let base = base.clone();
+        let session_id = session_id.clone();
+        let join_token = join_token.clone();
+        async move {
+            client
+                .post(format!("{base}/pair/join"))
+                .header("Origin", "http://localhost:4173")
+                .json(&json!({ "sessionID": session_id, "joinToken": join_token }))
+                .send()
+                .await
+                .expect("pair join request")
+        }
+    });
+
+    let pair_request = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("relay.pair_request")
+    })
+    .await;
+    let request_id = pair_request
+        .get("requestID")
+        .and_then(Value::as_str)
+        .expect("request id")
+        .to_string();
+    desktop_socket
+        .send(Message::Text(
+            json!({
+                "type": "relay.pair_decision",
+                "sessionID": session_id,
+                "requestID": request_id,
+                "approved": true,
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("pair decision send");
+
+    let join_response = join_future.await.expect("join task");
+    assert_eq!(join_response.status(), StatusCode::OK);
+    let join_payload: Value = join_response.json().await.expect("join payload");
+    let device_token = join_payload
+        .get("deviceSessionToken")
+        .and_then(Value::as_str)
+        .expect("device token")
+        .to_string();
+
+    let mut mobile_request = ws_url
+        .clone()
+        .into_client_request()
+        .expect("mobile request");
+    mobile_request.headers_mut().insert(
+        "Origin",
+        "http://localhost:4173".parse().expect("origin header"),
+    );
+    let (mut mobile_socket, _) = tokio_tungstenite::connect_async(mobile_request)
+        .await
+        .expect("mobile websocket");
+    mobile_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": device_token }).to_string(),
+        ))
+        .await
+        .expect("mobile auth send");
+    let mobile_auth = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+    let rotated_token = mobile_auth
+        .get("nextDeviceSessionToken")
+        .and_then(Value::as_str)
+        .expect("rotated token")
+        .to_string();
+
+    let stop_response = client
+        .post(format!("{base}/pair/stop"))
+        .json(&json!({
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+        }))
+        .send()
+        .await
+        .expect("pair stop request");
+    assert_eq!(stop_response.status(), StatusCode::OK);
+
+    let stopped_disconnect = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("disconnect")
+    })
+    .await;
+    assert_eq!(
+        stopped_disconnect.get("reason").and_then(Value::as_str),
+        Some("stopped_by_desktop")
+    );
+
+    let mut mobile_reconnect_request = ws_url
+        .into_client_request()
+        .expect("mobile reconnect request");
+    mobile_reconnect_request.headers_mut().insert(
+        "Origin",
+        "http://localhost:4173".parse().expect("origin header"),
+    );
+    let (mut mobile_reconnect_socket, _) =
+        tokio_tungstenite::connect_async(mobile_reconnect_request)
+            .await
+            .expect("mobile reconnect websocket");
+    mobile_reconnect_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": rotated_token }).to_string(),
         ))
         .await
         .expect("mobile reconnect auth send");
@@ -2051,632 +2724,2251 @@ async fn devices_list_and_revoke_remove_trusted_device() {
     .expect("expected reconnect attempt to resolve");
     assert!(
         reconnect_rejected,
-        "revoked device token should not authenticate after device revoke"
+        "stopped session should require re-pair and reject trusted-device reauth"
     );
 
     task.abort();
 }
 
 #[tokio::test]
-async fn invalid_mobile_command_is_rejected_and_not_forwarded() {
-    let (
-        _base,
-        task,
-        mut desktop_socket,
-        mut mobile_socket,
-        session_id,
-        _device_token,
-        _rotated_device_token,
-    ) = pair_connected_mobile(|_| {}).await;
+async fn stale_desktop_session_token_emits_session_expired_disconnect_and_policy_close() {
+    let (base, task) = spawn_test_server().await;
+    let client = reqwest::Client::new();
 
-    mobile_socket
-        .send(Message::Text(
-            json!({
-                "schemaVersion": 2,
-                "sessionID": session_id,
-                "seq": 1,
-                "payload": {
-                    "type": "command",
-                    "payload": {
-                        "name": "terminal.exec",
-                        "commandID": "cmd-1",
-                        "threadID": "11111111-1111-1111-1111-111111111111",
-                        "text": "rm -rf /"
-                    }
-                }
-            })
-            .to_string(),
-        ))
-        .await
-        .expect("send invalid command");
+    let session_id = random_token(16);
+    let join_token = random_token(32);
+    let desktop_session_token = random_token(32);
 
-    let relay_error = tokio::time::timeout(Duration::from_millis(1_000), mobile_socket.next())
-        .await
-        .expect("expected relay error")
-        .expect("relay error frame")
-        .expect("relay error message");
-    let relay_error_json: Value =
-        serde_json::from_str(relay_error.to_text().expect("relay error text"))
-            .expect("relay error json");
-    assert_eq!(
-        relay_error_json.get("type").and_then(Value::as_str),
-        Some("relay.error")
-    );
-    assert_eq!(
-        relay_error_json.get("error").and_then(Value::as_str),
-        Some("invalid_command")
-    );
+    let start_response = client
+        .post(format!("{base}/pair/start"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "joinToken": join_token,
+            "desktopSessionToken": desktop_session_token,
+            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+            "idleTimeoutSeconds": 1800,
+        }))
+        .send()
+        .await
+        .expect("pair start request");
+    assert_eq!(start_response.status(), StatusCode::OK);
+    let start_payload: Value = start_response.json().await.expect("pair start payload");
+    let ws_url = start_payload
+        .get("wsURL")
+        .and_then(Value::as_str)
+        .expect("ws url")
+        .to_string();
 
-    let desktop_next =
-        tokio::time::timeout(Duration::from_millis(250), desktop_socket.next()).await;
-    assert!(
-        desktop_next.is_err(),
-        "desktop unexpectedly received forwarded payload"
-    );
+    let stop_response = client
+        .post(format!("{base}/pair/stop"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+        }))
+        .send()
+        .await
+        .expect("pair stop request");
+    assert_eq!(stop_response.status(), StatusCode::OK);
+
+    let (mut stale_desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url)
+        .await
+        .expect("stale desktop websocket");
+    stale_desktop_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": desktop_session_token }).to_string(),
+        ))
+        .await
+        .expect("stale desktop auth send");
+
+    expect_disconnect_with_reason(&mut stale_desktop_socket, 1_000, "session_expired").await;
+    expect_policy_close(&mut stale_desktop_socket, 1_000).await;
 
     task.abort();
 }
 
 #[tokio::test]
-async fn mobile_command_with_unexpected_field_is_rejected_and_not_forwarded() {
-    let (
-        _base,
-        task,
-        mut desktop_socket,
-        mut mobile_socket,
-        session_id,
-        _device_token,
-        _rotated_device_token,
-    ) = pair_connected_mobile(|_| {}).await;
+async fn pair_refresh_rotates_join_token_without_stopping_session() {
+    let (base, task) = spawn_test_server().await;
+    let client = reqwest::Client::new();
 
-    mobile_socket
+    let session_id = random_token(16);
+    let original_join_token = random_token(32);
+    let refreshed_join_token = random_token(32);
+    let desktop_session_token = random_token(32);
+
+    let start_response = client
+        .post(format!("{base}/pair/start"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "joinToken": original_join_token,
+            "desktopSessionToken": desktop_session_token,
+            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+            "idleTimeoutSeconds": 1800,
+        }))
+        .send()
+        .await
+        .expect("pair start request");
+    assert_eq!(start_response.status(), StatusCode::OK);
+    let start_payload: Value = start_response.json().await.expect("pair start payload");
+    let ws_url = start_payload
+        .get("wsURL")
+        .and_then(Value::as_str)
+        .expect("ws url")
+        .to_string();
+
+    let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url)
+        .await
+        .expect("desktop websocket");
+    desktop_socket
         .send(Message::Text(
-            json!({
-                "schemaVersion": 2,
-                "sessionID": session_id,
-                "seq": 1,
-                "payload": {
-                    "type": "command",
-                    "payload": {
-                        "name": "thread.select",
-                        "commandID": "cmd-1",
-                        "threadID": "11111111-1111-1111-1111-111111111111",
-                        "unexpectedField": "not-allowed"
-                    }
-                }
-            })
-            .to_string(),
+            json!({ "type": "relay.auth", "token": desktop_session_token }).to_string(),
         ))
         .await
-        .expect("send invalid command with unexpected field");
+        .expect("desktop auth send");
+    let _desktop_auth = desktop_socket
+        .next()
+        .await
+        .expect("desktop auth message")
+        .expect("desktop auth frame");
 
-    let relay_error = tokio::time::timeout(Duration::from_millis(1_000), mobile_socket.next())
+    let refresh_response = client
+        .post(format!("{base}/pair/refresh"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "joinToken": refreshed_join_token,
+            "desktopSessionToken": desktop_session_token,
+            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+        }))
+        .send()
         .await
-        .expect("expected relay error")
-        .expect("relay error frame")
-        .expect("relay error message");
-    let relay_error_json: Value =
-        serde_json::from_str(relay_error.to_text().expect("relay error text"))
-            .expect("relay error json");
-    assert_eq!(
-        relay_error_json.get("type").and_then(Value::as_str),
-        Some("relay.error")
-    );
-    assert_eq!(
-        relay_error_json.get("error").and_then(Value::as_str),
-        Some("invalid_command")
-    );
+        .expect("pair refresh request");
+    assert_eq!(refresh_response.status(), StatusCode::OK);
 
-    let desktop_next =
-        tokio::time::timeout(Duration::from_millis(250), desktop_socket.next()).await;
-    assert!(
-        desktop_next.is_err(),
-        "desktop unexpectedly received forwarded payload"
-    );
+    let old_join_response = client
+        .post(format!("{base}/pair/join"))
+        .header("Origin", "http://localhost:4173")
+        .json(&json!({
+            "sessionID": session_id,
+            "joinToken": original_join_token,
+        }))
+        .send()
+        .await
+        .expect("pair join old token");
+    assert_eq!(old_join_response.status(), StatusCode::FORBIDDEN);
 
-    task.abort();
-}
+    let join_future = tokio::spawn({
+        let client = client.clone();
+        let base = base.clone();
+        let session_id = session_id.clone();
+        let refreshed_join_token = refreshed_join_token.clone();
+        async move {
+            client
+                .post(format!("{base}/pair/join"))
+                .header("Origin", "http://localhost:4173")
+                .json(&json!({
+                    "sessionID": session_id,
+                    "joinToken": refreshed_join_token,
+                }))
+                .send()
+                .await
+                .expect("pair join refreshed token")
+        }
+    });
 
-#[tokio::test]
-async fn mobile_command_ignores_spoofed_relay_metadata_and_forwards() {
-    let (
-        _base,
-        task,
-        mut desktop_socket,
-        mut mobile_socket,
-        session_id,
-        _device_token,
-        _rotated_device_token,
-    ) = pair_connected_mobile(|_| {}).await;
+    let pair_request = desktop_socket
+        .next()
+        .await
+        .expect("pair request frame")
+        .expect("pair request message");
+    let pair_request_json: Value =
+        serde_json::from_str(pair_request.to_text().expect("pair request text"))
+            .expect("pair request json");
+    let request_id = pair_request_json
+        .get("requestID")
+        .and_then(Value::as_str)
+        .expect("requestID")
+        .to_string();
 
-    mobile_socket
+    desktop_socket
         .send(Message::Text(
             json!({
-                "schemaVersion": 2,
+                "type": "relay.pair_decision",
                 "sessionID": session_id,
-                "seq": 1,
-                "timestamp": chrono::Utc::now().to_rfc3339(),
-                "relayConnectionID": "spoofed-connection",
-                "relayDeviceID": "spoofed-device",
-                "payload": {
-                    "type": "command",
-                    "payload": {
-                        "name": "thread.select",
-                        "commandID": "cmd-1",
-                        "threadID": "11111111-1111-1111-1111-111111111111"
-                    }
-                }
+                "requestID": request_id,
+                "approved": true,
             })
             .to_string(),
         ))
         .await
-        .expect("send command with spoofed metadata");
+        .expect("desktop pair decision send");
 
-    let forwarded = tokio::time::timeout(Duration::from_millis(1_000), desktop_socket.next())
+    let join_response = join_future.await.expect("join task");
+    assert_eq!(join_response.status(), StatusCode::OK);
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn pairing_endpoints_include_cors_headers_for_allowed_origin() {
+    let (base, task) = spawn_test_server().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .request(reqwest::Method::OPTIONS, format!("{base}/pair/join"))
+        .header("Origin", "http://localhost:4173")
+        .header("Access-Control-Request-Method", "POST")
+        .send()
         .await
-        .expect("expected forwarded command")
-        .expect("forwarded frame")
-        .expect("forwarded message");
-    let forwarded_json: Value =
-        serde_json::from_str(forwarded.to_text().expect("forwarded text")).expect("forwarded json");
+        .expect("options request");
+
+    assert!(response.status().is_success());
     assert_eq!(
-        forwarded_json
-            .pointer("/payload/payload/name")
-            .and_then(Value::as_str),
-        Some("thread.select")
-    );
-    assert_ne!(
-        forwarded_json
-            .get("relayConnectionID")
-            .and_then(Value::as_str),
-        Some("spoofed-connection")
-    );
-    assert_ne!(
-        forwarded_json.get("relayDeviceID").and_then(Value::as_str),
-        Some("spoofed-device")
+        response
+            .headers()
+            .get("access-control-allow-origin")
+            .and_then(|value| value.to_str().ok()),
+        Some("http://localhost:4173")
     );
 
     task.abort();
 }
 
 #[tokio::test]
-async fn invalid_snapshot_request_with_negative_last_seq_is_rejected() {
-    let (
-        _base,
-        task,
-        mut desktop_socket,
-        mut mobile_socket,
-        session_id,
-        _device_token,
-        _rotated_device_token,
-    ) = pair_connected_mobile(|_| {}).await;
+async fn devices_list_and_revoke_remove_trusted_device() {
+    let (base, task) = spawn_test_server().await;
+    let client = reqwest::Client::new();
 
-    mobile_socket
+    let session_id = random_token(16);
+    let join_token = random_token(32);
+    let desktop_session_token = random_token(32);
+
+    let start_response = client
+        .post(format!("{base}/pair/start"))
+        .json(&json!({
+            "sessionID": session_id,
+            "joinToken": join_token,
+            "desktopSessionToken": desktop_session_token,
+            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+            "idleTimeoutSeconds": 1800,
+        }))
+        .send()
+        .await
+        .expect("pair start request");
+    assert_eq!(start_response.status(), StatusCode::OK);
+    let start_payload: Value = start_response.json().await.expect("pair start payload");
+    let ws_url = start_payload
+        .get("wsURL")
+        .and_then(Value::as_str)
+        .expect("ws url")
+        .to_string();
+
+    let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url)
+        .await
+        .expect("desktop websocket");
+    desktop_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": desktop_session_token }).to_string(),
+        ))
+        .await
+        .expect("desktop auth send");
+    let _desktop_auth = desktop_socket
+        .next()
+        .await
+        .expect("desktop auth message")
+        .expect("desktop auth frame");
+
+    let join_future = tokio::spawn({
+        let client = client.clone();
+        let base = base.clone();
+        let session_id = session_id.clone();
+        let join_token = join_token.clone();
+        async move {
+            client
+                .post(format!("{base}/pair/join"))
+                .header("Origin", "http://localhost:4173")
+                .json(&json!({
+                    "sessionID": session_id,
+                    "joinToken": join_token,
+                    "deviceName": "Bikram iPhone"
+                }))
+                .send()
+                .await
+                .expect("pair join request")
+        }
+    });
+
+    let pair_request = desktop_socket
+        .next()
+        .await
+        .expect("pair request frame")
+        .expect("pair request message");
+    let pair_request_json: Value =
+        serde_json::from_str(pair_request.to_text().expect("pair request text"))
+            .expect("pair request json");
+    let request_id = pair_request_json
+        .get("requestID")
+        .and_then(Value::as_str)
+        .expect("requestID")
+        .to_string();
+
+    desktop_socket
         .send(Message::Text(
             json!({
-                "type": "relay.snapshot_request",
+                "type": "relay.pair_decision",
                 "sessionID": session_id,
-                "lastSeq": -1,
-                "reason": "integration-test"
+                "requestID": request_id,
+                "approved": true,
             })
             .to_string(),
         ))
         .await
-        .expect("send invalid snapshot request");
+        .expect("desktop pair decision send");
 
-    let relay_error = tokio::time::timeout(Duration::from_millis(1_000), mobile_socket.next())
-        .await
-        .expect("expected relay error")
-        .expect("relay error frame")
-        .expect("relay error message");
-    let relay_error_json: Value =
-        serde_json::from_str(relay_error.to_text().expect("relay error text"))
-            .expect("relay error json");
-    assert_eq!(
-        relay_error_json.get("type").and_then(Value::as_str),
-        Some("relay.error")
-    );
-    assert_eq!(
-        relay_error_json.get("error").and_then(Value::as_str),
-        Some("invalid_snapshot_request")
-    );
+    let join_response = join_future.await.expect("join task");
+    assert_eq!(join_response.status(), StatusCode::OK);
+    let join_payload: Value = join_response.json().await.expect("join payload");
+    let device_id = join_payload
+        .get("deviceID")
+        .and_then(Value::as_str)
+        .expect("deviceID")
+        .to_string();
+    let device_session_token = join_payload
+        .get("deviceSessionToken")
+        .and_then(Value::as_str)
+        .expect("deviceSessionToken")
+        .to_string();
 
-    let desktop_next =
-        tokio::time::timeout(Duration::from_millis(250), desktop_socket.next()).await;
-    assert!(
-        desktop_next.is_err(),
-        "desktop unexpectedly received forwarded snapshot payload"
+    let mut mobile_request = ws_url
+        .clone()
+        .into_client_request()
+        .expect("mobile request");
+    mobile_request.headers_mut().insert(
+        "Origin",
+        "http://localhost:4173".parse().expect("origin header"),
     );
-
-    task.abort();
-}
-
-#[tokio::test]
-async fn snapshot_request_accepts_numeric_string_last_seq_for_backward_compatibility() {
-    let (
-        _base,
-        task,
-        mut desktop_socket,
-        mut mobile_socket,
-        session_id,
-        _device_token,
-        _rotated_device_token,
-    ) = pair_connected_mobile(|_| {}).await;
-
+    let (mut mobile_socket, _) = tokio_tungstenite::connect_async(mobile_request)
+        .await
+        .expect("mobile websocket");
     mobile_socket
         .send(Message::Text(
             json!({
-                "type": "relay.snapshot_request",
-                "sessionID": session_id,
-                "lastSeq": "42",
-                "reason": "integration-test"
+                "type": "relay.auth",
+                "token": device_session_token,
+                "platform": "ios",
+                "appVersion": "2.4.0",
             })
             .to_string(),
         ))
         .await
-        .expect("send snapshot request with string lastSeq");
+        .expect("mobile auth send");
+    let mobile_auth = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+    let rotated_device_token = mobile_auth
+        .get("nextDeviceSessionToken")
+        .and_then(Value::as_str)
+        .expect("rotated device token")
+        .to_string();
 
-    let forwarded = tokio::time::timeout(Duration::from_millis(1_000), desktop_socket.next())
+    let rejected_list_response = client
+        .post(format!("{base}/devices/list"))
+        .header("Origin", "https://evil.example")
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+        }))
+        .send()
         .await
-        .expect("expected forwarded snapshot request")
-        .expect("forwarded frame")
-        .expect("forwarded message");
-    let forwarded_json: Value =
-        serde_json::from_str(forwarded.to_text().expect("forwarded text")).expect("forwarded json");
+        .expect("devices list disallowed origin");
+    assert_eq!(rejected_list_response.status(), StatusCode::FORBIDDEN);
+    let rejected_list_payload: Value = rejected_list_response
+        .json()
+        .await
+        .expect("devices list disallowed origin payload");
     assert_eq!(
-        forwarded_json.get("type").and_then(Value::as_str),
-        Some("relay.snapshot_request")
+        rejected_list_payload.get("error").and_then(Value::as_str),
+        Some("origin_not_allowed")
     );
 
-    let relay_error = tokio::time::timeout(Duration::from_millis(250), mobile_socket.next()).await;
-    assert!(
-        relay_error.is_err(),
-        "mobile unexpectedly received relay.error"
+    let list_response = client
+        .post(format!("{base}/devices/list"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+        }))
+        .send()
+        .await
+        .expect("devices list");
+    assert_eq!(list_response.status(), StatusCode::OK);
+    let list_payload: Value = list_response.json().await.expect("list payload");
+    let devices = list_payload
+        .get("devices")
+        .and_then(Value::as_array)
+        .expect("devices array");
+    assert_eq!(devices.len(), 1);
+    assert_eq!(
+        devices[0].get("deviceID").and_then(Value::as_str),
+        Some(device_id.as_str())
     );
+    assert_eq!(
+        devices[0].get("deviceName").and_then(Value::as_str),
+        Some("Bikram iPhone")
+    );
+    assert_eq!(
+        devices[0].get("platform").and_then(Value::as_str),
+        Some("ios")
+    );
+    assert_eq!(
+        devices[0].get("appVersion").and_then(Value::as_str),
+        Some("2.4.0")
+    );
+    assert_eq!(
+        devices[0].get("lastIP").and_then(Value::as_str),
+        Some("127.0.0.1")
+    );
+    assert!(devices[0].get("nickname").is_some_and(Value::is_null));
 
-    task.abort();
-}
-
-#[tokio::test]
-async fn per_device_command_rate_limit_blocks_excess_mobile_commands() {
-    let (
-        _base,
-        task,
-        mut desktop_socket,
-        mut mobile_socket,
-        session_id,
-        _device_token,
-        _rotated_device_token,
-    ) = pair_connected_mobile(|config| {
-        config.max_remote_commands_per_minute = 2;
-    })
-    .await;
-
-    for seq in 1..=3_u64 {
-        mobile_socket
-            .send(Message::Text(
-                json!({
-                    "schemaVersion": 2,
-                    "sessionID": session_id,
-                    "seq": seq,
-                    "payload": {
-                        "type": "command",
-                        "payload": {
-                            "name": "thread.select",
-                            "commandID": format!("cmd-{}", seq),
-                            "threadID": "11111111-1111-1111-1111-111111111111"
-                        }
-                    }
-                })
-                .to_string(),
-            ))
-            .await
-            .expect("send thread.select command");
-    }
-
-    for _ in 0..2 {
-        let forwarded = tokio::time::timeout(Duration::from_millis(1_000), desktop_socket.next())
-            .await
-            .expect("expected forwarded command")
-            .expect("forwarded frame")
-            .expect("forwarded message");
-        let forwarded_json: Value =
-            serde_json::from_str(forwarded.to_text().expect("forwarded text"))
-                .expect("forwarded json");
-        assert_eq!(
-            forwarded_json
-                .pointer("/payload/payload/name")
-                .and_then(Value::as_str),
-            Some("thread.select")
-        );
-    }
-
-    let relay_error = tokio::time::timeout(Duration::from_millis(1_000), mobile_socket.next())
+    let rename_response = client
+        .post(format!("{base}/devices/rename"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+            "deviceID": device_id,
+            "nickname": "  Kitchen phone  ",
+        }))
+        .send()
         .await
-        .expect("expected rate-limit error")
-        .expect("rate-limit frame")
-        .expect("rate-limit message");
-    let relay_error_json: Value =
-        serde_json::from_str(relay_error.to_text().expect("rate-limit text"))
-            .expect("rate-limit json");
+        .expect("device rename");
+    assert_eq!(rename_response.status(), StatusCode::OK);
+    let rename_payload: Value = rename_response.json().await.expect("rename payload");
     assert_eq!(
-        relay_error_json.get("type").and_then(Value::as_str),
-        Some("relay.error")
+        rename_payload
+            .pointer("/device/nickname")
+            .and_then(Value::as_str),
+        Some("Kitchen phone")
     );
     assert_eq!(
-        relay_error_json.get("error").and_then(Value::as_str),
-        Some("command_rate_limited")
-    );
-
-    let desktop_next =
-        tokio::time::timeout(Duration::from_millis(250), desktop_socket.next()).await;
-    assert!(
-        desktop_next.is_err(),
-        "desktop unexpectedly received extra forwarded payload"
+        rename_payload
+            .pointer("/device/deviceName")
+            .and_then(Value::as_str),
+        Some("Bikram iPhone")
     );
 
-    task.abort();
-}
+    let missing_rename_response = client
+        .post(format!("{base}/devices/rename"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+            "deviceID": random_token(12),
+            "nickname": "Nobody",
+        }))
+        .send()
+        .await
+        .expect("device rename for unknown device");
+    assert_eq!(missing_rename_response.status(), StatusCode::NOT_FOUND);
 
-#[tokio::test]
-async fn per_session_command_rate_limit_blocks_excess_mobile_commands() {
-    let (
-        _base,
-        task,
-        mut desktop_socket,
-        mut mobile_socket,
-        session_id,
-        _device_token,
-        _rotated_device_token,
-    ) = pair_connected_mobile(|config| {
-        config.max_remote_commands_per_minute = 10;
-        config.max_remote_session_commands_per_minute = 2;
-    })
-    .await;
+    let renamed_list_payload: Value = client
+        .post(format!("{base}/devices/list"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+        }))
+        .send()
+        .await
+        .expect("devices list after rename")
+        .json()
+        .await
+        .expect("devices list after rename payload");
+    assert_eq!(
+        renamed_list_payload
+            .pointer("/devices/0/nickname")
+            .and_then(Value::as_str),
+        Some("Kitchen phone")
+    );
 
-    for seq in 1..=3_u64 {
-        mobile_socket
-            .send(Message::Text(
-                json!({
+    mobile_socket.close(None).await.expect("mobile close");
+    let history_payload = tokio::time::timeout(Duration::from_millis(2_000), async {
+        loop {
+            let payload: Value = client
+                .post(format!("{base}/devices/history"))
+                .json(&json!({
                     "schemaVersion": 2,
                     "sessionID": session_id,
-                    "seq": seq,
-                    "payload": {
-                        "type": "command",
-                        "payload": {
-                            "name": "thread.select",
-                            "commandID": format!("cmd-{}", seq),
-                            "threadID": "11111111-1111-1111-1111-111111111111"
-                        }
-                    }
-                })
-                .to_string(),
-            ))
-            .await
-            .expect("send thread.select command");
-    }
-
-    for _ in 0..2 {
-        let forwarded = tokio::time::timeout(Duration::from_millis(1_000), desktop_socket.next())
-            .await
-            .expect("expected forwarded command")
-            .expect("forwarded frame")
-            .expect("forwarded message");
-        let forwarded_json: Value =
-            serde_json::from_str(forwarded.to_text().expect("forwarded text"))
-                .expect("forwarded json");
-        assert_eq!(
-            forwarded_json
-                .pointer("/payload/payload/name")
-                .and_then(Value::as_str),
-            Some("thread.select")
-        );
-    }
-
-    let relay_error = tokio::time::timeout(Duration::from_millis(1_000), mobile_socket.next())
-        .await
-        .expect("expected session rate-limit error")
-        .expect("rate-limit frame")
-        .expect("rate-limit message");
-    let relay_error_json: Value =
-        serde_json::from_str(relay_error.to_text().expect("rate-limit text"))
-            .expect("rate-limit json");
+                    "desktopSessionToken": desktop_session_token,
+                    "deviceID": device_id,
+                }))
+                .send()
+                .await
+                .expect("device history")
+                .json()
+                .await
+                .expect("device history payload");
+            let event_count = payload
+                .get("events")
+                .and_then(Value::as_array)
+                .map_or(0, Vec::len);
+            if event_count >= 2 {
+                return payload;
+            }
+            tokio::time::sleep(Duration::from_millis(25)).await;
+        }
+    })
+    .await
+    .expect("disconnect recorded in device history");
     assert_eq!(
-        relay_error_json.get("type").and_then(Value::as_str),
-        Some("relay.error")
+        history_payload
+            .pointer("/events/0/event")
+            .and_then(Value::as_str),
+        Some("connected")
     );
     assert_eq!(
-        relay_error_json.get("error").and_then(Value::as_str),
-        Some("command_rate_limited")
+        history_payload
+            .pointer("/events/0/remoteIP")
+            .and_then(Value::as_str),
+        Some("127.0.0.1")
     );
-
-    let desktop_next =
-        tokio::time::timeout(Duration::from_millis(250), desktop_socket.next()).await;
-    assert!(
-        desktop_next.is_err(),
-        "desktop unexpectedly received extra forwarded payload"
+    assert_eq!(
+        history_payload
+            .pointer("/events/1/event")
+            .and_then(Value::as_str),
+        Some("disconnected")
     );
+    assert_eq!(
+        history_payload
+            .pointer("/events/1/reason")
+            .and_then(Value::as_str),
+        Some("client_closed")
+    );
+    assert!(history_payload
+        .pointer("/events/1/durationMs")
+        .and_then(Value::as_i64)
+        .is_some_and(|duration| duration >= 0));
 
-    task.abort();
-}
+    let missing_history_response = client
+        .post(format!("{base}/devices/history"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+            "deviceID": random_token(12),
+        }))
+        .send()
+        .await
+        .expect("device history for unknown device");
+    assert_eq!(missing_history_response.status(), StatusCode::NOT_FOUND);
 
-#[tokio::test]
-async fn per_device_snapshot_request_rate_limit_blocks_excess_requests() {
-    let (
-        _base,
-        task,
-        mut desktop_socket,
-        mut mobile_socket,
-        session_id,
-        _device_token,
-        _rotated_device_token,
-    ) = pair_connected_mobile(|config| {
-        config.max_snapshot_requests_per_minute = 2;
+    let mut mobile_request = ws_url
+        .clone()
+        .into_client_request()
+        .expect("mobile request");
+    mobile_request.headers_mut().insert(
+        "Origin",
+        "http://localhost:4173".parse().expect("origin header"),
+    );
+    let (mut mobile_socket, _) = tokio_tungstenite::connect_async(mobile_request)
+        .await
+        .expect("mobile websocket reconnect");
+    mobile_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": rotated_device_token }).to_string(),
+        ))
+        .await
+        .expect("mobile reconnect auth send");
+    let mobile_auth = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
     })
     .await;
+    let rotated_device_token = mobile_auth
+        .get("nextDeviceSessionToken")
+        .and_then(Value::as_str)
+        .expect("rotated device token after reconnect")
+        .to_string();
 
-    for request_number in 1..=3_u64 {
-        mobile_socket
-            .send(Message::Text(
-                json!({
-                    "type": "relay.snapshot_request",
-                    "sessionID": session_id,
-                    "lastSeq": request_number,
-                    "reason": "integration-test"
-                })
-                .to_string(),
-            ))
-            .await
-            .expect("send snapshot request");
-    }
-
-    for _ in 0..2 {
-        let forwarded = tokio::time::timeout(Duration::from_millis(1_000), desktop_socket.next())
-            .await
-            .expect("expected forwarded snapshot request")
-            .expect("forwarded frame")
-            .expect("forwarded message");
-        let forwarded_json: Value =
-            serde_json::from_str(forwarded.to_text().expect("forwarded text"))
-                .expect("forwarded json");
-        assert_eq!(
-            forwarded_json.get("type").and_then(Value::as_str),
-            Some("relay.snapshot_request")
-        );
-    }
-
-    let relay_error = tokio::time::timeout(Duration::from_millis(1_000), mobile_socket.next())
+    let rejected_revoke_response = client
+        .post(format!("{base}/devices/revoke"))
+        .header("Origin", "https://evil.example")
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+            "deviceID": device_id,
+        }))
+        .send()
         .await
-        .expect("expected snapshot rate-limit error")
-        .expect("rate-limit frame")
-        .expect("rate-limit message");
-    let relay_error_json: Value =
-        serde_json::from_str(relay_error.to_text().expect("rate-limit text"))
-            .expect("rate-limit json");
+        .expect("device revoke disallowed origin");
+    assert_eq!(rejected_revoke_response.status(), StatusCode::FORBIDDEN);
+    let rejected_revoke_payload: Value = rejected_revoke_response
+        .json()
+        .await
+        .expect("device revoke disallowed origin payload");
     assert_eq!(
-        relay_error_json.get("type").and_then(Value::as_str),
-        Some("relay.error")
+        rejected_revoke_payload.get("error").and_then(Value::as_str),
+        Some("origin_not_allowed")
     );
+
+    let revoke_response = client
+        .post(format!("{base}/devices/revoke"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+            "deviceID": device_id,
+        }))
+        .send()
+        .await
+        .expect("device revoke");
+    assert_eq!(revoke_response.status(), StatusCode::OK);
+    let revoke_disconnect = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("disconnect")
+    })
+    .await;
     assert_eq!(
-        relay_error_json.get("error").and_then(Value::as_str),
-        Some("snapshot_rate_limited")
+        revoke_disconnect.get("reason").and_then(Value::as_str),
+        Some("device_revoked")
     );
 
-    let desktop_next =
-        tokio::time::timeout(Duration::from_millis(250), desktop_socket.next()).await;
+    let list_after_revoke = client
+        .post(format!("{base}/devices/list"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+        }))
+        .send()
+        .await
+        .expect("devices list after revoke");
+    assert_eq!(list_after_revoke.status(), StatusCode::OK);
+    let list_after_payload: Value = list_after_revoke
+        .json()
+        .await
+        .expect("list payload after revoke");
+    let devices_after = list_after_payload
+        .get("devices")
+        .and_then(Value::as_array)
+        .expect("devices array after revoke");
+    assert_eq!(devices_after.len(), 0);
+
+    let mut reconnect_request = ws_url
+        .into_client_request()
+        .expect("mobile reconnect request");
+    reconnect_request.headers_mut().insert(
+        "Origin",
+        "http://localhost:4173".parse().expect("origin header"),
+    );
+    let (mut mobile_reconnect_socket, _) = tokio_tungstenite::connect_async(reconnect_request)
+        .await
+        .expect("mobile reconnect websocket");
+    mobile_reconnect_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": rotated_device_token }).to_string(),
+        ))
+        .await
+        .expect("mobile reconnect auth send");
+    let reconnect_rejected = tokio::time::timeout(Duration::from_millis(1_000), async {
+        while let Some(frame) = mobile_reconnect_socket.next().await {
+            match frame {
+                Ok(Message::Text(raw)) => {
+                    let payload: Value =
+                        serde_json::from_str(raw.as_ref()).unwrap_or_else(|_| json!({}));
+                    if payload.get("type").and_then(Value::as_str) == Some("auth_ok") {
+                        return false;
+                    }
+                    if payload.get("type").and_then(Value::as_str) == Some("disconnect") {
+                        return true;
+                    }
+                }
+                Ok(Message::Close(_)) | Err(_) => return true,
+                _ => {}
+            }
+        }
+        true
+    })
+    .await
+    .expect("expected reconnect attempt to resolve");
     assert!(
-        desktop_next.is_err(),
-        "desktop unexpectedly received extra snapshot payload"
+        reconnect_rejected,
+        "revoked device token should not authenticate after device revoke"
     );
 
     task.abort();
 }
 
 #[tokio::test]
-async fn replayed_mobile_command_sequence_is_rejected() {
-    let (
-        _base,
-        task,
-        mut desktop_socket,
-        mut mobile_socket,
-        session_id,
-        _device_token,
-        _rotated_device_token,
-    ) = pair_connected_mobile(|_| {}).await;
-
-    let command_payload = json!({
-        "schemaVersion": 2,
-        "sessionID": session_id,
-        "seq": 1,
-        "payload": {
-            "type": "command",
-            "payload": {
-                "name": "thread.select",
-                "commandID": "cmd-1",
-                "threadID": "11111111-1111-1111-1111-111111111111"
-            }
-        }
-    })
-    .to_string();
+async fn scoped_mobile_issues_session_transfer_that_new_desktop_redeems_keeping_devices() {
+    let (base, task) = spawn_test_server().await;
+    let client = reqwest::Client::new();
 
-    mobile_socket
-        .send(Message::Text(command_payload.clone()))
-        .await
-        .expect("send first command");
+    let session_id = random_token(16);
+    let join_token = random_token(32);
+    let desktop_session_token = random_token(32);
 
-    let forwarded = tokio::time::timeout(Duration::from_millis(1_000), desktop_socket.next())
+    let start_response = client
+        .post(format!("{base}/pair/start"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "joinToken": join_token,
+            "desktopSessionToken": desktop_session_token,
+            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+            "idleTimeoutSeconds": 1800,
+        }))
+        .send()
         .await
-        .expect("first command forwarded")
-        .expect("forwarded frame")
-        .expect("forwarded message");
-    let forwarded_json: Value =
-        serde_json::from_str(forwarded.to_text().expect("forwarded text")).expect("forwarded json");
-    assert_eq!(
-        forwarded_json
-            .pointer("/payload/payload/name")
-            .and_then(Value::as_str),
-        Some("thread.select")
-    );
+        .expect("pair start request");
+    assert_eq!(start_response.status(), StatusCode::OK);
+    let start_payload: Value = start_response.json().await.expect("pair start payload");
+    let ws_url = start_payload
+        .get("wsURL")
+        .and_then(Value::as_str)
+        .expect("ws url")
+        .to_string();
 
-    mobile_socket
-        .send(Message::Text(command_payload))
+    let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url)
         .await
-        .expect("send replayed command");
-
-    let relay_error = tokio::time::timeout(Duration::from_millis(1_000), mobile_socket.next())
+        .expect("desktop websocket");
+    desktop_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": desktop_session_token }).to_string(),
+        ))
         .await
-        .expect("expected replay rejection")
-        .expect("replay rejection frame")
-        .expect("replay rejection message");
-    let relay_error_json: Value =
-        serde_json::from_str(relay_error.to_text().expect("replay rejection text"))
-            .expect("replay rejection json");
-    assert_eq!(
-        relay_error_json.get("type").and_then(Value::as_str),
-        Some("relay.error")
-    );
-    assert_eq!(
-        relay_error_json.get("error").and_then(Value::as_str),
-        Some("replayed_command")
-    );
-
-    let desktop_next =
-        tokio::time::timeout(Duration::from_millis(250), desktop_socket.next()).await;
-    assert!(
-        desktop_next.is_err(),
-        "desktop unexpectedly received replayed payload"
-    );
-
-    task.abort();
-}
-
-#[tokio::test]
-async fn per_socket_websocket_message_rate_limit_disconnects_abusive_client() {
-    let (
-        _base,
-        task,
-        mut desktop_socket,
-        mut mobile_socket,
-        session_id,
-        _device_token,
-        _rotated_device_token,
-    ) = pair_connected_mobile(|config| {
-        config.max_ws_messages_per_minute = 2;
-        config.max_remote_commands_per_minute = 10_000;
-        config.max_remote_session_commands_per_minute = 10_000;
+        .expect("desktop auth send");
+    next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
     })
     .await;
 
-    for seq in 1..=3_u64 {
-        mobile_socket
-            .send(Message::Text(
-                json!({
-                    "schemaVersion": 2,
+    let join_future = tokio::spawn({
+        let client = client.clone();
+        let base = base.clone();
+        let session_id = session_id.clone();
+        let join_token = join_token.clone();
+        async move {
+            client
+                .post(format!("{base}/pair/join"))
+                .header("Origin", "http://localhost:4173")
+                .json(&json!({
                     "sessionID": session_id,
-                    "seq": seq,
-                    "payload": {
-                        "type": "command",
-                        "payload": {
+                    "joinToken": join_token,
+                    "deviceName": "Test iPhone",
+                }))
+                .send()
+                .await
+                .expect("pair join request")
+        }
+    });
+    let pair_request = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("relay.pair_request")
+    })
+    .await;
+    let request_id = pair_request
+        .get("requestID")
+        .and_then(Value::as_str)
+        .expect("requestID")
+        .to_string();
+    desktop_socket
+        .send(Message::Text(
+            json!({
+                "type": "relay.pair_decision",
+                "sessionID": session_id,
+                "requestID": request_id,
+                "approved": true,
+                "scopes": ["session_transfer", "unknown_scope"],
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("desktop pair decision send");
+
+    let join_response = join_future.await.expect("join task");
+    assert_eq!(join_response.status(), StatusCode::OK);
+    let join_payload: Value = join_response.json().await.expect("join payload");
+    let device_token = join_payload
+        .get("deviceSessionToken")
+        .and_then(Value::as_str)
+        .expect("device token")
+        .to_string();
+
+    let mut mobile_request = ws_url
+        .clone()
+        .into_client_request()
+        .expect("mobile request");
+    mobile_request.headers_mut().insert(
+        "Origin",
+        "http://localhost:4173".parse().expect("origin header"),
+    );
+    let (mut mobile_socket, _) = tokio_tungstenite::connect_async(mobile_request)
+        .await
+        .expect("mobile websocket");
+    mobile_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": device_token }).to_string(),
+        ))
+        .await
+        .expect("mobile auth send");
+    let mobile_auth = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+    let rotated_device_token = mobile_auth
+        .get("nextDeviceSessionToken")
+        .and_then(Value::as_str)
+        .expect("rotated device token")
+        .to_string();
+
+    let issue_response = client
+        .post(format!("{base}/session/transfer/issue"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "deviceSessionToken": rotated_device_token,
+        }))
+        .send()
+        .await
+        .expect("session transfer issue");
+    assert_eq!(issue_response.status(), StatusCode::OK);
+    let issue_payload: Value = issue_response.json().await.expect("issue payload");
+    let transfer_token = issue_payload
+        .get("transferToken")
+        .and_then(Value::as_str)
+        .expect("transfer token")
+        .to_string();
+    assert!(issue_payload.get("expiresAt").is_some_and(Value::is_string));
+
+    let new_desktop_session_token = random_token(32);
+    let wrong_redeem_response = client
+        .post(format!("{base}/session/transfer/redeem"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "transferToken": random_token(32),
+            "desktopSessionToken": new_desktop_session_token,
+        }))
+        .send()
+        .await
+        .expect("session transfer redeem with wrong token");
+    assert_eq!(wrong_redeem_response.status(), StatusCode::FORBIDDEN);
+
+    let redeem_response = client
+        .post(format!("{base}/session/transfer/redeem"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "transferToken": transfer_token,
+            "desktopSessionToken": new_desktop_session_token,
+        }))
+        .send()
+        .await
+        .expect("session transfer redeem");
+    assert_eq!(redeem_response.status(), StatusCode::OK);
+    let redeem_payload: Value = redeem_response.json().await.expect("redeem payload");
+    assert_eq!(
+        redeem_payload.get("deviceCount").and_then(Value::as_u64),
+        Some(1)
+    );
+    assert_eq!(
+        redeem_payload.get("wsURL").and_then(Value::as_str),
+        Some(ws_url.as_str())
+    );
+    expect_disconnect_with_reason(&mut desktop_socket, 1_000, "session_transferred").await;
+
+    let reused_redeem_response = client
+        .post(format!("{base}/session/transfer/redeem"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "transferToken": transfer_token,
+            "desktopSessionToken": random_token(32),
+        }))
+        .send()
+        .await
+        .expect("session transfer redeem reuse");
+    assert_eq!(reused_redeem_response.status(), StatusCode::FORBIDDEN);
+
+    let old_desktop_list_response = client
+        .post(format!("{base}/devices/list"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+        }))
+        .send()
+        .await
+        .expect("devices list with old desktop token");
+    assert_eq!(old_desktop_list_response.status(), StatusCode::FORBIDDEN);
+
+    let (mut new_desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url)
+        .await
+        .expect("new desktop websocket");
+    new_desktop_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": new_desktop_session_token }).to_string(),
+        ))
+        .await
+        .expect("new desktop auth send");
+    next_matching_json_message(&mut new_desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+
+    let list_payload: Value = client
+        .post(format!("{base}/devices/list"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": new_desktop_session_token,
+        }))
+        .send()
+        .await
+        .expect("devices list with new desktop token")
+        .json()
+        .await
+        .expect("devices list payload");
+    assert_eq!(
+        list_payload.pointer("/devices/0/scopes"),
+        Some(&json!(["session_transfer"]))
+    );
+    assert_eq!(
+        list_payload
+            .pointer("/devices/0/connected")
+            .and_then(Value::as_bool),
+        Some(true)
+    );
+
+    mobile_socket
+        .send(Message::Text(
+            json!({
+                "schemaVersion": 2,
+                "sessionID": session_id,
+                "seq": 1,
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+                "payload": {
+                    "type": "command",
+                    "payload": {
+                        "name": "thread.select",
+                        "commandID": "cmd-1",
+                        "threadID": "11111111-1111-1111-1111-111111111111"
+                    }
+                }
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("mobile command send");
+    let forwarded = next_matching_json_message(&mut new_desktop_socket, 1_000, |payload| {
+        payload.pointer("/payload/type").and_then(Value::as_str) == Some("command")
+    })
+    .await;
+    assert_eq!(
+        forwarded
+            .pointer("/payload/payload/name")
+            .and_then(Value::as_str),
+        Some("thread.select")
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn session_device_cap_evicts_least_recently_seen_device_for_approved_join() {
+    let (base, task) = spawn_test_server().await;
+    let client = reqwest::Client::new();
+
+    let session_id = random_token(16);
+    let desktop_session_token = random_token(32);
+    let join_token_expires_at = chrono::Utc::now()
+        .checked_add_signed(chrono::Duration::minutes(2))
+        .unwrap()
+        .to_rfc3339();
+
+    let oversized_cap_response = client
+        .post(format!("{base}/pair/start"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "joinToken": random_token(32),
+            "desktopSessionToken": desktop_session_token,
+            "joinTokenExpiresAt": join_token_expires_at,
+            "maxDevices": 50,
+        }))
+        .send()
+        .await
+        .expect("pair start with oversized cap");
+    assert_eq!(oversized_cap_response.status(), StatusCode::BAD_REQUEST);
+
+    let first_join_token = random_token(32);
+    let start_response = client
+        .post(format!("{base}/pair/start"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "joinToken": first_join_token,
+            "desktopSessionToken": desktop_session_token,
+            "joinTokenExpiresAt": join_token_expires_at,
+            "maxDevices": 1,
+            "deviceEvictionPolicy": "least_recently_seen",
+        }))
+        .send()
+        .await
+        .expect("pair start request");
+    assert_eq!(start_response.status(), StatusCode::OK);
+    let start_payload: Value = start_response.json().await.expect("pair start payload");
+    assert_eq!(
+        start_payload.get("maxDevices").and_then(Value::as_u64),
+        Some(1)
+    );
+    assert_eq!(
+        start_payload
+            .get("deviceEvictionPolicy")
+            .and_then(Value::as_str),
+        Some("least_recently_seen")
+    );
+    let ws_url = start_payload
+        .get("wsURL")
+        .and_then(Value::as_str)
+        .expect("ws url")
+        .to_string();
+
+    let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url)
+        .await
+        .expect("desktop websocket");
+    desktop_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": desktop_session_token }).to_string(),
+        ))
+        .await
+        .expect("desktop auth send");
+    next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+
+    let (first_pair_request, first_join) = join_with_desktop_approval(
+        &client,
+        &base,
+        &mut desktop_socket,
+        &session_id,
+        &first_join_token,
+        "Old Phone",
+    )
+    .await;
+    assert!(first_pair_request.get("evictsDeviceID").is_none());
+    let first_device_id = first_join
+        .get("deviceID")
+        .and_then(Value::as_str)
+        .expect("first deviceID")
+        .to_string();
+    let first_device_token = first_join
+        .get("deviceSessionToken")
+        .and_then(Value::as_str)
+        .expect("first device token")
+        .to_string();
+
+    let mut mobile_request = ws_url
+        .clone()
+        .into_client_request()
+        .expect("mobile request");
+    mobile_request.headers_mut().insert(
+        "Origin",
+        "http://localhost:4173".parse().expect("origin header"),
+    );
+    let (mut first_mobile_socket, _) = tokio_tungstenite::connect_async(mobile_request)
+        .await
+        .expect("first mobile websocket");
+    first_mobile_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": first_device_token }).to_string(),
+        ))
+        .await
+        .expect("first mobile auth send");
+    next_matching_json_message(&mut first_mobile_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+
+    let second_join_token = random_token(32);
+    let refresh_response = client
+        .post(format!("{base}/pair/refresh"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "joinToken": second_join_token,
+            "desktopSessionToken": desktop_session_token,
+            "joinTokenExpiresAt": join_token_expires_at,
+        }))
+        .send()
+        .await
+        .expect("pair refresh request");
+    assert_eq!(refresh_response.status(), StatusCode::OK);
+
+    let (second_pair_request, second_join) = join_with_desktop_approval(
+        &client,
+        &base,
+        &mut desktop_socket,
+        &session_id,
+        &second_join_token,
+        "New Phone",
+    )
+    .await;
+    assert_eq!(
+        second_pair_request
+            .get("evictsDeviceID")
+            .and_then(Value::as_str),
+        Some(first_device_id.as_str())
+    );
+    let second_device_id = second_join
+        .get("deviceID")
+        .and_then(Value::as_str)
+        .expect("second deviceID")
+        .to_string();
+
+    expect_disconnect_with_reason(&mut first_mobile_socket, 1_000, "device_evicted").await;
+    let evicted_event = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("relay.device_evicted")
+    })
+    .await;
+    assert_eq!(
+        evicted_event.get("deviceID").and_then(Value::as_str),
+        Some(first_device_id.as_str())
+    );
+    assert_eq!(
+        evicted_event.get("deviceName").and_then(Value::as_str),
+        Some("Old Phone")
+    );
+    assert_eq!(
+        evicted_event
+            .get("replacedByDeviceID")
+            .and_then(Value::as_str),
+        Some(second_device_id.as_str())
+    );
+
+    let list_payload: Value = client
+        .post(format!("{base}/devices/list"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+        }))
+        .send()
+        .await
+        .expect("devices list")
+        .json()
+        .await
+        .expect("devices list payload");
+    let devices = list_payload
+        .get("devices")
+        .and_then(Value::as_array)
+        .expect("devices array");
+    assert_eq!(devices.len(), 1);
+    assert_eq!(
+        devices[0].get("deviceID").and_then(Value::as_str),
+        Some(second_device_id.as_str())
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn invite_redeemed_after_join_token_expiry_gets_full_approval_window() {
+    let (base, task) = spawn_test_server().await;
+    let client = reqwest::Client::new();
+
+    let session_id = random_token(16);
+    let desktop_session_token = random_token(32);
+    let start_response = client
+        .post(format!("{base}/pair/start"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "joinToken": random_token(32),
+            "desktopSessionToken": desktop_session_token,
+            "joinTokenExpiresAt": chrono::Utc::now()
+                .checked_add_signed(chrono::Duration::milliseconds(500))
+                .unwrap()
+                .to_rfc3339(),
+        }))
+        .send()
+        .await
+        .expect("pair start request");
+    assert_eq!(start_response.status(), StatusCode::OK);
+    let start_payload: Value = start_response.json().await.expect("pair start payload");
+    let ws_url = start_payload
+        .get("wsURL")
+        .and_then(Value::as_str)
+        .expect("ws url")
+        .to_string();
+
+    let create_payload: Value = client
+        .post(format!("{base}/invites/create"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+            "maxRedemptions": 1,
+            "expiresAt": chrono::Utc::now()
+                .checked_add_signed(chrono::Duration::minutes(10))
+                .unwrap()
+                .to_rfc3339(),
+        }))
+        .send()
+        .await
+        .expect("invite create")
+        .json()
+        .await
+        .expect("invite create payload");
+    let invite_token = create_payload
+        .get("inviteToken")
+        .and_then(Value::as_str)
+        .expect("invite token")
+        .to_string();
+
+    let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url)
+        .await
+        .expect("desktop websocket");
+    desktop_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": desktop_session_token }).to_string(),
+        ))
+        .await
+        .expect("desktop auth send");
+    next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    let (pair_request, join_payload) = join_with_desktop_approval(
+        &client,
+        &base,
+        &mut desktop_socket,
+        &session_id,
+        &invite_token,
+        "Late Invite Phone",
+    )
+    .await;
+    assert!(join_payload.get("deviceSessionToken").is_some());
+
+    let approval_expires_at = pair_request
+        .get("expiresAt")
+        .and_then(Value::as_str)
+        .and_then(|value| chrono::DateTime::parse_from_rfc3339(value).ok())
+        .expect("pair request expiresAt");
+    let approval_window = approval_expires_at.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    assert!(
+        approval_window > chrono::Duration::seconds(30),
+        "approval window was capped by the expired join token: {approval_window}"
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn auto_approve_invite_pairs_phones_until_redemptions_run_out_and_can_be_revoked() {
+    let (base, task) = spawn_test_server_with_config(|config| {
+        config.max_devices_per_session = 4;
+    })
+    .await;
+    let client = reqwest::Client::new();
+
+    let session_id = random_token(16);
+    let desktop_session_token = random_token(32);
+    let expires_at = chrono::Utc::now()
+        .checked_add_signed(chrono::Duration::minutes(10))
+        .unwrap()
+        .to_rfc3339();
+
+    let start_response = client
+        .post(format!("{base}/pair/start"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "joinToken": random_token(32),
+            "desktopSessionToken": desktop_session_token,
+            "joinTokenExpiresAt": expires_at,
+        }))
+        .send()
+        .await
+        .expect("pair start request");
+    assert_eq!(start_response.status(), StatusCode::OK);
+
+    let create_response = client
+        .post(format!("{base}/invites/create"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+            "maxRedemptions": 2,
+            "expiresAt": expires_at,
+            "scopes": ["session_transfer"],
+            "autoApprove": true,
+        }))
+        .send()
+        .await
+        .expect("invite create");
+    assert_eq!(create_response.status(), StatusCode::OK);
+    let create_payload: Value = create_response.json().await.expect("invite create payload");
+    let invite_token = create_payload
+        .get("inviteToken")
+        .and_then(Value::as_str)
+        .expect("invite token")
+        .to_string();
+    assert!(create_payload
+        .get("pairingURI")
+        .and_then(Value::as_str)
+        .is_some_and(|uri| uri.contains(&invite_token)));
+
+    for device_name in ["Demo iPad 1", "Demo iPad 2"] {
+        let join_response = client
+            .post(format!("{base}/pair/join"))
+            .header("Origin", "http://localhost:4173")
+            .json(&json!({
+                "sessionID": session_id,
+                "joinToken": invite_token,
+                "deviceName": device_name,
+            }))
+            .send()
+            .await
+            .expect("invite pair join");
+        assert_eq!(join_response.status(), StatusCode::OK);
+    }
+
+    let exhausted_response = client
+        .post(format!("{base}/pair/join"))
+        .header("Origin", "http://localhost:4173")
+        .json(&json!({
+            "sessionID": session_id,
+            "joinToken": invite_token,
+            "deviceName": "Demo iPad 3",
+        }))
+        .send()
+        .await
+        .expect("exhausted invite pair join");
+    assert_eq!(exhausted_response.status(), StatusCode::CONFLICT);
+    let exhausted_payload: Value = exhausted_response.json().await.expect("exhausted payload");
+    assert_eq!(
+        exhausted_payload.get("error").and_then(Value::as_str),
+        Some("invite_exhausted")
+    );
+
+    let devices_payload: Value = client
+        .post(format!("{base}/devices/list"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+        }))
+        .send()
+        .await
+        .expect("devices list")
+        .json()
+        .await
+        .expect("devices list payload");
+    let devices = devices_payload
+        .get("devices")
+        .and_then(Value::as_array)
+        .expect("devices array");
+    assert_eq!(devices.len(), 2);
+    assert!(devices
+        .iter()
+        .all(|device| device.get("scopes") == Some(&json!(["session_transfer"]))));
+
+    let second_create_payload: Value = client
+        .post(format!("{base}/invites/create"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+            "maxRedemptions": 5,
+            "expiresAt": expires_at,
+        }))
+        .send()
+        .await
+        .expect("second invite create")
+        .json()
+        .await
+        .expect("second invite create payload");
+    let second_invite_id = second_create_payload
+        .pointer("/invite/inviteID")
+        .and_then(Value::as_str)
+        .expect("second invite id")
+        .to_string();
+    let second_invite_token = second_create_payload
+        .get("inviteToken")
+        .and_then(Value::as_str)
+        .expect("second invite token")
+        .to_string();
+
+    let list_payload: Value = client
+        .post(format!("{base}/invites/list"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+        }))
+        .send()
+        .await
+        .expect("invites list")
+        .json()
+        .await
+        .expect("invites list payload");
+    let invites = list_payload
+        .get("invites")
+        .and_then(Value::as_array)
+        .expect("invites array");
+    assert_eq!(invites.len(), 2);
+    let first_invite = invites
+        .iter()
+        .find(|invite| {
+            invite.get("inviteID").and_then(Value::as_str) != Some(second_invite_id.as_str())
+        })
+        .expect("first invite listed");
+    assert_eq!(
+        first_invite.get("redemptions").and_then(Value::as_u64),
+        Some(2)
+    );
+    assert_eq!(
+        first_invite.get("autoApprove").and_then(Value::as_bool),
+        Some(true)
+    );
+    assert!(invites
+        .iter()
+        .all(|invite| invite.get("inviteToken").is_none()));
+
+    let revoke_response = client
+        .post(format!("{base}/invites/revoke"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+            "inviteID": second_invite_id,
+        }))
+        .send()
+        .await
+        .expect("invite revoke");
+    assert_eq!(revoke_response.status(), StatusCode::OK);
+
+    let revoked_join_response = client
+        .post(format!("{base}/pair/join"))
+        .header("Origin", "http://localhost:4173")
+        .json(&json!({
+            "sessionID": session_id,
+            "joinToken": second_invite_token,
+            "deviceName": "Late iPad",
+        }))
+        .send()
+        .await
+        .expect("revoked invite pair join");
+    assert_eq!(revoked_join_response.status(), StatusCode::FORBIDDEN);
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn invalid_mobile_command_is_rejected_and_not_forwarded() {
+    let (
+        _base,
+        task,
+        mut desktop_socket,
+        mut mobile_socket,
+        session_id,
+        _device_token,
+        _rotated_device_token,
+    ) = pair_connected_mobile(|_| {}).await;
+
+    mobile_socket
+        .send(Message::Text(
+            json!({
+                "schemaVersion": 2,
+                "sessionID": session_id,
+                "seq": 1,
+                "payload": {
+                    "type": "command",
+                    "payload": {
+                        "name": "terminal.exec",
+                        "commandID": "cmd-1",
+                        "threadID": "11111111-1111-1111-1111-111111111111",
+                        "text": "rm -rf /"
+                    }
+                }
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("send invalid command");
+
+    let relay_error = tokio::time::timeout(Duration::from_millis(1_000), mobile_socket.next())
+        .await
+        .expect("expected relay error")
+        .expect("relay error frame")
+        .expect("relay error message");
+    let relay_error_json: Value =
+        serde_json::from_str(relay_error.to_text().expect("relay error text"))
+            .expect("relay error json");
+    assert_eq!(
+        relay_error_json.get("type").and_then(Value::as_str),
+        Some("relay.error")
+    );
+    assert_eq!(
+        relay_error_json.get("error").and_then(Value::as_str),
+        Some("invalid_command")
+    );
+
+    let desktop_next =
+        tokio::time::timeout(Duration::from_millis(250), desktop_socket.next()).await;
+    assert!(
+        desktop_next.is_err(),
+        "desktop unexpectedly received forwarded payload"
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn mobile_command_with_unexpected_field_is_rejected_and_not_forwarded() {
+    let (
+        _base,
+        task,
+        mut desktop_socket,
+        mut mobile_socket,
+        session_id,
+        _device_token,
+        _rotated_device_token,
+    ) = pair_connected_mobile(|_| {}).await;
+
+    mobile_socket
+        .send(Message::Text(
+            json!({
+                "schemaVersion": 2,
+                "sessionID": session_id,
+                "seq": 1,
+                "payload": {
+                    "type": "command",
+                    "payload": {
+                        "name": "thread.select",
+                        "commandID": "cmd-1",
+                        "threadID": "11111111-1111-1111-1111-111111111111",
+                        "unexpectedField": "not-allowed"
+                    }
+                }
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("send invalid command with unexpected field");
+
+    let relay_error = tokio::time::timeout(Duration::from_millis(1_000), mobile_socket.next())
+        .await
+        .expect("expected relay error")
+        .expect("relay error frame")
+        .expect("relay error message");
+    let relay_error_json: Value =
+        serde_json::from_str(relay_error.to_text().expect("relay error text"))
+            .expect("relay error json");
+    assert_eq!(
+        relay_error_json.get("type").and_then(Value::as_str),
+        Some("relay.error")
+    );
+    assert_eq!(
+        relay_error_json.get("error").and_then(Value::as_str),
+        Some("invalid_command")
+    );
+
+    let desktop_next =
+        tokio::time::timeout(Duration::from_millis(250), desktop_socket.next()).await;
+    assert!(
+        desktop_next.is_err(),
+        "desktop unexpectedly received forwarded payload"
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn mobile_command_ignores_spoofed_relay_metadata_and_forwards() {
+    let (
+        _base,
+        task,
+        mut desktop_socket,
+        mut mobile_socket,
+        session_id,
+        _device_token,
+        _rotated_device_token,
+    ) = pair_connected_mobile(|_| {}).await;
+
+    mobile_socket
+        .send(Message::Text(
+            json!({
+                "schemaVersion": 2,
+                "sessionID": session_id,
+                "seq": 1,
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+                "relayConnectionID": "spoofed-connection",
+                "relayDeviceID": "spoofed-device",
+                "payload": {
+                    "type": "command",
+                    "payload": {
+                        "name": "thread.select",
+                        "commandID": "cmd-1",
+                        "threadID": "11111111-1111-1111-1111-111111111111"
+                    }
+                }
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("send command with spoofed metadata");
+
+    let forwarded = tokio::time::timeout(Duration::from_millis(1_000), desktop_socket.next())
+        .await
+        .expect("expected forwarded command")
+        .expect("forwarded frame")
+        .expect("forwarded message");
+    let forwarded_json: Value =
+        serde_json::from_str(forwarded.to_text().expect("forwarded text")).expect("forwarded json");
+    assert_eq!(
+        forwarded_json
+            .pointer("/payload/payload/name")
+            .and_then(Value::as_str),
+        Some("thread.select")
+    );
+    assert_ne!(
+        forwarded_json
+            .get("relayConnectionID")
+            .and_then(Value::as_str),
+        Some("spoofed-connection")
+    );
+    assert_ne!(
+        forwarded_json.get("relayDeviceID").and_then(Value::as_str),
+        Some("spoofed-device")
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn mobile_command_with_escaped_relay_metadata_keys_is_rewritten() {
+    let (
+        _base,
+        task,
+        mut desktop_socket,
+        mut mobile_socket,
+        session_id,
+        _device_token,
+        _rotated_device_token,
+    ) = pair_connected_mobile(|_| {}).await;
+
+    let frame = format!(
+        r#"{{"schemaVersion":2,"sessionID":"{session_id}","seq":1,"\u0072elayConnectionID":"spoofed-connection","relay\u0044eviceID":"spoofed-device","payload":{{"type":"command","payload":{{"name":"thread.select","commandID":"cmd-1","threadID":"11111111-1111-1111-1111-111111111111"}}}}}}"#
+    );
+    mobile_socket
+        .send(Message::Text(frame))
+        .await
+        .expect("send command with escaped metadata keys");
+
+    let forwarded = tokio::time::timeout(Duration::from_millis(1_000), desktop_socket.next())
+        .await
+        .expect("expected forwarded command")
+        .expect("forwarded frame")
+        .expect("forwarded message");
+    let forwarded = forwarded.to_text().expect("forwarded text").to_string();
+    assert!(!forwarded.contains("spoofed"), "forwarded: {forwarded}");
+    assert_eq!(forwarded.matches("relayDeviceID").count(), 1);
+    assert_eq!(forwarded.matches("relayConnectionID").count(), 1);
+    let forwarded_json: Value = serde_json::from_str(&forwarded).expect("forwarded json");
+    assert!(forwarded_json
+        .get("relayDeviceID")
+        .and_then(Value::as_str)
+        .is_some());
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn desktop_auth_frame_after_handshake_is_rejected_and_not_forwarded() {
+    let (
+        _base,
+        task,
+        mut desktop_socket,
+        mut mobile_socket,
+        session_id,
+        _device_token,
+        _rotated_device_token,
+    ) = pair_connected_mobile(|_| {}).await;
+
+    desktop_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": random_token(32) }).to_string(),
+        ))
+        .await
+        .expect("send repeated auth frame");
+    let relay_error = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("relay.error")
+    })
+    .await;
+    assert_eq!(
+        relay_error.get("error").and_then(Value::as_str),
+        Some("invalid_payload")
+    );
+
+    desktop_socket
+        .send(Message::Text(
+            json!({
+                "schemaVersion": 2,
+                "sessionID": session_id,
+                "seq": 1,
+                "payload": { "type": "event", "payload": { "name": "thread.updated" } }
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("send desktop event");
+    let forwarded = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
+        matches!(
+            payload.get("type").and_then(Value::as_str),
+            Some("relay.auth")
+        ) || payload.get("payload").is_some()
+    })
+    .await;
+    assert_eq!(
+        forwarded.pointer("/payload/type").and_then(Value::as_str),
+        Some("event")
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn session_byte_quota_drops_desktop_events_with_relay_error() {
+    let (
+        base,
+        task,
+        mut desktop_socket,
+        mut mobile_socket,
+        session_id,
+        _device_token,
+        _rotated_device_token,
+    ) = pair_connected_mobile(|config| {
+        config.session_byte_quota_per_instance = 600;
+        config.byte_quota_window_ms = 60_000;
+    })
+    .await;
+
+    let desktop_event = |seq: u64| {
+        json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "seq": seq,
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "payload": {
+                "type": "event",
+                "payload": { "name": "thread.delta", "text": "x".repeat(300) }
+            }
+        })
+        .to_string()
+    };
+
+    for seq in 1..=3 {
+        desktop_socket
+            .send(Message::Text(desktop_event(seq)))
+            .await
+            .expect("desktop event send");
+    }
+
+    let forwarded = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
+        payload.pointer("/payload/type").and_then(Value::as_str) == Some("event")
+    })
+    .await;
+    assert_eq!(forwarded.get("seq").and_then(Value::as_u64), Some(1));
+    let quota_error = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("relay.error")
+    })
+    .await;
+    assert_eq!(
+        quota_error.get("error").and_then(Value::as_str),
+        Some("byte_quota_exceeded")
+    );
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), mobile_socket.next())
+            .await
+            .is_err(),
+        "events over quota must not reach the mobile"
+    );
+
+    let metrics: Value = reqwest::Client::new()
+        .get(format!("{base}/metricsz"))
+        .send()
+        .await
+        .expect("metrics request")
+        .json()
+        .await
+        .expect("metrics payload");
+    assert_eq!(
+        metrics.get("byteQuotaDrops").and_then(Value::as_u64),
+        Some(2)
+    );
+    let bytes_in = metrics
+        .get("sessionBytesIn")
+        .and_then(Value::as_u64)
+        .unwrap_or_default();
+    assert!(bytes_in >= 3 * desktop_event(1).len() as u64);
+    assert!(
+        metrics
+            .get("sessionBytesOut")
+            .and_then(Value::as_u64)
+            .unwrap_or_default()
+            >= desktop_event(1).len() as u64
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn invalid_snapshot_request_with_negative_last_seq_is_rejected() {
+    let (
+        _base,
+        task,
+        mut desktop_socket,
+        mut mobile_socket,
+        session_id,
+        _device_token,
+        _rotated_device_token,
+    ) = pair_connected_mobile(|_| {}).await;
+
+    mobile_socket
+        .send(Message::Text(
+            json!({
+                "type": "relay.snapshot_request",
+                "sessionID": session_id,
+                "lastSeq": -1,
+                "reason": "integration-test"
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("send invalid snapshot request");
+
+    let relay_error = tokio::time::timeout(Duration::from_millis(1_000), mobile_socket.next())
+        .await
+        .expect("expected relay error")
+        .expect("relay error frame")
+        .expect("relay error message");
+    let relay_error_json: Value =
+        serde_json::from_str(relay_error.to_text().expect("relay error text"))
+            .expect("relay error json");
+    assert_eq!(
+        relay_error_json.get("type").and_then(Value::as_str),
+        Some("relay.error")
+    );
+    assert_eq!(
+        relay_error_json.get("error").and_then(Value::as_str),
+        Some("invalid_snapshot_request")
+    );
+
+    let desktop_next =
+        tokio::time::timeout(Duration::from_millis(250), desktop_socket.next()).await;
+    assert!(
+        desktop_next.is_err(),
+        "desktop unexpectedly received forwarded snapshot payload"
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn snapshot_request_accepts_numeric_string_last_seq_for_backward_compatibility() {
+    let (
+        _base,
+        task,
+        mut desktop_socket,
+        mut mobile_socket,
+        session_id,
+        _device_token,
+        _rotated_device_token,
+    ) = pair_connected_mobile(|_| {}).await;
+
+    mobile_socket
+        .send(Message::Text(
+            json!({
+                "type": "relay.snapshot_request",
+                "sessionID": session_id,
+                "lastSeq": "42",
+                "reason": "integration-test"
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("send snapshot request with string lastSeq");
+
+    let forwarded = tokio::time::timeout(Duration::from_millis(1_000), desktop_socket.next())
+        .await
+        .expect("expected forwarded snapshot request")
+        .expect("forwarded frame")
+        .expect("forwarded message");
+    let forwarded_json: Value =
+        serde_json::from_str(forwarded.to_text().expect("forwarded text")).expect("forwarded json");
+    assert_eq!(
+        forwarded_json.get("type").and_then(Value::as_str),
+        Some("relay.snapshot_request")
+    );
+
+    let relay_error = tokio::time::timeout(Duration::from_millis(250), mobile_socket.next()).await;
+    assert!(
+        relay_error.is_err(),
+        "mobile unexpectedly received relay.error"
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn per_device_command_rate_limit_blocks_excess_mobile_commands() {
+    let (
+        _base,
+        task,
+        mut desktop_socket,
+        mut mobile_socket,
+        session_id,
+        _device_token,
+        _rotated_device_token,
+    ) = pair_connected_mobile(|config| {
+        config.max_remote_commands_per_minute = 2;
+    })
+    .await;
+
+    for seq in 1..=3_u64 {
+        mobile_socket
+            .send(Message::Text(
+                json!({
+                    "schemaVersion": 2,
+                    "sessionID": session_id,
+                    "seq": seq,
+                    "payload": {
+                        "type": "command",
+                        "payload": {
+                            "name": "thread.select",
+                            "commandID": format!("cmd-{}", seq),
+                            "threadID": "11111111-1111-1111-1111-111111111111"
+                        }
+                    }
+                })
+                .to_string(),
+            ))
+            .await
+            .expect("send thread.select command");
+    }
+
+    for _ in 0..2 {
+        let forwarded = tokio::time::timeout(Duration::from_millis(1_000), desktop_socket.next())
+            .await
+            .expect("expected forwarded command")
+            .expect("forwarded frame")
+            .expect("forwarded message");
+        let forwarded_json: Value =
+            serde_json::from_str(forwarded.to_text().expect("forwarded text"))
+                .expect("forwarded json");
+        assert_eq!(
+            forwarded_json
+                .pointer("/payload/payload/name")
+                .and_then(Value::as_str),
+            Some("thread.select")
+        );
+    }
+
+    let relay_error = tokio::time::timeout(Duration::from_millis(1_000), mobile_socket.next())
+        .await
+        .expect("expected rate-limit error")
+        .expect("rate-limit frame")
+        .expect("rate-limit message");
+    let relay_error_json: Value =
+        serde_json::from_str(relay_error.to_text().expect("rate-limit text"))
+            .expect("rate-limit json");
+    assert_eq!(
+        relay_error_json.get("type").and_then(Value::as_str),
+        Some("relay.error")
+    );
+    assert_eq!(
+        relay_error_json.get("error").and_then(Value::as_str),
+        Some("command_rate_limited")
+    );
+
+    let desktop_next =
+        tokio::time::timeout(Duration::from_millis(250), desktop_socket.next()).await;
+    assert!(
+        desktop_next.is_err(),
+        "desktop unexpectedly received extra forwarded payload"
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn per_session_command_rate_limit_blocks_excess_mobile_commands() {
+    let (
+        _base,
+        task,
+        mut desktop_socket,
+        mut mobile_socket,
+        session_id,
+        _device_token,
+        _rotated_device_token,
+    ) = pair_connected_mobile(|config| {
+        config.max_remote_commands_per_minute = 10;
+        config.max_remote_session_commands_per_minute = 2;
+    })
+    .await;
+
+    for seq in 1..=3_u64 {
+        mobile_socket
+            .send(Message::Text(
+                json!({
+                    "schemaVersion": 2,
+                    "sessionID": session_id,
+                    "seq": seq,
+                    "payload": {
+                        "type": "command",
+                        "payload": {
+                            "name": "thread.select",
+                            "commandID": format!("cmd-{}", seq),
+                            "threadID": "11111111-1111-1111-1111-111111111111"
+                        }
+                    }
+                })
+                .to_string(),
+            ))
+            .await
+            .expect("send thread.select command");
+    }
+
+    for _ in 0..2 {
+        let forwarded = tokio::time::timeout(Duration::from_millis(1_000), desktop_socket.next())
+            .await
+            .expect("expected forwarded command")
+            .expect("forwarded frame")
+            .expect("forwarded message");
+        let forwarded_json: Value =
+            serde_json::from_str(forwarded.to_text().expect("forwarded text"))
+                .expect("forwarded json");
+        assert_eq!(
+            forwarded_json
+                .pointer("/payload/payload/name")
+                .and_then(Value::as_str),
+            Some("thread.select")
+        );
+    }
+
+    let relay_error = tokio::time::timeout(Duration::from_millis(1_000), mobile_socket.next())
+        .await
+        .expect("expected session rate-limit error")
+        .expect("rate-limit frame")
+        .expect("rate-limit message");
+    let relay_error_json: Value =
+        serde_json::from_str(relay_error.to_text().expect("rate-limit text"))
+            .expect("rate-limit json");
+    assert_eq!(
+        relay_error_json.get("type").and_then(Value::as_str),
+        Some("relay.error")
+    );
+    assert_eq!(
+        relay_error_json.get("error").and_then(Value::as_str),
+        Some("command_rate_limited")
+    );
+
+    let desktop_next =
+        tokio::time::timeout(Duration::from_millis(250), desktop_socket.next()).await;
+    assert!(
+        desktop_next.is_err(),
+        "desktop unexpectedly received extra forwarded payload"
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn per_device_snapshot_request_rate_limit_blocks_excess_requests() {
+    let (
+        _base,
+        task,
+        mut desktop_socket,
+        mut mobile_socket,
+        session_id,
+        _device_token,
+        _rotated_device_token,
+    ) = pair_connected_mobile(|config| {
+        config.max_snapshot_requests_per_minute = 2;
+    })
+    .await;
+
+    for request_number in 1..=3_u64 {
+        mobile_socket
+            .send(Message::Text(
+                json!({
+                    "type": "relay.snapshot_request",
+                    "sessionID": session_id,
+                    "lastSeq": request_number,
+                    "reason": "integration-test"
+                })
+                .to_string(),
+            ))
+            .await
+            .expect("send snapshot request");
+    }
+
+    for _ in 0..2 {
+        let forwarded = tokio::time::timeout(Duration::from_millis(1_000), desktop_socket.next())
+            .await
+            .expect("expected forwarded snapshot request")
+            .expect("forwarded frame")
+            .expect("forwarded message");
+        let forwarded_json: Value =
+            serde_json::from_str(forwarded.to_text().expect("forwarded text"))
+                .expect("forwarded json");
+        assert_eq!(
+            forwarded_json.get("type").and_then(Value::as_str),
+            Some("relay.snapshot_request")
+        );
+    }
+
+    let relay_error = tokio::time::timeout(Duration::from_millis(1_000), mobile_socket.next())
+        .await
+        .expect("expected snapshot rate-limit error")
+        .expect("rate-limit frame")
+        .expect("rate-limit message");
+    let relay_error_json: Value =
+        serde_json::from_str(relay_error.to_text().expect("rate-limit text"))
+            .expect("rate-limit json");
+    assert_eq!(
+        relay_error_json.get("type").and_then(Value::as_str),
+        Some("relay.error")
+    );
+    assert_eq!(
+        relay_error_json.get("error").and_then(Value::as_str),
+        Some("snapshot_rate_limited")
+    );
+
+    let desktop_next =
+        tokio::time::timeout(Duration::from_millis(250), desktop_socket.next()).await;
+    assert!(
+        desktop_next.is_err(),
+        "desktop unexpectedly received extra snapshot payload"
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn replayed_mobile_command_sequence_is_rejected() {
+    let (
+        _base,
+        task,
+        mut desktop_socket,
+        mut mobile_socket,
+        session_id,
+        _device_token,
+        _rotated_device_token,
+    ) = pair_connected_mobile(|_| {}).await;
+
+    let command_payload = json!({
+        "schemaVersion": 2,
+        "sessionID": session_id,
+        "seq": 1,
+        "payload": {
+            "type": "command",
+            "payload": {
+                "name": "thread.select",
+                "commandID": "cmd-1",
+                "threadID": "11111111-1111-1111-1111-111111111111"
+            }
+        }
+    })
+    .to_string();
+
+    mobile_socket
+        .send(Message::Text(command_payload.clone()))
+        .await
+        .expect("send first command");
+
+    let forwarded = tokio::time::timeout(Duration::from_millis(1_000), desktop_socket.next())
+        .await
+        .expect("first command forwarded")
+        .expect("forwarded frame")
+        .expect("forwarded message");
+    let forwarded_json: Value =
+        serde_json::from_str(forwarded.to_text().expect("forwarded text")).expect("forwarded json");
+    assert_eq!(
+        forwarded_json
+            .pointer("/payload/payload/name")
+            .and_then(Value::as_str),
+        Some("thread.select")
+    );
+
+    mobile_socket
+        .send(Message::Text(command_payload))
+        .await
+        .expect("send replayed command");
+
+    let relay_error = tokio::time::timeout(Duration::from_millis(1_000), mobile_socket.next())
+        .await
+        .expect("expected replay rejection")
+        .expect("replay rejection frame")
+        .expect("replay rejection message");
+    let relay_error_json: Value =
+        serde_json::from_str(relay_error.to_text().expect("replay rejection text"))
+            .expect("replay rejection json");
+    assert_eq!(
+        relay_error_json.get("type").and_then(Value::as_str),
+        Some("relay.error")
+    );
+    assert_eq!(
+        relay_error_json.get("error").and_then(Value::as_str),
+        Some("replayed_command")
+    );
+
+    let desktop_next =
+        tokio::time::timeout(Duration::from_millis(250), desktop_socket.next()).await;
+    assert!(
+        desktop_next.is_err(),
+        "desktop unexpectedly received replayed payload"
+    );
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn per_socket_websocket_message_rate_limit_disconnects_abusive_client() {
+    let (
+        _base,
+        task,
+        mut desktop_socket,
+        mut mobile_socket,
+        session_id,
+        _device_token,
+        _rotated_device_token,
+    ) = pair_connected_mobile(|config| {
+        config.max_ws_messages_per_minute = 2;
+        config.max_remote_commands_per_minute = 10_000;
+        config.max_remote_session_commands_per_minute = 10_000;
+    })
+    .await;
+
+    for seq in 1..=3_u64 {
+        mobile_socket
+            .send(Message::Text(
+                json!({
+                    "schemaVersion": 2,
+                    "sessionID": session_id,
+                    "seq": seq,
+                    "payload": {
+                        "type": "command",
+                        "payload": {
                             "name": "thread.select",
                             "commandID": format!("cmd-{}", seq),
                             "threadID": "11111111-1111-1111-1111-111111111111"
@@ -2686,51 +4978,737 @@ async fn per_socket_websocket_message_rate_limit_disconnects_abusive_client() {
                 .to_string(),
             ))
             .await
-            .expect("send command");
-    }
+            .expect("send command");
+    }
+
+    for _ in 0..2 {
+        // Relay control frames (device counts) may overtake forwarded commands.
+        let forwarded_json = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+            !payload
+                .get("type")
+                .and_then(Value::as_str)
+                .is_some_and(|message_type| message_type.starts_with("relay."))
+        })
+        .await;
+        assert_eq!(
+            forwarded_json
+                .pointer("/payload/payload/name")
+                .and_then(Value::as_str),
+            Some("thread.select")
+        );
+    }
+
+    let disconnect_or_close =
+        tokio::time::timeout(Duration::from_millis(1_500), mobile_socket.next())
+            .await
+            .expect("expected rate-limit disconnect frame");
+    match disconnect_or_close {
+        Some(Ok(Message::Text(text))) => {
+            let payload: Value = serde_json::from_str(text.as_ref()).expect("disconnect json");
+            assert_eq!(
+                payload.get("type").and_then(Value::as_str),
+                Some("disconnect")
+            );
+            assert_eq!(
+                payload.get("reason").and_then(Value::as_str),
+                Some("socket_rate_limited")
+            );
+        }
+        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {}
+        other => panic!("unexpected websocket frame: {other:?}"),
+    }
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn redis_persistence_restores_session_after_restart_when_configured() {
+    let Some(redis_url) = std::env::var("REMOTE_CONTROL_REDIS_TEST_URL")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+    else {
+        return;
+    };
+
+    let redis_key_prefix = format!("relay-test-{}", random_token(8));
+    let client = reqwest::Client::new();
+    let session_id = random_token(16);
+    let join_token = random_token(32);
+    let desktop_session_token = random_token(32);
+
+    let (base_a, task_a) = spawn_test_server_with_config(|config| {
+        config.redis_url = Some(redis_url.clone());
+        config.redis_key_prefix = redis_key_prefix.clone();
+    })
+    .await;
+
+    let start_response = client
+        .post(format!("{base_a}/pair/start"))
+        .json(&json!({
+            "sessionID": session_id,
+            "joinToken": join_token,
+            "desktopSessionToken": desktop_session_token,
+            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+            "idleTimeoutSeconds": 1800,
+        }))
+        .send()
+        .await
+        .expect("pair start request");
+    assert_eq!(start_response.status(), StatusCode::OK);
+
+    task_a.abort();
+
+    let (base_b, task_b) = spawn_test_server_with_config(|config| {
+        config.redis_url = Some(redis_url.clone());
+        config.redis_key_prefix = redis_key_prefix.clone();
+    })
+    .await;
+
+    let ws_url = base_b.replace("http://", "ws://") + "/ws";
+    let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url)
+        .await
+        .expect("desktop websocket after restart");
+    desktop_socket
+        .send(Message::Text(
+            json!({
+                "type": "relay.auth",
+                "token": desktop_session_token
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("desktop auth send");
+
+    let auth_message = tokio::time::timeout(Duration::from_millis(1_000), desktop_socket.next())
+        .await
+        .expect("auth frame timeout")
+        .expect("auth frame")
+        .expect("auth message");
+    let auth_json: Value =
+        serde_json::from_str(auth_message.to_text().expect("auth text")).expect("auth json");
+    assert_eq!(
+        auth_json.get("type").and_then(Value::as_str),
+        Some("auth_ok")
+    );
+    assert_eq!(
+        auth_json.get("role").and_then(Value::as_str),
+        Some("desktop")
+    );
+    assert_eq!(
+        auth_json.get("desktopConnected").and_then(Value::as_bool),
+        Some(true)
+    );
+    assert_eq!(
+        auth_json.get("sessionID").and_then(Value::as_str),
+        Some(session_id.as_str())
+    );
+
+    task_b.abort();
+}
+
+#[tokio::test]
+async fn redis_token_index_resolves_desktop_token_on_pod_started_before_session() {
+    let Some(redis_url) = std::env::var("REMOTE_CONTROL_REDIS_TEST_URL")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+    else {
+        return;
+    };
+
+    let redis_key_prefix = format!("relay-test-{}", random_token(8));
+    let client = reqwest::Client::new();
+    let session_id = random_token(16);
+    let join_token = random_token(32);
+    let desktop_session_token = random_token(32);
+
+    let (base_a, task_a) = spawn_test_server_with_config(|config| {
+        config.redis_url = Some(redis_url.clone());
+        config.redis_key_prefix = redis_key_prefix.clone();
+    })
+    .await;
+    let (base_b, task_b) = spawn_test_server_with_config(|config| {
+        config.redis_url = Some(redis_url.clone());
+        config.redis_key_prefix = redis_key_prefix.clone();
+    })
+    .await;
+
+    let start_response = client
+        .post(format!("{base_a}/pair/start"))
+        .json(&json!({
+            "sessionID": session_id,
+            "joinToken": join_token,
+            "desktopSessionToken": desktop_session_token,
+            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+            "idleTimeoutSeconds": 1800,
+        }))
+        .send()
+        .await
+        .expect("pair start request");
+    assert_eq!(start_response.status(), StatusCode::OK);
+
+    let ws_url = base_b.replace("http://", "ws://") + "/ws";
+    let (mut unknown_socket, _) = tokio_tungstenite::connect_async(&ws_url)
+        .await
+        .expect("unknown token websocket");
+    unknown_socket
+        .send(Message::Text(
+            json!({
+                "type": "relay.auth",
+                "token": random_token(32)
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("unknown token auth send");
+    expect_disconnect_with_reason(&mut unknown_socket, 1_000, "session_expired").await;
+
+    let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url)
+        .await
+        .expect("desktop websocket on second pod");
+    desktop_socket
+        .send(Message::Text(
+            json!({
+                "type": "relay.auth",
+                "token": desktop_session_token
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("desktop auth send");
+    let auth_json = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+    assert_eq!(
+        auth_json.get("sessionID").and_then(Value::as_str),
+        Some(session_id.as_str())
+    );
+
+    task_a.abort();
+    task_b.abort();
+}
+
+#[tokio::test]
+async fn redis_token_index_follows_session_saves_revokes_and_stop() {
+    use base64::Engine;
+    use sha2::Digest;
+
+    let Some(redis_url) = std::env::var("REMOTE_CONTROL_REDIS_TEST_URL")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+    else {
+        return;
+    };
+
+    let redis_key_prefix = format!("relay-test-{}", random_token(8));
+    let client = reqwest::Client::new();
+    let session_id = random_token(16);
+    let join_token = random_token(32);
+    let desktop_session_token = random_token(32);
+    let (base, task) = spawn_test_server_with_config(|config| {
+        config.redis_url = Some(redis_url.clone());
+        config.redis_key_prefix = redis_key_prefix.clone();
+    })
+    .await;
+    let mut redis = redis::Client::open(redis_url.as_str())
+        .expect("redis client")
+        .get_multiplexed_async_connection()
+        .await
+        .expect("redis connection");
+    let token_key = |token: &str| {
+        format!(
+            "{redis_key_prefix}:token:v1:{}",
+            base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .encode(sha2::Sha256::digest(token.as_bytes()))
+        )
+    };
+    let session_tokens_key = format!("{redis_key_prefix}:session:tokens:v1:{session_id}");
+
+    let start_response = client
+        .post(format!("{base}/pair/start"))
+        .json(&json!({
+            "sessionID": session_id,
+            "joinToken": join_token,
+            "desktopSessionToken": desktop_session_token,
+            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+            "idleTimeoutSeconds": 1800,
+        }))
+        .send()
+        .await
+        .expect("pair start request");
+    assert_eq!(start_response.status(), StatusCode::OK);
+    let mut desktop_socket = connect_authenticated_socket(&base, &desktop_session_token).await;
+    let (_, join_payload) = join_with_desktop_approval(
+        &client,
+        &base,
+        &mut desktop_socket,
+        &session_id,
+        &join_token,
+        "Token Index Phone",
+    )
+    .await;
+    let device_token = join_payload["deviceSessionToken"]
+        .as_str()
+        .expect("device token")
+        .to_string();
+    let device_id = join_payload["deviceID"]
+        .as_str()
+        .expect("device id")
+        .to_string();
+
+    let indexed_session = |value: Option<String>| value.as_deref() == Some(session_id.as_str());
+    let desktop_owner: Option<String> =
+        redis::AsyncCommands::get(&mut redis, token_key(&desktop_session_token))
+            .await
+            .expect("desktop token key");
+    assert!(indexed_session(desktop_owner));
+    let device_owner: Option<String> =
+        redis::AsyncCommands::get(&mut redis, token_key(&device_token))
+            .await
+            .expect("device token key");
+    assert!(indexed_session(device_owner));
+    let indexed: Vec<String> = redis::AsyncCommands::smembers(&mut redis, &session_tokens_key)
+        .await
+        .expect("session token set");
+    assert!(indexed.contains(&token_key(&desktop_session_token)));
+    assert!(indexed.contains(&token_key(&device_token)));
+
+    let revoke_response = client
+        .post(format!("{base}/devices/revoke"))
+        .json(&json!({
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+            "deviceID": device_id,
+        }))
+        .send()
+        .await
+        .expect("device revoke request");
+    assert_eq!(revoke_response.status(), StatusCode::OK);
+    let device_owner: Option<String> =
+        redis::AsyncCommands::get(&mut redis, token_key(&device_token))
+            .await
+            .expect("revoked device token key");
+    assert_eq!(
+        device_owner, None,
+        "a revoked device token leaves the index"
+    );
+    let indexed: Vec<String> = redis::AsyncCommands::smembers(&mut redis, &session_tokens_key)
+        .await
+        .expect("session token set after revoke");
+    assert!(!indexed.contains(&token_key(&device_token)));
+    assert!(indexed.contains(&token_key(&desktop_session_token)));
+
+    let stop_response = client
+        .post(format!("{base}/pair/stop"))
+        .json(&json!({
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+        }))
+        .send()
+        .await
+        .expect("pair stop request");
+    assert_eq!(stop_response.status(), StatusCode::OK);
+    let desktop_owner: Option<String> =
+        redis::AsyncCommands::get(&mut redis, token_key(&desktop_session_token))
+            .await
+            .expect("stopped desktop token key");
+    assert_eq!(desktop_owner, None, "a stopped session leaves the index");
+    let remaining: bool = redis::AsyncCommands::exists(&mut redis, &session_tokens_key)
+        .await
+        .expect("session token set exists");
+    assert!(!remaining);
+
+    task.abort();
+}
+
+#[tokio::test]
+async fn redis_persistence_preserves_rotated_mobile_grace_token_across_restart() {
+    let Some(redis_url) = std::env::var("REMOTE_CONTROL_REDIS_TEST_URL")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+    else {
+        return;
+    };
+
+    let redis_key_prefix = format!("relay-test-{}", random_token(8));
+    let client = reqwest::Client::new();
+    let session_id = random_token(16);
+    let join_token = random_token(32);
+    let desktop_session_token = random_token(32);
+
+    let (base_a, task_a) = spawn_test_server_with_config(|config| {
+        config.redis_url = Some(redis_url.clone());
+        config.redis_key_prefix = redis_key_prefix.clone();
+        config.token_rotation_grace_ms = 30_000;
+    })
+    .await;
+
+    let start_response = client
+        .post(format!("{base_a}/pair/start"))
+        .json(&json!({
+            "sessionID": session_id,
+            "joinToken": join_token,
+            "desktopSessionToken": desktop_session_token,
+            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+            "idleTimeoutSeconds": 1800,
+        }))
+        .send()
+        .await
+        .expect("pair start request");
+    assert_eq!(start_response.status(), StatusCode::OK);
+    let start_payload: Value = start_response.json().await.expect("pair start payload");
+    let ws_url = start_payload
+        .get("wsURL")
+        .and_then(Value::as_str)
+        .expect("ws url")
+        .to_string();
+
+    let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url)
+        .await
+        .expect("desktop websocket");
+    desktop_socket
+        .send(Message::Text(
+            json!({
+                "type": "relay.auth",
+                "token": desktop_session_token
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("desktop auth send");
+    let _desktop_auth = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+
+    let join_future = tokio::spawn({
+        let client = client.clone();
+        let base_a = base_a.clone();
+        let session_id = session_id.clone();
+        let join_token = join_token.clone();
+        async move {
+            client
+                .post(format!("{base_a}/pair/join"))
+                .header("Origin", "http://localhost:4173")
+                .json(&json!({
+                    "sessionID": session_id,
+                    "joinToken": join_token,
+                    "deviceName": "Restart Test iPhone",
+                }))
+                .send()
+                .await
+                .expect("pair join request")
+        }
+    });
+
+    let pair_request = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("relay.pair_request")
+    })
+    .await;
+    let request_id = pair_request
+        .get("requestID")
+        .and_then(Value::as_str)
+        .expect("requestID")
+        .to_string();
+
+    desktop_socket
+        .send(Message::Text(
+            json!({
+                "type": "relay.pair_decision",
+                "sessionID": session_id,
+                "requestID": request_id,
+                "approved": true,
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("desktop pair decision send");
+
+    let join_response = join_future.await.expect("join task");
+    assert_eq!(join_response.status(), StatusCode::OK);
+    let join_payload: Value = join_response.json().await.expect("join payload");
+    let first_device_token = join_payload
+        .get("deviceSessionToken")
+        .and_then(Value::as_str)
+        .expect("device token")
+        .to_string();
+
+    let mut mobile_request = ws_url
+        .clone()
+        .into_client_request()
+        .expect("mobile request");
+    mobile_request.headers_mut().insert(
+        "Origin",
+        "http://localhost:4173".parse().expect("origin header"),
+    );
+    let (mut mobile_socket, _) = tokio_tungstenite::connect_async(mobile_request)
+        .await
+        .expect("mobile websocket");
+    mobile_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": first_device_token }).to_string(),
+        ))
+        .await
+        .expect("mobile auth send");
+    let mobile_auth = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+    let _rotated_device_token = mobile_auth
+        .get("nextDeviceSessionToken")
+        .and_then(Value::as_str)
+        .expect("rotated device token")
+        .to_string();
+
+    mobile_socket
+        .close(None)
+        .await
+        .expect("mobile socket close");
+    desktop_socket
+        .close(None)
+        .await
+        .expect("desktop socket close");
+    task_a.abort();
 
-    for _ in 0..2 {
-        let forwarded = tokio::time::timeout(Duration::from_millis(1_000), desktop_socket.next())
-            .await
-            .expect("expected forwarded command")
-            .expect("forwarded frame")
-            .expect("forwarded message");
-        let forwarded_json: Value =
-            serde_json::from_str(forwarded.to_text().expect("forwarded text"))
-                .expect("forwarded json");
-        assert_eq!(
-            forwarded_json
-                .pointer("/payload/payload/name")
-                .and_then(Value::as_str),
-            Some("thread.select")
-        );
-    }
+    let (base_b, task_b) = spawn_test_server_with_config(|config| {
+        config.redis_url = Some(redis_url.clone());
+        config.redis_key_prefix = redis_key_prefix.clone();
+        config.token_rotation_grace_ms = 30_000;
+    })
+    .await;
 
-    let disconnect_or_close =
-        tokio::time::timeout(Duration::from_millis(1_500), mobile_socket.next())
-            .await
-            .expect("expected rate-limit disconnect frame");
-    match disconnect_or_close {
-        Some(Ok(Message::Text(text))) => {
-            let payload: Value = serde_json::from_str(text.as_ref()).expect("disconnect json");
-            assert_eq!(
-                payload.get("type").and_then(Value::as_str),
-                Some("disconnect")
-            );
-            assert_eq!(
-                payload.get("reason").and_then(Value::as_str),
-                Some("socket_rate_limited")
-            );
+    let mut reconnect_request = (base_b.replace("http://", "ws://") + "/ws")
+        .into_client_request()
+        .expect("reconnect request");
+    reconnect_request.headers_mut().insert(
+        "Origin",
+        "http://localhost:4173".parse().expect("origin header"),
+    );
+    let (mut reconnect_socket, _) = tokio_tungstenite::connect_async(reconnect_request)
+        .await
+        .expect("reconnect websocket");
+    reconnect_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": first_device_token }).to_string(),
+        ))
+        .await
+        .expect("reconnect auth send");
+
+    let reconnect_auth = next_matching_json_message(&mut reconnect_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+    assert_eq!(
+        reconnect_auth.get("role").and_then(Value::as_str),
+        Some("mobile")
+    );
+    assert!(reconnect_auth
+        .get("nextDeviceSessionToken")
+        .and_then(Value::as_str)
+        .is_some());
+
+    reconnect_socket
+        .close(None)
+        .await
+        .expect("reconnect socket close");
+    task_b.abort();
+}
+
+#[tokio::test]
+async fn redis_persistence_accepts_rotated_mobile_token_on_second_pod_immediately() {
+    let Some(redis_url) = std::env::var("REMOTE_CONTROL_REDIS_TEST_URL")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+    else {
+        return;
+    };
+
+    let redis_key_prefix = format!("relay-test-{}", random_token(8));
+    let client = reqwest::Client::new();
+    let session_id = random_token(16);
+    let join_token = random_token(32);
+    let desktop_session_token = random_token(32);
+
+    let (base_a, task_a) = spawn_test_server_with_config(|config| {
+        config.redis_url = Some(redis_url.clone());
+        config.redis_key_prefix = redis_key_prefix.clone();
+        config.token_rotation_grace_ms = 30_000;
+    })
+    .await;
+    let (base_b, task_b) = spawn_test_server_with_config(|config| {
+        config.redis_url = Some(redis_url.clone());
+        config.redis_key_prefix = redis_key_prefix.clone();
+        config.token_rotation_grace_ms = 30_000;
+    })
+    .await;
+
+    let start_response = client
+        .post(format!("{base_a}/pair/start"))
+        .json(&json!({
+            "sessionID": session_id,
+            "joinToken": join_token,
+            "desktopSessionToken": desktop_session_token,
+            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+            "idleTimeoutSeconds": 1800,
+        }))
+        .send()
+        .await
+        .expect("pair start request");
+    assert_eq!(start_response.status(), StatusCode::OK);
+    let start_payload: Value = start_response.json().await.expect("pair start payload");
+    let ws_url = start_payload
+        .get("wsURL")
+        .and_then(Value::as_str)
+        .expect("ws url")
+        .to_string();
+
+    let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url)
+        .await
+        .expect("desktop websocket");
+    desktop_socket
+        .send(Message::Text(
+            json!({
+                "type": "relay.auth",
+                "token": desktop_session_token
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("desktop auth send");
+    let _desktop_auth = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+
+    let join_future = tokio::spawn({
+        let client = client.clone();
+        let base_a = base_a.clone();
+        let session_id = session_id.clone();
+        let join_token = join_token.clone();
+        async move {
+            client
+                .post(format!("{base_a}/pair/join"))
+                .header("Origin", "http://localhost:4173")
+                .json(&json!({
+                    "sessionID": session_id,
+                    "joinToken": join_token,
+                    "deviceName": "Pod Hop iPhone",
+                }))
+                .send()
+                .await
+                .expect("pair join request")
         }
-        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {}
-        other => panic!("unexpected websocket frame: {other:?}"),
-    }
+    });
+
+    let pair_request = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("relay.pair_request")
+    })
+    .await;
+    let request_id = pair_request
+        .get("requestID")
+        .and_then(Value::as_str)
+        .expect("requestID")
+        .to_string();
+
+    desktop_socket
+        .send(Message::Text(
+            json!({
+                "type": "relay.pair_decision",
+                "sessionID": session_id,
+                "requestID": request_id,
+                "approved": true,
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("desktop pair decision send");
+
+    let join_response = join_future.await.expect("join task");
+    assert_eq!(join_response.status(), StatusCode::OK);
+    let join_payload: Value = join_response.json().await.expect("join payload");
+    let first_device_token = join_payload
+        .get("deviceSessionToken")
+        .and_then(Value::as_str)
+        .expect("device token")
+        .to_string();
+
+    let mut first_mobile_request = ws_url
+        .clone()
+        .into_client_request()
+        .expect("first mobile request");
+    first_mobile_request.headers_mut().insert(
+        "Origin",
+        "http://localhost:4173".parse().expect("origin header"),
+    );
+    let (mut first_mobile_socket, _) = tokio_tungstenite::connect_async(first_mobile_request)
+        .await
+        .expect("first mobile websocket");
+    first_mobile_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": first_device_token }).to_string(),
+        ))
+        .await
+        .expect("first mobile auth send");
+    let first_mobile_auth =
+        next_matching_json_message(&mut first_mobile_socket, 1_000, |payload| {
+            payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+        })
+        .await;
+    let rotated_device_token = first_mobile_auth
+        .get("nextDeviceSessionToken")
+        .and_then(Value::as_str)
+        .expect("rotated device token")
+        .to_string();
+
+    let mut second_mobile_request = (base_b.replace("http://", "ws://") + "/ws")
+        .into_client_request()
+        .expect("second mobile request");
+    second_mobile_request.headers_mut().insert(
+        "Origin",
+        "http://localhost:4173".parse().expect("origin header"),
+    );
+    let (mut second_mobile_socket, _) = tokio_tungstenite::connect_async(second_mobile_request)
+        .await
+        .expect("second mobile websocket");
+    second_mobile_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": rotated_device_token }).to_string(),
+        ))
+        .await
+        .expect("second mobile auth send");
+
+    let second_mobile_auth =
+        next_matching_json_message(&mut second_mobile_socket, 1_000, |payload| {
+            payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+        })
+        .await;
+    assert_eq!(
+        second_mobile_auth.get("role").and_then(Value::as_str),
+        Some("mobile")
+    );
+    assert!(second_mobile_auth
+        .get("nextDeviceSessionToken")
+        .and_then(Value::as_str)
+        .is_some());
 
-    task.abort();
+    second_mobile_socket
+        .close(None)
+        .await
+        .expect("second mobile socket close");
+    first_mobile_socket
+        .close(None)
+        .await
+        .expect("first mobile socket close");
+    desktop_socket
+        .close(None)
+        .await
+        .expect("desktop socket close");
+    task_b.abort();
+    task_a.abort();
 }
 
 #[tokio::test]
-async fn redis_persistence_restores_session_after_restart_when_configured() {
+async fn redis_persistence_accepts_fresh_pair_mobile_token_on_second_pod_immediately() {
     let Some(redis_url) = std::env::var("REMOTE_CONTROL_REDIS_TEST_URL")
         .ok()
         .filter(|value| !value.trim().is_empty())
@@ -2747,6 +5725,13 @@ async fn redis_persistence_restores_session_after_restart_when_configured() {
     let (base_a, task_a) = spawn_test_server_with_config(|config| {
         config.redis_url = Some(redis_url.clone());
         config.redis_key_prefix = redis_key_prefix.clone();
+        config.token_rotation_grace_ms = 30_000;
+    })
+    .await;
+    let (base_b, task_b) = spawn_test_server_with_config(|config| {
+        config.redis_url = Some(redis_url.clone());
+        config.redis_key_prefix = redis_key_prefix.clone();
+        config.token_rotation_grace_ms = 30_000;
     })
     .await;
 
@@ -2763,19 +5748,16 @@ async fn redis_persistence_restores_session_after_restart_when_configured() {
         .await
         .expect("pair start request");
     assert_eq!(start_response.status(), StatusCode::OK);
+    let start_payload: Value = start_response.json().await.expect("pair start payload");
+    let ws_url = start_payload
+        .get("wsURL")
+        .and_then(Value::as_str)
+        .expect("ws url")
+        .to_string();
 
-    task_a.abort();
-
-    let (base_b, task_b) = spawn_test_server_with_config(|config| {
-        config.redis_url = Some(redis_url.clone());
-        config.redis_key_prefix = redis_key_prefix.clone();
-    })
-    .await;
-
-    let ws_url = base_b.replace("http://", "ws://") + "/ws";
     let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url)
         .await
-        .expect("desktop websocket after restart");
+        .expect("desktop websocket");
     desktop_socket
         .send(Message::Text(
             json!({
@@ -2786,44 +5768,134 @@ async fn redis_persistence_restores_session_after_restart_when_configured() {
         ))
         .await
         .expect("desktop auth send");
+    let _desktop_auth = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
 
-    let auth_message = tokio::time::timeout(Duration::from_millis(1_000), desktop_socket.next())
+    let join_future = tokio::spawn({
+        let client = client.clone();
+        let base_a = base_a.clone();
+        let session_id = session_id.clone();
+        let join_token = join_token.clone();
+        async move {
+            client
+                .post(format!("{base_a}/pair/join"))
+                .header("Origin", "http://localhost:4173")
+                .json(&json!({
+                    "sessionID": session_id,
+                    "joinToken": join_token,
+                    "deviceName": "Fresh Pair Pod Hop iPhone",
+                }))
+                .send()
+                .await
+                .expect("pair join request")
+        }
+    });
+
+    let pair_request = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("relay.pair_request")
+    })
+    .await;
+    let request_id = pair_request
+        .get("requestID")
+        .and_then(Value::as_str)
+        .expect("requestID")
+        .to_string();
+
+    desktop_socket
+        .send(Message::Text(
+            json!({
+                "type": "relay.pair_decision",
+                "sessionID": session_id,
+                "requestID": request_id,
+                "approved": true,
+            })
+            .to_string(),
+        ))
         .await
-        .expect("auth frame timeout")
-        .expect("auth frame")
-        .expect("auth message");
-    let auth_json: Value =
-        serde_json::from_str(auth_message.to_text().expect("auth text")).expect("auth json");
-    assert_eq!(
-        auth_json.get("type").and_then(Value::as_str),
-        Some("auth_ok")
-    );
-    assert_eq!(
-        auth_json.get("role").and_then(Value::as_str),
-        Some("desktop")
-    );
-    assert_eq!(
-        auth_json.get("desktopConnected").and_then(Value::as_bool),
-        Some(true)
+        .expect("desktop pair decision send");
+
+    let join_response = join_future.await.expect("join task");
+    assert_eq!(join_response.status(), StatusCode::OK);
+    let join_payload: Value = join_response.json().await.expect("join payload");
+    let first_device_token = join_payload
+        .get("deviceSessionToken")
+        .and_then(Value::as_str)
+        .expect("device token")
+        .to_string();
+
+    let mut second_mobile_request = (base_b.replace("http://", "ws://") + "/ws")
+        .into_client_request()
+        .expect("second mobile request");
+    second_mobile_request.headers_mut().insert(
+        "Origin",
+        "http://localhost:4173".parse().expect("origin header"),
     );
+    let (mut second_mobile_socket, _) = tokio_tungstenite::connect_async(second_mobile_request)
+        .await
+        .expect("second mobile websocket");
+    second_mobile_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": first_device_token }).to_string(),
+        ))
+        .await
+        .expect("second mobile auth send");
+
+    let second_mobile_auth =
+        next_matching_json_message(&mut second_mobile_socket, 1_000, |payload| {
+            payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+        })
+        .await;
     assert_eq!(
-        auth_json.get("sessionID").and_then(Value::as_str),
-        Some(session_id.as_str())
+        second_mobile_auth.get("role").and_then(Value::as_str),
+        Some("mobile")
     );
+    assert!(second_mobile_auth
+        .get("nextDeviceSessionToken")
+        .and_then(Value::as_str)
+        .is_some());
 
+    second_mobile_socket
+        .close(None)
+        .await
+        .expect("second mobile socket close");
+    desktop_socket
+        .close(None)
+        .await
+        .expect("desktop socket close");
     task_b.abort();
+    task_a.abort();
 }
 
 #[tokio::test]
-async fn redis_persistence_preserves_rotated_mobile_grace_token_across_restart() {
+async fn nats_presence_probe_marks_second_pod_mobile_auth_online_and_forwards_commands() {
+    assert_second_pod_mobile_auth_sees_desktop_and_forwards_commands(false).await;
+}
+
+#[tokio::test]
+async fn presence_registry_routes_second_pod_mobile_commands_without_probe() {
+    assert_second_pod_mobile_auth_sees_desktop_and_forwards_commands(true).await;
+}
+
+async fn assert_second_pod_mobile_auth_sees_desktop_and_forwards_commands(
+    presence_registry_enabled: bool,
+) {
     let Some(redis_url) = std::env::var("REMOTE_CONTROL_REDIS_TEST_URL")
         .ok()
         .filter(|value| !value.trim().is_empty())
     else {
         return;
     };
+    let Some(nats_url) = std::env::var("REMOTE_CONTROL_NATS_TEST_URL")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+    else {
+        return;
+    };
 
     let redis_key_prefix = format!("relay-test-{}", random_token(8));
+    let nats_subject_prefix = format!("relay-test-{}", random_token(8));
     let client = reqwest::Client::new();
     let session_id = random_token(16);
     let join_token = random_token(32);
@@ -2832,6 +5904,18 @@ async fn redis_persistence_preserves_rotated_mobile_grace_token_across_restart()
     let (base_a, task_a) = spawn_test_server_with_config(|config| {
         config.redis_url = Some(redis_url.clone());
         config.redis_key_prefix = redis_key_prefix.clone();
+        config.nats_url = Some(nats_url.clone());
+        config.nats_subject_prefix = nats_subject_prefix.clone();
+        config.presence_registry_enabled = presence_registry_enabled;
+        config.token_rotation_grace_ms = 30_000;
+    })
+    .await;
+    let (base_b, task_b) = spawn_test_server_with_config(|config| {
+        config.redis_url = Some(redis_url.clone());
+        config.redis_key_prefix = redis_key_prefix.clone();
+        config.nats_url = Some(nats_url.clone());
+        config.nats_subject_prefix = nats_subject_prefix.clone();
+        config.presence_registry_enabled = presence_registry_enabled;
         config.token_rotation_grace_ms = 30_000;
     })
     .await;
@@ -2886,7 +5970,7 @@ async fn redis_persistence_preserves_rotated_mobile_grace_token_across_restart()
                 .json(&json!({
                     "sessionID": session_id,
                     "joinToken": join_token,
-                    "deviceName": "Restart Test iPhone",
+                    "deviceName": "NATS Pod Hop iPhone",
                 }))
                 .send()
                 .await
@@ -2926,134 +6010,122 @@ async fn redis_persistence_preserves_rotated_mobile_grace_token_across_restart()
         .expect("device token")
         .to_string();
 
-    let mut mobile_request = ws_url
-        .clone()
+    let mut second_mobile_request = (base_b.replace("http://", "ws://") + "/ws")
         .into_client_request()
-        .expect("mobile request");
-    mobile_request.headers_mut().insert(
+        .expect("second mobile request");
+    second_mobile_request.headers_mut().insert(
         "Origin",
         "http://localhost:4173".parse().expect("origin header"),
     );
-    let (mut mobile_socket, _) = tokio_tungstenite::connect_async(mobile_request)
+    let (mut second_mobile_socket, _) = tokio_tungstenite::connect_async(second_mobile_request)
         .await
-        .expect("mobile websocket");
-    mobile_socket
+        .expect("second mobile websocket");
+    second_mobile_socket
         .send(Message::Text(
             json!({ "type": "relay.auth", "token": first_device_token }).to_string(),
         ))
         .await
-        .expect("mobile auth send");
-    let mobile_auth = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
-        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
-    })
-    .await;
-    let _rotated_device_token = mobile_auth
-        .get("nextDeviceSessionToken")
-        .and_then(Value::as_str)
-        .expect("rotated device token")
-        .to_string();
-
-    mobile_socket
-        .close(None)
-        .await
-        .expect("mobile socket close");
-    desktop_socket
-        .close(None)
-        .await
-        .expect("desktop socket close");
-    task_a.abort();
-
-    let (base_b, task_b) = spawn_test_server_with_config(|config| {
-        config.redis_url = Some(redis_url.clone());
-        config.redis_key_prefix = redis_key_prefix.clone();
-        config.token_rotation_grace_ms = 30_000;
-    })
-    .await;
+        .expect("second mobile auth send");
 
-    let mut reconnect_request = (base_b.replace("http://", "ws://") + "/ws")
-        .into_client_request()
-        .expect("reconnect request");
-    reconnect_request.headers_mut().insert(
-        "Origin",
-        "http://localhost:4173".parse().expect("origin header"),
+    let second_mobile_auth =
+        next_matching_json_message(&mut second_mobile_socket, 1_500, |payload| {
+            payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+        })
+        .await;
+    assert_eq!(
+        second_mobile_auth.get("role").and_then(Value::as_str),
+        Some("mobile")
     );
-    let (mut reconnect_socket, _) = tokio_tungstenite::connect_async(reconnect_request)
-        .await
-        .expect("reconnect websocket");
-    reconnect_socket
+    assert_eq!(
+        second_mobile_auth
+            .get("desktopConnected")
+            .and_then(Value::as_bool),
+        Some(true)
+    );
+
+    second_mobile_socket
         .send(Message::Text(
-            json!({ "type": "relay.auth", "token": first_device_token }).to_string(),
+            json!({
+                "schemaVersion": 2,
+                "sessionID": session_id,
+                "seq": 1,
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+                "payload": {
+                    "type": "command",
+                    "payload": {
+                        "name": "thread.select",
+                        "commandID": "cmd-cross-pod-1",
+                        "threadID": "11111111-1111-1111-1111-111111111111"
+                    }
+                }
+            })
+            .to_string(),
         ))
         .await
-        .expect("reconnect auth send");
+        .expect("cross-pod command send");
 
-    let reconnect_auth = next_matching_json_message(&mut reconnect_socket, 1_000, |payload| {
-        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    let forwarded = next_matching_json_message(&mut desktop_socket, 1_500, |payload| {
+        payload
+            .pointer("/payload/payload/name")
+            .and_then(Value::as_str)
+            == Some("thread.select")
     })
     .await;
     assert_eq!(
-        reconnect_auth.get("role").and_then(Value::as_str),
-        Some("mobile")
+        forwarded
+            .pointer("/payload/payload/commandID")
+            .and_then(Value::as_str),
+        Some("cmd-cross-pod-1")
     );
-    assert!(reconnect_auth
-        .get("nextDeviceSessionToken")
-        .and_then(Value::as_str)
-        .is_some());
 
-    reconnect_socket
+    second_mobile_socket
         .close(None)
         .await
-        .expect("reconnect socket close");
+        .expect("second mobile socket close");
+    desktop_socket
+        .close(None)
+        .await
+        .expect("desktop socket close");
     task_b.abort();
+    task_a.abort();
 }
 
 #[tokio::test]
-async fn redis_persistence_accepts_rotated_mobile_token_on_second_pod_immediately() {
-    let Some(redis_url) = std::env::var("REMOTE_CONTROL_REDIS_TEST_URL")
-        .ok()
-        .filter(|value| !value.trim().is_empty())
-    else {
-        return;
-    };
-
-    let redis_key_prefix = format!("relay-test-{}", random_token(8));
+async fn in_process_bus_routes_pairing_and_commands_between_instances() {
+    let bus: Arc<dyn CrossInstanceBus> = Arc::new(InProcessCrossInstanceBus::new());
     let client = reqwest::Client::new();
     let session_id = random_token(16);
     let join_token = random_token(32);
     let desktop_session_token = random_token(32);
 
-    let (base_a, task_a) = spawn_test_server_with_config(|config| {
-        config.redis_url = Some(redis_url.clone());
-        config.redis_key_prefix = redis_key_prefix.clone();
-        config.token_rotation_grace_ms = 30_000;
-    })
-    .await;
-    let (base_b, task_b) = spawn_test_server_with_config(|config| {
-        config.redis_url = Some(redis_url.clone());
-        config.redis_key_prefix = redis_key_prefix.clone();
-        config.token_rotation_grace_ms = 30_000;
-    })
-    .await;
+    let (base_a, task_a) = spawn_test_server_with_bus(Some(bus.clone()), |_| {}).await;
+    let (base_b, task_b) = spawn_test_server_with_bus(Some(bus.clone()), |_| {}).await;
 
-    let start_response = client
-        .post(format!("{base_a}/pair/start"))
-        .json(&json!({
-            "sessionID": session_id,
-            "joinToken": join_token,
-            "desktopSessionToken": desktop_session_token,
-            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
-            "idleTimeoutSeconds": 1800,
-        }))
-        .send()
-        .await
-        .expect("pair start request");
-    assert_eq!(start_response.status(), StatusCode::OK);
-    let start_payload: Value = start_response.json().await.expect("pair start payload");
-    let ws_url = start_payload
-        .get("wsURL")
-        .and_then(Value::as_str)
-        .expect("ws url")
-        .to_string();
+    // Without shared persistence each instance learns about the session from its own pair start.
+    let mut ws_url = String::new();
+    for base in [&base_a, &base_b] {
+        let start_response = client
+            .post(format!("{base}/pair/start"))
+            .json(&json!({
+                "sessionID": session_id,
+                "joinToken": join_token,
+                "desktopSessionToken": desktop_session_token,
+                "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+                "idleTimeoutSeconds": 1800,
+            }))
+            .send()
+            .await
+            .expect("pair start request");
+        assert_eq!(start_response.status(), StatusCode::OK);
+        if ws_url.is_empty() {
+            let start_payload: Value = start_response.json().await.expect("pair start payload");
+            ws_url = start_payload
+                .get("wsURL")
+                .and_then(Value::as_str)
+                .expect("ws url")
+                .to_string();
+        }
+    }
 
     let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url)
         .await
@@ -3075,17 +6147,17 @@ async fn redis_persistence_accepts_rotated_mobile_token_on_second_pod_immediatel
 
     let join_future = tokio::spawn({
         let client = client.clone();
-        let base_a = base_a.clone();
+        let base_b = base_b.clone();
         let session_id = session_id.clone();
         let join_token = join_token.clone();
         async move {
             client
-                .post(format!("{base_a}/pair/join"))
+                .post(format!("{base_b}/pair/join"))
                 .header("Origin", "http://localhost:4173")
                 .json(&json!({
                     "sessionID": session_id,
                     "joinToken": join_token,
-                    "deviceName": "Pod Hop iPhone",
+                    "deviceName": "In-Process Pod Hop iPhone",
                 }))
                 .send()
                 .await
@@ -3119,97 +6191,411 @@ async fn redis_persistence_accepts_rotated_mobile_token_on_second_pod_immediatel
     let join_response = join_future.await.expect("join task");
     assert_eq!(join_response.status(), StatusCode::OK);
     let join_payload: Value = join_response.json().await.expect("join payload");
-    let first_device_token = join_payload
+    let device_token = join_payload
         .get("deviceSessionToken")
         .and_then(Value::as_str)
         .expect("device token")
         .to_string();
 
-    let mut first_mobile_request = ws_url
-        .clone()
+    let mut mobile_request = (base_b.replace("http://", "ws://") + "/ws")
         .into_client_request()
-        .expect("first mobile request");
-    first_mobile_request.headers_mut().insert(
+        .expect("mobile request");
+    mobile_request.headers_mut().insert(
         "Origin",
         "http://localhost:4173".parse().expect("origin header"),
     );
-    let (mut first_mobile_socket, _) = tokio_tungstenite::connect_async(first_mobile_request)
+    let (mut mobile_socket, _) = tokio_tungstenite::connect_async(mobile_request)
         .await
-        .expect("first mobile websocket");
-    first_mobile_socket
+        .expect("mobile websocket");
+    mobile_socket
         .send(Message::Text(
-            json!({ "type": "relay.auth", "token": first_device_token }).to_string(),
+            json!({ "type": "relay.auth", "token": device_token }).to_string(),
         ))
         .await
-        .expect("first mobile auth send");
-    let first_mobile_auth =
-        next_matching_json_message(&mut first_mobile_socket, 1_000, |payload| {
-            payload.get("type").and_then(Value::as_str) == Some("auth_ok")
-        })
-        .await;
-    let rotated_device_token = first_mobile_auth
-        .get("nextDeviceSessionToken")
+        .expect("mobile auth send");
+
+    let mobile_auth = next_matching_json_message(&mut mobile_socket, 1_500, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+    assert_eq!(
+        mobile_auth.get("desktopConnected").and_then(Value::as_bool),
+        Some(true)
+    );
+
+    mobile_socket
+        .send(Message::Text(
+            json!({
+                "schemaVersion": 2,
+                "sessionID": session_id,
+                "seq": 1,
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+                "payload": {
+                    "type": "command",
+                    "payload": {
+                        "name": "thread.select",
+                        "commandID": "cmd-in-process-1",
+                        "threadID": "11111111-1111-1111-1111-111111111111"
+                    }
+                }
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("cross-instance command send");
+
+    let forwarded = next_matching_json_message(&mut desktop_socket, 1_500, |payload| {
+        payload
+            .pointer("/payload/payload/name")
+            .and_then(Value::as_str)
+            == Some("thread.select")
+    })
+    .await;
+    assert_eq!(
+        forwarded
+            .pointer("/payload/payload/commandID")
+            .and_then(Value::as_str),
+        Some("cmd-in-process-1")
+    );
+
+    mobile_socket
+        .close(None)
+        .await
+        .expect("mobile socket close");
+    desktop_socket
+        .close(None)
+        .await
+        .expect("desktop socket close");
+    task_b.abort();
+    task_a.abort();
+}
+
+#[tokio::test]
+async fn session_transfer_on_one_instance_keeps_mobiles_on_other_instances_connected() {
+    let bus: Arc<dyn CrossInstanceBus> = Arc::new(InProcessCrossInstanceBus::new());
+    let client = reqwest::Client::new();
+    let session_id = random_token(16);
+    let join_token = random_token(32);
+    let desktop_session_token = random_token(32);
+
+    let (base_a, task_a) = spawn_test_server_with_bus(Some(bus.clone()), |_| {}).await;
+    let (base_b, task_b) = spawn_test_server_with_bus(Some(bus.clone()), |_| {}).await;
+
+    for base in [&base_a, &base_b] {
+        let start_response = client
+            .post(format!("{base}/pair/start"))
+            .json(&json!({
+                "sessionID": session_id,
+                "joinToken": join_token,
+                "desktopSessionToken": desktop_session_token,
+                "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+                "idleTimeoutSeconds": 1800,
+            }))
+            .send()
+            .await
+            .expect("pair start request");
+        assert_eq!(start_response.status(), StatusCode::OK);
+    }
+
+    let ws_url_a = base_a.replace("http://", "ws://") + "/ws";
+    let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url_a)
+        .await
+        .expect("desktop websocket");
+    desktop_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": desktop_session_token }).to_string(),
+        ))
+        .await
+        .expect("desktop auth send");
+    next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+
+    let (_, join_payload) = join_with_desktop_approval(
+        &client,
+        &base_b,
+        &mut desktop_socket,
+        &session_id,
+        &join_token,
+        "Other Pod iPhone",
+    )
+    .await;
+    let device_token = join_payload
+        .get("deviceSessionToken")
         .and_then(Value::as_str)
-        .expect("rotated device token")
+        .expect("device token")
         .to_string();
 
-    let mut second_mobile_request = (base_b.replace("http://", "ws://") + "/ws")
+    let mut mobile_request = (base_b.replace("http://", "ws://") + "/ws")
         .into_client_request()
-        .expect("second mobile request");
-    second_mobile_request.headers_mut().insert(
+        .expect("mobile request");
+    mobile_request.headers_mut().insert(
         "Origin",
         "http://localhost:4173".parse().expect("origin header"),
     );
-    let (mut second_mobile_socket, _) = tokio_tungstenite::connect_async(second_mobile_request)
+    let (mut mobile_socket, _) = tokio_tungstenite::connect_async(mobile_request)
         .await
-        .expect("second mobile websocket");
-    second_mobile_socket
+        .expect("mobile websocket");
+    mobile_socket
         .send(Message::Text(
-            json!({ "type": "relay.auth", "token": rotated_device_token }).to_string(),
+            json!({ "type": "relay.auth", "token": device_token }).to_string(),
+        ))
+        .await
+        .expect("mobile auth send");
+    next_matching_json_message(&mut mobile_socket, 1_500, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+
+    let issue_payload: Value = client
+        .post(format!("{base_a}/session/transfer/issue"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "desktopSessionToken": desktop_session_token,
+        }))
+        .send()
+        .await
+        .expect("session transfer issue")
+        .json()
+        .await
+        .expect("issue payload");
+    let transfer_token = issue_payload
+        .get("transferToken")
+        .and_then(Value::as_str)
+        .expect("transfer token")
+        .to_string();
+
+    let new_desktop_session_token = random_token(32);
+    let redeem_response = client
+        .post(format!("{base_a}/session/transfer/redeem"))
+        .json(&json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "transferToken": transfer_token,
+            "desktopSessionToken": new_desktop_session_token,
+        }))
+        .send()
+        .await
+        .expect("session transfer redeem");
+    assert_eq!(redeem_response.status(), StatusCode::OK);
+    expect_disconnect_with_reason(&mut desktop_socket, 1_000, "session_transferred").await;
+
+    let desktop_status = next_matching_json_message(&mut mobile_socket, 1_500, |payload| {
+        matches!(
+            payload.get("type").and_then(Value::as_str),
+            Some("disconnect" | "relay.desktop_status")
+        )
+    })
+    .await;
+    assert_eq!(
+        desktop_status.get("type").and_then(Value::as_str),
+        Some("relay.desktop_status")
+    );
+    assert_eq!(
+        desktop_status
+            .get("desktopConnected")
+            .and_then(Value::as_bool),
+        Some(false)
+    );
+
+    let (mut new_desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url_a)
+        .await
+        .expect("new desktop websocket");
+    new_desktop_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": new_desktop_session_token }).to_string(),
+        ))
+        .await
+        .expect("new desktop auth send");
+    next_matching_json_message(&mut new_desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+    new_desktop_socket
+        .send(Message::Text(
+            json!({
+                "schemaVersion": 2,
+                "sessionID": session_id,
+                "seq": 1,
+                "payload": { "type": "event", "payload": { "name": "thread.updated" } }
+            })
+            .to_string(),
         ))
         .await
-        .expect("second mobile auth send");
+        .expect("new desktop event send");
+    let forwarded = next_matching_json_message(&mut mobile_socket, 1_500, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("disconnect")
+            || payload.get("payload").is_some()
+    })
+    .await;
+    assert_eq!(
+        forwarded.pointer("/payload/type").and_then(Value::as_str),
+        Some("event")
+    );
 
-    let second_mobile_auth =
-        next_matching_json_message(&mut second_mobile_socket, 1_000, |payload| {
-            payload.get("type").and_then(Value::as_str) == Some("auth_ok")
-        })
-        .await;
+    task_b.abort();
+    task_a.abort();
+}
+
+#[tokio::test]
+async fn redis_transfer_token_is_redeemed_once_across_instances() {
+    let Some(redis_url) = std::env::var("REMOTE_CONTROL_REDIS_TEST_URL")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+    else {
+        return;
+    };
+
+    let redis_key_prefix = format!("relay-test-{}", random_token(8));
+    let configure = |config: &mut RelayConfig| {
+        config.redis_url = Some(redis_url.clone());
+        config.redis_key_prefix = redis_key_prefix.clone();
+    };
+    let (base_a, task_a) = spawn_test_server_with_config(configure).await;
+    let (base_b, task_b) = spawn_test_server_with_config(configure).await;
+    let client = reqwest::Client::new();
+
+    let session_id = random_token(16);
+    let other_session_id = random_token(16);
+    let desktop_session_token = random_token(32);
+    let other_desktop_session_token = random_token(32);
+    for (session_id, desktop_session_token) in [
+        (&session_id, &desktop_session_token),
+        (&other_session_id, &other_desktop_session_token),
+    ] {
+        let start_response = client
+            .post(format!("{base_a}/pair/start"))
+            .json(&json!({
+                "sessionID": session_id,
+                "joinToken": random_token(32),
+                "desktopSessionToken": desktop_session_token,
+                "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+                "idleTimeoutSeconds": 1800,
+            }))
+            .send()
+            .await
+            .expect("pair start request");
+        assert_eq!(start_response.status(), StatusCode::OK);
+    }
+
+    let issue = |base: String, desktop_session_token: String| {
+        let client = client.clone();
+        let session_id = session_id.clone();
+        async move {
+            let issue_response = client
+                .post(format!("{base}/session/transfer/issue"))
+                .json(&json!({
+                    "schemaVersion": 2,
+                    "sessionID": session_id,
+                    "desktopSessionToken": desktop_session_token,
+                }))
+                .send()
+                .await
+                .expect("session transfer issue");
+            assert_eq!(issue_response.status(), StatusCode::OK);
+            let issue_payload: Value = issue_response.json().await.expect("issue payload");
+            issue_payload["transferToken"]
+                .as_str()
+                .expect("transfer token")
+                .to_string()
+        }
+    };
+    let redeem = |base: String, transfer_token: String, desktop_session_token: String| {
+        let client = client.clone();
+        let session_id = session_id.clone();
+        async move {
+            client
+                .post(format!("{base}/session/transfer/redeem"))
+                .json(&json!({
+                    "schemaVersion": 2,
+                    "sessionID": session_id,
+                    "transferToken": transfer_token,
+                    "desktopSessionToken": desktop_session_token,
+                }))
+                .send()
+                .await
+                .expect("session transfer redeem")
+        }
+    };
+
+    // Both instances hold the same grant, but only one of them may spend it.
+    let transfer_token = issue(base_a.clone(), desktop_session_token.clone()).await;
+    let desktop_token_a = random_token(32);
+    let desktop_token_b = random_token(32);
+    let (redeemed_a, redeemed_b) = tokio::join!(
+        redeem(
+            base_a.clone(),
+            transfer_token.clone(),
+            desktop_token_a.clone()
+        ),
+        redeem(
+            base_b.clone(),
+            transfer_token.clone(),
+            desktop_token_b.clone()
+        ),
+    );
+    let mut statuses = [redeemed_a.status(), redeemed_b.status()];
+    statuses.sort();
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::FORBIDDEN]);
+    let (winning_base, current_desktop_token) = if redeemed_a.status() == StatusCode::OK {
+        (base_a.clone(), desktop_token_a)
+    } else {
+        (base_b.clone(), desktop_token_b)
+    };
+
+    // Instance B never loaded the other session, so only the persisted token index knows that
+    // its desktop token is taken.
+    let transfer_token = issue(winning_base, current_desktop_token).await;
+    // Let instance B's per-request reload throttle lapse so it picks up the new grant.
+    tokio::time::sleep(Duration::from_millis(1_100)).await;
+    let in_use_response = redeem(
+        base_b.clone(),
+        transfer_token.clone(),
+        other_desktop_session_token,
+    )
+    .await;
+    assert_eq!(in_use_response.status(), StatusCode::CONFLICT);
+    let in_use_payload: Value = in_use_response.json().await.expect("in use payload");
     assert_eq!(
-        second_mobile_auth.get("role").and_then(Value::as_str),
-        Some("mobile")
+        in_use_payload.get("error").and_then(Value::as_str),
+        Some("desktop_session_token_in_use")
     );
-    assert!(second_mobile_auth
-        .get("nextDeviceSessionToken")
-        .and_then(Value::as_str)
-        .is_some());
+    let retry_response = redeem(base_b.clone(), transfer_token, random_token(32)).await;
+    assert_eq!(retry_response.status(), StatusCode::OK);
 
-    second_mobile_socket
-        .close(None)
-        .await
-        .expect("second mobile socket close");
-    first_mobile_socket
-        .close(None)
-        .await
-        .expect("first mobile socket close");
-    desktop_socket
-        .close(None)
-        .await
-        .expect("desktop socket close");
     task_b.abort();
     task_a.abort();
 }
 
 #[tokio::test]
-async fn redis_persistence_accepts_fresh_pair_mobile_token_on_second_pod_immediately() {
+async fn nats_cross_pod_desktop_events_preserve_publish_order() {
+    assert_cross_pod_desktop_events_preserve_publish_order(false).await;
+}
+
+#[tokio::test]
+async fn nats_jetstream_cross_pod_desktop_events_preserve_publish_order() {
+    assert_cross_pod_desktop_events_preserve_publish_order(true).await;
+}
+
+async fn assert_cross_pod_desktop_events_preserve_publish_order(nats_jetstream_enabled: bool) {
     let Some(redis_url) = std::env::var("REMOTE_CONTROL_REDIS_TEST_URL")
         .ok()
         .filter(|value| !value.trim().is_empty())
     else {
         return;
     };
+    let Some(nats_url) = std::env::var("REMOTE_CONTROL_NATS_TEST_URL")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+    else {
+        return;
+    };
 
     let redis_key_prefix = format!("relay-test-{}", random_token(8));
+    let nats_subject_prefix = format!("relay-test-{}", random_token(8));
+    let nats_jetstream_stream = format!("relay-test-{}", random_token(8));
     let client = reqwest::Client::new();
     let session_id = random_token(16);
     let join_token = random_token(32);
@@ -3218,12 +6604,20 @@ async fn redis_persistence_accepts_fresh_pair_mobile_token_on_second_pod_immedia
     let (base_a, task_a) = spawn_test_server_with_config(|config| {
         config.redis_url = Some(redis_url.clone());
         config.redis_key_prefix = redis_key_prefix.clone();
+        config.nats_url = Some(nats_url.clone());
+        config.nats_subject_prefix = nats_subject_prefix.clone();
+        config.nats_jetstream_enabled = nats_jetstream_enabled;
+        config.nats_jetstream_stream = nats_jetstream_stream.clone();
         config.token_rotation_grace_ms = 30_000;
     })
     .await;
     let (base_b, task_b) = spawn_test_server_with_config(|config| {
         config.redis_url = Some(redis_url.clone());
         config.redis_key_prefix = redis_key_prefix.clone();
+        config.nats_url = Some(nats_url.clone());
+        config.nats_subject_prefix = nats_subject_prefix.clone();
+        config.nats_jetstream_enabled = nats_jetstream_enabled;
+        config.nats_jetstream_stream = nats_jetstream_stream.clone();
         config.token_rotation_grace_ms = 30_000;
     })
     .await;
@@ -3278,7 +6672,7 @@ async fn redis_persistence_accepts_fresh_pair_mobile_token_on_second_pod_immedia
                 .json(&json!({
                     "sessionID": session_id,
                     "joinToken": join_token,
-                    "deviceName": "Fresh Pair Pod Hop iPhone",
+                    "deviceName": "Ordered Event iPhone",
                 }))
                 .send()
                 .await
@@ -3334,20 +6728,62 @@ async fn redis_persistence_accepts_fresh_pair_mobile_token_on_second_pod_immedia
         ))
         .await
         .expect("second mobile auth send");
-
-    let second_mobile_auth =
-        next_matching_json_message(&mut second_mobile_socket, 1_000, |payload| {
+    let _second_mobile_auth =
+        next_matching_json_message(&mut second_mobile_socket, 1_500, |payload| {
             payload.get("type").and_then(Value::as_str) == Some("auth_ok")
         })
         .await;
-    assert_eq!(
-        second_mobile_auth.get("role").and_then(Value::as_str),
-        Some("mobile")
-    );
-    assert!(second_mobile_auth
-        .get("nextDeviceSessionToken")
-        .and_then(Value::as_str)
-        .is_some());
+
+    let expected_bodies = (1..=8)
+        .map(|index| format!("event-{index}"))
+        .collect::<Vec<_>>();
+
+    for (seq, body) in expected_bodies.iter().enumerate() {
+        desktop_socket
+            .send(Message::Text(
+                json!({
+                    "schemaVersion": 2,
+                    "sessionID": session_id,
+                    "seq": seq + 1,
+                    "timestamp": chrono::Utc::now().to_rfc3339(),
+                    "payload": {
+                        "type": "event",
+                        "payload": {
+                            "name": "relay.order_probe",
+                            "threadID": "thread-order",
+                            "body": body,
+                            "messageID": format!("msg-order-{}", seq + 1),
+                            "role": "assistant",
+                            "createdAt": chrono::Utc::now().to_rfc3339(),
+                        }
+                    }
+                })
+                .to_string(),
+            ))
+            .await
+            .expect("ordered event send");
+    }
+
+    let mut received_bodies = Vec::new();
+    while received_bodies.len() < expected_bodies.len() {
+        let event = next_matching_json_message(&mut second_mobile_socket, 1_500, |payload| {
+            payload.pointer("/payload/type").and_then(Value::as_str) == Some("event")
+                && payload
+                    .pointer("/payload/payload/name")
+                    .and_then(Value::as_str)
+                    == Some("relay.order_probe")
+        })
+        .await;
+        received_bodies.push(
+            event
+                .pointer("/payload/payload/body")
+                .and_then(Value::as_str)
+                .expect("ordered event body")
+                .to_string(),
+        );
+    }
+
+    assert_eq!(received_bodies, expected_bodies);
 
     second_mobile_socket
         .close(None)
@@ -3362,52 +6798,24 @@ async fn redis_persistence_accepts_fresh_pair_mobile_token_on_second_pod_immedia
 }
 
 #[tokio::test]
-async fn nats_presence_probe_marks_second_pod_mobile_auth_online_and_forwards_commands() {
-    let Some(redis_url) = std::env::var("REMOTE_CONTROL_REDIS_TEST_URL")
-        .ok()
-        .filter(|value| !value.trim().is_empty())
-    else {
-        return;
-    };
-    let Some(nats_url) = std::env::var("REMOTE_CONTROL_NATS_TEST_URL")
-        .ok()
-        .filter(|value| !value.trim().is_empty())
-    else {
-        return;
-    };
-
-    let redis_key_prefix = format!("relay-test-{}", random_token(8));
-    let nats_subject_prefix = format!("relay-test-{}", random_token(8));
+async fn async_pair_join_reports_pending_then_approved_through_pair_status() {
+    let (base, task) = spawn_test_server().await;
     let client = reqwest::Client::new();
+
     let session_id = random_token(16);
     let join_token = random_token(32);
     let desktop_session_token = random_token(32);
-
-    let (base_a, task_a) = spawn_test_server_with_config(|config| {
-        config.redis_url = Some(redis_url.clone());
-        config.redis_key_prefix = redis_key_prefix.clone();
-        config.nats_url = Some(nats_url.clone());
-        config.nats_subject_prefix = nats_subject_prefix.clone();
-        config.token_rotation_grace_ms = 30_000;
-    })
-    .await;
-    let (base_b, task_b) = spawn_test_server_with_config(|config| {
-        config.redis_url = Some(redis_url.clone());
-        config.redis_key_prefix = redis_key_prefix.clone();
-        config.nats_url = Some(nats_url.clone());
-        config.nats_subject_prefix = nats_subject_prefix.clone();
-        config.token_rotation_grace_ms = 30_000;
-    })
-    .await;
-
     let start_response = client
-        .post(format!("{base_a}/pair/start"))
+        .post(format!("{base}/pair/start"))
         .json(&json!({
+            "schemaVersion": 2,
             "sessionID": session_id,
             "joinToken": join_token,
             "desktopSessionToken": desktop_session_token,
-            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
-            "idleTimeoutSeconds": 1800,
+            "joinTokenExpiresAt": chrono::Utc::now()
+                .checked_add_signed(chrono::Duration::minutes(2))
+                .unwrap()
+                .to_rfc3339(),
         }))
         .send()
         .await
@@ -3425,49 +6833,82 @@ async fn nats_presence_probe_marks_second_pod_mobile_auth_online_and_forwards_co
         .expect("desktop websocket");
     desktop_socket
         .send(Message::Text(
-            json!({
-                "type": "relay.auth",
-                "token": desktop_session_token
-            })
-            .to_string(),
+            json!({ "type": "relay.auth", "token": desktop_session_token }).to_string(),
         ))
         .await
         .expect("desktop auth send");
-    let _desktop_auth = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+    next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
         payload.get("type").and_then(Value::as_str) == Some("auth_ok")
     })
     .await;
 
-    let join_future = tokio::spawn({
-        let client = client.clone();
-        let base_a = base_a.clone();
-        let session_id = session_id.clone();
-        let join_token = join_token.clone();
-        async move {
-            client
-                .post(format!("{base_a}/pair/join"))
-                .header("Origin", "http://localhost:4173")
-                .json(&json!({
-                    "sessionID": session_id,
-                    "joinToken": join_token,
-                    "deviceName": "NATS Pod Hop iPhone",
-                }))
-                .send()
-                .await
-                .expect("pair join request")
-        }
-    });
+    let join_response = client
+        .post(format!("{base}/pair/join"))
+        .header("Origin", "http://localhost:4173")
+        .json(&json!({
+            "sessionID": session_id,
+            "joinToken": join_token,
+            "deviceName": "Async Phone",
+            "async": true,
+        }))
+        .send()
+        .await
+        .expect("async pair join request");
+    assert_eq!(join_response.status(), StatusCode::ACCEPTED);
+    let join_payload: Value = join_response.json().await.expect("async join payload");
+    assert_eq!(
+        join_payload.get("status").and_then(Value::as_str),
+        Some("pending")
+    );
+    let request_id = join_payload
+        .get("requestID")
+        .and_then(Value::as_str)
+        .expect("requestID")
+        .to_string();
+    let status_token = join_payload
+        .get("statusToken")
+        .and_then(Value::as_str)
+        .expect("statusToken")
+        .to_string();
+
+    let pair_status = |token: String, wait_ms: u64| {
+        client
+            .post(format!("{base}/pair/status"))
+            .header("Origin", "http://localhost:4173")
+            .json(&json!({
+                "sessionID": session_id,
+                "requestID": request_id,
+                "statusToken": token,
+                "waitMs": wait_ms,
+            }))
+            .send()
+    };
+
+    let pending_response = pair_status(status_token.clone(), 0)
+        .await
+        .expect("pair status request");
+    assert_eq!(pending_response.status(), StatusCode::OK);
+    let pending_payload: Value = pending_response.json().await.expect("pending payload");
+    assert_eq!(
+        pending_payload.get("status").and_then(Value::as_str),
+        Some("pending")
+    );
+    assert!(pending_payload.get("deviceSessionToken").is_none());
+
+    let wrong_token_response = pair_status(random_token(32), 0)
+        .await
+        .expect("pair status with wrong token");
+    assert_eq!(wrong_token_response.status(), StatusCode::FORBIDDEN);
 
+    let long_poll = tokio::spawn(pair_status(status_token.clone(), 5_000));
     let pair_request = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
         payload.get("type").and_then(Value::as_str) == Some("relay.pair_request")
     })
     .await;
-    let request_id = pair_request
-        .get("requestID")
-        .and_then(Value::as_str)
-        .expect("requestID")
-        .to_string();
-
+    assert_eq!(
+        pair_request.get("requestID").and_then(Value::as_str),
+        Some(request_id.as_str())
+    );
     desktop_socket
         .send(Message::Text(
             json!({
@@ -3481,142 +6922,460 @@ async fn nats_presence_probe_marks_second_pod_mobile_auth_online_and_forwards_co
         .await
         .expect("desktop pair decision send");
 
-    let join_response = join_future.await.expect("join task");
-    assert_eq!(join_response.status(), StatusCode::OK);
-    let join_payload: Value = join_response.json().await.expect("join payload");
-    let first_device_token = join_payload
+    let approved_response = long_poll
+        .await
+        .expect("long poll task")
+        .expect("long poll request");
+    assert_eq!(approved_response.status(), StatusCode::OK);
+    let approved_payload: Value = approved_response.json().await.expect("approved payload");
+    assert_eq!(
+        approved_payload.get("status").and_then(Value::as_str),
+        Some("approved")
+    );
+    assert_eq!(
+        approved_payload.get("wsURL").and_then(Value::as_str),
+        Some(ws_url.as_str())
+    );
+    let device_session_token = approved_payload
         .get("deviceSessionToken")
         .and_then(Value::as_str)
-        .expect("device token")
+        .expect("device session token")
         .to_string();
 
-    let mut second_mobile_request = (base_b.replace("http://", "ws://") + "/ws")
+    let mut mobile_request = ws_url
+        .clone()
         .into_client_request()
-        .expect("second mobile request");
-    second_mobile_request.headers_mut().insert(
+        .expect("mobile request");
+    mobile_request.headers_mut().insert(
         "Origin",
         "http://localhost:4173".parse().expect("origin header"),
     );
-    let (mut second_mobile_socket, _) = tokio_tungstenite::connect_async(second_mobile_request)
+    let (mut mobile_socket, _) = tokio_tungstenite::connect_async(mobile_request)
         .await
-        .expect("second mobile websocket");
-    second_mobile_socket
+        .expect("mobile websocket");
+    mobile_socket
         .send(Message::Text(
-            json!({ "type": "relay.auth", "token": first_device_token }).to_string(),
+            json!({ "type": "relay.auth", "token": device_session_token }).to_string(),
         ))
         .await
-        .expect("second mobile auth send");
+        .expect("mobile auth send");
+    next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
 
-    let second_mobile_auth =
-        next_matching_json_message(&mut second_mobile_socket, 1_500, |payload| {
-            payload.get("type").and_then(Value::as_str) == Some("auth_ok")
-        })
-        .await;
-    assert_eq!(
-        second_mobile_auth.get("role").and_then(Value::as_str),
-        Some("mobile")
-    );
-    assert_eq!(
-        second_mobile_auth
-            .get("desktopConnected")
-            .and_then(Value::as_bool),
-        Some(true)
+    task.abort();
+}
+
+#[tokio::test]
+async fn async_pair_status_long_poll_resolves_when_desktop_is_on_another_instance() {
+    let bus: Arc<dyn CrossInstanceBus> = Arc::new(InProcessCrossInstanceBus::new());
+    let redis_url = std::env::var("REMOTE_CONTROL_REDIS_TEST_URL")
+        .ok()
+        .filter(|value| !value.trim().is_empty());
+    let redis_key_prefix = format!("relay-test-{}", random_token(8));
+    let client = reqwest::Client::new();
+    let session_id = random_token(16);
+    let join_token = random_token(32);
+    let desktop_session_token = random_token(32);
+
+    let configure = |config: &mut RelayConfig| {
+        config.redis_url = redis_url.clone();
+        config.redis_key_prefix = redis_key_prefix.clone();
+    };
+    let (base_a, task_a) = spawn_test_server_with_bus(Some(bus.clone()), configure).await;
+    let (base_b, task_b) = spawn_test_server_with_bus(Some(bus.clone()), configure).await;
+
+    // With Redis the second instance loads the session; without it each one is seeded.
+    let start_bases = if redis_url.is_some() {
+        vec![&base_a]
+    } else {
+        vec![&base_a, &base_b]
+    };
+    for base in start_bases {
+        let start_response = client
+            .post(format!("{base}/pair/start"))
+            .json(&json!({
+                "sessionID": session_id,
+                "joinToken": join_token,
+                "desktopSessionToken": desktop_session_token,
+                "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+                "idleTimeoutSeconds": 1800,
+            }))
+            .send()
+            .await
+            .expect("pair start request");
+        assert_eq!(start_response.status(), StatusCode::OK);
+    }
+
+    let ws_url_a = base_a.replace("http://", "ws://") + "/ws";
+    let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url_a)
+        .await
+        .expect("desktop websocket");
+    desktop_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": desktop_session_token }).to_string(),
+        ))
+        .await
+        .expect("desktop auth send");
+    next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+
+    let join_response = client
+        .post(format!("{base_b}/pair/join"))
+        .header("Origin", "http://localhost:4173")
+        .json(&json!({
+            "sessionID": session_id,
+            "joinToken": join_token,
+            "deviceName": "Remote Async Phone",
+            "async": true,
+        }))
+        .send()
+        .await
+        .expect("async pair join request");
+    assert_eq!(join_response.status(), StatusCode::ACCEPTED);
+    let join_payload: Value = join_response.json().await.expect("async join payload");
+    let request_id = join_payload
+        .get("requestID")
+        .and_then(Value::as_str)
+        .expect("requestID")
+        .to_string();
+    let status_token = join_payload
+        .get("statusToken")
+        .and_then(Value::as_str)
+        .expect("statusToken")
+        .to_string();
+
+    let long_poll = tokio::spawn(
+        client
+            .post(format!("{base_b}/pair/status"))
+            .header("Origin", "http://localhost:4173")
+            .json(&json!({
+                "sessionID": session_id,
+                "requestID": request_id,
+                "statusToken": status_token,
+                "waitMs": 5_000,
+            }))
+            .send(),
     );
+    next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("relay.pair_request")
+            && payload.get("requestID").and_then(Value::as_str) == Some(request_id.as_str())
+    })
+    .await;
+    // Let the long-poll settle into its wait before the decision lands.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(!long_poll.is_finished());
 
-    second_mobile_socket
+    desktop_socket
         .send(Message::Text(
             json!({
-                "schemaVersion": 2,
+                "type": "relay.pair_decision",
                 "sessionID": session_id,
-                "seq": 1,
-                "timestamp": chrono::Utc::now().to_rfc3339(),
-                "payload": {
-                    "type": "command",
-                    "payload": {
-                        "name": "thread.select",
-                        "commandID": "cmd-cross-pod-1",
-                        "threadID": "11111111-1111-1111-1111-111111111111"
-                    }
-                }
+                "requestID": request_id,
+                "approved": true,
             })
             .to_string(),
         ))
         .await
-        .expect("cross-pod command send");
+        .expect("desktop pair decision send");
 
-    let forwarded = next_matching_json_message(&mut desktop_socket, 1_500, |payload| {
-        payload
-            .pointer("/payload/payload/name")
-            .and_then(Value::as_str)
-            == Some("thread.select")
-    })
-    .await;
+    let approved_response = tokio::time::timeout(Duration::from_millis(2_000), long_poll)
+        .await
+        .expect("long poll resolves promptly")
+        .expect("long poll task")
+        .expect("long poll request");
+    assert_eq!(approved_response.status(), StatusCode::OK);
+    let approved_payload: Value = approved_response.json().await.expect("approved payload");
     assert_eq!(
-        forwarded
-            .pointer("/payload/payload/commandID")
-            .and_then(Value::as_str),
-        Some("cmd-cross-pod-1")
+        approved_payload.get("status").and_then(Value::as_str),
+        Some("approved")
     );
+    assert!(approved_payload.get("deviceSessionToken").is_some());
 
-    second_mobile_socket
-        .close(None)
-        .await
-        .expect("second mobile socket close");
-    desktop_socket
-        .close(None)
-        .await
-        .expect("desktop socket close");
     task_b.abort();
     task_a.abort();
 }
 
+async fn connect_authenticated_socket(base: &str, token: &str) -> TestSocket {
+    let mut request = (base.replace("http://", "ws://") + "/ws")
+        .into_client_request()
+        .expect("websocket request");
+    request.headers_mut().insert(
+        "Origin",
+        "http://localhost:4173".parse().expect("origin header"),
+    );
+    let (mut socket, _) = tokio_tungstenite::connect_async(request)
+        .await
+        .expect("websocket connect");
+    socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": token }).to_string(),
+        ))
+        .await
+        .expect("auth send");
+    next_matching_json_message(&mut socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+    socket
+}
+
 #[tokio::test]
-async fn nats_cross_pod_desktop_events_preserve_publish_order() {
+async fn presence_route_follows_desktop_to_another_instance_within_cache_ttl() {
     let Some(redis_url) = std::env::var("REMOTE_CONTROL_REDIS_TEST_URL")
         .ok()
         .filter(|value| !value.trim().is_empty())
     else {
         return;
     };
-    let Some(nats_url) = std::env::var("REMOTE_CONTROL_NATS_TEST_URL")
-        .ok()
-        .filter(|value| !value.trim().is_empty())
-    else {
-        return;
+    let bus: Arc<dyn CrossInstanceBus> = Arc::new(InProcessCrossInstanceBus::new());
+    let redis_key_prefix = format!("relay-test-{}", random_token(8));
+    let client = reqwest::Client::new();
+    let session_id = random_token(16);
+    let join_token = random_token(32);
+    let desktop_session_token = random_token(32);
+
+    // The route cache outlives the whole test, so only the change notification can move it.
+    let configure = |config: &mut RelayConfig| {
+        config.redis_url = Some(redis_url.clone());
+        config.redis_key_prefix = redis_key_prefix.clone();
+        config.presence_registry_enabled = true;
+        config.presence_route_cache_ttl_ms = 25_000;
+    };
+    let (base_a, task_a) = spawn_test_server_with_bus(Some(bus.clone()), configure).await;
+    let (base_b, task_b) = spawn_test_server_with_bus(Some(bus.clone()), configure).await;
+    let (base_c, task_c) = spawn_test_server_with_bus(Some(bus.clone()), configure).await;
+
+    let start_response = client
+        .post(format!("{base_b}/pair/start"))
+        .json(&json!({
+            "sessionID": session_id,
+            "joinToken": join_token,
+            "desktopSessionToken": desktop_session_token,
+            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+            "idleTimeoutSeconds": 1800,
+        }))
+        .send()
+        .await
+        .expect("pair start request");
+    assert_eq!(start_response.status(), StatusCode::OK);
+
+    let mut desktop_on_b = connect_authenticated_socket(&base_b, &desktop_session_token).await;
+    let (_, join_payload) = join_with_desktop_approval(
+        &client,
+        &base_a,
+        &mut desktop_on_b,
+        &session_id,
+        &join_token,
+        "Presence Route Phone",
+    )
+    .await;
+    let device_token = join_payload
+        .get("deviceSessionToken")
+        .and_then(Value::as_str)
+        .expect("device token");
+    let mut mobile_on_a = connect_authenticated_socket(&base_a, device_token).await;
+
+    let command = |seq: u64| {
+        json!({
+            "schemaVersion": 2,
+            "sessionID": session_id,
+            "seq": seq,
+            "payload": {
+                "type": "command",
+                "payload": {
+                    "name": "thread.select",
+                    "commandID": format!("cmd-{seq}"),
+                    "threadID": "thread-1"
+                }
+            }
+        })
+        .to_string()
     };
+    let is_command = |seq: u64| {
+        move |payload: &Value| {
+            payload
+                .pointer("/payload/payload/commandID")
+                .and_then(Value::as_str)
+                == Some(format!("cmd-{seq}").as_str())
+        }
+    };
+
+    mobile_on_a
+        .send(Message::Text(command(1)))
+        .await
+        .expect("first command send");
+    next_matching_json_message(&mut desktop_on_b, 2_000, is_command(1)).await;
+
+    desktop_on_b.close(None).await.expect("desktop close on b");
+    let mut desktop_on_c = connect_authenticated_socket(&base_c, &desktop_session_token).await;
+    next_matching_json_message(&mut mobile_on_a, 2_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("relay.desktop_status")
+            && payload.get("desktopConnected").and_then(Value::as_bool) == Some(true)
+    })
+    .await;
+    // Give the change notification a moment to reach instance A.
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    mobile_on_a
+        .send(Message::Text(command(2)))
+        .await
+        .expect("second command send");
+    next_matching_json_message(&mut desktop_on_c, 2_000, is_command(2)).await;
+
+    task_c.abort();
+    task_b.abort();
+    task_a.abort();
+}
 
+#[tokio::test]
+async fn concurrent_pair_joins_on_another_instance_both_reach_the_desktop_decision() {
+    let bus: Arc<dyn CrossInstanceBus> = Arc::new(InProcessCrossInstanceBus::new());
+    let redis_url = std::env::var("REMOTE_CONTROL_REDIS_TEST_URL")
+        .ok()
+        .filter(|value| !value.trim().is_empty());
     let redis_key_prefix = format!("relay-test-{}", random_token(8));
-    let nats_subject_prefix = format!("relay-test-{}", random_token(8));
     let client = reqwest::Client::new();
     let session_id = random_token(16);
     let join_token = random_token(32);
     let desktop_session_token = random_token(32);
 
-    let (base_a, task_a) = spawn_test_server_with_config(|config| {
-        config.redis_url = Some(redis_url.clone());
+    let configure = |config: &mut RelayConfig| {
+        config.redis_url = redis_url.clone();
         config.redis_key_prefix = redis_key_prefix.clone();
-        config.nats_url = Some(nats_url.clone());
-        config.nats_subject_prefix = nats_subject_prefix.clone();
-        config.token_rotation_grace_ms = 30_000;
+        config.trust_proxy = true;
+        config.max_pending_pair_requests_per_session = 2;
+    };
+    let (base_a, task_a) = spawn_test_server_with_bus(Some(bus.clone()), configure).await;
+    let (base_b, task_b) = spawn_test_server_with_bus(Some(bus.clone()), configure).await;
+
+    let start_bases = if redis_url.is_some() {
+        vec![&base_a]
+    } else {
+        vec![&base_a, &base_b]
+    };
+    for base in start_bases {
+        let start_response = client
+            .post(format!("{base}/pair/start"))
+            .json(&json!({
+                "sessionID": session_id,
+                "joinToken": join_token,
+                "desktopSessionToken": desktop_session_token,
+                "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
+                "idleTimeoutSeconds": 1800,
+            }))
+            .send()
+            .await
+            .expect("pair start request");
+        assert_eq!(start_response.status(), StatusCode::OK);
+    }
+
+    let ws_url_a = base_a.replace("http://", "ws://") + "/ws";
+    let (mut desktop_socket, _) = tokio_tungstenite::connect_async(&ws_url_a)
+        .await
+        .expect("desktop websocket");
+    desktop_socket
+        .send(Message::Text(
+            json!({ "type": "relay.auth", "token": desktop_session_token }).to_string(),
+        ))
+        .await
+        .expect("desktop auth send");
+    next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+
+    let spawn_join = |requester_ip: &'static str, device_name: &'static str| {
+        tokio::spawn(
+            client
+                .post(format!("{base_b}/pair/join"))
+                .header("Origin", "http://localhost:4173")
+                .header("X-Forwarded-For", requester_ip)
+                .json(&json!({
+                    "sessionID": session_id,
+                    "joinToken": join_token,
+                    "deviceName": device_name,
+                }))
+                .send(),
+        )
+    };
+    let first_join = spawn_join("203.0.113.10", "First Phone");
+    let first_request = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("relay.pair_request")
+    })
+    .await;
+    // The second join refreshes the session on the joining instance while the first waits.
+    let second_join = spawn_join("203.0.113.20", "Second Phone");
+    let second_request = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("relay.pair_request")
     })
     .await;
-    let (base_b, task_b) = spawn_test_server_with_config(|config| {
-        config.redis_url = Some(redis_url.clone());
-        config.redis_key_prefix = redis_key_prefix.clone();
-        config.nats_url = Some(nats_url.clone());
-        config.nats_subject_prefix = nats_subject_prefix.clone();
-        config.token_rotation_grace_ms = 30_000;
+
+    for (pair_request, approved) in [(&first_request, false), (&second_request, true)] {
+        desktop_socket
+            .send(Message::Text(
+                json!({
+                    "type": "relay.pair_decision",
+                    "sessionID": session_id,
+                    "requestID": pair_request.get("requestID").and_then(Value::as_str).expect("requestID"),
+                    "approved": approved,
+                })
+                .to_string(),
+            ))
+            .await
+            .expect("desktop pair decision send");
+    }
+
+    let first_response = first_join
+        .await
+        .expect("first join task")
+        .expect("first join request");
+    assert_eq!(first_response.status(), StatusCode::FORBIDDEN);
+    let first_payload: Value = first_response.json().await.expect("first join payload");
+    assert_eq!(
+        first_payload.get("error").and_then(Value::as_str),
+        Some("pair_request_denied")
+    );
+
+    let second_response = second_join
+        .await
+        .expect("second join task")
+        .expect("second join request");
+    assert_eq!(second_response.status(), StatusCode::OK);
+    let second_payload: Value = second_response.json().await.expect("second join payload");
+    assert!(second_payload.get("deviceSessionToken").is_some());
+
+    task_b.abort();
+    task_a.abort();
+}
+
+#[tokio::test]
+async fn session_holds_multiple_pending_pair_requests_bounded_per_requester_ip() {
+    let (base, task) = spawn_test_server_with_config(|config| {
+        config.trust_proxy = true;
+        config.max_pending_pair_requests_per_session = 2;
+        config.max_pending_pair_requests_per_ip = 1;
     })
     .await;
+    let client = reqwest::Client::new();
 
+    let session_id = random_token(16);
+    let join_token = random_token(32);
+    let desktop_session_token = random_token(32);
     let start_response = client
-        .post(format!("{base_a}/pair/start"))
+        .post(format!("{base}/pair/start"))
         .json(&json!({
+            "schemaVersion": 2,
             "sessionID": session_id,
             "joinToken": join_token,
             "desktopSessionToken": desktop_session_token,
-            "joinTokenExpiresAt": chrono::Utc::now().checked_add_signed(chrono::Duration::minutes(2)).unwrap().to_rfc3339(),
-            "idleTimeoutSeconds": 1800,
+            "joinTokenExpiresAt": chrono::Utc::now()
+                .checked_add_signed(chrono::Duration::minutes(2))
+                .unwrap()
+                .to_rfc3339(),
         }))
         .send()
         .await
@@ -3634,152 +7393,281 @@ async fn nats_cross_pod_desktop_events_preserve_publish_order() {
         .expect("desktop websocket");
     desktop_socket
         .send(Message::Text(
-            json!({
-                "type": "relay.auth",
-                "token": desktop_session_token
-            })
-            .to_string(),
+            json!({ "type": "relay.auth", "token": desktop_session_token }).to_string(),
         ))
         .await
         .expect("desktop auth send");
-    let _desktop_auth = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+    next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
         payload.get("type").and_then(Value::as_str) == Some("auth_ok")
     })
     .await;
 
-    let join_future = tokio::spawn({
-        let client = client.clone();
-        let base_a = base_a.clone();
-        let session_id = session_id.clone();
-        let join_token = join_token.clone();
-        async move {
-            client
-                .post(format!("{base_a}/pair/join"))
-                .header("Origin", "http://localhost:4173")
-                .json(&json!({
-                    "sessionID": session_id,
-                    "joinToken": join_token,
-                    "deviceName": "Ordered Event iPhone",
-                }))
-                .send()
-                .await
-                .expect("pair join request")
-        }
-    });
+    let async_join = |requester_ip: &'static str, device_name: &'static str| {
+        client
+            .post(format!("{base}/pair/join"))
+            .header("Origin", "http://localhost:4173")
+            .header("X-Forwarded-For", requester_ip)
+            .json(&json!({
+                "sessionID": session_id,
+                "joinToken": join_token,
+                "deviceName": device_name,
+                "async": true,
+            }))
+            .send()
+    };
 
-    let pair_request = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
-        payload.get("type").and_then(Value::as_str) == Some("relay.pair_request")
-    })
-    .await;
-    let request_id = pair_request
+    let first_response = async_join("203.0.113.10", "First Phone")
+        .await
+        .expect("first join");
+    assert_eq!(first_response.status(), StatusCode::ACCEPTED);
+    let first_payload: Value = first_response.json().await.expect("first join payload");
+    let first_request_id = first_payload
         .get("requestID")
         .and_then(Value::as_str)
-        .expect("requestID")
+        .expect("first requestID")
         .to_string();
 
-    desktop_socket
-        .send(Message::Text(
-            json!({
-                "type": "relay.pair_decision",
-                "sessionID": session_id,
-                "requestID": request_id,
-                "approved": true,
-            })
-            .to_string(),
-        ))
+    let repeat_response = async_join("203.0.113.10", "First Phone Again")
         .await
-        .expect("desktop pair decision send");
+        .expect("repeat join");
+    assert_eq!(repeat_response.status(), StatusCode::CONFLICT);
+    let repeat_payload: Value = repeat_response.json().await.expect("repeat join payload");
+    assert_eq!(
+        repeat_payload.get("error").and_then(Value::as_str),
+        Some("pair_request_in_progress")
+    );
+    assert_eq!(
+        repeat_payload.get("requestID").and_then(Value::as_str),
+        Some(first_request_id.as_str())
+    );
 
-    let join_response = join_future.await.expect("join task");
-    assert_eq!(join_response.status(), StatusCode::OK);
-    let join_payload: Value = join_response.json().await.expect("join payload");
-    let first_device_token = join_payload
-        .get("deviceSessionToken")
+    let second_response = async_join("203.0.113.20", "Second Phone")
+        .await
+        .expect("second join");
+    assert_eq!(second_response.status(), StatusCode::ACCEPTED);
+    let second_payload: Value = second_response.json().await.expect("second join payload");
+    let second_request_id = second_payload
+        .get("requestID")
         .and_then(Value::as_str)
-        .expect("device token")
+        .expect("second requestID")
         .to_string();
+    assert_ne!(first_request_id, second_request_id);
 
-    let mut second_mobile_request = (base_b.replace("http://", "ws://") + "/ws")
-        .into_client_request()
-        .expect("second mobile request");
-    second_mobile_request.headers_mut().insert(
-        "Origin",
-        "http://localhost:4173".parse().expect("origin header"),
-    );
-    let (mut second_mobile_socket, _) = tokio_tungstenite::connect_async(second_mobile_request)
-        .await
-        .expect("second mobile websocket");
-    second_mobile_socket
-        .send(Message::Text(
-            json!({ "type": "relay.auth", "token": first_device_token }).to_string(),
-        ))
+    let full_response = async_join("203.0.113.30", "Third Phone")
         .await
-        .expect("second mobile auth send");
-    let _second_mobile_auth =
-        next_matching_json_message(&mut second_mobile_socket, 1_500, |payload| {
-            payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+        .expect("third join");
+    assert_eq!(full_response.status(), StatusCode::CONFLICT);
+    let full_payload: Value = full_response.json().await.expect("third join payload");
+    assert_eq!(
+        full_payload.get("error").and_then(Value::as_str),
+        Some("pair_requests_full")
+    );
+
+    let mut seen_request_ids = Vec::new();
+    for _ in 0..2 {
+        let pair_request = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+            payload.get("type").and_then(Value::as_str) == Some("relay.pair_request")
         })
         .await;
+        seen_request_ids.push(
+            pair_request
+                .get("requestID")
+                .and_then(Value::as_str)
+                .expect("pair request id")
+                .to_string(),
+        );
+    }
+    seen_request_ids.sort();
+    let mut expected_request_ids = vec![first_request_id.clone(), second_request_id.clone()];
+    expected_request_ids.sort();
+    assert_eq!(seen_request_ids, expected_request_ids);
 
-    let expected_bodies = (1..=8)
-        .map(|index| format!("event-{index}"))
-        .collect::<Vec<_>>();
-
-    for (seq, body) in expected_bodies.iter().enumerate() {
+    for (request_id, approved) in [(&first_request_id, false), (&second_request_id, true)] {
         desktop_socket
             .send(Message::Text(
                 json!({
-                    "schemaVersion": 2,
+                    "type": "relay.pair_decision",
                     "sessionID": session_id,
-                    "seq": seq + 1,
-                    "timestamp": chrono::Utc::now().to_rfc3339(),
-                    "payload": {
-                        "type": "event",
-                        "payload": {
-                            "name": "relay.order_probe",
-                            "threadID": "thread-order",
-                            "body": body,
-                            "messageID": format!("msg-order-{}", seq + 1),
-                            "role": "assistant",
-                            "createdAt": chrono::Utc::now().to_rfc3339(),
-                        }
-                    }
+                    "requestID": request_id,
+                    "approved": approved,
                 })
                 .to_string(),
             ))
             .await
-            .expect("ordered event send");
+            .expect("desktop pair decision send");
     }
 
-    let mut received_bodies = Vec::new();
-    while received_bodies.len() < expected_bodies.len() {
-        let event = next_matching_json_message(&mut second_mobile_socket, 1_500, |payload| {
-            payload.pointer("/payload/type").and_then(Value::as_str) == Some("event")
-                && payload
-                    .pointer("/payload/payload/name")
-                    .and_then(Value::as_str)
-                    == Some("relay.order_probe")
-        })
-        .await;
-        received_bodies.push(
-            event
-                .pointer("/payload/payload/body")
-                .and_then(Value::as_str)
-                .expect("ordered event body")
-                .to_string(),
+    for (request_id, status_token, requester_ip, expected_status) in [
+        (
+            &first_request_id,
+            first_payload.get("statusToken").and_then(Value::as_str),
+            "203.0.113.10",
+            "rejected",
+        ),
+        (
+            &second_request_id,
+            second_payload.get("statusToken").and_then(Value::as_str),
+            "203.0.113.20",
+            "approved",
+        ),
+    ] {
+        let status_payload: Value = client
+            .post(format!("{base}/pair/status"))
+            .header("Origin", "http://localhost:4173")
+            .header("X-Forwarded-For", requester_ip)
+            .json(&json!({
+                "sessionID": session_id,
+                "requestID": request_id,
+                "statusToken": status_token,
+                "waitMs": 2_000,
+            }))
+            .send()
+            .await
+            .expect("pair status request")
+            .json()
+            .await
+            .expect("pair status payload");
+        assert_eq!(
+            status_payload.get("status").and_then(Value::as_str),
+            Some(expected_status)
         );
     }
 
-    assert_eq!(received_bodies, expected_bodies);
+    task.abort();
+}
 
-    second_mobile_socket
-        .close(None)
+#[tokio::test]
+async fn v3_mobile_and_v2_desktop_exchange_envelopes_through_schema_adapters() {
+    let (
+        base,
+        task,
+        mut desktop_socket,
+        mobile_socket,
+        session_id,
+        _device_token,
+        rotated_device_token,
+    ) = pair_connected_mobile(|_| {}).await;
+    drop(mobile_socket);
+
+    let connect_mobile = || async {
+        let mut request = (base.replace("http://", "ws://") + "/ws")
+            .into_client_request()
+            .expect("mobile request");
+        request.headers_mut().insert(
+            "Origin",
+            "http://localhost:4173".parse().expect("origin header"),
+        );
+        tokio_tungstenite::connect_async(request)
+            .await
+            .expect("mobile websocket")
+            .0
+    };
+
+    let mut unsupported_socket = connect_mobile().await;
+    unsupported_socket
+        .send(Message::Text(
+            json!({
+                "type": "relay.auth",
+                "token": rotated_device_token,
+                "supportedSchemaVersions": [1],
+            })
+            .to_string(),
+        ))
         .await
-        .expect("second mobile socket close");
+        .expect("unsupported auth send");
+    let unsupported_error = next_matching_json_message(&mut unsupported_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("relay.error")
+    })
+    .await;
+    assert_eq!(
+        unsupported_error.get("error").and_then(Value::as_str),
+        Some("unsupported_schema_version")
+    );
+
+    let mut mobile_socket = connect_mobile().await;
+    mobile_socket
+        .send(Message::Text(
+            json!({
+                "type": "relay.auth",
+                "token": rotated_device_token,
+                "supportedSchemaVersions": [2, 3],
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("v3 mobile auth send");
+    let mobile_auth = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("auth_ok")
+    })
+    .await;
+    assert_eq!(
+        mobile_auth.get("schemaVersion").and_then(Value::as_u64),
+        Some(3)
+    );
+
     desktop_socket
-        .close(None)
+        .send(Message::Text(
+            json!({
+                "schemaVersion": 2,
+                "sessionID": session_id,
+                "seq": 1,
+                "timestamp": "2026-01-01T00:00:00Z",
+                "payload": {
+                    "type": "snapshot",
+                    "payload": { "projects": [{ "name": "General" }] }
+                }
+            })
+            .to_string(),
+        ))
         .await
-        .expect("desktop socket close");
-    task_b.abort();
-    task_a.abort();
+        .expect("desktop v2 snapshot send");
+    let v3_snapshot = next_matching_json_message(&mut mobile_socket, 1_000, |payload| {
+        payload.get("type").and_then(Value::as_str) == Some("snapshot")
+    })
+    .await;
+    assert_eq!(
+        v3_snapshot.get("schemaVersion").and_then(Value::as_u64),
+        Some(3)
+    );
+    assert_eq!(
+        v3_snapshot.pointer("/body/projects/0/name"),
+        Some(&json!("General"))
+    );
+    assert!(v3_snapshot.get("payload").is_none());
+
+    mobile_socket
+        .send(Message::Text(
+            json!({
+                "schemaVersion": 3,
+                "sessionID": session_id,
+                "seq": 1,
+                "type": "command",
+                "body": {
+                    "name": "thread.select",
+                    "commandID": "cmd-v3",
+                    "threadID": "thread-v3"
+                }
+            })
+            .to_string(),
+        ))
+        .await
+        .expect("mobile v3 command send");
+    let forwarded = next_matching_json_message(&mut desktop_socket, 1_000, |payload| {
+        payload.pointer("/payload/type").and_then(Value::as_str) == Some("command")
+    })
+    .await;
+    assert_eq!(
+        forwarded.get("schemaVersion").and_then(Value::as_u64),
+        Some(2)
+    );
+    assert_eq!(
+        forwarded
+            .pointer("/payload/payload/threadID")
+            .and_then(Value::as_str),
+        Some("thread-v3")
+    );
+    assert!(forwarded.get("relayDeviceID").is_some());
+    assert!(forwarded.get("body").is_none());
+
+    task.abort();
 }
@@ -71,6 +71,8 @@ struct LoadHarnessSummary {
     p95_latency_ms: u128,
     p99_latency_ms: u128,
     max_latency_ms: u128,
+    command_phase_ms: u128,
+    commands_per_second: u64,
     p95_latency_budget_ms: u64,
     passes_latency_budget: bool,
     outbound_send_failures: u64,
@@ -423,6 +425,7 @@ async fn relay_parallel_sessions_load_harness() {
 
     let semaphore = Arc::new(Semaphore::new(cfg.setup_concurrency.max(1)));
     let roundtrip_samples_us = Arc::new(Mutex::new(Vec::<u128>::new()));
+    let command_windows = Arc::new(Mutex::new(Vec::<(Instant, Instant)>::new()));
     let errors = Arc::new(Mutex::new(Vec::<String>::new()));
 
     let mut tasks = Vec::with_capacity(cfg.sessions);
@@ -433,6 +436,7 @@ async fn relay_parallel_sessions_load_harness() {
         let origin = cfg.origin.clone();
         let client = client.clone();
         let roundtrip_samples_us = Arc::clone(&roundtrip_samples_us);
+        let command_windows = Arc::clone(&command_windows);
         let roundtrip_timeout = Duration::from_millis(cfg.roundtrip_timeout_ms);
         let messages_per_session = cfg.messages_per_session;
 
@@ -444,6 +448,7 @@ async fn relay_parallel_sessions_load_harness() {
 
             let mut handles = pair_connected_mobile(&base, &origin, &client).await?;
 
+            let command_phase_start = Instant::now();
             for seq in 1..=messages_per_session {
                 let payload = json!({
                     "schemaVersion": 2,
@@ -475,6 +480,10 @@ async fn relay_parallel_sessions_load_harness() {
                     .await
                     .push(start.elapsed().as_micros().max(1));
             }
+            command_windows
+                .lock()
+                .await
+                .push((command_phase_start, Instant::now()));
 
             // Best-effort session close to keep harness deterministic.
             let _ = client
@@ -518,6 +527,8 @@ async fn relay_parallel_sessions_load_harness() {
             p95_latency_ms: 0,
             p99_latency_ms: 0,
             max_latency_ms: 0,
+            command_phase_ms: 0,
+            commands_per_second: 0,
             p95_latency_budget_ms: cfg.p95_latency_budget_ms,
             passes_latency_budget: false,
             outbound_send_failures: 0,
@@ -557,6 +568,18 @@ async fn relay_parallel_sessions_load_harness() {
     let p99_ms = micros_to_millis_ceil(p99_us);
     let max_ms = micros_to_millis_ceil(max_us);
 
+    // Wall time from the first command sent to the last one forwarded, across all sessions.
+    let windows = command_windows.lock().await.clone();
+    let command_phase_us = match (
+        windows.iter().map(|(start, _)| *start).min(),
+        windows.iter().map(|(_, end)| *end).max(),
+    ) {
+        (Some(start), Some(end)) => end.duration_since(start).as_micros().max(1),
+        _ => 1,
+    };
+    let command_phase_ms = micros_to_millis_ceil(command_phase_us);
+    let commands_per_second = (samples.len() as u128 * 1_000_000 / command_phase_us) as u64;
+
     let metrics = reqwest::get(format!("{base}/metricsz"))
         .await
         .expect("metrics request")
@@ -594,6 +617,8 @@ async fn relay_parallel_sessions_load_harness() {
         p95_latency_ms: p95_ms,
         p99_latency_ms: p99_ms,
         max_latency_ms: max_ms,
+        command_phase_ms,
+        commands_per_second,
         p95_latency_budget_ms: cfg.p95_latency_budget_ms,
         passes_latency_budget,
         outbound_send_failures,
@@ -609,7 +634,7 @@ async fn relay_parallel_sessions_load_harness() {
     }
 
     println!(
-        "relay load harness summary: sessions={} messages_per_session={} samples={} p50={}us/{}ms p95={}us/{}ms p99={}us/{}ms max={}us/{}ms outboundSendFailures={} slowConsumerDisconnects={} wsAuthFailures={}",
+        "relay load harness summary: sessions={} messages_per_session={} samples={} p50={}us/{}ms p95={}us/{}ms p99={}us/{}ms max={}us/{}ms commandPhase={}ms commandsPerSecond={} outboundSendFailures={} slowConsumerDisconnects={} wsAuthFailures={}",
         cfg.sessions,
         cfg.messages_per_session,
         samples.len(),
@@ -621,6 +646,8 @@ async fn relay_parallel_sessions_load_harness() {
         p99_ms,
         max_us,
         max_ms,
+        command_phase_ms,
+        commands_per_second,
         outbound_send_failures,
         slow_consumer_disconnects,
         ws_auth_failures,